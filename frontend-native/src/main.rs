@@ -1,9 +1,66 @@
-use std::{collections::HashMap, error::Error, fs, io::{Read, BufReader, BufWriter}, path::PathBuf, time::{Duration, Instant}};
-use nen_emulator::{joypad::JoypadButton as NesJoypadButton, nes::Nes};
-use sdl2::{audio::{AudioQueue, AudioSpecDesired, AudioStatus}, controller::{Axis, Button}, event::Event, keyboard::Keycode};
+use std::{collections::HashMap, env, error::Error, fs, io::Read, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use clap::{Parser, ValueEnum};
+use nen_emulator::{savestate, prelude::{ConsoleTiming, EmuConfig, JoypadButton as NesJoypadButton, Nes, StepOutcome, SAVE_BUNDLE_EXTENSION}};
+use sdl2::{audio::{AudioQueue, AudioSpecDesired, AudioStatus}, controller::{Axis, Button, GameController}, event::Event, keyboard::Keycode, pixels::PixelFormatEnum, rect::Rect, render::Canvas, surface::Surface, video::Window};
+
+#[derive(Parser)]
+#[command(about = "NEN Emulator, a NES emulator. Roms can also be dropped on the window.")]
+struct Args {
+  /// Rom to run, .nes or .zip
+  rom: Option<String>,
+  /// Start in fullscreen
+  #[arg(long)]
+  fullscreen: bool,
+  /// Size of the window, in multiples of the NES resolution
+  #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=16))]
+  scale: u32,
+  /// Run games as on a console of this region, instead of the detected one
+  #[arg(long)]
+  region: Option<Region>,
+  /// Savestate slot used by the save and load keys, loaded right away when a rom is given
+  #[arg(long)]
+  state: Option<u8>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Region { Ntsc, Pal, Dendy }
+
+impl From<Region> for ConsoleTiming {
+  fn from(region: Region) -> Self {
+    match region {
+      Region::Ntsc => ConsoleTiming::NTSC,
+      Region::Pal => ConsoleTiming::PAL,
+      Region::Dendy => ConsoleTiming::Dendy,
+    }
+  }
+}
 
 enum InputAction {
-  Game(NesJoypadButton), Pause, Reset, Mute, Save, Load, SpriteLimit
+  Game(NesJoypadButton), Pause, Reset, Mute, Save, Load, SpriteLimit, Screenshot
+}
+
+impl InputAction {
+  fn from_name(name: &str) -> Option<Self> {
+    let action = match name {
+      "a" => Self::Game(NesJoypadButton::a),
+      "b" => Self::Game(NesJoypadButton::b),
+      "select" => Self::Game(NesJoypadButton::select),
+      "start" => Self::Game(NesJoypadButton::start),
+      "up" => Self::Game(NesJoypadButton::up),
+      "down" => Self::Game(NesJoypadButton::down),
+      "left" => Self::Game(NesJoypadButton::left),
+      "right" => Self::Game(NesJoypadButton::right),
+      "pause" => Self::Pause,
+      "reset" => Self::Reset,
+      "mute" => Self::Mute,
+      "save" => Self::Save,
+      "load" => Self::Load,
+      "sprite_limit" => Self::SpriteLimit,
+      "screenshot" => Self::Screenshot,
+      _ => return None,
+    };
+    Some(action)
+  }
 }
 
 const AXIS_DEAD_ZONE: i16 = 10_000;
@@ -28,6 +85,7 @@ impl Keymaps {
       (Keycode::NUM_9, InputAction::Save),
       (Keycode::NUM_0, InputAction::Load),
       (Keycode::NUM_1, InputAction::SpriteLimit),
+      (Keycode::F12, InputAction::Screenshot),
     ]);
 
     let default_padmap = HashMap::from([
@@ -46,9 +104,100 @@ impl Keymaps {
 
     Keymaps { keymap: default_keymap, padmap: default_padmap }
   }
+
+  // The mapping file has a [keyboard] and a [controller] section, with `<SDL key or button name> = <action>` lines.
+  // A section in the file replaces the default mapping of that section, the other one is kept.
+  pub fn load(path: &Path) -> Self {
+    let mut keymaps = Self::new();
+    let Ok(text) = fs::read_to_string(path) else { return keymaps; };
+
+    let mut section = "";
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') { continue; }
+
+      if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+        section = match name.trim() {
+          "keyboard" => { keymaps.keymap.clear(); "keyboard" }
+          "controller" => { keymaps.padmap.clear(); "controller" }
+          other => { eprintln!("{}:{}: unknown section [{other}]", path.display(), i+1); "" }
+        };
+        continue;
+      }
+
+      let Some((input, action)) = line.split_once('=') else {
+        eprintln!("{}:{}: expected `<input> = <action>`", path.display(), i+1);
+        continue;
+      };
+      let (input, action) = (input.trim(), action.trim());
+      let Some(action) = InputAction::from_name(action) else {
+        eprintln!("{}:{}: unknown action {action}", path.display(), i+1);
+        continue;
+      };
+
+      match section {
+        "keyboard" => match Keycode::from_name(input) {
+          Some(key) => { keymaps.keymap.insert(key, action); }
+          None => eprintln!("{}:{}: unknown key {input}", path.display(), i+1),
+        }
+        "controller" => match Button::from_string(input) {
+          Some(button) => { keymaps.padmap.insert(button, action); }
+          None => eprintln!("{}:{}: unknown controller button {input}", path.display(), i+1),
+        }
+        _ => {}
+      }
+    }
+
+    keymaps
+  }
+}
+
+// Controllers are plugged in the NES ports in connection order, the keyboard always drives port 1.
+// A controller which is unplugged gets its port back when plugged again, if nobody took it in the meantime.
+struct Pads {
+  ports: [Option<GameController>; 2],
+  // name of the controller which last left each port
+  left: [Option<String>; 2],
+}
+
+impl Pads {
+  fn new() -> Self {
+    Self { ports: [None, None], left: [None, None] }
+  }
+
+  fn attach(&mut self, controller: GameController) {
+    let name = controller.name();
+    let free = |i: &usize| self.ports[*i].is_none();
+    let port = (0..self.ports.len()).filter(free).find(|&i| self.left[i].as_ref() == Some(&name))
+      .or_else(|| (0..self.ports.len()).filter(free).find(|&i| self.left[i].is_none()))
+      .or_else(|| (0..self.ports.len()).find(free));
+
+    match port {
+      Some(port) => {
+        println!("Controller {name} plugged in port {}", port+1);
+        self.ports[port] = Some(controller);
+        self.left[port] = None;
+      }
+      None => println!("Controller {name} connected, but both ports are taken"),
+    }
+  }
+
+  fn detach(&mut self, instance_id: u32) -> Option<usize> {
+    let port = self.port(instance_id)?;
+    let controller = self.ports[port].take()?;
+    println!("Controller {} unplugged from port {}", controller.name(), port+1);
+    self.left[port] = Some(controller.name());
+    Some(port)
+  }
+
+  fn port(&self, instance_id: u32) -> Option<usize> {
+    self.ports.iter().position(|controller|
+      controller.as_ref().is_some_and(|c| c.instance_id() == instance_id)
+    )
+  }
 }
 
-fn open_rom(path: &str) -> Result<Nes, Box<dyn Error>> {
+fn open_rom(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
 	let mut bytes = Vec::new();
 	let file = fs::File::open(path)?;
 
@@ -62,126 +211,215 @@ fn open_rom(path: &str) -> Result<Nes, Box<dyn Error>> {
 			fs::File::open(path).map(|mut f| f.read_to_end(&mut bytes))
 		)?;
 
-	
-  Nes::boot_from_bytes(&bytes)
-    .map_err(|msg| msg.into())
+  Ok(bytes)
+}
+
+// Where the files of the games are written. Next to the rom fails for roms opened from zips or read-only places,
+// so they go in the user's data directory, unless moved with the NEN_SAVE_DIR, NEN_STATE_DIR and NEN_SCREENSHOT_DIR variables.
+struct Dirs {
+  saves: PathBuf,
+  states: PathBuf,
+  screenshots: PathBuf,
+}
+
+impl Dirs {
+  fn from_env() -> Self {
+    let data = data_dir();
+    let dir = |var: &str, default: &str| env::var_os(var)
+      .map(PathBuf::from)
+      .unwrap_or_else(|| data.join(default));
+
+    Self {
+      saves: dir("NEN_SAVE_DIR", "saves"),
+      states: dir("NEN_STATE_DIR", "states"),
+      screenshots: dir("NEN_SCREENSHOT_DIR", "screenshots"),
+    }
+  }
+}
+
+fn data_dir() -> PathBuf {
+  let base = if cfg!(target_os = "windows") {
+    env::var_os("APPDATA").map(PathBuf::from)
+  } else if cfg!(target_os = "macos") {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+  } else {
+    env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+      .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+  };
+  base.unwrap_or_default().join("nen-emulator")
+}
+
+// The name of the rom file, without its folder and extension
+fn game_name(rom_path: &str) -> String {
+  Path::new(rom_path).file_stem()
+    .map(|stem| stem.to_string_lossy().into_owned())
+    .unwrap_or_else(|| String::from("game"))
 }
 
-fn save_sram(ctx: &EmuCtx) {
-  if let Some(data) = ctx.emu.save_sram() {
-    let path = PathBuf::from(&ctx.rom_path).with_extension("srm");
-    let _ = fs::write(path, data)
-      .inspect_err(|e| eprintln!("Couldn't save: {e}"));
+// The file of the game in one of the directories. The rom name may contain dots, so the extension is appended.
+fn game_file(dir: &Path, ctx: &EmuCtx, extension: &str) -> PathBuf {
+  dir.join(format!("{}.{extension}", game_name(&ctx.rom_path)))
+}
+
+fn write_game_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  fs::write(path, data)
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH)
+    .map(|time| time.as_secs())
+    .unwrap_or(0)
+}
+
+fn save_sram(ctx: &mut EmuCtx) {
+  if let Some(data) = ctx.emu.save_bundle(unix_now()) {
+    let path = game_file(&ctx.dirs.saves, ctx, SAVE_BUNDLE_EXTENSION);
+    let _ = write_game_file(&path, &data)
+      .inspect_err(|e| eprintln!("Couldn't save to {}: {e}", path.display()));
   }
 }
 
 fn load_sram(ctx: &mut EmuCtx) {
-  let path = PathBuf::from(&ctx.rom_path).with_extension("srm");
-  if let Ok(data) = fs::read(path) {
+  // saves of older versions are next to the rom, they move to the saves directory on the next save
+  let path = game_file(&ctx.dirs.saves, ctx, SAVE_BUNDLE_EXTENSION);
+  let old_path = PathBuf::from(&ctx.rom_path).with_extension(SAVE_BUNDLE_EXTENSION);
+  if let Ok(data) = fs::read(&path).or_else(|_| fs::read(&old_path)) {
+    if let Err(e) = ctx.emu.load_bundle(&data) {
+      eprintln!("Couldn't load save: {e}");
+    }
+  } else if let Ok(data) = fs::read(old_path.with_extension("srm")) {
+    // saves from older versions, they are converted to a bundle on the next save
     ctx.emu.load_sram(data);
   }
 }
 
+// Slot 0 is the savestate of older versions, which had a single one
+fn state_file(ctx: &EmuCtx) -> PathBuf {
+  match ctx.state_slot {
+    0 => game_file(&ctx.dirs.states, ctx, "cmbsv"),
+    slot => game_file(&ctx.dirs.states, ctx, &format!("{slot}.cmbsv")),
+  }
+}
+
+// Savestates of the versions before the json format can't be loaded, but they aren't thrown away either
+fn keep_legacy_state(path: &Path) {
+  let Ok(bytes) = fs::read(path) else { return };
+  if !savestate::is_legacy(&bytes) { return; }
+
+  let backup = path.with_extension("cmbsv.old");
+  match fs::rename(path, &backup) {
+    Ok(_) => eprintln!("The savestate in this slot was made by an older version, it was moved to {}", backup.display()),
+    Err(e) => eprintln!("Couldn't move the old savestate {} out of the way: {e}", path.display()),
+  }
+}
+
 fn save_state(ctx: &EmuCtx) {
-  let path = PathBuf::from(&ctx.rom_path).with_extension("cmbsv");
-  let writer = BufWriter::new(fs::File::create(path).expect("Couldn't create savestate file"));
-  let _ = pot::to_writer(&ctx.emu, writer)
-    .inspect_err(|e| eprintln!("Couldn't write the savestate to file: {e}"));
+  let path = state_file(ctx);
+  keep_legacy_state(&path);
+  let _ = write_game_file(&path, &ctx.emu.save_state())
+    .inspect_err(|e| eprintln!("Couldn't write the savestate to {}: {e}", path.display()));
 }
 
 fn load_state(ctx: &mut EmuCtx) {
-  let path = PathBuf::from(&ctx.rom_path).with_extension("cmbsv");
-  let savestate = fs::File::open(path);
-
-  match savestate {
-    Ok(file) => {
-      let reader = BufReader::new(file);
-      let new_emu = pot::from_reader(reader);
-      match new_emu {
-        Ok(new_emu) => {
-          ctx.emu.load_from_emu(new_emu);
-        }
-        Err(e) => eprintln!("Couldn't deserialize emu: {e:?}")
+  let path = state_file(ctx);
+  match fs::read(&path) {
+    Ok(bytes) if savestate::is_legacy(&bytes) => eprintln!(
+      "{} was made by an older version of the emulator, whose savestates can't be loaded anymore. \
+      It is kept as {} when a new savestate is made in this slot.",
+      path.display(), path.with_extension("cmbsv.old").display(),
+    ),
+    Ok(bytes) => {
+      if let Err(e) = ctx.emu.load_state(&bytes) {
+        eprintln!("Couldn't load state: {e}");
       }
     }
     Err(e) => eprintln!("Couldn't load state: {e:?}")
   }
 }
 
-fn handle_input(keys: &Keymaps, event: &Event, ctx: &mut EmuCtx) {
+fn save_screenshot(ctx: &EmuCtx) {
+  let screen = ctx.emu.get_screen();
+  let mut pixels = screen.buffer.to_vec();
+  let path = ctx.dirs.screenshots.join(format!("{}-{}.bmp", game_name(&ctx.rom_path), unix_now()));
+
+  let res = fs::create_dir_all(&ctx.dirs.screenshots).map_err(|e| e.to_string())
+    .and_then(|_| Surface::from_data(&mut pixels, screen.width as u32, screen.height as u32, screen.pitch() as u32, PixelFormatEnum::RGBA32))
+    .and_then(|surface| surface.save_bmp(&path));
+  match res {
+    Ok(_) => println!("Screenshot saved to {}", path.display()),
+    Err(e) => eprintln!("Couldn't save the screenshot to {}: {e}", path.display()),
+  }
+}
+
+fn run_action(action: &InputAction, pressed: bool, port: usize, ctx: &mut EmuCtx) {
   let emu = &mut ctx.emu;
   let joypad = emu.get_joypad();
+  let buttons = if port == 0 { &mut joypad.buttons1 } else { &mut joypad.buttons2 };
+
+  match (action, pressed) {
+    (InputAction::Game(button), true) => buttons.insert(*button),
+    (InputAction::Game(button), false) => buttons.remove(*button),
+    (InputAction::Pause, true) => {
+      ctx.is_paused = !ctx.is_paused;
+      match &ctx.audio.status() {
+        AudioStatus::Playing => ctx.audio.pause(),
+        _=> ctx.audio.resume(),
+      }
+    },
+    (InputAction::Reset, true) => emu.reset(),
+    (InputAction::Mute, true) => {
+      match &ctx.audio.status() {
+        AudioStatus::Playing => ctx.audio.pause(),
+        _=> ctx.audio.resume(),
+      }
+    }
+    (InputAction::Save, true) => save_state(ctx),
+    (InputAction::Load, true) => load_state(ctx),
+    (InputAction::SpriteLimit, true) => ctx.emu.toggle_sprite_limit(),
+    (InputAction::Screenshot, true) => save_screenshot(ctx),
+    _ => {}
+  }
+}
+
+fn set_stick(ctx: &mut EmuCtx, port: usize, value: i16, negative: NesJoypadButton, positive: NesJoypadButton) {
+  let joypad = ctx.emu.get_joypad();
+  let buttons = if port == 0 { &mut joypad.buttons1 } else { &mut joypad.buttons2 };
+
+  if value > AXIS_DEAD_ZONE { buttons.insert(positive); }
+  else if value < -AXIS_DEAD_ZONE { buttons.insert(negative); }
+  else {
+    buttons.remove(negative);
+    buttons.remove(positive);
+  }
+}
 
+fn handle_input(keys: &Keymaps, pads: &Pads, event: &Event, ctx: &mut EmuCtx) {
   match event {
-    Event::KeyDown { keycode, .. } 
-    | Event::KeyUp { keycode, .. } => {
-      if let Some(keycode) = keycode {
-        if let Some(action) = keys.keymap.get(&keycode) {
-          match (action, event) {
-            (InputAction::Game(button), Event::KeyDown {..}) => joypad.buttons1.insert(*button),
-            (InputAction::Game(button), Event::KeyUp {..}) => joypad.buttons1.remove(*button),
-            (InputAction::Pause, Event::KeyDown {..}) => {
-              ctx.is_paused = !ctx.is_paused;
-              match &ctx.audio.status() {
-                AudioStatus::Playing => ctx.audio.pause(),
-                _=> ctx.audio.resume(),
-              }
-            },
-            (InputAction::Reset, Event::KeyDown {..}) => emu.reset(),
-            (InputAction::Mute, Event::KeyDown {..}) => {
-              match &ctx.audio.status() {
-                AudioStatus::Playing => ctx.audio.pause(),
-                _=> ctx.audio.resume(),
-              }
-            }
-            (InputAction::Save, Event::KeyDown {..}) => save_state(ctx),
-            (InputAction::Load, Event::KeyDown {..}) => load_state(ctx),
-            (InputAction::SpriteLimit, Event::KeyDown {..}) => ctx.emu.toggle_sprite_limit(),
-            _ => {}
-          }
-        }
+    Event::KeyDown { keycode: Some(keycode), .. }
+    | Event::KeyUp { keycode: Some(keycode), .. } => {
+      if let Some(action) = keys.keymap.get(keycode) {
+        run_action(action, matches!(event, Event::KeyDown {..}), 0, ctx);
       }
     }
 
-    Event::ControllerButtonDown { button, .. } 
-    | Event::ControllerButtonUp { button, .. }  => {
-      if let Some(action) = keys.padmap.get(&button) {
-        match (action, event) {
-          (InputAction::Game(button), Event::ControllerButtonDown {..}) => joypad.buttons1.insert(*button),
-          (InputAction::Game(button), Event::ControllerButtonUp {..}) => joypad.buttons1.remove(*button),
-          (InputAction::Pause, Event::ControllerButtonDown {..}) => {
-            ctx.is_paused = !ctx.is_paused;
-            match &ctx.audio.status() {
-              AudioStatus::Playing => ctx.audio.pause(),
-              _=> ctx.audio.resume(),
-            }
-          }
-          (InputAction::Reset, Event::ControllerButtonDown {..}) => emu.reset(),
-          (InputAction::Mute, Event::KeyDown {..}) => {
-            match &ctx.audio.status() {
-              AudioStatus::Playing => ctx.audio.pause(),
-              _=> ctx.audio.resume(),
-            }
-          }
-          _ => {}
-        }
+    Event::ControllerButtonDown { which, button, .. }
+    | Event::ControllerButtonUp { which, button, .. } => {
+      if let (Some(action), Some(port)) = (keys.padmap.get(button), pads.port(*which)) {
+        run_action(action, matches!(event, Event::ControllerButtonDown {..}), port, ctx);
       }
     }
 
-    Event::ControllerAxisMotion { axis: Axis::LeftX, value, .. } => {
-      if *value > AXIS_DEAD_ZONE { joypad.buttons1.insert(NesJoypadButton::right); }
-      else if *value < -AXIS_DEAD_ZONE { joypad.buttons1.insert(NesJoypadButton::left); }
-      else {
-        joypad.buttons1.remove(NesJoypadButton::left);
-        joypad.buttons1.remove(NesJoypadButton::right);
+    Event::ControllerAxisMotion { which, axis: Axis::LeftX, value, .. } => {
+      if let Some(port) = pads.port(*which) {
+        set_stick(ctx, port, *value, NesJoypadButton::left, NesJoypadButton::right);
       }
     }
-    Event::ControllerAxisMotion { axis: Axis::LeftY, value, .. } => {
-      if *value > AXIS_DEAD_ZONE { joypad.buttons1.insert(NesJoypadButton::down); }
-      else if *value < -AXIS_DEAD_ZONE { joypad.buttons1.insert(NesJoypadButton::up); }
-      else {
-        joypad.buttons1.remove(NesJoypadButton::up);
-        joypad.buttons1.remove(NesJoypadButton::down);
+    Event::ControllerAxisMotion { which, axis: Axis::LeftY, value, .. } => {
+      if let Some(port) = pads.port(*which) {
+        set_stick(ctx, port, *value, NesJoypadButton::up, NesJoypadButton::down);
       }
     }
     _ => {}
@@ -195,39 +433,60 @@ struct EmuCtx {
   audio: AudioQueue<f32>,
   ms_frame: Duration,
   rom_path: String,
+  dirs: Dirs,
+  state_slot: u8,
+}
+
+// The current game keeps running if the new rom is invalid
+fn load_rom(ctx: &mut EmuCtx, canvas: &mut Canvas<Window>, filename: String) -> Result<(), Box<dyn Error>> {
+  // it can already be saved
+  save_sram(ctx);
+  let rom = open_rom(&filename)?;
+  ctx.emu.swap_rom_from_file(&rom, &filename)?;
+
+  for diagnostic in &ctx.emu.get_cart_header().diagnostics {
+    eprintln!("Header warning: {diagnostic}");
+  }
+
+  canvas.window_mut().set_title(&format!("NEN Emulator - {}", game_name(&filename)))?;
+  ctx.rom_path = filename;
+  ctx.is_paused = false;
+  ctx.is_running = true;
+  ctx.ms_frame = Duration::from_secs_f32(1.0 / ctx.emu.get_fps());
+
+  load_sram(ctx);
+  Ok(())
 }
 
 fn main() {
-  const SCALE: f32 = 3.0;
-  const WINDOW_WIDTH:  u32  = (SCALE * 32  as f32* 8.0) as u32;
-  const WINDOW_HEIGHT: u32  = (SCALE * 30 as f32* 8.0) as u32;
+  let args = Args::parse();
 
   let sdl = sdl2::init().unwrap();
   let video= sdl.video().unwrap();
   let audio = sdl.audio().unwrap();
-  let window = video.window("NEN Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
-      .position_centered()
-      .resizable()
-      .build()
-      .unwrap();
+  let mut window = video.window("NEN Emulator", args.scale * 32 * 8, args.scale * 30 * 8);
+  window.position_centered().resizable();
+  if args.fullscreen {
+    window.fullscreen_desktop();
+  }
+  let window = window.build().unwrap();
   let mut canvas = window
       .into_canvas()
       .accelerated()
       .build()
       .unwrap();
 
-  // keep aspect ratio
-  canvas.set_logical_size(32*8, 30*8).unwrap();
-
   let controller = sdl.game_controller().unwrap();
-  let mut controllers = Vec::new();
+  let mut pads = Pads::new();
     
   let mut events = sdl.event_pump().unwrap();
   let texture_creator = canvas.texture_creator();
 
-  let keymaps = Keymaps::new();
+  let keymaps = Keymaps::load(&data_dir().join("input.cfg"));
 
-  let emu = Nes::boot_empty();
+  let mut emu = Nes::boot_empty();
+  // applied to every rom booted from now on
+  emu.set_config(EmuConfig { region: args.region.map(ConsoleTiming::from), ..*emu.get_config() }).unwrap();
 
   let mut texture = texture_creator.create_texture_target(
     sdl2::pixels::PixelFormatEnum::RGBA32, emu.get_screen().width as u32, emu.get_screen().height as u32
@@ -249,21 +508,39 @@ fn main() {
     audio: audio_dev,
     emu,
     rom_path: String::new(),
+    dirs: Dirs::from_env(),
+    state_slot: args.state.unwrap_or(0),
   };
 
+  if let Some(rom) = args.rom {
+    if let Err(e) = load_rom(&mut ctx, &mut canvas, rom) {
+      eprintln!("{e}");
+      std::process::exit(1);
+    }
+    if args.state.is_some() {
+      load_state(&mut ctx);
+    }
+  }
+
   const SAMPLES_PER_FRAME: u32 = 735;
   
   'running: loop {
     let ms_since_start = Instant::now();
 
     if !ctx.is_paused {
-      ctx.emu.step_until_vblank();
+      let mut outcome = ctx.emu.step_until_vblank();
 
       let is_muted = ctx.audio.status() != AudioStatus::Playing;
 
       // if you don't have enough audio, we run for another frame
-      if !is_muted && ctx.audio.size() < SAMPLES_PER_FRAME*3 {
-        ctx.emu.step_until_vblank();
+      if !is_muted && outcome == StepOutcome::Ok && ctx.audio.size() < SAMPLES_PER_FRAME*3 {
+        outcome = ctx.emu.step_until_vblank();
+      }
+
+      if outcome == StepOutcome::Jammed {
+        eprintln!("The game crashed (cpu jammed). Press R to reset, then Space to resume.");
+        ctx.is_paused = true;
+        ctx.audio.pause();
       }
 
       if is_muted { ctx.emu.get_samples(); }
@@ -274,12 +551,12 @@ fn main() {
 
     for event in events.poll_iter() {
       if ctx.is_running {
-        handle_input(&keymaps, &event, &mut ctx);
+        handle_input(&keymaps, &pads, &event, &mut ctx);
       }
 
       match event {
         Event::Quit { .. } => {
-          save_sram(&ctx);
+          save_sram(&mut ctx);
           break 'running;
         }
         Event::KeyDown { keycode, .. } => {
@@ -297,33 +574,26 @@ fn main() {
           ctx.audio.pause();
           ctx.audio.clear();
 
-          let res = open_rom(&filename);
-          match res {
-            Ok(new_emu) => {
-              save_sram(&ctx);
-
-              ctx.rom_path = filename;
-              ctx.emu = new_emu;
-              ctx.is_paused = false;
-              ctx.is_running = true;
-              ctx.ms_frame = Duration::from_secs_f32(1.0 / ctx.emu.get_fps());
-
-              load_sram(&mut ctx);
-            },
-            Err(e) => eprintln!("{e}"),
+          if let Err(e) = load_rom(&mut ctx, &mut canvas, filename) {
+            eprintln!("{e}");
           }
 
           ctx.audio.resume();
         }
         Event::ControllerDeviceAdded { which , .. } => {
           match controller.open(which) {
-            Ok(controller) => {
-                println!("Found controller: {}\n", controller.name());
-                controllers.push(controller);
-            }
+            Ok(controller) => pads.attach(controller),
             Err(_) => eprintln!("A controller was connected, but I couldn't initialize it\n")
           }
         }
+        Event::ControllerDeviceRemoved { which, .. } => {
+          // the buttons held on the unplugged controller would stay stuck
+          match pads.detach(which) {
+            Some(0) => ctx.emu.get_joypad().buttons1 = NesJoypadButton::empty(),
+            Some(_) => ctx.emu.get_joypad().buttons2 = NesJoypadButton::empty(),
+            None => {}
+          }
+        }
         _ => {}
       }
     }
@@ -335,7 +605,15 @@ fn main() {
       ctx.emu.get_screen().pitch()
     ).unwrap();
 
-    canvas.copy(&texture, None, None).unwrap();
+    // keep aspect ratio
+    let (window_width, window_height) = canvas.output_size().unwrap();
+    let src = ctx.emu.visible_area();
+    let dst = ctx.emu.display_rect(window_width, window_height);
+    canvas.copy(
+      &texture,
+      Rect::new(src.x as i32, src.y as i32, src.width, src.height),
+      Rect::new(dst.x as i32, dst.y as i32, dst.width, dst.height),
+    ).unwrap();
     canvas.present();
 
     let ms_elapsed = Instant::now() - ms_since_start;
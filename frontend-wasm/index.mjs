@@ -31,7 +31,7 @@ window.addEventListener('keydown', event => {
     if (pressed === undefined) { return }
 
     if (isNaN(pressed.button)) {
-        if (pressed.button === 'pause') { emu.is_paused = !emu.is_paused }
+        if (pressed.button === 'pause') { emu.set_paused(!emu.is_paused()) }
         else if (pressed.button == 'reset') { emu.reset() }
     } else {
         emu.button_pressed(pressed.button)
@@ -43,7 +43,7 @@ window.addEventListener('keyup', event => {
     if (pressed === undefined) { return }
 
     if (isNaN(pressed.button)) {
-        if (pressed.button === 'pause') { emu.is_paused = !emu.is_paused }
+        if (pressed.button === 'pause') { emu.set_paused(!emu.is_paused()) }
         else if (pressed.button == 'reset') { emu.reset() }
     } else {
         emu.button_released(pressed.button)
@@ -94,26 +94,26 @@ inputRom.addEventListener('change', async event => {
         animationId = renderLoop()
     } catch(err) {
         console.error(err)
-        emu.is_paused = true
+        emu.set_paused(true)
     }
 })
 
 
 pauseBtn.addEventListener('click', event => {
-    if (emu.is_paused) {
+    if (emu.is_paused()) {
         animationId = renderLoop()
     } else {
         cancelAnimationFrame(animationId)
     }
-    emu.is_paused = !emu.is_paused
-    pauseBtn.innerText = emu.is_paused ? '▶️' : '⏸️' 
+    emu.set_paused(!emu.is_paused())
+    pauseBtn.innerText = emu.is_paused() ? '▶️' : '⏸️' 
 })
 
 resetBtn.addEventListener('click', event => {
-    if (emu.is_paused) {
+    if (emu.is_paused()) {
         animationId = renderLoop()
     }
-    emu.is_paused = false
+    emu.set_paused(false)
     emu.reset()
     pauseBtn.innerText = '⏸️'
 })
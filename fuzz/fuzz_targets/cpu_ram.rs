@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nen_emulator::{cpu::{Cpu, CpuFlags}, mem::Memory};
+
+// Runs random instruction streams on the cpu core with a flat ram backend.
+// Run with `cargo fuzz run cpu_ram`
+fuzz_target!(|data: &[u8]| {
+  if data.len() < 2 { return; }
+
+  let mut cpu = Cpu::with_ram64kb();
+  cpu.decimal_enabled = data[0] & 1 != 0;
+  cpu.p = CpuFlags::from_bits_retain(data[1]);
+  cpu.pc = 0x0200;
+  for (i, byte) in data[2..].iter().take(0x10000 - 0x200).enumerate() {
+    cpu.write(0x0200 + i as u16, *byte);
+  }
+
+  for _ in 0..data.len() * 2 {
+    if cpu.jammed { break; }
+    cpu.step();
+  }
+});
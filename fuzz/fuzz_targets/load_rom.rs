@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nen_emulator::nes::Nes;
+
+// Loading any sequence of bytes should return an error, never panic.
+// Run with `cargo fuzz run load_rom`
+fuzz_target!(|rom: &[u8]| {
+  if let Ok(mut emu) = Nes::boot_from_bytes(rom) {
+    // run a few instructions, to exercise the mapper setup
+    for _ in 0..1000 { emu.step(); }
+  }
+});
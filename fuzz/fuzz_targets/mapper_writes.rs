@@ -0,0 +1,42 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nen_emulator::cart::Cart;
+
+// Builds a rom for the mapper chosen by the first two bytes, then feeds the rest of the input
+// as register writes, interleaved with cpu and ppu reads.
+// Run with `cargo fuzz run mapper_writes`
+fuzz_target!(|data: &[u8]| {
+  if data.len() < 3 { return; }
+  let (mapper, submapper_and_sizes, ops) = (data[0], data[1], &data[2..]);
+
+  let prg_banks = 1 << (submapper_and_sizes & 0b11);
+  let chr_banks = (submapper_and_sizes >> 2) & 0b11;
+
+  let mut rom = vec![0u8; 16];
+  rom[0..4].copy_from_slice(b"NES\x1A");
+  rom[4] = prg_banks;
+  rom[5] = chr_banks;
+  rom[6] = (mapper << 4) | (data[1] >> 7) << 1;
+  rom[7] = mapper & 0xF0;
+  rom.extend((0..prg_banks as usize * 16 * 1024 + chr_banks as usize * 8 * 1024).map(|i| i as u8));
+
+  let Ok(mut cart) = Cart::new(&rom) else { return; };
+
+  for op in ops.chunks_exact(3) {
+    let addr = u16::from_le_bytes([op[0], op[1]]) as usize;
+    let val = op[2];
+
+    match addr {
+      0x4020..=0xFFFF => {
+        cart.prg_write(addr, val);
+        cart.prg_read(addr);
+      }
+      _ => {
+        let ppu_addr = addr % 0x3000;
+        cart.vram_write(ppu_addr, val);
+        cart.vram_read(ppu_addr);
+      }
+    }
+  }
+});
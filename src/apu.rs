@@ -6,7 +6,7 @@ use noise::Noise;
 use pulse::Pulse;
 use triangle::Triangle;
 
-use crate::cart::{ConsoleTiming, SharedCart};
+use crate::{cart::{ConsoleTiming, SharedCart}, codec::{codec_enum, codec_fields}, mapper};
 
 mod envelope;
 
@@ -14,12 +14,19 @@ pub mod pulse;
 mod triangle;
 mod noise;
 mod dmc;
+mod resampler;
+mod playback;
+
+pub use resampler::{ResampleQuality, DEFAULT_SAMPLE_RATE};
+pub(crate) use playback::Playback;
+use resampler::Resampler;
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct ApuDivider {
   pub period: u16,
   pub count: u16,
 }
+codec_fields!(ApuDivider { period, count });
 impl ApuDivider {
   pub fn set_period_low(&mut self, val: u8) {
     self.period = self.period & 0xFF00
@@ -52,6 +59,7 @@ struct LengthCounter {
   pub halted: bool,
   pub enabled: bool,
 }
+codec_fields!(LengthCounter { count, halted, enabled });
 
 impl LengthCounter {
   pub fn reload(&mut self, val: u8) {
@@ -77,6 +85,41 @@ impl LengthCounter {
   }
 }
 
+// What a channel is doing, for apu viewers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelState {
+  // enabled through $4015
+  pub enabled: bool,
+  pub period: u16,
+  pub length_counter: u8,
+  // the triangle has no envelope, but a linear counter
+  pub envelope: Option<u8>,
+  pub linear_counter: Option<u8>,
+  // current output level, 0 to 15
+  pub output: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DmcState {
+  pub enabled: bool,
+  pub period: u16,
+  pub level: u8,
+  // address of the next sample byte, and how many are left to fetch
+  pub address: u16,
+  pub remaining: u16,
+  pub loop_enabled: bool,
+  pub irq_enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApuState {
+  pub pulse1: ChannelState,
+  pub pulse2: ChannelState,
+  pub triangle: ChannelState,
+  pub noise: ChannelState,
+  pub dmc: DmcState,
+}
+
 pub trait Channel: Default {
   fn step_timer(&mut self);
   fn step_quarter(&mut self);
@@ -87,10 +130,26 @@ pub trait Channel: Default {
   fn get_sample(&self) -> u8;
 }
 
+// Output filters applied after mixing
+// https://www.nesdev.org/wiki/APU_Mixer
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum FilterProfile {
+  // high pass at 90hz and 440hz, low pass at 14khz
+  #[default] Nes,
+  // high pass at 37hz
+  Famicom,
+  // no console filtering, only anti aliasing
+  Flat,
+  // raw mixer output, for external processing
+  Off,
+}
+codec_enum!(FilterProfile { Nes, Famicom, Flat, Off });
+
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum FrameCounterMode {
   #[default] Step4, Step5
 }
+codec_enum!(FrameCounterMode { Step4, Step5 });
 impl From<u8> for FrameCounterMode {
   fn from(value: u8) -> Self {
     match value {
@@ -142,9 +201,13 @@ pub struct Apu {
   pub frame_irq_flag: Option<()>,
 
   pub samples: Vec<f32>,
-  cycles_per_sample: f32,
-  sample_cycles: f32,
+  #[serde(default)]
+  resampler: Resampler,
 
+  #[serde(default)]
+  filter_profile: FilterProfile,
+  #[serde(skip)]
+  expansion_gain: f32,
   low_pass_filter: LowPassIIR,
   high_pass_filter0: HighPassIIR,
   high_pass_filter1: HighPassIIR,
@@ -152,6 +215,30 @@ pub struct Apu {
 
   cycles: usize,
 }
+codec_fields!(Apu { timing, pulse1, pulse2, triangle, noise, dmc, frame_mode, frame_write_delay, frame_tmp, irq_disabled, frame_irq_flag, samples, resampler, filter_profile, low_pass_filter, high_pass_filter0, high_pass_filter1, quality_filter, cycles });
+
+
+// Non linear dac output levels
+// https://www.nesdev.org/wiki/APU_Mixer#Lookup_Table
+const PULSE_TABLE: [f32; 31] = {
+  let mut table = [0.0; 31];
+  let mut i = 1;
+  while i < table.len() {
+    table[i] = 95.52 / (8128.0 / i as f32 + 100.0);
+    i += 1;
+  }
+  table
+};
+
+const TND_TABLE: [f32; 203] = {
+  let mut table = [0.0; 203];
+  let mut i = 1;
+  while i < table.len() {
+    table[i] = 163.67 / (24329.0 / i as f32 + 100.0);
+    i += 1;
+  }
+  table
+};
 
 // pub fn sample_f32_to_i16(sample: f32) -> i16 {
 //   (sample * u16::MAX as f32).clamp(0.0, u16::MAX as f32) as i16
@@ -161,44 +248,107 @@ impl Apu {
   pub fn new(cart: SharedCart) -> Self {
     let timing = cart.as_ref().header.timing;
 
-    let cycles_per_sample = 
-      timing.frame_cpu_cycles() / ((44100.0 / timing.fps()) as f32);
-    let cpu_hz = timing.cpu_hz() as f32;
-
-    Self {
+    let mut apu = Self {
       timing,
       cart,
       noise: Noise::new(timing),
       dmc: Dmc::new(timing),
+      ..Default::default()
+    };
+    apu.resampler.configure(timing.cpu_hz() as u32, DEFAULT_SAMPLE_RATE, ResampleQuality::Linear);
+    apu.build_filters(FilterProfile::Nes);
+    apu.expansion_gain = mapper::board_expansion_gain(&apu.cart.as_ref().header);
+    apu
+  }
 
-      cycles_per_sample,
+  pub fn set_expansion_gain(&mut self, gain: f32) {
+    self.expansion_gain = gain;
+  }
 
-      high_pass_filter0: HighPassIIR
-        ::new(cpu_hz, 90.0),
-      high_pass_filter1: HighPassIIR
-        ::new(cpu_hz, 440.0),
-      low_pass_filter: LowPassIIR
-        ::new(cpu_hz, 14_000.0),
-      quality_filter: LowPassIIR
-        ::new(cpu_hz, 0.40 * 44_100.0),
+  pub fn set_filter_profile(&mut self, profile: FilterProfile) {
+    // the filters state is part of savestates, rebuilding them when loading one would click
+    if profile != self.filter_profile {
+      self.build_filters(profile);
+    }
+  }
 
-      ..Default::default()
+  pub fn set_sample_rate(&mut self, sample_rate: u32, quality: ResampleQuality) {
+    let output_rate = self.resampler.output_rate();
+    self.resampler.configure(self.timing.cpu_hz() as u32, sample_rate, quality);
+    // the anti aliasing filter follows the output rate
+    if self.resampler.output_rate() != output_rate {
+      self.build_filters(self.filter_profile);
+    }
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.resampler.output_rate()
+  }
+
+  fn build_filters(&mut self, profile: FilterProfile) {
+    let cpu_hz = self.timing.cpu_hz() as f32;
+    let high_pass_cutoff = if profile == FilterProfile::Famicom { 37.0 } else { 90.0 };
+
+    self.filter_profile = profile;
+    self.high_pass_filter0 = HighPassIIR::new(cpu_hz, high_pass_cutoff);
+    self.high_pass_filter1 = HighPassIIR::new(cpu_hz, 440.0);
+    self.low_pass_filter = LowPassIIR::new(cpu_hz, 14_000.0);
+    self.quality_filter = LowPassIIR::new(cpu_hz, 0.40 * self.resampler.output_rate() as f32);
+  }
+
+  fn filter_sample(&mut self, sample: f32) -> f32 {
+    match self.filter_profile {
+      FilterProfile::Nes => {
+        self.high_pass_filter0.consume(sample);
+        self.high_pass_filter1.consume(self.high_pass_filter0.output());
+        self.low_pass_filter.consume(self.high_pass_filter1.output());
+        self.quality_filter.consume(self.low_pass_filter.output());
+      }
+      FilterProfile::Famicom => {
+        self.high_pass_filter0.consume(sample);
+        self.quality_filter.consume(self.high_pass_filter0.output());
+      }
+      FilterProfile::Flat => self.quality_filter.consume(sample),
+      FilterProfile::Off => return sample,
     }
+
+    self.quality_filter.output()
   }
 
   pub fn wire_cart(&mut self, cart: SharedCart) {
 		self.cart = cart;
 	}
 
+  // https://www.nesdev.org/wiki/CPU_power_up_state#After_reset
   pub fn reset(&mut self) {
     self.pulse1.set_enabled(false);
     self.pulse2.set_enabled(false);
     self.triangle.set_enabled(false);
     self.noise.set_enabled(false);
     self.dmc.set_enabled(false);
+    self.triangle.reset();
+    self.dmc.reset();
 
     self.cycles = 0;
-    self.sample_cycles = 0.0;
+    self.resampler.reset();
+
+    // the frame counter restarts, as if $4017 was written again with its last value
+    self.frame_irq_flag = None;
+    self.write_reg(0x4017, self.frame_tmp);
+  }
+
+  pub fn debug_state(&self) -> ApuState {
+    ApuState {
+      pulse1: self.pulse1.debug_state(),
+      pulse2: self.pulse2.debug_state(),
+      triangle: self.triangle.debug_state(),
+      noise: self.noise.debug_state(),
+      dmc: self.dmc.debug_state(),
+    }
+  }
+
+  pub fn set_mute_ultrasonic_triangle(&mut self, mute: bool) {
+    self.triangle.mute_ultrasonic = mute;
   }
 
   pub fn consume_samples(&mut self) -> Vec<f32> {
@@ -208,34 +358,17 @@ impl Apu {
   }
 
   pub fn step(&mut self) {
-    // A frame lasts 29780.5 CPU cycles.
-    // We have to output 44100 hertz of samples per second.
-    // We have 60 frames per second.
-    // Meaning for a single frame we need 44100 / 60 = 735 samples.
-    // Then, we have to output a sample every 29780.5 / 735 = 40.5 cycles!
-
-    // if self.sample_cycles >= self.samples_per_second {
-    //   let sample = self.mix_channels();
-    //   self.current_sample = Some(sample);
-    //   self.sample_cycles -= self.samples_per_second;
-    // }
-    // self.sample_cycles += 1.0;
-
-    // OPT: this if is EXTREMELY costly
+    // OPT: mixing and filtering every cycle is costly
     let sample = self.mix_channels();
-    self.high_pass_filter0.consume(sample);
-    self.high_pass_filter1.consume(self.high_pass_filter0.output());
-    self.low_pass_filter.consume(self.high_pass_filter1.output());
-    self.quality_filter.consume(self.low_pass_filter.output());
-
-    if self.sample_cycles >= self.cycles_per_sample {
-      let output = self.quality_filter.output();
-      self.samples.push(output);
-      self.sample_cycles -= self.cycles_per_sample;
+    let output = self.filter_sample(sample);
+    self.resampler.push(output, &mut self.samples);
+
+    // headless users might never consume the samples, drop the oldest ones so they don't pile up past a second
+    let max_buffered = self.resampler.output_rate() as usize;
+    if self.samples.len() >= max_buffered {
+      self.samples.drain(..max_buffered / 2);
     }
-    
-    self.sample_cycles += 1.0;
-    
+
     self.dmc.step_timer();
     self.triangle.step_timer();
     if self.cycles % 2 == 1 {
@@ -295,6 +428,7 @@ impl Apu {
         }
 
         if !self.irq_disabled {
+          if self.frame_irq_flag.is_none() { nen_log!(trace, "nen::apu", "frame irq"); }
           self.frame_irq_flag = Some(());
         }
 
@@ -316,6 +450,7 @@ impl Apu {
         }
         
         if !self.irq_disabled {
+          if self.frame_irq_flag.is_none() { nen_log!(trace, "nen::apu", "frame irq"); }
           self.frame_irq_flag = Some(());
         }
 
@@ -334,14 +469,11 @@ impl Apu {
     let noise    = self.noise.get_sample();
     let dmc = self.dmc.get_sample();
 
-    let ext_out = self.cart.as_mut().mapper.get_sample();
+    let ext_out = self.cart.as_mut().mapper.get_sample() * self.expansion_gain;
+
+    let pulse_out = PULSE_TABLE[(pulse1 + pulse2) as usize];
+    let tnd_out = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
 
-    let pulse_out = 0.00752 * (pulse1 + pulse2) as f32;
-    let tnd_out = 
-      0.00851 * triangle as f32
-      + 0.00494 * noise as f32
-      + 0.00335 * dmc as f32;
-      
     let sum = pulse_out + tnd_out + ext_out;
     sum
   }
@@ -404,6 +536,7 @@ impl Apu {
         self.dmc.irq_flag = None;
       }
       0x4017 => {
+        nen_log!(trace, "nen::apu", "frame counter = {val:08b}");
         self.frame_tmp = val;
         self.irq_disabled = val & 0x40 == 0x40;
         if self.irq_disabled {
@@ -425,6 +558,7 @@ pub struct LowPassIIR {
   previous_output: f32,
   delta: f32,
 }
+codec_fields!(LowPassIIR { alpha, previous_output, delta });
 
 impl LowPassIIR {
   pub fn new(sample_rate: f32, cutoff_frequency: f32) -> LowPassIIR {
@@ -455,6 +589,7 @@ pub struct HighPassIIR {
   previous_input: f32,
   delta: f32,
 }
+codec_fields!(HighPassIIR { alpha, previous_output, previous_input, delta });
 
 impl HighPassIIR {
   pub fn new(sample_rate: f32, cutoff_frequency: f32) -> HighPassIIR {
@@ -1,7 +1,7 @@
 #![allow(unused)]
 
-use crate::{cart::ConsoleTiming, dma::{Dma, DmcDma}};
-use super::{Channel, ApuDivider};
+use crate::{cart::ConsoleTiming, codec::codec_fields, dma::{Dma, DmcDma}};
+use super::{Channel, ApuDivider, DmcState};
 
 const RATE_TABLE_NTSC: [u16; 16] = [
   428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54
@@ -25,13 +25,19 @@ pub struct Dmc {
   length: u16,
   shift_reg: u8,
   silence: bool,
+  // level as heard by the mixer, which follows level gradually when reducing pops
+  #[serde(default)]
+  output: u8,
+  #[serde(skip)]
+  pub reduce_pops: bool,
 
   pub reader: DmcDma,
 }
+codec_fields!(Dmc { timing, irq_enabled, irq_flag, loop_enabled, timer, buffer, level, bits_remaining, address, length, shift_reg, silence, output, reader });
 
 impl Default for Dmc {
   fn default() -> Self {
-    Self { timing: Default::default(), irq_enabled: Default::default(), irq_flag: Default::default(), loop_enabled: Default::default(), timer: Default::default(), level: Default::default(), buffer: Default::default(), bits_remaining: Default::default(), address: Default::default(), length: Default::default(), shift_reg: Default::default(), silence: true, reader: Default::default() }
+    Self { timing: Default::default(), irq_enabled: Default::default(), irq_flag: Default::default(), loop_enabled: Default::default(), timer: Default::default(), level: Default::default(), buffer: Default::default(), bits_remaining: Default::default(), address: Default::default(), length: Default::default(), shift_reg: Default::default(), silence: true, output: Default::default(), reduce_pops: true, reader: Default::default() }
   }
 }
 
@@ -49,10 +55,29 @@ impl Dmc {
     }
   }
 
+  pub fn debug_state(&self) -> DmcState {
+    DmcState {
+      enabled: self.is_enabled(),
+      period: self.timer.period,
+      level: self.level,
+      address: self.reader.addr,
+      remaining: self.reader.remaining,
+      loop_enabled: self.loop_enabled,
+      irq_enabled: self.irq_enabled,
+    }
+  }
+
+  // Only the lowest bit of the output level survives a reset
+  pub fn reset(&mut self) {
+    self.level &= 1;
+    self.irq_flag = None;
+  }
+
   pub fn write_ctrl(&mut self, val: u8) {
     self.irq_enabled = val & 0b1000_0000 != 0;
     self.loop_enabled = val & 0b0100_0000 != 0;
-    self.timer.period = self.rate_table()[val as usize & 0b1111];
+    // rates are in cpu cycles, while the divider takes period + 2 steps to clock
+    self.timer.period = self.rate_table()[val as usize & 0b1111] - 2;
 
     if !self.irq_enabled {
       self.irq_flag = None;
@@ -60,15 +85,9 @@ impl Dmc {
   }
 
   pub fn write_level(&mut self, val: u8) {
-    let previous_level = self.level;
-    let new_level = val & 0b0111_1111;
-
-    // Reduce dmc popping
-    if new_level.abs_diff(previous_level) <= 50 {
-      self.level = new_level;
-    } else {
-      self.level = 50;
-    }
+    // Games (Battletoads, Fester's Quest) write big jumps here, which pop on real hardware as well.
+    // When reducing pops, the output is ramped to the new level in step_timer().
+    self.level = val & 0b0111_1111;
   }
 
   pub fn write_addr(&mut self, val: u8) {
@@ -81,12 +100,12 @@ impl Dmc {
 
   pub fn load_sample(&mut self, sample: u8) {
     self.buffer = Some(sample);
-    self.bits_remaining = 8;
 
     if !self.reader.is_transfering() {
       if self.loop_enabled {
         self.restart_dma();
       } else if self.irq_enabled {
+        nen_log!(trace, "nen::apu", "dmc irq");
         self.irq_flag = Some(());
       }
     }
@@ -110,10 +129,12 @@ impl Channel for Dmc {
         } else if self.level >= 2 {
           self.level -= 2;
         }
-        self.shift_reg >>= 1;
       }
+      self.shift_reg >>= 1;
 
+      self.bits_remaining = self.bits_remaining.saturating_sub(1);
       if self.bits_remaining == 0 {
+        // a new output cycle starts, emptying the sample buffer (which triggers the next dma fetch)
         self.bits_remaining = 8;
 
         if let Some(data) = self.buffer.take() {
@@ -122,10 +143,15 @@ impl Channel for Dmc {
         } else {
           self.silence = true;
         }
-      } else if self.bits_remaining > 0 {
-        self.bits_remaining -= 1;
       }
     });
+
+    if self.reduce_pops {
+      if self.output < self.level { self.output += 1; }
+      else if self.output > self.level { self.output -= 1; }
+    } else {
+      self.output = self.level;
+    }
   }
 
   fn step_half(&mut self) {}
@@ -147,6 +173,107 @@ impl Channel for Dmc {
   }
 
   fn get_sample(&self) -> u8 {
-    self.level
+    self.output
+  }
+}
+#[cfg(test)]
+mod dmc_tests {
+  use super::*;
+
+  // The fastest rate, 54 cpu cycles per output bit on ntsc
+  fn fast_dmc(reduce_pops: bool) -> Dmc {
+    let mut dmc = Dmc::new(ConsoleTiming::NTSC);
+    dmc.reduce_pops = reduce_pops;
+    dmc.write_ctrl(0x0F);
+    dmc
+  }
+
+  fn step(dmc: &mut Dmc, cycles: usize) {
+    for _ in 0..cycles { dmc.step_timer(); }
+  }
+
+  #[test]
+  fn output_cycle_timing() {
+    let mut dmc = fast_dmc(false);
+    dmc.write_level(64);
+    dmc.load_sample(0xFF);
+
+    // the first clock starts an output cycle, which empties the buffer right away
+    step(&mut dmc, 1);
+    assert!(dmc.is_empty());
+    assert_eq!(dmc.get_sample(), 64);
+
+    // then a bit is played every 54 cycles
+    for bit in 1..=8 {
+      step(&mut dmc, 53);
+      assert_eq!(dmc.get_sample(), 64 + (bit - 1) * 2, "bit {bit}");
+      step(&mut dmc, 1);
+      assert_eq!(dmc.get_sample(), 64 + bit * 2, "bit {bit}");
+    }
+
+    // the buffer was empty when the next output cycle started, which is silent
+    step(&mut dmc, 54 * 8);
+    assert_eq!(dmc.get_sample(), 80);
+  }
+
+  #[test]
+  fn silence_until_the_buffer_is_filled() {
+    let mut dmc = fast_dmc(false);
+    dmc.write_level(64);
+    step(&mut dmc, 1);
+    assert!(dmc.silence);
+
+    // a sample loaded in the middle of a silent output cycle waits for the next one
+    step(&mut dmc, 54 * 3);
+    dmc.load_sample(0x00);
+    step(&mut dmc, 54 * 5);
+    assert_eq!(dmc.get_sample(), 64);
+    assert!(dmc.is_empty());
+    assert!(!dmc.silence);
+
+    step(&mut dmc, 54);
+    assert_eq!(dmc.get_sample(), 62);
+    step(&mut dmc, 54 * 7);
+    assert_eq!(dmc.get_sample(), 48);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn level_clamps() {
+    let mut dmc = fast_dmc(false);
+    dmc.write_level(125);
+    dmc.load_sample(0xFF);
+    step(&mut dmc, 1 + 54 * 8);
+    assert_eq!(dmc.get_sample(), 127);
+
+    // played after the silent output cycle which started when the buffer was empty
+    dmc.write_level(3);
+    dmc.load_sample(0x00);
+    step(&mut dmc, 54 * 16);
+    assert_eq!(dmc.get_sample(), 1);
+  }
+
+  #[test]
+  fn reduce_pops_ramps_the_output() {
+    let mut dmc = fast_dmc(true);
+    dmc.write_level(100);
+    step(&mut dmc, 1);
+    assert_eq!(dmc.get_sample(), 1);
+    step(&mut dmc, 49);
+    assert_eq!(dmc.get_sample(), 50);
+    step(&mut dmc, 50);
+    assert_eq!(dmc.get_sample(), 100);
+    step(&mut dmc, 10);
+    assert_eq!(dmc.get_sample(), 100);
+
+    // and down as well
+    dmc.write_level(90);
+    step(&mut dmc, 5);
+    assert_eq!(dmc.get_sample(), 95);
+
+    // without it, the output jumps to the level
+    let mut dmc = fast_dmc(false);
+    dmc.write_level(100);
+    step(&mut dmc, 1);
+    assert_eq!(dmc.get_sample(), 100);
+  }
+}
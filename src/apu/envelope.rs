@@ -1,7 +1,10 @@
+use crate::codec::{codec_enum, codec_fields};
+
 #[derive(Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 enum EnvelopeMode {
   #[default] OneShot, Loop
 }
+codec_enum!(EnvelopeMode { OneShot, Loop });
 impl From<u8> for EnvelopeMode {
   fn from(value: u8) -> Self {
     match value {
@@ -15,6 +18,7 @@ impl From<u8> for EnvelopeMode {
 enum VolumeMode {
   #[default] Envelope, Constant
 }
+codec_enum!(VolumeMode { Envelope, Constant });
 impl From<u8> for VolumeMode {
   fn from(value: u8) -> Self {
     match value {
@@ -33,6 +37,7 @@ pub(super) struct Envelope {
   envelope_mode: EnvelopeMode,
   volume_mode: VolumeMode,
 }
+codec_fields!(Envelope { start, level, envelope_count, decay_count, envelope_mode, volume_mode });
 impl Envelope {
   pub fn set(&mut self, val: u8) {
     self.envelope_mode = EnvelopeMode::from((val >> 5) & 1);
@@ -1,6 +1,6 @@
-use crate::cart::ConsoleTiming;
+use crate::{cart::ConsoleTiming, codec::codec_fields};
 
-use super::{envelope::Envelope, Channel, LengthCounter, ApuDivider};
+use super::{envelope::Envelope, Channel, ChannelState, LengthCounter, ApuDivider};
 
 const NOISE_PERIOD_NTSC: [u16; 16] = [
   4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
@@ -19,6 +19,7 @@ pub(super) struct Noise {
   length: LengthCounter,
   envelope_enabled: bool,
 }
+codec_fields!(Noise { timing, envelope, loop_enabled, timer, shift_reg, length, envelope_enabled });
 
 impl Default for Noise {
     fn default() -> Self {
@@ -33,6 +34,17 @@ impl Noise {
     res
   }
 
+  pub fn debug_state(&self) -> ChannelState {
+    ChannelState {
+      enabled: self.length.enabled,
+      period: self.timer.period,
+      length_counter: self.length.count,
+      envelope: Some(self.envelope.volume()),
+      linear_counter: None,
+      output: self.get_sample(),
+    }
+  }
+
   pub fn set_ctrl(&mut self, val: u8) {
     self.length.halted = (val >> 5) & 1 != 0;
     self.envelope.set(val);
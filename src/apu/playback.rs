@@ -0,0 +1,93 @@
+// What get_samples() hands to the frontend when the emulation doesn't run at real time.
+// Audio queues expect a frame of samples for every frame shown: while paused, a frame of silence is given instead of nothing,
+// so that the queue doesn't run dry, and in slow motion the samples are stretched over the time the frame is shown.
+// Going to and from silence is done with short ramps, as a jump of the level is heard as a click.
+
+// length of the ramps, about 6ms at 44.1khz
+const FADE_SAMPLES: f32 = 256.0;
+const MIN_SPEED: f32 = 0.05;
+const MAX_SPEED: f32 = 8.0;
+
+pub(crate) struct Playback {
+  paused: bool,
+  speed: f32,
+  gain: f32,
+  // last sample before the gain, held to fade out from
+  held: f32,
+  // last input sample, and position of the next stretched sample past it, in input samples
+  prev: f32,
+  pos: f64,
+  // silence owed, as frames don't last a whole number of samples
+  silence_carry: f64,
+}
+
+impl Default for Playback {
+  fn default() -> Self {
+    Self { paused: false, speed: 1.0, gain: 1.0, held: 0.0, prev: 0.0, pos: 0.0, silence_carry: 0.0 }
+  }
+}
+
+impl Playback {
+  pub fn set_paused(&mut self, paused: bool) {
+    self.paused = paused;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+
+  // 0.5 plays the sound of a frame over two frames, an octave lower
+  pub fn set_speed(&mut self, speed: f32) {
+    self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+  }
+
+  pub fn speed(&self) -> f32 {
+    self.speed
+  }
+
+  // Takes the samples made since the last call, which lasts a frame of `frame_samples` at normal speed
+  pub fn process(&mut self, samples: Vec<f32>, frame_samples: f64) -> Vec<f32> {
+    let mut samples = if self.speed == 1.0 { samples } else { self.stretch(&samples) };
+
+    let fade_out_from = if !self.paused {
+      samples.len()
+    } else if samples.is_empty() {
+      // the held level fades out right away
+      self.silence_carry += frame_samples;
+      let count = self.silence_carry as usize;
+      self.silence_carry -= count as f64;
+      samples = vec![self.held; count];
+      0
+    } else {
+      // the frames advanced while paused fade out at their end
+      samples.len().saturating_sub(FADE_SAMPLES as usize)
+    };
+    for (i, sample) in samples.iter_mut().enumerate() {
+      let target = if i >= fade_out_from { 0.0 } else { 1.0 };
+      self.gain = if target > self.gain {
+        (self.gain + 1.0 / FADE_SAMPLES).min(target)
+      } else {
+        (self.gain - 1.0 / FADE_SAMPLES).max(target)
+      };
+      self.held = *sample;
+      *sample *= self.gain;
+    }
+
+    samples
+  }
+
+  // Linear interpolation, the pitch goes down with the speed as on a tape
+  fn stretch(&mut self, samples: &[f32]) -> Vec<f32> {
+    let step = self.speed as f64;
+    let mut out = Vec::with_capacity((samples.len() as f64 / step) as usize + 1);
+    for &sample in samples {
+      while self.pos < 1.0 {
+        out.push(self.prev + (sample - self.prev) * self.pos as f32);
+        self.pos += step;
+      }
+      self.pos -= 1.0;
+      self.prev = sample;
+    }
+    out
+  }
+}
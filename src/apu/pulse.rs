@@ -1,4 +1,6 @@
-use super::{envelope::Envelope, Channel, LengthCounter, ApuDivider};
+use crate::codec::{codec_enum, codec_fields};
+
+use super::{envelope::Envelope, Channel, ChannelState, LengthCounter, ApuDivider};
 
 const PULSE_SEQUENCES: [[u8; 8]; 4] = [
   [ 0, 1, 0, 0, 0, 0, 0, 0 ],
@@ -15,6 +17,7 @@ const PULSE_SEQUENCES: [[u8; 8]; 4] = [
 enum PulseDutyMode {
   #[default] Duty12, Duty25, Duty50, Duty25Neg,
 }
+codec_enum!(PulseDutyMode { Duty12, Duty25, Duty50, Duty25Neg });
 impl From<u8> for PulseDutyMode {
   fn from(value: u8) -> Self {
     match value {
@@ -44,7 +47,19 @@ pub struct Pulse {
 
   length: LengthCounter,
 }
+codec_fields!(Pulse { timer, duty_mode, duty_idx, envelope, sweep_enabled, sweep_reload, sweep_shift, sweep_negate, sweep_period, sweep_count, length });
 impl Pulse {
+  pub fn debug_state(&self) -> ChannelState {
+    ChannelState {
+      enabled: self.length.enabled,
+      period: self.timer.period,
+      length_counter: self.length.count,
+      envelope: Some(self.envelope.volume()),
+      linear_counter: None,
+      output: self.get_sample(),
+    }
+  }
+
   pub fn set_ctrl(&mut self, val: u8) {
     self.duty_mode = PulseDutyMode::from((val >> 6) & 0b11);
     self.length.halted = (val >> 5) & 1 == 1;
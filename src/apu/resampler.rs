@@ -0,0 +1,156 @@
+use core::f64::consts::PI;
+use std::collections::VecDeque;
+
+use crate::codec::{codec_enum, codec_fields};
+
+// How the apu output, made at the cpu rate, is brought to the rate of the sound card.
+// Linear interpolates between the two nearest apu samples, it is cheap but lets some aliasing of high notes through.
+// Sinc averages the apu output down to a couple hundred khz, then applies a windowed sinc filter,
+// which keeps high notes clean, at a few times the cost.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ResampleQuality { #[default] Linear, Sinc }
+codec_enum!(ResampleQuality { Linear, Sinc });
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+const MIN_SAMPLE_RATE: u32 = 8_000;
+const MAX_SAMPLE_RATE: u32 = 192_000;
+
+// the sinc filter runs on averages of this many apu samples
+const SINC_DECIMATION: u32 = 8;
+// zero crossings of the sinc on each side, the more the sharper the cutoff
+const SINC_ZERO_CROSSINGS: f64 = 8.0;
+// points of the kernel tabulated between two input samples, the rest is interpolated
+const SINC_PHASES: usize = 64;
+// passband, as a fraction of the output nyquist frequency
+const SINC_CUTOFF: f64 = 0.9;
+
+// Output samples are placed with a fractional position in the input, kept in f64,
+// so that the pitch doesn't drift from rounding the number of cycles per sample.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub(super) struct Resampler {
+  quality: ResampleQuality,
+  input_rate: u32,
+  output_rate: u32,
+  // input samples per output sample
+  step: f64,
+  // position of the next output sample, in input samples past the previous one
+  pos: f64,
+  last: f32,
+  // sum and count of the apu samples being averaged, for sinc
+  acc: f32,
+  acc_count: u32,
+  // the latest averaged samples, oldest first, as many as the kernel spans
+  history: VecDeque<f32>,
+  #[serde(skip)]
+  kernel: Vec<f32>,
+}
+codec_fields!(Resampler { quality, input_rate, output_rate, step, pos, last, acc, acc_count, history });
+
+impl Resampler {
+  // Changing the rates or the quality starts over, otherwise the running state is kept, as after loading a savestate
+  pub fn configure(&mut self, input_rate: u32, output_rate: u32, quality: ResampleQuality) {
+    let output_rate = output_rate.clamp(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE);
+    let changed = (self.input_rate, self.output_rate, self.quality) != (input_rate, output_rate, quality);
+
+    self.input_rate = input_rate;
+    self.output_rate = output_rate;
+    self.quality = quality;
+    if changed {
+      self.build_kernel();
+      self.reset();
+    } else if self.kernel.is_empty() {
+      // the kernel isn't part of savestates
+      self.build_kernel();
+    }
+  }
+
+  pub fn output_rate(&self) -> u32 {
+    self.output_rate
+  }
+
+  fn decimated_rate(&self) -> f64 {
+    match self.quality {
+      ResampleQuality::Linear => self.input_rate as f64,
+      ResampleQuality::Sinc => self.input_rate as f64 / SINC_DECIMATION as f64,
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.pos = 0.0;
+    self.last = 0.0;
+    self.acc = 0.0;
+    self.acc_count = 0;
+    // silence before the first samples
+    let taps = self.kernel.len().saturating_sub(2) / SINC_PHASES;
+    self.history.clear();
+    self.history.resize(taps * 2, 0.0);
+  }
+
+  fn build_kernel(&mut self) {
+    self.step = self.decimated_rate() / self.output_rate as f64;
+    self.kernel.clear();
+    if self.quality == ResampleQuality::Linear { return; }
+
+    // https://ccrma.stanford.edu/~jos/resample/
+    // a sinc cutting at the output nyquist frequency, in cycles per input sample, with a blackman window
+    let cutoff = SINC_CUTOFF * (self.output_rate as f64).min(self.decimated_rate()) / 2.0 / self.decimated_rate();
+    let half_width = SINC_ZERO_CROSSINGS / (2.0 * cutoff);
+    let taps = half_width.ceil() as usize;
+
+    self.kernel = (0..=taps * SINC_PHASES + 1)
+      .map(|i| {
+        let u = i as f64 / SINC_PHASES as f64;
+        if u >= half_width { return 0.0; }
+        let x = 2.0 * cutoff * u;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        let w = PI * u / half_width;
+        let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+        (2.0 * cutoff * sinc * window) as f32
+      })
+      .collect();
+  }
+
+  pub fn push(&mut self, sample: f32, out: &mut Vec<f32>) {
+    match self.quality {
+      ResampleQuality::Linear => {
+        while self.pos < 1.0 {
+          out.push(self.last + (sample - self.last) * self.pos as f32);
+          self.pos += self.step;
+        }
+        self.pos -= 1.0;
+        self.last = sample;
+      }
+      ResampleQuality::Sinc => {
+        self.acc += sample;
+        self.acc_count += 1;
+        if self.acc_count == SINC_DECIMATION {
+          let averaged = self.acc / SINC_DECIMATION as f32;
+          self.acc = 0.0;
+          self.acc_count = 0;
+          self.push_decimated(averaged, out);
+        }
+      }
+    }
+  }
+
+  fn push_decimated(&mut self, sample: f32, out: &mut Vec<f32>) {
+    self.history.pop_front();
+    self.history.push_back(sample);
+
+    // output samples fall between the two samples in the middle of the history
+    let center = self.history.len() / 2 - 1;
+    while self.pos < 1.0 {
+      let mut sum = 0.0;
+      for (i, val) in self.history.iter().enumerate() {
+        let dist = ((i as f64 - center as f64 - self.pos).abs() * SINC_PHASES as f64) as f32;
+        let idx = dist as usize;
+        let frac = dist - idx as f32;
+        let coeff = self.kernel[idx] + (self.kernel[idx + 1] - self.kernel[idx]) * frac;
+        sum += val * coeff;
+      }
+      out.push(sum);
+      self.pos += self.step;
+    }
+    self.pos -= 1.0;
+  }
+}
@@ -1,4 +1,6 @@
-use super::{Channel, LengthCounter, ApuDivider};
+use crate::codec::codec_fields;
+
+use super::{Channel, ChannelState, LengthCounter, ApuDivider};
 
 const TRIANGLE_SEQUENCE: [u8; 32] = [
   15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
@@ -13,9 +15,23 @@ pub(super) struct Triangle {
   length: LengthCounter,
   timer: ApuDivider,
   duty_idx: usize,
+  #[serde(skip)]
+  pub mute_ultrasonic: bool,
 }
+codec_fields!(Triangle { linear_reload, linear_period, linear_count, length, timer, duty_idx });
 
 impl Triangle {
+  pub fn debug_state(&self) -> ChannelState {
+    ChannelState {
+      enabled: self.length.enabled,
+      period: self.timer.period,
+      length_counter: self.length.count,
+      envelope: None,
+      linear_counter: Some(self.linear_count),
+      output: self.get_sample(),
+    }
+  }
+
   pub fn set_ctrl(&mut self, val: u8) {
     self.length.halted = (val >> 7) != 0;
     self.linear_period = val & 0b0111_1111;
@@ -33,6 +49,11 @@ impl Triangle {
     self.linear_reload = true;
   }
 
+  // The sequencer restarts from its first step on reset
+  pub fn reset(&mut self) {
+    self.duty_idx = 0;
+  }
+
   fn step_linear(&mut self) {
     if self.linear_reload {
       self.linear_count = self.linear_period;
@@ -46,9 +67,11 @@ impl Triangle {
 impl Channel for Triangle {
   fn step_timer(&mut self) {
     self.timer.step(|timer| {
-      // timer period higher than 2 silences ultrasonic frequencies
+      // periods lower than 2 play ultrasonic frequencies, which are heard as pops on most mixers.
+      // When muted, the sequencer is stopped, like other emulators do.
+      let ultrasonic = self.mute_ultrasonic && timer.period < 2;
       if self.length.count > 0 && self.linear_count > 0 
-        && timer.period >= 2 && timer.period < 0x7FE
+        && !ultrasonic && timer.period < 0x7FE
       {
         self.duty_idx = 
           (self.duty_idx + 1) % TRIANGLE_SEQUENCE.len();
@@ -0,0 +1,142 @@
+use std::fmt;
+
+use crate::{cart::{Cart, CartBanking, PpuTarget, PrgTarget}, mapper};
+
+// What a page of the cpu or ppu address space is currently mapped to, for debuggers.
+// Offsets are in bytes from the start of the relative memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankTarget {
+  Prg(usize),
+  // (enabled, offset)
+  SRam(bool, usize),
+  Chr(usize),
+  CiRam(usize),
+  // handled by the mapper itself (registers, expansion audio, ...)
+  Cart,
+  // fixed value provided by the mapper (mmc5 fill mode, exram)
+  Value(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageMapping {
+  pub addr: u16,
+  pub size: u16,
+  pub target: BankTarget,
+}
+
+impl PageMapping {
+  // The bank number, if the page was a bank as big as itself
+  pub fn bank(&self) -> Option<usize> {
+    match self.target {
+      BankTarget::Prg(offset) | BankTarget::SRam(_, offset)
+      | BankTarget::Chr(offset) | BankTarget::CiRam(offset) => Some(offset / self.size as usize),
+      BankTarget::Cart | BankTarget::Value(_) => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankMap {
+  // $5000-$FFFF, in 4kb pages
+  pub cpu: Vec<PageMapping>,
+  // $0000-$2FFF, in 1kb pages
+  pub ppu: Vec<PageMapping>,
+}
+
+const CPU_PAGE_SIZE: u16 = 0x1000;
+const PPU_PAGE_SIZE: u16 = 0x400;
+
+impl BankMap {
+  pub fn new(cart: &Cart) -> Self {
+    // mapping an address can change the mapper state (e.g. mmc2 latches), so a copy of it is probed
+    let mut state = Vec::new();
+    cart.mapper.save(&mut state);
+    let mut mapper = mapper::load_mapper(&cart.header, &state).expect("mapper should always load its own state");
+    let banks = serde_json::to_value(&cart.banks).expect("banks should always be serializable");
+    let mut banks: CartBanking = serde_json::from_value(banks).expect("banks should always be deserializable");
+
+    let cpu = (0x5000..=0xFFFF).step_by(CPU_PAGE_SIZE as usize)
+      .map(|addr| {
+        let target = match mapper.map_prg_addr(&mut banks, addr) {
+          PrgTarget::Prg(offset) => BankTarget::Prg(offset % cart.prg.len().max(1)),
+          PrgTarget::SRam(enabled, offset) => BankTarget::SRam(enabled, offset % cart.sram.len().max(1)),
+          PrgTarget::SRamProtected(offset) => BankTarget::SRam(true, offset % cart.sram.len().max(1)),
+          PrgTarget::Cart => BankTarget::Cart,
+          PrgTarget::ExRam(val) => BankTarget::Value(val),
+        };
+        PageMapping { addr: addr as u16, size: CPU_PAGE_SIZE, target }
+      })
+      .collect();
+
+    let ppu = (0..0x3000).step_by(PPU_PAGE_SIZE as usize)
+      .map(|addr| {
+        let target = match mapper.map_ppu_addr(&mut banks, addr) {
+          PpuTarget::Chr(offset) | PpuTarget::ChrProtected(offset) => BankTarget::Chr(offset % cart.chr.len().max(1)),
+          PpuTarget::CiRam(offset) => BankTarget::CiRam(offset),
+          PpuTarget::Value(val) => BankTarget::Value(val),
+        };
+        PageMapping { addr: addr as u16, size: PPU_PAGE_SIZE, target }
+      })
+      .collect();
+
+    Self { cpu, ppu }
+  }
+}
+
+impl fmt::Display for PageMapping {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "${:04X}-${:04X}: ", self.addr, self.addr as usize + self.size as usize - 1)?;
+    match self.target {
+      BankTarget::Prg(offset) => write!(f, "PRG  bank {:3} (${offset:06X})", offset / self.size as usize),
+      BankTarget::SRam(enabled, offset) => write!(f, "SRAM bank {:3} (${offset:06X}){}",
+        offset / self.size as usize, if enabled { "" } else { " disabled" }),
+      BankTarget::Chr(offset) => write!(f, "CHR  bank {:3} (${offset:06X})", offset / self.size as usize),
+      BankTarget::CiRam(offset) => write!(f, "CIRAM page {} (${offset:04X})", offset / self.size as usize),
+      BankTarget::Cart => write!(f, "mapper"),
+      BankTarget::Value(val) => write!(f, "value ${val:02X}"),
+    }
+  }
+}
+
+impl fmt::Display for BankMap {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "CPU")?;
+    for page in &self.cpu { writeln!(f, "  {page}")?; }
+    writeln!(f, "PPU")?;
+    for page in &self.ppu { writeln!(f, "  {page}")?; }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod bank_map_tests {
+  use super::*;
+
+  #[test]
+  fn probing_has_no_side_effects() {
+    // MMC2, 128kb prg, 32kb chr
+    let mut rom = vec![0; 16 + 128 * 1024 + 32 * 1024];
+    rom[0..4].copy_from_slice(b"NES\x1A");
+    rom[4] = 8;
+    rom[5] = 4;
+    rom[6] = 0x90;
+    let mut cart = Cart::new(&rom).unwrap();
+
+    cart.prg_write(0xA000, 5);
+    // latch 0 selects the FD bank, latch 1 the FE one
+    cart.prg_write(0xB000, 2);
+    cart.prg_write(0xC000, 3);
+    cart.prg_write(0xE000, 4);
+    cart.prg_write(0xD000, 6);
+    cart.vram_read(0x0FD8);
+    cart.vram_read(0x1FE8);
+
+    let map = BankMap::new(&cart);
+    assert_eq!(map.cpu[3], PageMapping { addr: 0x8000, size: 0x1000, target: BankTarget::Prg(5 * 0x2000) });
+    assert_eq!(map.cpu[10].target, BankTarget::Prg(15 * 0x2000 + 0x1000));
+    assert_eq!(map.ppu[0].target, BankTarget::Chr(2 * 0x1000));
+    assert_eq!(map.ppu[4].target, BankTarget::Chr(4 * 0x1000));
+    // probing $0FE8 didn't flip the latch
+    assert_eq!(map, BankMap::new(&cart));
+  }
+}
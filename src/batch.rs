@@ -0,0 +1,57 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Mutex}, thread};
+
+use crate::{joypad::JoypadButton, nes::Nes};
+
+// A rom to run in a batch, along with the buttons held on the first controller at every frame.
+// Once the script ends, no button is held.
+#[derive(Clone, Default)]
+pub struct BatchJob {
+  pub rom: Vec<u8>,
+  pub inputs: Vec<JoypadButton>,
+}
+
+impl BatchJob {
+  pub fn new(rom: Vec<u8>) -> Self {
+    Self { rom, inputs: Vec::new() }
+  }
+
+  pub fn with_inputs(rom: Vec<u8>, inputs: Vec<JoypadButton>) -> Self {
+    Self { rom, inputs }
+  }
+}
+
+// Runs every job for the given amount of frames, spread over a pool of threads.
+// Returns, in the same order as the jobs, the framebuffer hash of every frame, or why the rom couldn't be booted.
+pub fn run_batch(jobs: &[BatchJob], frames: usize, threads: usize) -> Vec<Result<Vec<u64>, String>> {
+  let results = Mutex::new(vec![Ok(Vec::new()); jobs.len()]);
+  let next_job = AtomicUsize::new(0);
+  let threads = threads.clamp(1, jobs.len().max(1));
+
+  thread::scope(|s| {
+    for _ in 0..threads {
+      s.spawn(|| loop {
+        let i = next_job.fetch_add(1, Ordering::Relaxed);
+        let Some(job) = jobs.get(i) else { break; };
+
+        let res = run_job(job, frames);
+        results.lock().unwrap()[i] = res;
+      });
+    }
+  });
+
+  results.into_inner().unwrap()
+}
+
+fn run_job(job: &BatchJob, frames: usize) -> Result<Vec<u64>, String> {
+  let mut emu = Nes::boot_from_bytes(&job.rom)?;
+
+  let hashes = (0..frames).map(|frame| {
+    emu.get_joypad().buttons1 = job.inputs.get(frame).copied().unwrap_or(JoypadButton::empty());
+    emu.step_until_vblank();
+    // nobody is listening
+    emu.consume_samples();
+    emu.get_screen().hash()
+  }).collect();
+
+  Ok(hashes)
+}
@@ -0,0 +1,83 @@
+use std::{env, fs, path::Path, sync::mpsc, thread, time::Duration};
+
+use nen_emulator::nes::{Nes, StepOutcome};
+
+// Loads every rom in a folder, runs it headlessly for a few seconds and reports the ones that
+// hang, panic, crash or only show a blank screen.
+// Usage: compat_runner <roms folder> [seconds to run, default 5]
+
+enum Report { Ok, Invalid(String), Jammed(usize), Blank, Panic(String), Hang }
+
+fn run_rom(rom: Vec<u8>, frames: usize) -> Report {
+  let mut emu = match Nes::boot_from_bytes(&rom) {
+    Ok(emu) => emu,
+    Err(e) => return Report::Invalid(e),
+  };
+
+  for frame in 0..frames {
+    if emu.step_until_vblank() == StepOutcome::Jammed {
+      return Report::Jammed(frame);
+    }
+  }
+
+  if emu.get_screen().is_blank() { Report::Blank } else { Report::Ok }
+}
+
+fn test_rom(path: &Path, seconds: u64) -> Report {
+  let rom = match fs::read(path) {
+    Ok(rom) => rom,
+    Err(e) => return Report::Invalid(e.to_string()),
+  };
+
+  let (tx, rx) = mpsc::channel();
+  let handle = thread::spawn(move || {
+    let _ = tx.send(run_rom(rom, seconds as usize * 60));
+  });
+
+  // emulation is way faster than real time, we give it plenty of time before calling it a hang
+  match rx.recv_timeout(Duration::from_secs(seconds * 10)) {
+    Ok(report) => report,
+    Err(mpsc::RecvTimeoutError::Timeout) => Report::Hang,
+    Err(mpsc::RecvTimeoutError::Disconnected) => {
+      let payload = handle.join().unwrap_err();
+      let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+      Report::Panic(msg)
+    }
+  }
+}
+
+fn main() {
+  let mut args = env::args().skip(1);
+  let Some(folder) = args.next() else {
+    eprintln!("Usage: compat_runner <roms folder> [seconds]");
+    return;
+  };
+  let seconds = args.next().and_then(|s| s.parse().ok()).unwrap_or(5);
+
+  let mut paths: Vec<_> = fs::read_dir(&folder)
+    .expect("couldn't read the roms folder")
+    .filter_map(|entry| entry.ok().map(|e| e.path()))
+    .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("nes")))
+    .collect();
+  paths.sort();
+
+  let (mut ok, mut failed) = (0, 0);
+  for path in &paths {
+    let name = path.file_name().unwrap().to_string_lossy();
+    let report = test_rom(path, seconds);
+
+    match &report {
+      Report::Ok => { ok += 1; continue; }
+      Report::Invalid(e) => println!("[INVALID] {name}: {e}"),
+      Report::Jammed(frame) => println!("[JAMMED]  {name}: cpu jammed at frame {frame}"),
+      Report::Blank => println!("[BLANK]   {name}: blank screen after {seconds} seconds"),
+      Report::Panic(msg) => println!("[PANIC]   {name}: {msg}"),
+      Report::Hang => println!("[HANG]    {name}: timed out"),
+    }
+    failed += 1;
+  }
+
+  println!("\n{ok} ok, {failed} failed, {} total", paths.len());
+}
@@ -0,0 +1,38 @@
+use std::{env, fs};
+
+use nen_emulator::{cart::ConsoleTiming, movie::Movie, nes::Nes};
+
+// Plays an FCEUX .fm2 movie headlessly, and prints the hashes of the final state,
+// to check that a TAS syncs the same way on other emulators.
+// Usage: movie_verify <rom> <movie.fm2>
+
+fn main() {
+  let mut args = env::args().skip(1);
+  let (Some(rom_path), Some(movie_path)) = (args.next(), args.next()) else {
+    eprintln!("Usage: movie_verify <rom> <movie.fm2>");
+    return;
+  };
+
+  let rom = fs::read(&rom_path).expect("couldn't read the rom");
+  let text = fs::read_to_string(&movie_path).expect("couldn't read the movie");
+
+  let movie = match Movie::from_fm2(&text) {
+    Ok(movie) => movie,
+    Err(e) => { eprintln!("Invalid movie: {e}"); return; }
+  };
+  let mut emu = match Nes::boot_from_bytes(&rom) {
+    Ok(emu) => emu,
+    Err(e) => { eprintln!("Invalid rom: {e}"); return; }
+  };
+
+  if movie.pal != (emu.get_cart_header().timing == ConsoleTiming::PAL) {
+    eprintln!("Warning: the movie and the rom disagree on the console region, it will likely desync");
+  }
+
+  let report = movie.play(&mut emu);
+  println!("frames:      {}", report.frames);
+  println!("rerecords:   {}", movie.rerecords);
+  println!("screen hash: {:016X}", report.screen_hash);
+  println!("ram hash:    {:016X}", report.ram_hash);
+  println!("state hash:  {:016X}", report.state_hash);
+}
@@ -0,0 +1,224 @@
+use std::{env, fs};
+
+use nen_emulator::{config::EmuConfig, frame::FrameBuffer, movie::Movie, nes::{Nes, StepOutcome}};
+
+// Headless frontend, for CI, bug reports and scripting.
+// Runs a rom for some frames, optionally driven by an .fm2 movie, and dumps what it produced.
+// Usage: nen run <rom> [--frames N] [--input movie.fm2] [--config config.json]
+//                      [--screenshot out.png] [--audio out.wav] [--state out.sav]
+
+const USAGE: &str = "Usage: nen run <rom> [--frames N] [--input movie.fm2] [--config config.json] \
+  [--screenshot out.png] [--audio out.wav] [--state out.sav]";
+
+const DEFAULT_FRAMES: usize = 600;
+
+#[derive(Default)]
+struct RunArgs {
+  rom: String,
+  frames: Option<usize>,
+  input: Option<String>,
+  config: Option<String>,
+  screenshot: Option<String>,
+  audio: Option<String>,
+  state: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<RunArgs, String> {
+  match args.next().as_deref() {
+    Some("run") => {}
+    Some(cmd) => return Err(format!("Unknown command: {cmd}")),
+    None => return Err("Missing command".to_string()),
+  }
+
+  let mut run = RunArgs::default();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with("--") {
+      if !run.rom.is_empty() { return Err(format!("Unexpected argument: {arg}")); }
+      run.rom = arg;
+      continue;
+    }
+
+    let value = args.next().ok_or(format!("Missing value for {arg}"))?;
+    match arg.as_str() {
+      "--frames" => run.frames = Some(value.parse().map_err(|_| format!("Invalid frame count: {value}"))?),
+      "--input" => run.input = Some(value),
+      "--config" => run.config = Some(value),
+      "--screenshot" => run.screenshot = Some(value),
+      "--audio" => run.audio = Some(value),
+      "--state" => run.state = Some(value),
+      _ => return Err(format!("Unknown option: {arg}")),
+    }
+  }
+
+  if run.rom.is_empty() { return Err("Missing rom".to_string()); }
+  Ok(run)
+}
+
+// https://www.w3.org/TR/png/#D-CRCAppendix
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for &byte in bytes {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for &byte in bytes {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  (b << 16) | a
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = png.len();
+  png.extend_from_slice(kind);
+  png.extend_from_slice(data);
+  let crc = crc32(&png[start..]);
+  png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// An rgb png with uncompressed deflate blocks, so that no compression library is needed.
+// Screenshots are small enough that the size doesn't matter.
+fn encode_png(screen: &FrameBuffer) -> Vec<u8> {
+  let mut pixels = Vec::with_capacity((screen.width * 3 + 1) * screen.height);
+  for row in screen.buffer.chunks(screen.pitch()) {
+    // no filter
+    pixels.push(0);
+    for rgba in row.chunks(4) {
+      pixels.extend_from_slice(&rgba[..3]);
+    }
+  }
+
+  let mut zlib = vec![0x78, 0x01];
+  let blocks: Vec<_> = pixels.chunks(0xFFFF).collect();
+  for (i, block) in blocks.iter().enumerate() {
+    zlib.push((i == blocks.len() - 1) as u8);
+    let len = block.len() as u16;
+    zlib.extend_from_slice(&len.to_le_bytes());
+    zlib.extend_from_slice(&(!len).to_le_bytes());
+    zlib.extend_from_slice(block);
+  }
+  zlib.extend_from_slice(&adler32(&pixels).to_be_bytes());
+
+  let mut header = Vec::new();
+  header.extend_from_slice(&(screen.width as u32).to_be_bytes());
+  header.extend_from_slice(&(screen.height as u32).to_be_bytes());
+  // 8 bits depth, truecolor, default compression, filter and interlacing
+  header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+  let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+  png_chunk(&mut png, b"IHDR", &header);
+  png_chunk(&mut png, b"IDAT", &zlib);
+  png_chunk(&mut png, b"IEND", &[]);
+  png
+}
+
+// 16 bits mono pcm
+fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+  let data_len = samples.len() as u32 * 2;
+  let mut wav = Vec::with_capacity(44 + data_len as usize);
+  wav.extend_from_slice(b"RIFF");
+  wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+  wav.extend_from_slice(b"WAVEfmt ");
+  wav.extend_from_slice(&16u32.to_le_bytes());
+  // pcm format, 1 channel
+  wav.extend_from_slice(&1u16.to_le_bytes());
+  wav.extend_from_slice(&1u16.to_le_bytes());
+  wav.extend_from_slice(&sample_rate.to_le_bytes());
+  wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+  wav.extend_from_slice(&2u16.to_le_bytes());
+  wav.extend_from_slice(&16u16.to_le_bytes());
+  wav.extend_from_slice(b"data");
+  wav.extend_from_slice(&data_len.to_le_bytes());
+
+  for sample in samples {
+    let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+    wav.extend_from_slice(&pcm.to_le_bytes());
+  }
+  wav
+}
+
+fn write_file(path: &str, bytes: &[u8]) -> Result<(), String> {
+  fs::write(path, bytes).map_err(|e| format!("Couldn't write {path}: {e}"))
+}
+
+fn run(args: RunArgs) -> Result<(), String> {
+  let rom = fs::read(&args.rom).map_err(|e| format!("Couldn't read {}: {e}", args.rom))?;
+
+  let config = match &args.config {
+    Some(path) => {
+      let json = fs::read_to_string(path).map_err(|e| format!("Couldn't read {path}: {e}"))?;
+      EmuConfig::from_json(&json)?
+    }
+    None => EmuConfig::default(),
+  };
+
+  let movie = match &args.input {
+    Some(path) => {
+      let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {path}: {e}"))?;
+      Some(Movie::from_fm2(&text)?)
+    }
+    None => None,
+  };
+
+  let mut emu = Nes::new_from_file(&rom, &args.rom, config)?;
+
+  // with a movie, we run it to its end unless told otherwise
+  let frames = args.frames
+    .or(movie.as_ref().map(|movie| movie.frames.len()))
+    .unwrap_or(DEFAULT_FRAMES);
+
+  let mut samples = Vec::new();
+  let mut ran = 0;
+  for frame in 0..frames {
+    if let Some(movie) = &movie {
+      movie.apply_frame(frame, &mut emu);
+    }
+
+    if emu.step_until_vblank() == StepOutcome::Jammed {
+      eprintln!("Warning: the cpu jammed at frame {frame}");
+      break;
+    }
+
+    if args.audio.is_some() {
+      samples.extend(emu.get_samples());
+    } else {
+      emu.consume_samples();
+    }
+    ran += 1;
+  }
+
+  if let Some(path) = &args.screenshot {
+    write_file(path, &encode_png(emu.get_screen()))?;
+  }
+  if let Some(path) = &args.audio {
+    write_file(path, &encode_wav(&samples, emu.get_sample_rate()))?;
+  }
+  if let Some(path) = &args.state {
+    write_file(path, &emu.save_state())?;
+  }
+
+  println!("frames:      {ran}");
+  println!("screen hash: {:016X}", emu.get_screen().hash());
+  println!("state hash:  {:016X}", emu.state_hash());
+  Ok(())
+}
+
+fn main() {
+  let args = match parse_args(env::args().skip(1)) {
+    Ok(args) => args,
+    Err(e) => { eprintln!("{e}\n{USAGE}"); std::process::exit(2); }
+  };
+
+  if let Err(e) = run(args) {
+    eprintln!("{e}");
+    std::process::exit(1);
+  }
+}
@@ -1,5 +1,5 @@
 
-use crate::{apu::Apu, cart::{Cart, ConsoleTiming, SharedCart}, dma::{Dma, OamDma}, joypad::Joypad, mem::Memory, ppu::Ppu};
+use crate::{apu::Apu, codec::codec_fields, config::RamInit, cart::{Cart, ConsoleTiming, SharedCart}, dma::{Dma, OamDma}, joypad::Joypad, mem::Memory, ppu::Ppu};
 
 #[derive(Debug)]
 enum BusDst {
@@ -18,6 +18,7 @@ pub struct Bus {
   pub joypad: Joypad,
   pub oam_dma: OamDma,
 }
+codec_fields!(Bus { timing, ram, cart, ppu, ppu_pal_cycles, apu, joypad, oam_dma });
 
 fn map_address(addr: u16) -> (BusDst, usize) {
   let addr = addr as usize;
@@ -100,7 +101,10 @@ impl Memory for Bus {
       _ => self.ppu_step_nstc(),
     };
 
-    self.apu.step();
+    // the apu doesn't run during the overclocked scanlines, so that the sound plays at the right speed
+    if !self.ppu.is_overclocking() {
+      self.apu.step();
+    }
     self.cart.as_mut().mapper.notify_cpu_cycle();
   }
 
@@ -130,6 +134,10 @@ impl Memory for Bus {
   }
 }
 
+// The cart pointer is shared only between the bus, ppu and apu, which all live inside the bus.
+// Moving the whole bus to another thread moves every user of the pointer along with it.
+unsafe impl Send for Bus {}
+
 impl Drop for Bus {
   fn drop(&mut self) {
     // This is needed, as we're manually managing a cart pointer to heap
@@ -140,6 +148,16 @@ impl Drop for Bus {
 }
 
 impl Bus {
+  // Reads memory without side effects, for debuggers. Io registers read as 0.
+  pub fn peek(&mut self, addr: u16) -> u8 {
+    let (dst, addr) = map_address(addr);
+    match dst {
+      BusDst::Ram => self.ram[addr],
+      BusDst::SRam | BusDst::Prg => self.cart.as_mut().prg_peek(addr),
+      _ => 0,
+    }
+  }
+
   pub fn new(cart: Cart) -> Self {
     let timing = cart.header.timing;
     let shared_cart = SharedCart::new(cart); 
@@ -159,6 +177,15 @@ impl Bus {
     }
   }
 
+  // The power on content of ram is unreliable on real consoles
+  pub fn init_ram(&mut self, init: RamInit) {
+    match init {
+      RamInit::Zeroes => self.ram.fill(0),
+      RamInit::Ones => self.ram.fill(0xFF),
+      RamInit::Random => self.cart.as_mut().rng.fill(&mut self.ram),
+    }
+  }
+
   fn ppu_step_nstc(&mut self) {
     for _ in 0..3 { self.ppu.step(); }
   }
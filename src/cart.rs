@@ -1,11 +1,20 @@
+use core::fmt;
+
 use serde::ser::SerializeStruct;
-use crate::mapper::{self, Banking, ChrBanking, Dummy, Mapper, PrgBanking, SramBanking, CiramBanking};
+use crate::codec::{codec_enum, codec_fields, NenCodec, Reader};
+use crate::frame::{crc32, fnv_hash};
+use crate::overrides::{self, GameOverride};
+use crate::rng::Rng;
+use crate::mapper::{self, Banking, ChrBanking, Dummy, FlashOp, Mapper, FLASH_SECTOR_SIZE, PrgBanking, SramBanking, CiramBanking};
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CartHeader {
   pub format: HeaderFormat,
   pub console_type: ConsoleType,
   pub timing: ConsoleTiming,
+  // where the timing comes from, as most iNes headers don't tell it
+  #[serde(skip)]
+  pub timing_source: RegionSource,
 
   pub game_title: String,
   pub has_trainer: bool,
@@ -27,6 +36,16 @@ pub struct CartHeader {
   pub prg_ram_size: usize,
   pub eeprom_size: usize,
   pub chr_nvram_size: usize,
+  // hash of the whole rom data after the header, to tell games apart regardless of their file name or header
+  #[serde(default)]
+  pub rom_hash: u64,
+  // crc32 of prg and chr, which the builtin compatibility overrides are keyed by.
+  // Savestates are of the same rom, it is taken from the running game when loading one.
+  #[serde(skip)]
+  pub rom_crc32: u32,
+
+  #[serde(skip)]
+  pub diagnostics: Vec<HeaderDiagnostic>,
 }
 impl CartHeader {
   pub fn chr_real_size(&self) -> usize {
@@ -38,16 +57,88 @@ impl CartHeader {
     }
   }
 
+  // NES 2.0 declares the battery backed ram apart from the volatile one, boards with both map them as one.
+  // When the header tells nothing, the board does.
   pub fn sram_real_size(&self) -> usize {
-    if self.has_battery && self.eeprom_size > 0 { 
-      self.eeprom_size
-    } else if self.prg_ram_size > 0 { 
-      self.prg_ram_size 
-    } else { 8 * 1024 }
+    let nvram_size = if self.has_battery { self.eeprom_size } else { 0 };
+    match self.prg_ram_size + nvram_size {
+      0 => mapper::board_prg_ram_size(self),
+      size => size,
+    }
+  }
+}
+
+// Errors which prevent a rom from being loaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmuError {
+  // The file is shorter than what the header declares
+  Truncated { expected: usize, got: usize },
+  InvalidHeader(&'static str),
+  NoPrgData,
+  UnsupportedMapper(String),
+  // Famicom Disk System images need the disk drive and its bios, which aren't emulated
+  DiskSystemImage,
+}
+
+impl fmt::Display for EmuError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EmuError::Truncated { expected, got } => 
+        write!(f, "Rom is truncated: expected {expected} bytes, got {got}"),
+      EmuError::InvalidHeader(e) => write!(f, "Not a valid iNES/Nes2.0 rom: {e}"),
+      EmuError::NoPrgData => write!(f, "Rom doesn't contain any prg data"),
+      EmuError::UnsupportedMapper(e) => write!(f, "{e}"),
+      EmuError::DiskSystemImage => write!(f, "Famicom Disk System images are not supported"),
+    }
+  }
+}
+
+impl From<EmuError> for String {
+  fn from(e: EmuError) -> Self {
+    e.to_string()
+  }
+}
+
+// Anomalies found while parsing the header, and the corrections applied to them
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderDiagnostic {
+  // Bytes 7-15 are filled with garbage (usually a ripper signature, like "DiskDude!").
+  // The mapper high nibble and the prg ram size were ignored.
+  DirtyHeader(String),
+  // The header declares no prg banks; the prg size was inferred from the file size.
+  ZeroPrgBanks { inferred_banks: usize },
+  // The declared chr rom doesn't fit in the file; it was cut to the available data.
+  ChrSizeMismatch { declared: usize, available: usize },
+  // The file contains more data than what the header declares; it is ignored.
+  TrailingData(usize),
+  // The header doesn't set the battery flag, but the board is known to always have one.
+  BatteryInferred,
+  // A known fix for this game was applied, see overrides.rs
+  Overridden(String),
+}
+
+impl fmt::Display for HeaderDiagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      HeaderDiagnostic::DirtyHeader(garbage) => 
+        write!(f, "dirty header ({garbage:?}), ignoring mapper high nibble and prg ram size"),
+      HeaderDiagnostic::ZeroPrgBanks { inferred_banks } => 
+        write!(f, "header declares no prg banks, inferred {inferred_banks} banks from file size"),
+      HeaderDiagnostic::ChrSizeMismatch { declared, available } => 
+        write!(f, "header declares {declared} bytes of chr rom, but only {available} are available"),
+      HeaderDiagnostic::TrailingData(len) => 
+        write!(f, "file has {len} bytes of trailing data, ignoring them"),
+      HeaderDiagnostic::BatteryInferred => 
+        write!(f, "battery flag not set, but the board always has one"),
+      HeaderDiagnostic::Overridden(name) => 
+        write!(f, "applied the compatibility override for {name}"),
+    }
   }
 }
 
 const NES_MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const FDS_MAGIC: &[u8] = b"FDS\x1A";
+const FDS_DISK_INFO: &[u8] = b"\x01*NINTENDO-HVC*";
 const HEADER_SIZE: usize = 16;
 const PRG_ROM_PAGE_SIZE: usize = 1024 * 16;
 const CHR_ROM_PAGE_SIZE: usize = 1024 * 8;
@@ -62,11 +153,13 @@ pub enum Mirroring {
   SingleScreenB, 
   FourScreen
 }
+codec_enum!(Mirroring { Horizontal, Vertical, SingleScreenA, SingleScreenB, FourScreen });
 
 #[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum ConsoleType { #[default] NES, VsSystem, Playchoice10, Other }
 #[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum ConsoleTiming { NTSC, PAL, World, Dendy, #[default] Unknown }
+codec_enum!(ConsoleTiming { NTSC, PAL, World, Dendy, Unknown });
 impl ConsoleTiming {
   pub fn fps(&self) -> f32 {
     use ConsoleTiming::*;
@@ -85,6 +178,15 @@ impl ConsoleTiming {
     }
   }
 
+  // The ppu runs at the master clock divided by 4 on ntsc consoles, by 5 on pal and dendy ones
+  pub fn ppu_hz(&self) -> f64 {
+    use ConsoleTiming::*;
+    match self {
+      PAL | Dendy => 26_601_712.0 / 5.0,
+      _ => 21_477_272.0 / 4.0,
+    }
+  }
+
   pub fn frame_ppu_cycles(&self) -> usize {
     use ConsoleTiming::*;
     match self {
@@ -102,6 +204,17 @@ impl ConsoleTiming {
     }
   }
 
+  // Width of a pixel relative to its height, on a tv of the region.
+  // The ppu pixel clock doesn't match the square pixels rate of the video standard, so pixels are drawn wider.
+  // https://www.nesdev.org/wiki/Overscan
+  pub fn pixel_aspect_ratio(&self) -> f32 {
+    use ConsoleTiming::*;
+    match self {
+      PAL | Dendy => 2_950_000.0 / 2_128_137.0,
+      _ => 8.0 / 7.0,
+    }
+  }
+
   pub fn vblank_len(&self) -> usize {
     use ConsoleTiming::*;
     match self {
@@ -111,6 +224,62 @@ impl ConsoleTiming {
   }
 }
 
+// How the region of a game was decided
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RegionSource {
+  // NES 2.0 headers declare the timing
+  Header,
+  // A known fix for this game, see overrides.rs
+  Database,
+  // The region tag in the name of the rom file, like "(E)" or "(Europe)"
+  FileName,
+  // Forced by EmuConfig::region
+  Config,
+  // Nothing told the region, the game runs as NTSC
+  #[default]
+  Default,
+}
+
+// GoodNES and No-Intro tags. Brazil is PAL-M, which has NTSC timing.
+const PAL_TAGS: &[&str] = &[
+  "E", "A", "G", "F", "S", "I", "PAL", "Europe", "Australia", "Germany", "France", "Spain",
+  "Italy", "Sweden", "Netherlands", "Scandinavia", "UK",
+];
+const NTSC_TAGS: &[&str] = &["U", "J", "K", "NTSC", "USA", "Japan", "Korea", "Canada", "Brazil"];
+
+fn is_pal_tag(tag: &str) -> bool { PAL_TAGS.contains(&tag) }
+fn is_ntsc_tag(tag: &str) -> bool { NTSC_TAGS.contains(&tag) }
+
+// The region of the tags in parentheses of a rom file name.
+// A name with both PAL and NTSC tags, like "(USA, Europe)", is released everywhere and tells nothing.
+pub fn region_from_file_name(file_name: &str) -> Option<ConsoleTiming> {
+  let name = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+  let groups = name.split('(').skip(1)
+    .filter_map(|rest| rest.split_once(')'))
+    .map(|(group, _)| group);
+
+  let (mut pal, mut ntsc) = (false, false);
+  for group in groups {
+    let mut tags: Vec<&str> = group.split(',').map(str::trim).collect();
+    // GoodNES joins the letters, like "(JU)"
+    if !is_pal_tag(group) && !is_ntsc_tag(group) && group.chars().all(|c| c.is_ascii_uppercase()) {
+      tags = (0..group.len()).map(|i| &group[i..i + 1]).collect();
+    }
+
+    // other groups, like "(Rev A)", are not about regions
+    if tags.iter().all(|tag| is_pal_tag(tag) || is_ntsc_tag(tag)) {
+      pal |= tags.iter().any(|tag| is_pal_tag(tag));
+      ntsc |= tags.iter().any(|tag| is_ntsc_tag(tag));
+    }
+  }
+
+  match (pal, ntsc) {
+    (true, false) => Some(ConsoleTiming::PAL),
+    (false, true) => Some(ConsoleTiming::NTSC),
+    _ => None,
+  }
+}
+
 pub fn is_nes_rom(rom: &[u8]) -> bool {
   if rom.len() < 4 { return false; }
 
@@ -118,10 +287,19 @@ pub fn is_nes_rom(rom: &[u8]) -> bool {
   magic_str == NES_MAGIC 
 }
 
+// .fds files, with the fwNES header or without it, starting directly with the disk info block
+pub fn is_fds_image(rom: &[u8]) -> bool {
+  rom.starts_with(FDS_MAGIC) || rom.starts_with(FDS_DISK_INFO)
+}
+
 impl CartHeader {
   pub fn new(rom: &[u8]) -> Result<Self, &'static str> {
     let mut header = CartHeader::default();
 
+    if rom.len() < HEADER_SIZE {
+      return Err("File too small to contain a 16 bytes header");
+    }
+
     if !is_nes_rom(rom) {
       return Err("Nintendo header magic values not found");
     }
@@ -132,7 +310,8 @@ impl CartHeader {
 
     header.prg_size = header.prg_16kb_banks as usize * PRG_ROM_PAGE_SIZE;
     header.chr_size = header.chr_8kb_banks as usize * CHR_ROM_PAGE_SIZE;
-    // iNes header doesn't hold information about chr ram size, so it defaults to 8kb if no chr rom is present
+    // iNes header doesn't hold information about chr ram size, so it defaults to 8kb if no chr rom is present.
+    // Boards known to carry more chr ram are fixed up in validate()
     header.chr_ram_size = if header.uses_chr_ram { CHR_ROM_PAGE_SIZE } else { 0 };
     
     let nametbl_mirroring = rom[6] & 1;
@@ -154,19 +333,12 @@ impl CartHeader {
 
     header.format = if rom[7] & 0b0000_1100 == 0x8 { HeaderFormat::Nes2_0 } else { HeaderFormat::INes };
     // This field was a later addition to iNes, so most games do not use it, even if they contain prg_ram.
-    // If it is 0, prg ram is inferred as 8kb.
-    header.prg_ram_size = rom[8] as usize * 1024;
-
-    let title_start = HEADER_SIZE + header.prg_size-32;
-    let title_bytes = &rom[title_start..title_start+16];
-    header.game_title = String::from_utf8_lossy(title_bytes)
-      .into_owned()
-      .chars()
-      .filter(|c| c.is_ascii_alphanumeric() || c.is_ascii_punctuation() || c.is_ascii_whitespace())
-      .collect::<String>()
-      .trim().to_string();
+    // It counts 8kb units. If it is 0, prg ram is inferred as 8kb.
+    header.prg_ram_size = rom[8] as usize * 8 * 1024;
 
     if header.format == HeaderFormat::INes {
+      header.validate(rom);
+      header.game_title = parse_title(rom, &header);
       return Ok(header);
     }
 
@@ -201,6 +373,12 @@ impl CartHeader {
       } else if header.submapper == 2 {
         header.mapper_name = String::from("BNROM");
       }
+    } else if header.mapper == 210 {
+      if header.submapper == 1 {
+        header.mapper_name = String::from("Namco 175");
+      } else if header.submapper == 2 {
+        header.mapper_name = String::from("Namco 340");
+      }
     }
 
     header.prg_16kb_banks = ((rom[9] as usize & 0b1111) << 8) + rom[4] as usize;
@@ -208,6 +386,7 @@ impl CartHeader {
 
     header.prg_size = header.prg_16kb_banks * PRG_ROM_PAGE_SIZE;
     header.chr_size = header.chr_8kb_banks * CHR_ROM_PAGE_SIZE;
+    header.uses_chr_ram = header.chr_8kb_banks == 0;
 
     header.prg_ram_size   = if rom[10] & 0b0000_1111 == 0 { 0 } else {64 << (rom[10] & 0b0000_1111)};
     header.eeprom_size    = if rom[10] & 0b1111_0000 == 0 { 0 } else {64 << (rom[10] >> 4)};
@@ -220,9 +399,96 @@ impl CartHeader {
       2 => ConsoleTiming::World,
       _ => ConsoleTiming::Dendy,
     };
+    header.timing_source = RegionSource::Header;
 
+    header.validate(rom);
+    header.game_title = parse_title(rom, &header);
     Ok(header)
   }
+
+  fn validate(&mut self, rom: &[u8]) {
+    // https://www.nesdev.org/wiki/INES#Variant_comparison
+    if self.format == HeaderFormat::INes 
+      && (rom[7] & 0b0000_1100 == 0b0100 || rom[12..HEADER_SIZE].iter().any(|&b| b != 0))
+    {
+      let garbage = String::from_utf8_lossy(&rom[7..HEADER_SIZE])
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .collect();
+      self.diagnostics.push(HeaderDiagnostic::DirtyHeader(garbage));
+
+      self.mapper = rom[6] as u16 >> 4;
+      self.mapper_name = mapper::mapper_name(self.mapper).to_string();
+      self.prg_ram_size = 0;
+    }
+
+    if !self.has_battery && mapper::board_has_battery(self) {
+      self.has_battery = true;
+      self.diagnostics.push(HeaderDiagnostic::BatteryInferred);
+    }
+
+    let prg_start = HEADER_SIZE + if self.has_trainer { 512 } else { 0 };
+    let chr_rom_size = if self.uses_chr_ram { 0 } else { self.chr_size };
+
+    if self.prg_16kb_banks == 0 {
+      let available = rom.len().saturating_sub(prg_start + chr_rom_size);
+      self.prg_16kb_banks = available / PRG_ROM_PAGE_SIZE;
+      self.prg_size = self.prg_16kb_banks * PRG_ROM_PAGE_SIZE;
+      self.diagnostics.push(HeaderDiagnostic::ZeroPrgBanks { inferred_banks: self.prg_16kb_banks });
+    }
+
+    let chr_start = prg_start + self.prg_size;
+    if !self.uses_chr_ram && chr_start + self.chr_size > rom.len() {
+      let available = rom.len().saturating_sub(chr_start);
+      self.diagnostics.push(HeaderDiagnostic::ChrSizeMismatch { declared: self.chr_size, available });
+
+      self.chr_8kb_banks = available / CHR_ROM_PAGE_SIZE;
+      self.chr_size = self.chr_8kb_banks * CHR_ROM_PAGE_SIZE;
+      if self.chr_size == 0 {
+        // no usable chr rom, the best bet is it being a chr ram board
+        self.chr_8kb_banks = 1;
+        self.chr_size = CHR_ROM_PAGE_SIZE;
+        self.uses_chr_ram = true;
+      }
+    }
+
+    if self.uses_chr_ram {
+      match self.format {
+        HeaderFormat::INes => self.chr_ram_size = mapper::board_chr_ram_size(self),
+        // some NES 2.0 dumps only declare battery backed chr ram, or no chr ram at all
+        HeaderFormat::Nes2_0 if self.chr_ram_size == 0 => {
+          self.chr_ram_size = if self.chr_nvram_size > 0 {
+            self.chr_nvram_size
+          } else { mapper::board_chr_ram_size(self) };
+        }
+        _ => {}
+      }
+    }
+
+    let chr_rom_size = if self.uses_chr_ram { 0 } else { self.chr_size };
+    let data_end = prg_start + self.prg_size + chr_rom_size;
+    // Playchoice roms append their own data after chr
+    if rom.len() > data_end && !matches!(self.console_type, ConsoleType::Playchoice10) {
+      self.diagnostics.push(HeaderDiagnostic::TrailingData(rom.len() - data_end));
+    }
+  }
+}
+
+fn parse_title(rom: &[u8], header: &CartHeader) -> String {
+  // Some games store their title in the last 32 bytes of prg
+  if header.prg_size < 32 { return String::new(); }
+
+  let title_start = HEADER_SIZE + header.prg_size-32;
+  let Some(title_bytes) = rom.get(title_start..title_start+16) else {
+    return String::new();
+  };
+
+  String::from_utf8_lossy(title_bytes)
+    .into_owned()
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric() || c.is_ascii_punctuation() || c.is_ascii_whitespace())
+    .collect::<String>()
+    .trim().to_string()
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -232,6 +498,7 @@ pub struct CartBanking {
   pub sram: Banking<SramBanking>,
   pub ciram: Banking<CiramBanking>,
 }
+codec_fields!(CartBanking { prg, chr, sram, ciram });
 impl Default for CartBanking {
   fn default() -> Self {
     let header = &Default::default();
@@ -291,22 +558,31 @@ impl<'de> serde::Deserialize<'de> for SharedCart {
   }
 }
 
+impl NenCodec for SharedCart {
+  fn encode(&self, out: &mut Vec<u8>) {
+    self.as_ref().encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    self.as_mut().decode(input)
+  }
+}
+
 impl Default for SharedCart {
   fn default() -> Self {
     Self(core::ptr::null_mut())
   }
 }
 
-#[derive(serde::Deserialize)]
 pub struct Cart {
   pub header: CartHeader,
-  #[serde(skip)]
   pub prg: Box<[u8]>,
   pub chr: Box<[u8]>,
   pub sram: Box<[u8]>,
   pub ciram: Box<[u8]>,
   pub banks: CartBanking,
   pub mapper: Box<dyn Mapper>,
+  pub rng: Rng,
 }
 
 impl Default for Cart {
@@ -318,7 +594,8 @@ impl Default for Cart {
       ciram: Default::default(),
       sram: Default::default(),
       banks: Default::default(),
-      mapper: Box::new(Dummy)
+      mapper: Box::new(Dummy),
+      rng: Default::default(),
     }
   }
 }
@@ -327,7 +604,7 @@ impl serde::Serialize for Cart {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
   where
       S: serde::Serializer {
-    let mut se = serializer.serialize_struct("Cart", 6)?;
+    let mut se = serializer.serialize_struct("Cart", 7)?;
 
     // we do not care to serialize prg
     se.skip_field("prg")?;
@@ -336,7 +613,10 @@ impl serde::Serialize for Cart {
     se.serialize_field("sram", &self.sram)?;
     se.serialize_field("ciram", &self.ciram)?;
     se.serialize_field("banks", &self.banks)?;
-    se.serialize_field("mapper", &self.mapper)?;
+    let mut state = Vec::new();
+    self.mapper.save(&mut state);
+    se.serialize_field("mapper", &MapperState { id: self.header.mapper, state })?;
+    se.serialize_field("rng", &self.rng)?;
 
     // we only serialize chr if it is chr ram
     if self.header.uses_chr_ram {
@@ -349,22 +629,137 @@ impl serde::Serialize for Cart {
   }
 }
 
-pub enum PpuTarget { Chr(usize), CiRam(usize), Value(u8) }
-pub enum PrgTarget { Prg(usize), SRam(bool, usize), Cart, ExRam(u8) }
+// The mapper is built again from the header when loading, and given back the state it saved
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MapperState {
+  id: u16,
+  state: Vec<u8>,
+}
+
+#[derive(serde::Deserialize)]
+struct CartState {
+  header: CartHeader,
+  chr: Box<[u8]>,
+  sram: Box<[u8]>,
+  ciram: Box<[u8]>,
+  banks: CartBanking,
+  mapper: MapperState,
+  #[serde(default)]
+  rng: Rng,
+}
+
+impl<'de> serde::Deserialize<'de> for Cart {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+      D: serde::Deserializer<'de> {
+    let cart = CartState::deserialize(deserializer)?;
+    if cart.mapper.id != cart.header.mapper {
+      return Err(serde::de::Error::custom(format!("mapper {} doesn't match the header's {}", cart.mapper.id, cart.header.mapper)));
+    }
+    let mapper = mapper::load_mapper(&cart.header, &cart.mapper.state)
+      .map_err(serde::de::Error::custom)?;
+
+    Ok(Self {
+      header: cart.header,
+      prg: Default::default(),
+      chr: cart.chr,
+      sram: cart.sram,
+      ciram: cart.ciram,
+      banks: cart.banks,
+      mapper,
+      rng: cart.rng,
+    })
+  }
+}
+
+// As the json savestates, the header and prg are left out, and chr when it isn't ram.
+// The mapper writes its own state, the emulator checks that it is loaded in the same mapper.
+impl NenCodec for Cart {
+  fn encode(&self, out: &mut Vec<u8>) {
+    if self.header.uses_chr_ram {
+      self.chr.encode(out);
+    }
+    self.sram.encode(out);
+    self.ciram.encode(out);
+    self.banks.encode(out);
+    self.rng.encode(out);
+
+    let mut mapper = Vec::new();
+    self.mapper.save(&mut mapper);
+    mapper.encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    if self.header.uses_chr_ram {
+      self.chr.decode(input)?;
+    }
+    self.sram.decode(input)?;
+    self.ciram.decode(input)?;
+    self.banks.decode(input)?;
+    self.rng.decode(input)?;
+
+    let len = input.take_len()?;
+    self.mapper.load(input.take(len)?)
+  }
+}
+
+// ChrProtected is chr ram the board write protects: readable, but writes are ignored
+pub enum PpuTarget { Chr(usize), ChrProtected(usize), CiRam(usize), Value(u8) }
+
+// Mirrors an offset into data smaller than the banks, or whose last bank is cut short.
+// Most offsets are already in range, and skip the folding.
+fn wrap(offset: usize, len: usize) -> usize {
+  if offset < len { offset } else { mapper::mirror(offset, len) }
+}
+// SRam carries whether the ram is enabled, SRamProtected is readable but ignores writes
+pub enum PrgTarget { Prg(usize), SRam(bool, usize), SRamProtected(usize), Cart, ExRam(u8) }
 
 impl Cart {
-  pub fn new(rom: &[u8]) -> Result<Self, String> {
+  pub fn new(rom: &[u8]) -> Result<Self, EmuError> {
+    Self::new_from_file(rom, "")
+  }
+
+  // The file name tells the region of games with an iNes header, when the database doesn't know them
+  pub fn new_from_file(rom: &[u8], file_name: &str) -> Result<Self, EmuError> {
+    Self::new_with_overrides(rom, file_name, &[])
+  }
+
+  // The given overrides take precedence over the builtin ones
+  pub fn new_with_overrides(rom: &[u8], file_name: &str, overrides: &[GameOverride]) -> Result<Self, EmuError> {
+    if is_fds_image(rom) {
+      return Err(EmuError::DiskSystemImage);
+    }
     if rom.len() < HEADER_SIZE {
-      return Err("File too small to contain a 16 bytes header".to_string());
+      return Err(EmuError::Truncated { expected: HEADER_SIZE, got: rom.len() });
     }
     
-    let header = CartHeader::new(&rom)
-      .map_err(|e| format!("Not a valid iNES/Nes2.0 rom: {e}"))?;
+    let mut header = CartHeader::new(&rom)
+      .map_err(EmuError::InvalidHeader)?;
+    header.rom_hash = fnv_hash(&rom[HEADER_SIZE..]);
+    let trainer_size = if header.has_trainer { 512 } else { 0 };
+    header.rom_crc32 = crc32(rom.get(HEADER_SIZE + trainer_size..).unwrap_or_default());
+    if let Some(entry) = overrides::find(&header, overrides) {
+      entry.apply_to_header(&mut header);
+    }
+    if header.timing_source == RegionSource::Default {
+      if let Some(timing) = region_from_file_name(file_name) {
+        header.timing = timing;
+        header.timing_source = RegionSource::FileName;
+      }
+    }
 
-    println!("Loaded NES ROM: {:#?}", header);
+    nen_log!(info, "nen::cart", "Loaded NES ROM: {:?}", header);
+    if header.prg_size == 0 {
+      return Err(EmuError::NoPrgData);
+    }
 
     let prg_start = HEADER_SIZE + if header.has_trainer { 512 } else { 0 };
     let chr_start = prg_start + header.prg_size;
+    // a truncated chr is repaired by the header validation, a truncated prg can't be
+    if chr_start > rom.len() {
+      let chr_rom_size = if header.uses_chr_ram { 0 } else { header.chr_size };
+      return Err(EmuError::Truncated { expected: chr_start + chr_rom_size, got: rom.len() });
+    }
 
     let prg = rom[prg_start..chr_start]
       .to_vec().into_boxed_slice();
@@ -382,19 +777,64 @@ impl Cart {
     let ciram = vec![0; ciram_size].into_boxed_slice();
     
     let mut banks = CartBanking::new(&header);
-    let mapper = mapper::new_mapper(&header, &mut banks)?;
+    let mapper = mapper::new_mapper(&header, &mut banks)
+      .map_err(EmuError::UnsupportedMapper)?;
     
-    Ok(Cart { header, prg, chr, sram, ciram, banks, mapper })
+    Ok(Cart { header, prg, chr, sram, ciram, banks, mapper, rng: Rng::default() })
+  }
+
+  // Puts the board back in its power on state. The mapper registers are cleared, and the ram without a battery is lost.
+  // The roms, the battery backed ram and the mapper nvram are kept.
+  pub fn power_cycle(&mut self) {
+    let nvram = self.mapper.get_mapper_nvram();
+    self.banks = CartBanking::new(&self.header);
+    self.mapper = mapper::new_mapper(&self.header, &mut self.banks)
+      .expect("the mapper was already built for this header");
+    if let Some(nvram) = nvram {
+      self.mapper.set_mapper_nvram(nvram);
+    }
+
+    if !self.header.has_battery {
+      self.sram.fill(0);
+    }
+    if self.header.uses_chr_ram {
+      self.chr.fill(0);
+    }
+    self.ciram.fill(0);
   }
 
   pub fn get_sram(&self) -> Option<Vec<u8>> {
-    if self.header.has_battery {
+    if mapper::board_has_flash(&self.header) {
+      Some(self.prg.to_vec())
+    } else if self.header.has_battery {
       Some(self.sram.to_vec())
     } else { None }
   }
 
   pub fn set_sram(&mut self, data: Vec<u8>) {
-    self.sram = data.into_boxed_slice();
+    if mapper::board_has_flash(&self.header) {
+      // a flash save is the whole prg rom, anything else is not for this game
+      if data.len() == self.prg.len() {
+        self.prg = data.into_boxed_slice();
+      }
+    } else {
+      // the ram keeps the size of the board, a save of another size is cut or padded
+      let mut data = data;
+      data.resize(self.sram.len(), 0);
+      self.sram = data.into_boxed_slice();
+    }
+  }
+
+  fn flash_write(&mut self, op: FlashOp) {
+    match op {
+      // programming can only clear bits
+      FlashOp::Program(addr, val) => self.prg[wrap(addr, self.prg.len())] &= val,
+      FlashOp::EraseSector(addr) => {
+        let start = wrap(addr, self.prg.len()) & !(FLASH_SECTOR_SIZE-1);
+        self.prg[start..start+FLASH_SECTOR_SIZE].fill(0xFF);
+      }
+      FlashOp::EraseChip => self.prg.fill(0xFF),
+    }
   }
 
   pub fn prg_read(&mut self, addr: usize) -> u8 {
@@ -404,7 +844,8 @@ impl Cart {
       PrgTarget::SRam(enabled, mapped) => if enabled {
           self.sram_read(mapped)
         } else { 0xde }
-      PrgTarget::Prg(mapped) => self.prg[mapped],
+      PrgTarget::SRamProtected(mapped) => self.sram_read(mapped),
+      PrgTarget::Prg(mapped) => self.prg[wrap(mapped, self.prg.len())],
       _ => 0,
     }
   }
@@ -415,11 +856,41 @@ impl Cart {
       PrgTarget::SRam(enabled, mapped) => if enabled {
         self.sram_write(mapped, val);
       }
-      PrgTarget::Prg(_) => self.mapper.prg_write(&mut self.banks, addr, val),
+      PrgTarget::SRamProtected(_) => nen_log!(trace, "nen::cart", "write to protected sram at ${addr:04X} ignored"),
+      PrgTarget::Prg(mapped) => {
+        let val = if mapper::board_has_bus_conflicts(&self.header) {
+          val & self.prg[wrap(mapped, self.prg.len())]
+        } else { val };
+
+        self.mapper.prg_write(&mut self.banks, addr, val);
+        if let Some(op) = self.mapper.poll_flash() {
+          self.flash_write(op);
+        }
+      }
       _ => {}
     }
   }
 
+  // Reads prg and sram without going through the mapper registers, for debuggers
+  pub fn prg_peek(&mut self, addr: usize) -> u8 {
+    match self.mapper.map_prg_addr(&mut self.banks, addr) {
+      PrgTarget::SRam(_, mapped) | PrgTarget::SRamProtected(mapped) => self.sram.get(wrap(mapped, self.sram.len().max(1))).copied().unwrap_or(0),
+      PrgTarget::Prg(mapped) => self.prg[wrap(mapped, self.prg.len())],
+      PrgTarget::ExRam(val) => val,
+      PrgTarget::Cart => 0,
+    }
+  }
+
+  // Where an address of the cpu is mapped in the prg rom, if it is
+  pub fn prg_offset(&mut self, addr: usize) -> Option<usize> {
+    if addr < 0x4020 { return None; }
+
+    match self.mapper.map_prg_addr(&mut self.banks, addr) {
+      PrgTarget::Prg(mapped) => Some(wrap(mapped, self.prg.len())),
+      _ => None,
+    }
+  }
+
   pub fn cart_read(&mut self, addr: usize) -> u8 {
     self.mapper.cart_read(addr)
   }
@@ -427,19 +898,20 @@ impl Cart {
     self.mapper.cart_write(&mut self.banks, addr, val);
   }
 
+  // Boards with 2kb or 4kb of ram don't decode the upper address lines, so it is mirrored across $6000-$7FFF
   pub fn sram_read(&mut self, addr: usize) -> u8 {
-    self.sram[addr % self.sram.len()]
+    self.sram[wrap(addr, self.sram.len())]
   }
   pub fn sram_write(&mut self, addr: usize, val: u8) {
-    self.sram[addr % self.sram.len()] = val;
+    self.sram[wrap(addr, self.sram.len())] = val;
   }
 
   pub fn vram_read(&mut self, addr: usize) -> u8 {
     let target = self.mapper.map_ppu_addr(&mut self.banks, addr);
     match target {
       PpuTarget::CiRam(mapped) => self.ciram[mapped],
-      PpuTarget::Chr(mapped)   => self.chr[mapped],
-      _ => 0,
+      PpuTarget::Chr(mapped) | PpuTarget::ChrProtected(mapped) => self.chr[wrap(mapped, self.chr.len())],
+      PpuTarget::Value(val)    => val,
     }
   }
 
@@ -447,8 +919,9 @@ impl Cart {
     let target = self.mapper.map_ppu_addr(&mut self.banks, addr);
     match target {
       PpuTarget::CiRam(mapped) => self.ciram[mapped] = val,
-      PpuTarget::Chr(mapped)   => if self.header.uses_chr_ram { self.chr[mapped] = val; }
-      _ => {}
+      PpuTarget::Chr(mapped)   => if self.header.uses_chr_ram { self.chr[wrap(mapped, self.chr.len())] = val; }
+      PpuTarget::ChrProtected(_) => nen_log!(trace, "nen::cart", "write to protected chr at ${addr:04X} ignored"),
+      PpuTarget::Value(_) => {}
     }
   }
 }
@@ -471,4 +944,250 @@ mod cart_tests {
       println!()
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn reject_fds_images() {
+    let mut image = b"FDS\x1A\x01".to_vec();
+    image.resize(16 + 65500, 0);
+    assert!(matches!(Cart::new(&image), Err(EmuError::DiskSystemImage)));
+
+    let mut image = b"\x01*NINTENDO-HVC*".to_vec();
+    image.resize(65500, 0);
+    assert!(matches!(Cart::new(&image), Err(EmuError::DiskSystemImage)));
+  }
+
+  #[test]
+  fn region_from_file_names() {
+    let names = [
+      ("Super Mario Bros. (E) [!].nes", Some(ConsoleTiming::PAL)),
+      ("roms/(U)/Elite (Europe) (Rev A).nes", Some(ConsoleTiming::PAL)),
+      ("Kirby's Adventure (Germany, France).nes", Some(ConsoleTiming::PAL)),
+      ("Zelda (J).nes", Some(ConsoleTiming::NTSC)),
+      ("Mega Man (USA).nes", Some(ConsoleTiming::NTSC)),
+      ("Tetris (JU) [!].nes", Some(ConsoleTiming::NTSC)),
+      ("Tetris (UE).nes", None),
+      ("Battletoads (USA, Europe).nes", None),
+      ("Homebrew (PRG1).nes", None),
+      ("", None),
+    ];
+    for (name, region) in names {
+      assert_eq!(region_from_file_name(name), region, "{name}");
+    }
+  }
+
+  #[test]
+  fn repair_dirty_header() {
+    let mut rom = vec![0; HEADER_SIZE + PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = 1;
+    rom[5] = 2;
+    rom[6] = 0x10;
+    rom[7..16].copy_from_slice(b"DiskDude!");
+
+    let header = CartHeader::new(&rom).unwrap();
+    assert_eq!(header.mapper, 1);
+    assert_eq!(header.chr_size, CHR_ROM_PAGE_SIZE);
+    assert_eq!(header.diagnostics, vec![
+      HeaderDiagnostic::DirtyHeader("DiskDude!".to_string()),
+      HeaderDiagnostic::ChrSizeMismatch { declared: 2 * CHR_ROM_PAGE_SIZE, available: CHR_ROM_PAGE_SIZE },
+    ]);
+  }
+
+  #[test]
+  fn chr_ram_size() {
+    let mut rom = vec![0; HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = 2;
+    // UNROM 512 over iNes
+    rom[6] = 0xE0;
+    rom[7] = 0x10;
+    assert_eq!(CartHeader::new(&rom).unwrap().chr_real_size(), 32 * 1024);
+
+    // NROM over NES 2.0, declaring 16kb of chr ram
+    rom[6] = 0;
+    rom[7] = 0x08;
+    rom[11] = 8;
+    assert_eq!(CartHeader::new(&rom).unwrap().chr_real_size(), 16 * 1024);
+
+    // NES 2.0 without any chr ram declared
+    rom[11] = 0;
+    assert_eq!(CartHeader::new(&rom).unwrap().chr_real_size(), 8 * 1024);
+  }
+
+  #[test]
+  fn battery_inferred() {
+    let mut rom = vec![0; HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = 2;
+    // MMC1 with chr ram and no prg ram declared, as SGROM (Mega Man 2)
+    rom[6] = 0x10;
+    let header = CartHeader::new(&rom).unwrap();
+    assert!(!header.has_battery);
+    assert!(header.diagnostics.is_empty());
+
+    // 8kb of prg ram, as SNROM
+    rom[8] = 1;
+    let header = CartHeader::new(&rom).unwrap();
+    assert!(header.has_battery);
+    assert_eq!(header.diagnostics, vec![HeaderDiagnostic::BatteryInferred]);
+
+    // NES 2.0 with volatile prg ram only, as Metroid
+    rom[7] = 0x08;
+    rom[8] = 0;
+    rom[10] = 7;
+    assert!(!CartHeader::new(&rom).unwrap().has_battery);
+
+    // NES 2.0 with battery backed prg ram
+    rom[10] = 7 << 4;
+    assert!(CartHeader::new(&rom).unwrap().has_battery);
+  }
+
+  // Write protects its chr ram with bit 0 of any register write
+  #[derive(serde::Serialize)]
+  struct ChrProtectMapper { protected: bool }
+  codec_fields!(ChrProtectMapper { protected });
+
+  impl Mapper for ChrProtectMapper {
+    fn new(_: &CartHeader, _: &mut CartBanking) -> Box<Self> {
+      Box::new(Self { protected: false })
+    }
+
+    fn prg_write(&mut self, _: &mut CartBanking, _: usize, val: u8) {
+      self.protected = val & 1 != 0;
+    }
+
+    fn map_ppu_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PpuTarget {
+      match addr {
+        0x0000..=0x1FFF if self.protected => PpuTarget::ChrProtected(banks.chr.translate(addr)),
+        0x0000..=0x1FFF => PpuTarget::Chr(banks.chr.translate(addr)),
+        _ => PpuTarget::CiRam(banks.ciram.translate(addr)),
+      }
+    }
+  }
+
+  #[test]
+  fn chr_write_protect() {
+    let mut rom = vec![0; HEADER_SIZE + PRG_ROM_PAGE_SIZE];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = 1;
+    let mut cart = Cart::new(&rom).unwrap();
+    cart.mapper = ChrProtectMapper::new(&cart.header, &mut cart.banks);
+
+    cart.vram_write(0x0100, 0x42);
+    cart.prg_write(0x8000, 1);
+    cart.vram_write(0x0100, 0x99);
+    // still readable while protected, and the nametables aren't affected
+    assert_eq!(cart.vram_read(0x0100), 0x42);
+    cart.vram_write(0x2000, 0x99);
+    assert_eq!(cart.vram_read(0x2000), 0x99);
+
+    cart.prg_write(0x8000, 0);
+    cart.vram_write(0x0100, 0x99);
+    assert_eq!(cart.vram_read(0x0100), 0x99);
+  }
+
+  #[test]
+  fn sunsoft4_chr_nametables() {
+    let chr_size = 32 * CHR_ROM_PAGE_SIZE;
+    let mut rom = vec![0; HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE + chr_size];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = 2;
+    rom[5] = 32;
+    rom[6] = 0x40;
+    rom[7] = 0x40;
+    // every 1kb chr bank is filled with its own number
+    let chr_start = HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE;
+    for (i, bank) in rom[chr_start..].chunks_mut(1024).enumerate() {
+      bank.fill(i as u8);
+    }
+
+    let mut cart = Cart::new(&rom).unwrap();
+    cart.prg_write(0xC000, 0x05);
+    cart.prg_write(0xD000, 0x06);
+
+    // vertical mirroring, chr rom nametables
+    cart.prg_write(0xE000, 0x10);
+    assert_eq!(cart.vram_read(0x2000), 0x85);
+    assert_eq!(cart.vram_read(0x2400), 0x86);
+    assert_eq!(cart.vram_read(0x2800), 0x85);
+
+    // horizontal mirroring
+    cart.prg_write(0xE000, 0x11);
+    assert_eq!(cart.vram_read(0x2400), 0x85);
+    assert_eq!(cart.vram_read(0x2800), 0x86);
+
+    // back to ciram, which can't be overwritten by chr rom writes
+    cart.vram_write(0x2000, 0xAA);
+    cart.prg_write(0xE000, 0x01);
+    assert_eq!(cart.vram_read(0x2000), 0);
+  }
+
+  #[test]
+  fn truncated_rom() {
+    let mut rom = vec![0; HEADER_SIZE + PRG_ROM_PAGE_SIZE];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = 2;
+
+    assert_eq!(Cart::new(&rom).err(), Some(EmuError::Truncated {
+      expected: HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE, got: rom.len()
+    }));
+    assert!(Cart::new(&rom[..10]).is_err());
+  }
+
+  // A flashable board with a battery, its prg erased
+  fn flash_cart(mapper: u8, prg_banks: u8) -> Cart {
+    let mut rom = vec![0; HEADER_SIZE];
+    rom[0..4].copy_from_slice(&NES_MAGIC);
+    rom[4] = prg_banks;
+    rom[6] = (mapper << 4) | 0b10;
+    rom[7] = mapper & 0xF0;
+    rom.resize(HEADER_SIZE + prg_banks as usize * PRG_ROM_PAGE_SIZE, 0xFF);
+    Cart::new(&rom).unwrap()
+  }
+
+  #[test]
+  fn flash_writes() {
+    let mut cart = flash_cart(30, 32);
+    // programming only clears bits
+    cart.flash_write(FlashOp::Program(0x1234, 0xF0));
+    cart.flash_write(FlashOp::Program(0x1234, 0x3C));
+    assert_eq!(cart.prg[0x1234], 0x30);
+    // addresses past the chip wrap around
+    cart.flash_write(FlashOp::Program(cart.prg.len() + 0x0FFF, 0));
+    cart.flash_write(FlashOp::Program(0x2000, 0));
+
+    cart.flash_write(FlashOp::EraseSector(0x1234));
+    assert!(cart.prg[0x1000..0x2000].iter().all(|&b| b == 0xFF));
+    assert_eq!((cart.prg[0x0FFF], cart.prg[0x2000]), (0, 0));
+
+    cart.flash_write(FlashOp::EraseChip);
+    assert!(cart.prg.iter().all(|&b| b == 0xFF));
+  }
+
+  #[test]
+  fn flashed_prg_is_the_save() {
+    // UNROM 512 decodes the command addresses with the bank at $8000
+    let mut unrom512 = flash_cart(30, 32);
+    let writes = [(0xC000, 1), (0x9555, 0xAA), (0xC000, 0), (0xAAAA, 0x55), (0xC000, 1), (0x9555, 0xA0), (0xC000, 3), (0x8010, 0x42)];
+    for (addr, val) in writes { unrom512.prg_write(addr, val); }
+    assert_eq!(unrom512.prg[3 * PRG_ROM_PAGE_SIZE + 0x10], 0x42);
+
+    // GTROM maps the whole 32kb bank to the chip
+    let mut gtrom = flash_cart(111, 32);
+    let writes = [(0xD555, 0xAA), (0xAAAA, 0x55), (0xD555, 0xA0), (0x8123, 0x42)];
+    for (addr, val) in writes { gtrom.prg_write(addr, val); }
+    assert_eq!(gtrom.prg[0x123], 0x42);
+
+    for (mapper, flashed) in [(30, unrom512), (111, gtrom)] {
+      let save = flashed.get_sram().unwrap();
+      assert_eq!(save.len(), flashed.prg.len());
+
+      let mut cart = flash_cart(mapper, 32);
+      // a save of another size isn't for this game
+      cart.set_sram(vec![0; 8 * 1024]);
+      assert!(cart.prg.iter().all(|&b| b == 0xFF));
+      cart.set_sram(save);
+      assert_eq!(cart.prg, flashed.prg, "mapper {mapper}");
+    }
+  }
+}
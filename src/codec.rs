@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+
+// A compact binary encoding of the emulator state, for the fast savestates used by quick slots and rewind.
+// Fields are written one after the other in little endian, with no names, tags nor versioning:
+// unlike the json savestates, the layout changes with any field added, and only the same build can read it back.
+// Decoding happens in place, so whatever isn't part of the state (settings, the screen, the rom) is left as it is.
+pub trait NenCodec {
+  fn encode(&self, out: &mut Vec<u8>);
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String>;
+
+  // Sequences of values. Byte buffers (ram, chr ram) are the bulk of the state, and are copied in one go.
+  fn encode_slice(items: &[Self], out: &mut Vec<u8>) where Self: Sized {
+    items.iter().for_each(|val| val.encode(out));
+  }
+
+  fn decode_slice(items: &mut [Self], input: &mut Reader) -> Result<(), String> where Self: Sized {
+    items.iter_mut().try_for_each(|val| val.decode(input))
+  }
+}
+
+pub struct Reader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(bytes: &'a [u8]) -> Self {
+    Self { bytes }
+  }
+
+  pub fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+    if len > self.bytes.len() {
+      return Err("Savestate is corrupted: it is truncated".to_string());
+    }
+    let (taken, rest) = self.bytes.split_at(len);
+    self.bytes = rest;
+    Ok(taken)
+  }
+
+  pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+    Ok(self.take(N)?.try_into().unwrap())
+  }
+
+  // Lengths of sequences, checked against what is left, so that a corrupted one doesn't allocate gigabytes
+  pub fn take_len(&mut self) -> Result<usize, String> {
+    let len = u32::from_le_bytes(self.take_array()?) as usize;
+    if len > self.bytes.len() {
+      return Err(format!("Savestate is corrupted: a sequence of {len} elements is longer than the data left"));
+    }
+    Ok(len)
+  }
+
+  pub fn finish(self) -> Result<(), String> {
+    match self.bytes.len() {
+      0 => Ok(()),
+      len => Err(format!("Savestate is corrupted: {len} bytes are left over")),
+    }
+  }
+}
+
+// Encodes the given fields of a struct, in order.
+// Fields left out are kept as they are when decoding, as the ones marked #[serde(skip)].
+macro_rules! codec_fields {
+  ($ty:ty { $($field:ident),* $(,)? }) => {
+    impl $crate::codec::NenCodec for $ty {
+      fn encode(&self, _out: &mut Vec<u8>) {
+        $( $crate::codec::NenCodec::encode(&self.$field, _out); )*
+      }
+
+      fn decode(&mut self, _input: &mut $crate::codec::Reader) -> Result<(), String> {
+        $( $crate::codec::NenCodec::decode(&mut self.$field, _input)?; )*
+        Ok(())
+      }
+    }
+  };
+}
+pub(crate) use codec_fields;
+
+// Enums without data, encoded as the index of their variant
+macro_rules! codec_enum {
+  ($ty:ident { $($variant:ident),* $(,)? }) => {
+    impl $crate::codec::NenCodec for $ty {
+      fn encode(&self, out: &mut Vec<u8>) {
+        let variants = [$($ty::$variant),*];
+        let idx = variants.iter().position(|v| core::mem::discriminant(v) == core::mem::discriminant(self));
+        out.push(idx.unwrap() as u8);
+      }
+
+      fn decode(&mut self, input: &mut $crate::codec::Reader) -> Result<(), String> {
+        let idx = input.take_array::<1>()?[0];
+        let variants = [$($ty::$variant),*];
+        *self = variants.into_iter().nth(idx as usize)
+          .ok_or(format!("Savestate is corrupted: {idx} isn't a variant of {}", stringify!($ty)))?;
+        Ok(())
+      }
+    }
+  };
+}
+pub(crate) use codec_enum;
+
+// Bitflags, encoded as their bits
+macro_rules! codec_bits {
+  ($ty:ty) => {
+    impl $crate::codec::NenCodec for $ty {
+      fn encode(&self, out: &mut Vec<u8>) {
+        $crate::codec::NenCodec::encode(&self.bits(), out);
+      }
+
+      fn decode(&mut self, input: &mut $crate::codec::Reader) -> Result<(), String> {
+        let mut bits = self.bits();
+        $crate::codec::NenCodec::decode(&mut bits, input)?;
+        *self = <$ty>::from_bits_retain(bits);
+        Ok(())
+      }
+    }
+  };
+}
+pub(crate) use codec_bits;
+
+// Structs of bitfields, encoded as the integer holding them
+macro_rules! codec_bitfield {
+  ($ty:ty) => {
+    impl $crate::codec::NenCodec for $ty {
+      fn encode(&self, out: &mut Vec<u8>) {
+        $crate::codec::NenCodec::encode(&self.into_bits(), out);
+      }
+
+      fn decode(&mut self, input: &mut $crate::codec::Reader) -> Result<(), String> {
+        let mut bits = self.into_bits();
+        $crate::codec::NenCodec::decode(&mut bits, input)?;
+        *self = <$ty>::from_bits(bits);
+        Ok(())
+      }
+    }
+  };
+}
+pub(crate) use codec_bitfield;
+
+macro_rules! codec_le_bytes {
+  ($($ty:ty),*) => {$(
+    impl NenCodec for $ty {
+      fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+      }
+
+      fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+        *self = <$ty>::from_le_bytes(input.take_array()?);
+        Ok(())
+      }
+    }
+  )*};
+}
+codec_le_bytes!(u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl NenCodec for u8 {
+  fn encode(&self, out: &mut Vec<u8>) {
+    out.push(*self);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    *self = input.take_array::<1>()?[0];
+    Ok(())
+  }
+
+  fn encode_slice(items: &[Self], out: &mut Vec<u8>) {
+    out.extend_from_slice(items);
+  }
+
+  fn decode_slice(items: &mut [Self], input: &mut Reader) -> Result<(), String> {
+    items.copy_from_slice(input.take(items.len())?);
+    Ok(())
+  }
+}
+
+// sizes are always encoded as 64 bits, so that states don't depend on the platform
+impl NenCodec for usize {
+  fn encode(&self, out: &mut Vec<u8>) {
+    (*self as u64).encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let mut val = 0u64;
+    val.decode(input)?;
+    *self = usize::try_from(val).map_err(|_| format!("Savestate is corrupted: {val} doesn't fit in a usize"))?;
+    Ok(())
+  }
+}
+
+impl NenCodec for isize {
+  fn encode(&self, out: &mut Vec<u8>) {
+    (*self as i64).encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let mut val = 0i64;
+    val.decode(input)?;
+    *self = isize::try_from(val).map_err(|_| format!("Savestate is corrupted: {val} doesn't fit in an isize"))?;
+    Ok(())
+  }
+}
+
+impl NenCodec for bool {
+  fn encode(&self, out: &mut Vec<u8>) {
+    out.push(*self as u8);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let mut val = 0u8;
+    val.decode(input)?;
+    *self = val != 0;
+    Ok(())
+  }
+}
+
+impl NenCodec for () {
+  fn encode(&self, _out: &mut Vec<u8>) {}
+  fn decode(&mut self, _input: &mut Reader) -> Result<(), String> { Ok(()) }
+}
+
+impl<A: NenCodec, B: NenCodec> NenCodec for (A, B) {
+  fn encode(&self, out: &mut Vec<u8>) {
+    self.0.encode(out);
+    self.1.encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    self.0.decode(input)?;
+    self.1.decode(input)
+  }
+}
+
+impl<T: NenCodec + Default> NenCodec for Option<T> {
+  fn encode(&self, out: &mut Vec<u8>) {
+    match self {
+      Some(val) => { out.push(1); val.encode(out); }
+      None => out.push(0),
+    }
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let mut is_some = false;
+    is_some.decode(input)?;
+    if !is_some {
+      *self = None;
+      return Ok(());
+    }
+    self.get_or_insert_with(T::default).decode(input)
+  }
+}
+
+impl<T: NenCodec, const N: usize> NenCodec for [T; N] {
+  fn encode(&self, out: &mut Vec<u8>) {
+    T::encode_slice(self, out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    T::decode_slice(self, input)
+  }
+}
+
+// Sequences which can change length are prefixed with it
+impl<T: NenCodec + Default> NenCodec for Vec<T> {
+  fn encode(&self, out: &mut Vec<u8>) {
+    (self.len() as u32).encode(out);
+    T::encode_slice(self, out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let len = input.take_len()?;
+    self.resize_with(len, T::default);
+    T::decode_slice(self, input)
+  }
+}
+
+impl<T: NenCodec + Default> NenCodec for Box<[T]> {
+  fn encode(&self, out: &mut Vec<u8>) {
+    (self.len() as u32).encode(out);
+    T::encode_slice(self, out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let len = input.take_len()?;
+    if len != self.len() {
+      *self = (0..len).map(|_| T::default()).collect();
+    }
+    T::decode_slice(self, input)
+  }
+}
+
+impl<T: NenCodec + Default> NenCodec for VecDeque<T> {
+  fn encode(&self, out: &mut Vec<u8>) {
+    (self.len() as u32).encode(out);
+    let (front, back) = self.as_slices();
+    T::encode_slice(front, out);
+    T::encode_slice(back, out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let len = input.take_len()?;
+    self.resize_with(len, T::default);
+    T::decode_slice(self.make_contiguous(), input)
+  }
+}
@@ -0,0 +1,116 @@
+use crate::{apu::{FilterProfile, ResampleQuality, DEFAULT_SAMPLE_RATE}, cart::ConsoleTiming, frame::AspectMode, joypad::OppositeDirections, ppu::RenderAccuracy};
+
+// What the internal ram holds at power on. Real consoles leave it mostly random.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RamInit { #[default] Zeroes, Ones, Random }
+
+// Pixels hidden at every border of the picture, as most tvs did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Overscan {
+  pub top: u8,
+  pub bottom: u8,
+  pub left: u8,
+  pub right: u8,
+}
+
+// Emulation settings which don't come from the rom, and can be changed by the frontend.
+// Missing fields take their default, so frontends can persist it as a whole, and older files still load.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct EmuConfig {
+  // Runs the game as if on a console of this region, instead of the detected one (see Nes::region()).
+  // Only applied when booting.
+  pub region: Option<ConsoleTiming>,
+  // ANE ($8B) and LXA ($AB) mix the accumulator with a "magic constant", which differs between console units
+  // (0xEE, 0xFF and 0x00 are the common ones).
+  // Defaults to 0xFF, where ANE behaves as TXA + AND, and LXA as an immediate LAX.
+  pub unstable_magic: u8,
+  // Ramps the dmc output on $4011 writes, instead of jumping to the new level, which is heard as a pop
+  pub dmc_reduce_pops: bool,
+  // Stops the triangle when its period is set below 2, as the ultrasonic output pops on real mixers
+  pub mute_ultrasonic_triangle: bool,
+  pub filter_profile: FilterProfile,
+  // Rate of the samples handed to the frontend, from 8khz to 192khz
+  pub sample_rate: u32,
+  pub resample_quality: ResampleQuality,
+  // Volume of the cartridge expansion audio. When None, the level of the game's board is used.
+  pub expansion_gain: Option<f32>,
+  // Emulates oam decay while rendering is off, and oamaddr corruption when rendering starts
+  pub accurate_oam: bool,
+  // Drops sprites past the 8th on a scanline, like the real ppu. Off by default, as it causes flicker.
+  // The game's compatibility override, if any, has the last word.
+  pub sprite_limit: bool,
+  // Dot is slower, but needed by the games which rely on the exact timing of the sprite evaluation and fetches
+  pub render_accuracy: RenderAccuracy,
+  // Overclocking, as Mesen does it: scanlines where only the cpu runs, while the ppu and the apu wait.
+  // The ones before the nmi give the game logic more time, which reduces slowdown; the ones after lengthen vblank.
+  // Games which count cycles against the ppu can break, 0 and 0 is the real console.
+  pub overclock_pre_nmi: u16,
+  pub overclock_post_nmi: u16,
+  // Filters Left+Right and Up+Down pressed together, on both controllers and macros
+  pub opposite_directions: OppositeDirections,
+  // Seed of the emulator's random numbers. Runs with the same seed and inputs are identical.
+  pub rng_seed: u64,
+  // Only applied when booting. Random values are useful to catch homebrew reading uninitialized memory.
+  pub ram_init: RamInit,
+
+  // Presentation settings, the core doesn't use them.
+  // They are kept here so that the whole configuration is in one place.
+  pub overscan: Overscan,
+  pub aspect_mode: AspectMode,
+  // Presses per second of the turbo buttons
+  pub turbo_rate: u8,
+}
+
+impl Default for EmuConfig {
+  fn default() -> Self {
+    Self {
+      region: None,
+      unstable_magic: 0xFF,
+      dmc_reduce_pops: true,
+      mute_ultrasonic_triangle: true,
+      filter_profile: FilterProfile::Nes,
+      sample_rate: DEFAULT_SAMPLE_RATE,
+      resample_quality: ResampleQuality::Linear,
+      expansion_gain: None,
+      accurate_oam: false,
+      sprite_limit: false,
+      render_accuracy: RenderAccuracy::Scanline,
+      overclock_pre_nmi: 0,
+      overclock_post_nmi: 0,
+      opposite_directions: OppositeDirections::Allow,
+      rng_seed: 0,
+      ram_init: RamInit::Zeroes,
+      overscan: Overscan::default(),
+      aspect_mode: AspectMode::Correct,
+      turbo_rate: 15,
+    }
+  }
+}
+
+impl EmuConfig {
+  pub fn from_json(json: &str) -> Result<Self, String> {
+    serde_json::from_str(json).map_err(|e| format!("Invalid config: {e}"))
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).expect("config should always be serializable")
+  }
+}
+
+#[cfg(test)]
+mod config_tests {
+  use super::*;
+
+  #[test]
+  fn partial_config() {
+    let config = EmuConfig::from_json(r#"{ "sprite_limit": true, "overscan": { "top": 8, "bottom": 8 } }"#).unwrap();
+    assert!(config.sprite_limit);
+    assert_eq!(config.overscan, Overscan { top: 8, bottom: 8, left: 0, right: 0 });
+    assert_eq!(config.unstable_magic, EmuConfig::default().unstable_magic);
+
+    assert_eq!(EmuConfig::from_json(&config.to_json()), Ok(config));
+    assert!(EmuConfig::from_json("42").is_err());
+  }
+}
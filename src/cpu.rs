@@ -2,7 +2,7 @@ use core::{cell::OnceCell, fmt, ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr}};
 
 use bitflags::bitflags;
 
-use crate::{bus::Bus, cart::Cart, instr::{AddressingMode, Instruction, INSTRUCTIONS, RMW_INSTRS}, mem::{Memory, Ram64Kb}};
+use crate::{bus::Bus, cart::Cart, codec::{codec_bits, NenCodec, Reader}, instr::{AddressingMode, Instruction, INSTRUCTIONS, RMW_INSTRS}, mem::{Memory, Ram64Kb}};
 
 bitflags! {
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
@@ -18,6 +18,7 @@ bitflags! {
     const negative  = 0b1000_0000;
   }
 }
+codec_bits!(CpuFlags);
 
 // https://www.nesdev.org/wiki/CPU_ALL
 pub const STACK_START: usize = 0x0100;
@@ -44,9 +45,63 @@ pub struct Cpu<M: Memory> {
   pub y: u8,
   pub cycles: usize,
   pub jammed: bool,
+  // see EmuConfig
+  #[serde(default)]
+  pub unstable_magic: u8,
+  // The 2A03 has no decimal mode, this is only useful when running the core as a generic 6502
+  #[serde(default)]
+  pub decimal_enabled: bool,
+  // External interrupt lines, for when the memory doesn't drive them through nmi_poll() and irq_poll()
+  #[serde(default)]
+  nmi_pending: bool,
+  #[serde(default)]
+  irq_line: bool,
+  // the interrupt flag as seen by the last irq poll, see step()
+  #[serde(default = "irq_poll_inhibited")]
+  irq_poll_inhibit: bool,
   pub bus: M,
 }
 
+// Savestates older than the field resume as after a reset, with no irq polled before the next instruction
+fn irq_poll_inhibited() -> bool { true }
+
+// the generic cpu is saved along with its memory
+impl<M: Memory + NenCodec> NenCodec for Cpu<M> {
+  fn encode(&self, out: &mut Vec<u8>) {
+    self.pc.encode(out);
+    self.sp.encode(out);
+    self.p.encode(out);
+    self.a.encode(out);
+    self.x.encode(out);
+    self.y.encode(out);
+    self.cycles.encode(out);
+    self.jammed.encode(out);
+    self.unstable_magic.encode(out);
+    self.decimal_enabled.encode(out);
+    self.nmi_pending.encode(out);
+    self.irq_line.encode(out);
+    self.irq_poll_inhibit.encode(out);
+    self.bus.encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    self.pc.decode(input)?;
+    self.sp.decode(input)?;
+    self.p.decode(input)?;
+    self.a.decode(input)?;
+    self.x.decode(input)?;
+    self.y.decode(input)?;
+    self.cycles.decode(input)?;
+    self.jammed.decode(input)?;
+    self.unstable_magic.decode(input)?;
+    self.decimal_enabled.decode(input)?;
+    self.nmi_pending.decode(input)?;
+    self.irq_line.decode(input)?;
+    self.irq_poll_inhibit.decode(input)?;
+    self.bus.decode(input)
+  }
+}
+
 impl<M: Memory> Memory for Cpu<M> {
   fn read(&mut self, addr: u16) -> u8 {
     let res = self.bus.read(addr);
@@ -72,8 +127,9 @@ impl<M: Memory> fmt::Debug for Cpu<M> {
 }
 
 
-impl Cpu<Ram64Kb> {
-  pub fn with_ram64kb() -> Self {
+// The core only needs a Memory implementation, so it can be used outside of the NES as a generic 6502.
+impl<M: Memory> Cpu<M> {
+  pub fn with_memory(bus: M) -> Self {
     Self {
       pc: PC_RESET,
       sp: SP_RESET,
@@ -82,23 +138,35 @@ impl Cpu<Ram64Kb> {
       p: P_RESET,
       cycles: 0,
       jammed: false,
-      bus: Ram64Kb { mem: [0; 64 * 1024] },
+      unstable_magic: 0xFF,
+      decimal_enabled: false,
+      nmi_pending: false,
+      irq_line: false,
+      irq_poll_inhibit: true,
+      bus,
     }
   }
+
+  // Nmi is edge triggered: it is serviced once, before the next instruction
+  pub fn set_nmi(&mut self) {
+    self.nmi_pending = true;
+  }
+
+  // Irq is level triggered: it is serviced as long as the line is held and interrupts are enabled
+  pub fn set_irq(&mut self, active: bool) {
+    self.irq_line = active;
+  }
+}
+
+impl Cpu<Ram64Kb> {
+  pub fn with_ram64kb() -> Self {
+    Self::with_memory(Ram64Kb::new())
+  }
 }
 
 impl Cpu<Bus> {
   pub fn with_cart(cart: Cart) -> Self {
-    let mut cpu = Self {
-      pc: PC_RESET,
-      sp: SP_RESET,
-      a: 0, x: 0, y: 0,
-      // At boot, only interrupt flag is enabled
-      p: P_RESET,
-      cycles: 0,
-      jammed: false,
-      bus: Bus::new(cart),
-    };
+    let mut cpu = Self::with_memory(Bus::new(cart));
 
     // boot only if cart contains prg
     if !cpu.bus.cart.as_mut().prg.is_empty() {
@@ -111,9 +179,20 @@ impl Cpu<Bus> {
 
 impl<M: Memory> Cpu<M> {
   pub fn reset(&mut self) {
+    // Reset runs the interrupt sequence with the stack writes turned into reads, 7 cycles in total
+    // https://www.nesdev.org/wiki/CPU_interrupts
+    self.tick();
+    self.tick();
+    for _ in 0..3 {
+      self.read(self.sp_addr());
+      self.sp = self.sp.wrapping_sub(1);
+    }
+
+    self.p.insert(CpuFlags::irq_off);
     self.pc = self.read16(PC_RESET);
-    self.sp = self.sp.wrapping_sub(3);
-    self.p = self.p | CpuFlags::irq_off;
+    self.nmi_pending = false;
+    self.irq_poll_inhibit = true;
+    self.jammed = false;
   }
 
   fn set_carry(&mut self, res: u16) {
@@ -229,6 +308,11 @@ enum InstrDst {
 impl<M: Memory> Cpu<M> {
   pub fn step(&mut self) {
     if self.bus.handle_dma() { return; }
+    // a jammed cpu doesn't execute anymore, but the rest of the system keeps going until a reset
+    if self.jammed {
+      self.tick();
+      return;
+    }
 
     self.interrupts_poll();
     
@@ -236,17 +320,38 @@ impl<M: Memory> Cpu<M> {
     let instr = &INSTRUCTIONS[opcode as usize];
     let mut op = self.get_operand_with_addressing(instr);
     
+    let irq_off = self.p.contains(CpuFlags::irq_off);
     self.execute(opcode, &mut op);
     // self.cycles += instr.cycles;
+
+    // Irqs are polled before the last cycle of an instruction, but CLI, SEI and PLP change the interrupt flag
+    // in their last cycle, so the change is only seen after the next instruction.
+    // https://www.nesdev.org/wiki/CPU_interrupts#Delayed_IRQ_response_after_CLI,_SEI,_and_PLP
+    self.irq_poll_inhibit = match opcode {
+      0x58 | 0x78 | 0x28 => irq_off,
+      _ => self.p.contains(CpuFlags::irq_off),
+    };
+  }
+
+  fn nmi_poll(&mut self) -> bool {
+    self.bus.nmi_poll() || core::mem::take(&mut self.nmi_pending)
   }
 
   fn interrupts_poll(&mut self) {
-    if self.bus.nmi_poll() {
+    if self.nmi_poll() {
       self.handle_interrupt(NMI_ISR);
-    } else if self.bus.irq_poll() && !self.p.contains(CpuFlags::irq_off) {
+    } else if (self.bus.irq_poll() || self.irq_line) && !self.irq_poll_inhibit {
       self.handle_interrupt(IRQ_ISR);
     }
   }
+
+  // An nmi happening while an irq or brk is pushing the return address hijacks the vector fetch
+  // https://www.nesdev.org/wiki/CPU_interrupts#Interrupt_hijacking
+  fn interrupt_vector(&mut self, isr_addr: u16) -> u16 {
+    if isr_addr == IRQ_ISR && self.nmi_poll() {
+      NMI_ISR
+    } else { isr_addr }
+  }
   
   fn handle_interrupt(&mut self, isr_addr: u16) {
     // https://www.nesdev.org/wiki/CPU_interrupts
@@ -254,7 +359,9 @@ impl<M: Memory> Cpu<M> {
     self.tick();
 
     self.stack_push16(self.pc);
-    let pushable = self.p.clone().union(CpuFlags::brkpush);
+    let isr_addr = self.interrupt_vector(isr_addr);
+    // hardware interrupts push the brk flag as 0
+    let pushable = self.p.clone().union(CpuFlags::unused).difference(CpuFlags::brk);
     self.stack_push(pushable.bits());
     self.p.insert(CpuFlags::irq_off);
     self.pc = self.read16(isr_addr);
@@ -468,13 +575,56 @@ impl<M: Memory> Cpu<M> {
     self.a = res as u8;
   }
 
+  fn in_decimal_mode(&self) -> bool {
+    self.decimal_enabled && self.p.contains(CpuFlags::decimal)
+  }
+
+  // http://www.6502.org/tutorials/decimal_mode.html#A
+  fn decimal_addition(&mut self, val: u8) {
+    let (a, val, carry) = (self.a as u16, val as u16, self.carry() as u16);
+
+    let mut low = (a & 0x0F) + (val & 0x0F) + carry;
+    if low >= 0x0A { low = ((low + 0x06) & 0x0F) + 0x10; }
+    let mut res = (a & 0xF0) + (val & 0xF0) + low;
+
+    // nmos 6502 takes the zero flag from the binary result, and negative and overflow before the high nibble adjust
+    self.set_zero((a + val + carry) as u8);
+    self.set_neg(res as u8);
+    self.set_overflow(a, val, res);
+
+    if res >= 0xA0 { res += 0x60; }
+    self.set_carry(res);
+    self.a = res as u8;
+  }
+
+  fn decimal_subtraction(&mut self, val: u8) {
+    let (a, val, carry) = (self.a as i16, val as i16, self.carry() as i16);
+
+    let mut low = (a & 0x0F) - (val & 0x0F) + carry - 1;
+    if low < 0 { low = ((low - 0x06) & 0x0F) - 0x10; }
+    let mut res = (a & 0xF0) - (val & 0xF0) + low;
+    if res < 0 { res -= 0x60; }
+
+    // flags are the same as binary mode
+    self.addition((val as u8).not());
+    self.a = res as u8;
+  }
+
   fn adc(&mut self, op: &mut Operand) {
     let val = self.get_operand_value(op);
-    self.addition(val);
+    if self.in_decimal_mode() {
+      self.decimal_addition(val);
+    } else {
+      self.addition(val);
+    }
   }
   fn sbc(&mut self, op: &mut Operand) {
     let val = self.get_operand_value(op);
-    self.addition(val.not());
+    if self.in_decimal_mode() {
+      self.decimal_subtraction(val);
+    } else {
+      self.addition(val.not());
+    }
   }
 
   fn compare(&mut self, reg: u8, op: &mut Operand) {
@@ -654,9 +804,10 @@ impl<M: Memory> Cpu<M> {
 
   fn brk(&mut self, op: &mut Operand) {
     self.stack_push16(self.pc.wrapping_add(1));
+    let isr_addr = self.interrupt_vector(IRQ_ISR);
     self.php(op);
     self.p.insert(CpuFlags::irq_off);
-    self.pc = self.read16(IRQ_ISR);
+    self.pc = self.read16(isr_addr);
   }
 
   fn rti(&mut self, op: &mut Operand) {
@@ -788,13 +939,14 @@ impl<M: Memory> Cpu<M> {
 
   // also called XAA
   fn ane(&mut self, op: &mut Operand) {
-    self.txa(op);
-    self.and(op);
+    let val = self.get_operand_value(op);
+    self.a = (self.a | self.unstable_magic) & self.x & val;
+    self.set_zn(self.a);
   }
 
   // also called LAXI
   fn lxa(&mut self, op: &mut Operand) {
-    let val = self.get_operand_value(op);
+    let val = (self.a | self.unstable_magic) & self.get_operand_value(op);
     self.set_zn(val);
     self.a = val;
     self.x = val;
@@ -802,8 +954,8 @@ impl<M: Memory> Cpu<M> {
 
   // also called KIL, HLT
   fn jam(&mut self, _: &mut Operand) {
+    nen_log!(warn, "nen::cpu", "cpu jammed at {:04X}", self.pc);
     self.jammed = true;
-    panic!("System jammed! (reached JAM instruction)")
   }
 }
 
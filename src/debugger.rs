@@ -0,0 +1,5 @@
+// Tools to debug games (mostly homebrew) running in the emulator
+pub mod symbols;
+pub mod disasm;
+pub mod breakpoints;
+pub mod session;
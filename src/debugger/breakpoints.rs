@@ -0,0 +1,84 @@
+use crate::{instr::{AddressingMode, INSTRUCTIONS}, nes::Nes};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BreakKind {
+  // the cpu is about to execute an instruction in the range
+  Exec,
+  // watchpoints, the instruction about to be executed accesses memory in the range
+  Read,
+  Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Breakpoint {
+  pub kind: BreakKind,
+  // inclusive range of cpu addresses
+  pub start: u16,
+  pub end: u16,
+  pub enabled: bool,
+}
+
+impl Breakpoint {
+  pub fn new(kind: BreakKind, addr: u16) -> Self {
+    Self { kind, start: addr, end: addr, enabled: true }
+  }
+
+  pub fn range(kind: BreakKind, start: u16, end: u16) -> Self {
+    Self { kind, start, end, enabled: true }
+  }
+
+  fn contains(&self, addr: u16) -> bool {
+    (self.start..=self.end).contains(&addr)
+  }
+}
+
+const WRITE_INSTRS: [&str; 8] = ["STA", "STX", "STY", "SAX", "SHA", "SHX", "SHY", "TAS"];
+const RMW_INSTRS: [&str; 12] = ["ASL", "LSR", "ROL", "ROR", "INC", "DEC", "SLO", "SRE", "RLA", "RRA", "ISC", "DCP"];
+
+// The memory the instruction at pc is about to access, as (address, reads, writes)
+pub fn next_access(nes: &mut Nes) -> Option<(u16, bool, bool)> {
+  let cpu = nes.get_cpu();
+  let (pc, x, y) = (cpu.pc, cpu.x, cpu.y);
+  let instr = &INSTRUCTIONS[nes.peek(pc) as usize];
+  let byte = nes.peek(pc.wrapping_add(1));
+  let word = u16::from_le_bytes([byte, nes.peek(pc.wrapping_add(2))]);
+
+  use AddressingMode::*;
+  let addr = match instr.addressing {
+    ZeroPage => byte as u16,
+    ZeroPageX => byte.wrapping_add(x) as u16,
+    ZeroPageY => byte.wrapping_add(y) as u16,
+    Absolute => word,
+    AbsoluteX => word.wrapping_add(x as u16),
+    AbsoluteY => word.wrapping_add(y as u16),
+    IndirectX => {
+      let ptr = byte.wrapping_add(x);
+      u16::from_le_bytes([nes.peek(ptr as u16), nes.peek(ptr.wrapping_add(1) as u16)])
+    }
+    IndirectY => {
+      let base = u16::from_le_bytes([nes.peek(byte as u16), nes.peek(byte.wrapping_add(1) as u16)]);
+      base.wrapping_add(y as u16)
+    }
+    _ => return None,
+  };
+
+  match instr.name {
+    "JMP" | "JSR" => None,
+    name if WRITE_INSTRS.contains(&name) => Some((addr, false, true)),
+    name if RMW_INSTRS.contains(&name) => Some((addr, true, true)),
+    _ => Some((addr, true, false)),
+  }
+}
+
+// The index of the first enabled breakpoint hit by the instruction about to be executed
+pub fn check(breakpoints: &[Breakpoint], nes: &mut Nes) -> Option<usize> {
+  let pc = nes.get_cpu().pc;
+  let access = next_access(nes);
+
+  breakpoints.iter().position(|bp| bp.enabled && match (bp.kind, access) {
+    (BreakKind::Exec, _) => bp.contains(pc),
+    (BreakKind::Read, Some((addr, true, _))) => bp.contains(addr),
+    (BreakKind::Write, Some((addr, _, true))) => bp.contains(addr),
+    _ => false,
+  })
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use crate::{instr::{AddressingMode, INSTRUCTIONS}, nes::Nes};
+use super::symbols::SymbolTable;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+  pub addr: u16,
+  pub bytes: Vec<u8>,
+  pub mnemonic: &'static str,
+  pub operand: String,
+  // symbol of the instruction address
+  pub label: Option<String>,
+  pub comment: Option<String>,
+}
+
+impl fmt::Display for DisasmLine {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let bytes = self.bytes.iter()
+      .map(|b| format!("{b:02X}"))
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    let label = self.label.as_ref().map(|l| format!("{l}:")).unwrap_or_default();
+    write!(f, "{:04X}  {bytes:<8}  {label:<16} {} {}", self.addr, self.mnemonic, self.operand)?;
+    if let Some(comment) = &self.comment {
+      write!(f, " ; {}", comment.replace('\n', " "))?;
+    }
+    Ok(())
+  }
+}
+
+fn symbol_name(nes: &mut Nes, symbols: &SymbolTable, addr: u16) -> Option<String> {
+  let prg_offset = nes.get_cart().prg_offset(addr as usize);
+  symbols.lookup(addr, prg_offset).map(|s| s.name.clone())
+}
+
+// Disassembles the instruction at addr, as currently mapped.
+// Addresses with a symbol are shown with their name.
+pub fn disassemble(nes: &mut Nes, addr: u16, symbols: &SymbolTable) -> DisasmLine {
+  let opcode = nes.peek(addr);
+  let instr = &INSTRUCTIONS[opcode as usize];
+  let bytes: Vec<u8> = (0..instr.bytes as u16)
+    .map(|i| nes.peek(addr.wrapping_add(i)))
+    .collect();
+
+  let byte = bytes.get(1).copied().unwrap_or(0);
+  let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or(0)]);
+  let mut name_or_hex = |target: u16, zero_page: bool| {
+    symbol_name(nes, symbols, target).unwrap_or_else(|| {
+      if zero_page { format!("${target:02X}") } else { format!("${target:04X}") }
+    })
+  };
+
+  use AddressingMode::*;
+  let operand = match instr.addressing {
+    Implicit => String::new(),
+    Accumulator => "A".to_string(),
+    Immediate => format!("#${byte:02X}"),
+    ZeroPage => name_or_hex(byte as u16, true),
+    ZeroPageX => format!("{},X", name_or_hex(byte as u16, true)),
+    ZeroPageY => format!("{},Y", name_or_hex(byte as u16, true)),
+    Relative => {
+      let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+      name_or_hex(target, false)
+    }
+    Absolute => name_or_hex(word, false),
+    AbsoluteX => format!("{},X", name_or_hex(word, false)),
+    AbsoluteY => format!("{},Y", name_or_hex(word, false)),
+    Indirect => format!("({})", name_or_hex(word, false)),
+    IndirectX => format!("({},X)", name_or_hex(byte as u16, true)),
+    IndirectY => format!("({}),Y", name_or_hex(byte as u16, true)),
+  };
+
+  let prg_offset = nes.get_cart().prg_offset(addr as usize);
+  let symbol = symbols.lookup(addr, prg_offset);
+
+  DisasmLine {
+    addr,
+    bytes,
+    mnemonic: instr.name,
+    operand,
+    label: symbol.map(|s| s.name.clone()),
+    comment: symbol.and_then(|s| s.comment.clone()),
+  }
+}
+
+// Disassembles `count` instructions, starting from addr
+pub fn disassemble_range(nes: &mut Nes, addr: u16, count: usize, symbols: &SymbolTable) -> Vec<DisasmLine> {
+  let mut addr = addr;
+  (0..count).map(|_| {
+    let line = disassemble(nes, addr, symbols);
+    addr = addr.wrapping_add(line.bytes.len() as u16);
+    line
+  }).collect()
+}
+
+// The instruction about to be executed, along with the cpu registers and the ppu position
+pub fn trace_line(nes: &mut Nes, symbols: &SymbolTable) -> String {
+  let pc = nes.get_cpu().pc;
+  let line = disassemble(nes, pc, symbols);
+  let cpu = nes.get_cpu();
+  let cpu = format!("A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", cpu.a, cpu.x, cpu.y, cpu.p.bits(), cpu.sp);
+  let (_, scanline, dot) = nes.ppu_position();
+  format!("{:<60} {cpu} PPU:{scanline:3},{dot:3}", line.to_string())
+}
+
+#[cfg(test)]
+mod disasm_tests {
+  use crate::debugger::symbols::{Symbol, SymbolAddr};
+  use super::*;
+
+  #[test]
+  fn disassemble_with_symbols() {
+    let code = [
+      0xA9, 0x05,         // lda #5
+      0x8D, 0x00, 0x03,   // sta $0300
+      0xB5, 0x10,         // lda $10,x
+      0xD0, 0xF7,         // bne $c000
+      0x6C, 0xFC, 0xFF,   // jmp ($fffc)
+    ];
+    let mut rom = vec![0; 16 + 16 * 1024 + 8 * 1024];
+    rom[0..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+    rom[16..16 + code.len()].copy_from_slice(&code);
+    let mut nes = Nes::boot_from_bytes(&rom).unwrap();
+
+    let mut symbols = SymbolTable::new();
+    symbols.insert(SymbolAddr::Prg(0), Symbol { name: "reset".to_string(), comment: Some("entry".to_string()) });
+    symbols.insert(SymbolAddr::Cpu(0x300), Symbol { name: "oam_buf".to_string(), comment: None });
+
+    let lines: Vec<_> = disassemble_range(&mut nes, 0xC000, 5, &symbols).iter()
+      .map(|line| format!("{} {}", line.mnemonic, line.operand))
+      .collect();
+    assert_eq!(lines, ["LDA #$05", "STA oam_buf", "LDA $10,X", "BNE reset", "JMP ($FFFC)"]);
+
+    let line = disassemble(&mut nes, 0xC000, &symbols);
+    assert_eq!(line.bytes, [0xA9, 0x05]);
+    assert_eq!(line.to_string(), "C000  A9 05     reset:           LDA #$05 ; entry");
+  }
+}
@@ -0,0 +1,93 @@
+use crate::nes::{Nes, StepOutcome};
+use super::{breakpoints::{self, Breakpoint}, symbols::SymbolTable};
+
+// Everything the user set up while debugging a game, so it can be saved and restored in a later session.
+// Sessions are bound to the rom they were made for, by its hash.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DebugSession {
+  pub rom_hash: u64,
+  pub breakpoints: Vec<Breakpoint>,
+  // loaded labels, along with the user's own labels and comments
+  pub symbols: SymbolTable,
+}
+
+impl DebugSession {
+  pub fn new(nes: &Nes) -> Self {
+    Self { rom_hash: nes.get_cart_header().rom_hash, ..Default::default() }
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).expect("debug session should always be serializable")
+  }
+
+  pub fn from_json(json: &str, nes: &Nes) -> Result<Self, String> {
+    let session: Self = serde_json::from_str(json)
+      .map_err(|e| format!("Debug session is corrupted: {e}"))?;
+
+    if session.rom_hash != nes.get_cart_header().rom_hash {
+      return Err("Debug session is for a different game".to_string());
+    }
+    Ok(session)
+  }
+
+  // Runs until the next vblank, or until a breakpoint is hit. Returns the index of the breakpoint hit, if any.
+  // The instruction at the current pc is executed without being checked, so that execution can resume from a breakpoint.
+  pub fn run_until_break(&self, nes: &mut Nes) -> Option<usize> {
+    loop {
+      let pc = nes.get_cpu().pc;
+      if nes.step() == StepOutcome::Jammed { return None; }
+
+      // dma cycles don't move the pc, a breakpoint is only hit once per instruction
+      if nes.get_cpu().pc != pc {
+        if let Some(hit) = breakpoints::check(&self.breakpoints, nes) { return Some(hit); }
+      }
+      if nes.get_bus().poll_vblank() { return None; }
+    }
+  }
+}
+
+#[cfg(test)]
+mod session_tests {
+  use crate::debugger::{breakpoints::BreakKind, symbols::{Symbol, SymbolAddr}};
+  use super::*;
+
+  fn build_rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 16 + 16 * 1024 + 8 * 1024];
+    rom[0..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+    rom[16..16 + code.len()].copy_from_slice(code);
+    rom[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&0xC000u16.to_le_bytes());
+    rom
+  }
+
+  #[test]
+  fn breakpoints_and_persistence() {
+    let code = [
+      0xA2, 0x00,         // C000: ldx #0
+      0xE8,               // C002: inx
+      0x8E, 0x00, 0x03,   // C003: stx $0300
+      0xAD, 0x00, 0x03,   // C006: lda $0300
+      0x4C, 0x02, 0xC0,   // C009: jmp $c002
+    ];
+    let mut nes = Nes::boot_from_bytes(&build_rom(&code)).unwrap();
+
+    let mut session = DebugSession::new(&nes);
+    session.breakpoints.push(Breakpoint::new(BreakKind::Read, 0x0300));
+    session.breakpoints.push(Breakpoint::range(BreakKind::Write, 0x0200, 0x03FF));
+    session.breakpoints.push(Breakpoint::new(BreakKind::Exec, 0xC009));
+    session.symbols.insert(SymbolAddr::Cpu(0x300), Symbol { name: "counter".to_string(), comment: None });
+
+    assert_eq!(session.run_until_break(&mut nes), Some(1));
+    assert_eq!(nes.get_cpu().pc, 0xC003);
+    assert_eq!(session.run_until_break(&mut nes), Some(0));
+    assert_eq!(session.run_until_break(&mut nes), Some(2));
+    session.breakpoints[1].enabled = false;
+    assert_eq!(session.run_until_break(&mut nes), Some(0));
+    assert_eq!(nes.get_cpu().x, 2);
+
+    let json = session.to_json();
+    assert_eq!(DebugSession::from_json(&json, &nes), Ok(session));
+
+    let other = Nes::boot_from_bytes(&build_rom(&[0xEA])).unwrap();
+    assert!(DebugSession::from_json(&json, &other).is_err());
+  }
+}
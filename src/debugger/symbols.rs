@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+// Where a symbol lives. Labels in prg rom and sram are bound to the bank they are in,
+// so they are only shown when that bank is mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+pub enum SymbolAddr {
+  Cpu(u16),
+  Prg(usize),
+  SRam(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Symbol {
+  pub name: String,
+  pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+  symbols: HashMap<SymbolAddr, Symbol>,
+}
+
+// json maps can only have string keys, so the table is stored as a list
+impl serde::Serialize for SymbolTable {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: serde::Serializer {
+    let mut symbols: Vec<_> = self.symbols.iter().collect();
+    symbols.sort_by_key(|(addr, _)| **addr);
+    serializer.collect_seq(symbols)
+  }
+}
+
+impl<'de> serde::Deserialize<'de> for SymbolTable {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: serde::Deserializer<'de> {
+    let symbols = Vec::<(SymbolAddr, Symbol)>::deserialize(deserializer)?;
+    Ok(Self { symbols: symbols.into_iter().collect() })
+  }
+}
+
+impl SymbolTable {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn insert(&mut self, addr: SymbolAddr, symbol: Symbol) {
+    self.symbols.insert(addr, symbol);
+  }
+
+  pub fn get(&self, addr: SymbolAddr) -> Option<&Symbol> {
+    self.symbols.get(&addr)
+  }
+
+  pub fn len(&self) -> usize {
+    self.symbols.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.symbols.is_empty()
+  }
+
+  // Loads a symbol file, picking the format from its name.
+  // Returns how many symbols were loaded.
+  pub fn load(&mut self, file_name: &str, text: &str) -> Result<usize, String> {
+    let lower = file_name.to_lowercase();
+
+    if lower.ends_with(".dbg") {
+      Ok(self.load_ca65_dbg(text))
+    } else if lower.ends_with(".mlb") {
+      Ok(self.load_mesen_mlb(text))
+    } else if lower.ends_with(".ram.nl") {
+      Ok(self.load_fceux_nl(text, None))
+    } else if lower.ends_with(".nl") {
+      // bank files are named game.nes.<bank in hex>.nl
+      let bank = lower.trim_end_matches(".nl").rsplit('.').next()
+        .and_then(|bank| usize::from_str_radix(bank, 16).ok());
+      Ok(self.load_fceux_nl(text, bank))
+    } else {
+      Err(format!("Unknown symbol file format: {file_name}"))
+    }
+  }
+
+  // FCEUX name lists, lines are "$C000#label#comment".
+  // Bank files hold the labels of a 16kb prg bank, the ram file has no bank.
+  // https://fceux.com/web/help/NLFilesFormat.html
+  pub fn load_fceux_nl(&mut self, text: &str, bank: Option<usize>) -> usize {
+    let mut count = 0;
+
+    for line in text.lines() {
+      let mut fields = line.splitn(3, '#');
+      let (Some(addr), Some(name)) = (fields.next(), fields.next()) else { continue; };
+      let Some(addr) = addr.strip_prefix('$')
+        .and_then(|addr| u16::from_str_radix(addr.trim(), 16).ok()) else { continue; };
+      let comment = fields.next().map(str::trim).filter(|c| !c.is_empty()).map(String::from);
+      if name.is_empty() && comment.is_none() { continue; }
+
+      let addr = match bank {
+        Some(bank) if addr >= 0x8000 => SymbolAddr::Prg(bank * 0x4000 + (addr as usize & 0x3FFF)),
+        _ => SymbolAddr::Cpu(addr),
+      };
+      self.insert(addr, Symbol { name: name.to_string(), comment });
+      count += 1;
+    }
+
+    count
+  }
+
+  // Mesen label files, lines are "P:0123:label:comment", where the address can also be a range.
+  // Both the old single letter memory types and the Mesen 2 ones are supported.
+  pub fn load_mesen_mlb(&mut self, text: &str) -> usize {
+    let mut count = 0;
+
+    for line in text.lines() {
+      let mut fields = line.splitn(4, ':');
+      let (Some(kind), Some(addr), Some(name)) = (fields.next(), fields.next(), fields.next()) else { continue; };
+      let comment = fields.next().map(str::trim).filter(|c| !c.is_empty())
+        .map(|c| c.replace("\\n", "\n"));
+
+      let mut range = addr.split('-');
+      let Some(start) = range.next().and_then(|a| usize::from_str_radix(a, 16).ok()) else { continue; };
+
+      let addr = match kind {
+        "P" | "NesPrgRom" => SymbolAddr::Prg(start),
+        "S" | "NesSaveRam" | "W" | "NesWorkRam" => SymbolAddr::SRam(start),
+        "R" | "NesInternalRam" => SymbolAddr::Cpu(start as u16 & 0x7FF),
+        "G" | "NesMemory" => SymbolAddr::Cpu(start as u16),
+        _ => continue,
+      };
+      self.insert(addr, Symbol { name: name.to_string(), comment });
+      count += 1;
+    }
+
+    count
+  }
+
+  // ca65/ld65 debug files, only the labels are taken:
+  // sym id=0,name="main",addrsize=absolute,scope=0,def=1,ref=2,val=0xC000,seg=0,type=lab
+  pub fn load_ca65_dbg(&mut self, text: &str) -> usize {
+    let mut count = 0;
+
+    for line in text.lines() {
+      let Some(fields) = line.strip_prefix("sym\t").or_else(|| line.strip_prefix("sym ")) else { continue; };
+
+      let mut name = None;
+      let mut val = None;
+      let mut is_label = false;
+      for field in fields.split(',') {
+        match field.split_once('=') {
+          Some(("name", v)) => name = Some(v.trim_matches('"')),
+          Some(("val", v)) => val = v.strip_prefix("0x").and_then(|v| u16::from_str_radix(v, 16).ok()),
+          Some(("type", v)) => is_label = v == "lab",
+          _ => {}
+        }
+      }
+
+      if let (true, Some(name), Some(val)) = (is_label, name, val) {
+        self.insert(SymbolAddr::Cpu(val), Symbol { name: name.to_string(), comment: None });
+        count += 1;
+      }
+    }
+
+    count
+  }
+
+  // The symbol of a cpu address. `prg_offset` is where the address is currently mapped in prg rom, if it is.
+  pub fn lookup(&self, addr: u16, prg_offset: Option<usize>) -> Option<&Symbol> {
+    prg_offset.and_then(|offset| self.get(SymbolAddr::Prg(offset)))
+      .or_else(|| match addr {
+        0x0000..=0x1FFF => self.get(SymbolAddr::Cpu(addr & 0x7FF)),
+        0x6000..=0x7FFF => self.get(SymbolAddr::SRam(addr as usize - 0x6000)),
+        _ => None,
+      })
+      .or_else(|| self.get(SymbolAddr::Cpu(addr)))
+  }
+}
+
+#[cfg(test)]
+mod symbols_tests {
+  use super::*;
+
+  #[test]
+  fn symbol_formats() {
+    let mut symbols = SymbolTable::new();
+
+    let nl = "$C000#reset#entry point\n$0010#player_x#\n#garbage\n";
+    assert_eq!(symbols.load("game.nes.1.nl", nl), Ok(2));
+    let mlb = "P:0123:nmi:vblank handler\nR:0300:oam_buf\nS:0000-0001:score:\nX:0000:bad\n";
+    assert_eq!(symbols.load("game.mlb", mlb), Ok(3));
+    let dbg = "version\tmajor=2,minor=0\nsym\tid=0,name=\"main\",addrsize=absolute,val=0xE000,seg=0,type=lab\nsym\tid=1,name=\"SIZE\",val=0x10,type=equ\n";
+    assert_eq!(symbols.load("game.dbg", dbg), Ok(1));
+    assert!(symbols.load("game.sym", "").is_err());
+
+    let reset = symbols.get(SymbolAddr::Prg(0x4000)).unwrap();
+    assert_eq!(reset.name, "reset");
+    assert_eq!(reset.comment.as_deref(), Some("entry point"));
+
+    assert_eq!(symbols.lookup(0x8123, Some(0x123)).unwrap().name, "nmi");
+    assert_eq!(symbols.lookup(0x8123, Some(0x4123)), None);
+    assert_eq!(symbols.lookup(0x0810, None).unwrap().name, "player_x");
+    assert_eq!(symbols.lookup(0x0300, None).unwrap().name, "oam_buf");
+    assert_eq!(symbols.lookup(0x6000, None).unwrap().name, "score");
+    assert_eq!(symbols.lookup(0xE000, Some(0x7E000)).unwrap().name, "main");
+  }
+}
@@ -1,5 +1,7 @@
 use std::u16;
 
+use crate::codec::codec_fields;
+
 pub trait Dma: Default {
   fn current(&mut self) -> u16;
   fn is_transfering(&self) -> bool;
@@ -9,10 +11,15 @@ pub trait Dma: Default {
 pub struct OamDma {
   pub start: u16,
   pub offset: u16,
+  // cpu page of the last transfer, games usually keep their sprites buffer at $0200
+  #[serde(default)]
+  pub last_page: Option<u8>,
 }
+codec_fields!(OamDma { start, offset, last_page });
 
 impl OamDma {
   pub fn init(&mut self, start: u8) {
+    self.last_page = Some(start);
     self.start = (start as u16) << 8;
     self.offset = 256;
   }
@@ -35,6 +42,7 @@ pub struct DmcDma {
   pub addr: u16,
   pub remaining: u16,
 }
+codec_fields!(DmcDma { addr, remaining });
 
 impl DmcDma {
   pub fn init(&mut self, addr: u16, length: u16) {
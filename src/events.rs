@@ -0,0 +1,148 @@
+use crate::{cpu::CpuFlags, frame::fnv_hash, joypad::JoypadButton, nes::Nes};
+
+// Things that happened in the emulator since the last Nes::poll_events()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuEvent {
+  // the ppu started a new frame
+  FrameStart(u64),
+  VBlank,
+  // the mapper raised its irq line
+  MapperIrq,
+  // the mapper kept the cpu busy with irqs for a whole frame, usually a mapper bug or an unacknowledged irq
+  IrqStorm,
+  CpuJammed,
+  // the battery backed ram changed during the last frame, and should be persisted
+  SramChanged,
+  StateLoaded,
+  Reset,
+  PowerCycle,
+}
+
+// How long the game took to see the input changes, from the change to the first controllers latch which read it.
+// Changes are noticed between cpu instructions, so the delays are precise to an instruction.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct InputLatency {
+  // input changes the game has seen
+  pub count: u32,
+  // cpu cycles and frames of the last change
+  pub last_cycles: u64,
+  pub last_frames: u64,
+  pub min_cycles: u64,
+  pub max_cycles: u64,
+  total_cycles: u64,
+  total_frames: u64,
+}
+
+impl InputLatency {
+  fn record(&mut self, cycles: u64, frames: u64) {
+    self.min_cycles = if self.count == 0 { cycles } else { self.min_cycles.min(cycles) };
+    self.max_cycles = self.max_cycles.max(cycles);
+    self.last_cycles = cycles;
+    self.last_frames = frames;
+    self.total_cycles += cycles;
+    self.total_frames += frames;
+    self.count += 1;
+  }
+
+  pub fn average_cycles(&self) -> f64 {
+    self.total_cycles as f64 / self.count.max(1) as f64
+  }
+
+  pub fn average_frames(&self) -> f64 {
+    self.total_frames as f64 / self.count.max(1) as f64
+  }
+}
+
+// Past this many instructions with the mapper irq pending and enabled in a single frame, we call it a storm
+const IRQ_STORM_THRESHOLD: u32 = 1000;
+// Events are dropped from the oldest if nobody polls them
+const MAX_PENDING_EVENTS: usize = 1024;
+
+#[derive(Default)]
+pub struct EventTracker {
+  pending: Vec<EmuEvent>,
+  frame: u64,
+  vblank_frame: Option<u64>,
+  irq_line: bool,
+  irq_busy_count: u32,
+  sram_hash: Option<u64>,
+  pub input_latency: InputLatency,
+  input_buttons: (JoypadButton, JoypadButton),
+  // cpu cycle and frame of the last input change the game hasn't latched yet
+  input_change: Option<(u64, u64)>,
+  input_latches: u64,
+}
+
+impl EventTracker {
+  pub fn push(&mut self, event: EmuEvent) {
+    if self.pending.len() >= MAX_PENDING_EVENTS {
+      self.pending.remove(0);
+    }
+    self.pending.push(event);
+  }
+
+  pub fn take(&mut self) -> Vec<EmuEvent> {
+    core::mem::take(&mut self.pending)
+  }
+
+  // Called after every cpu step
+  pub fn track(&mut self, nes: &mut Nes, was_jammed: bool) {
+    let cpu = nes.get_cpu();
+    let cart = cpu.bus.cart.as_mut();
+    let ppu = &cpu.bus.ppu;
+
+    if cpu.jammed && !was_jammed {
+      self.push(EmuEvent::CpuJammed);
+    }
+
+    let irq_line = cart.mapper.poll_irq();
+    if irq_line && !self.irq_line {
+      self.push(EmuEvent::MapperIrq);
+    }
+    if irq_line && !cpu.p.contains(CpuFlags::irq_off) {
+      self.irq_busy_count += 1;
+    }
+    self.irq_line = irq_line;
+
+    let joypad = &cpu.bus.joypad;
+    let buttons = (joypad.buttons1, joypad.buttons2);
+    if buttons != self.input_buttons {
+      self.input_buttons = buttons;
+      self.input_change = Some((cpu.cycles as u64, ppu.frame));
+    }
+    // the first latch after the change reads it, even when macros or the opposite directions filter alter what the game gets
+    if joypad.latches != self.input_latches {
+      self.input_latches = joypad.latches;
+      // a savestate loaded in between takes the clock back, the change is lost with it
+      if let Some((cycles, frame)) = self.input_change.take() {
+        if let (Some(cycles), Some(frames)) = ((cpu.cycles as u64).checked_sub(cycles), ppu.frame.checked_sub(frame)) {
+          self.input_latency.record(cycles, frames);
+        }
+      }
+    }
+
+    if ppu.scanline >= 241 && self.vblank_frame != Some(ppu.frame) {
+      self.vblank_frame = Some(ppu.frame);
+      self.push(EmuEvent::VBlank);
+    }
+
+    if ppu.frame != self.frame {
+      self.frame = ppu.frame;
+
+      if self.irq_busy_count > IRQ_STORM_THRESHOLD {
+        self.push(EmuEvent::IrqStorm);
+      }
+      self.irq_busy_count = 0;
+
+      if cart.header.has_battery {
+        let sram_hash = fnv_hash(&cart.sram);
+        if self.sram_hash.is_some_and(|hash| hash != sram_hash) {
+          self.push(EmuEvent::SramChanged);
+        }
+        self.sram_hash = Some(sram_hash);
+      }
+
+      self.push(EmuEvent::FrameStart(ppu.frame));
+    }
+  }
+}
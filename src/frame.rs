@@ -16,14 +16,97 @@ pub static SYS_COLORS: LazyLock<[RGBColor; 64]> = LazyLock::new(|| {
   colors.try_into().unwrap()
 });
 
+// The rgba bytes of every color, for each of the 8 emphasis combinations, indexed by emphasis << 6 | color.
+// Pixels are written many times per frame, so they are a single lookup and copy.
+static RGBA_COLORS: LazyLock<[[u8; PIXEL_BYTES]; 8 * 64]> = LazyLock::new(|| {
+  std::array::from_fn(|i| {
+    let (emphasis, color) = (i >> 6, &SYS_COLORS[i & 0x3F]);
+    let mut rgba = [color.0, color.1, color.2, 255];
+    if emphasis != 0 {
+      for (channel, val) in rgba[..3].iter_mut().enumerate() {
+        if emphasis & (1 << channel) == 0 {
+          *val = (*val as f32 * EMPHASIS_ATTENUATION) as u8;
+        }
+      }
+    }
+    rgba
+  })
+});
+
+
+// How the picture is stretched to fit a window. Correct gives the pixels the width they had on a tv of the console's region.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AspectMode { #[default] Correct, SquarePixels }
+
+// An area of the screen or of a window, in pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayRect {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl DisplayRect {
+  // The biggest area with the picture's aspect ratio which fits in the window, centered
+  pub fn fit(window: (u32, u32), picture: (u32, u32), pixel_aspect_ratio: f32) -> Self {
+    let picture_width = picture.0 as f32 * pixel_aspect_ratio;
+    let picture_height = picture.1 as f32;
+    let scale = (window.0 as f32 / picture_width).min(window.1 as f32 / picture_height);
+
+    let width = ((picture_width * scale).round() as u32).min(window.0);
+    let height = ((picture_height * scale).round() as u32).min(window.1);
+    Self { x: (window.0 - width) / 2, y: (window.1 - height) / 2, width, height }
+  }
+}
+
+// FNV-1a, stable across platforms and rust versions, so it can be stored as a golden value
+pub fn fnv_hash(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+    (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+  })
+}
+
+// CRC-32 as zip computes it. Rom databases list dumps by the crc32 of their prg and chr.
+pub fn crc32(bytes: &[u8]) -> u32 {
+  !bytes.iter().fold(!0u32, |crc, byte| {
+    (0..8).fold(crc ^ *byte as u32, |crc, _| {
+      if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 }
+    })
+  })
+}
 
 pub const GREYSCALE_PALETTE: [u8; 4] = [0x3F, 0x00, 0x10, 0x20];
 
 const PIXEL_BYTES: usize = 4;
+// https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+// Brightness a pixel keeps from the previous frame with phosphor blending, out of 256
+const PHOSPHOR_DECAY: u16 = 192;
+
+// How each frame is composited with the previous one.
+// Games which can't fit all their sprites on a scanline often draw them every other frame, which flickers on modern screens.
+// Mix shows the average of the last two frames, Phosphor lets bright pixels fade out over a few frames, like a crt.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBlend { #[default] Off, Mix, Phosphor }
+
+// When the picture was completed, so that recorders and netplay can line up audio and video without counting frames themselves
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+  // frames since power on, a reset doesn't clear it
+  pub index: u64,
+  // emulated time since power on, when vblank started
+  pub time_ns: u64,
+  // ntsc consoles alternate between an even and an odd field, the odd ones a dot shorter while rendering
+  pub odd_field: bool,
+}
+
 pub struct FrameBuffer {
   pub buffer: Box<[u8]>,
   pub width: usize,
   pub height: usize,
+  // only filled in the screen of the emulator
+  pub info: FrameInfo,
 }
 
 impl Default for FrameBuffer {
@@ -35,7 +118,7 @@ impl Default for FrameBuffer {
 impl FrameBuffer {
   pub fn new(width: usize, height: usize) -> Self {
     let buffer = vec![0; width * height * PIXEL_BYTES].into_boxed_slice();
-    Self { buffer, width, height }
+    Self { buffer, width, height, info: FrameInfo::default() }
   }
 
   pub fn nes_screen() -> Self {
@@ -47,12 +130,55 @@ impl FrameBuffer {
   }
 
   pub fn set_pixel(&mut self, x: usize, y: usize, color_id: u8) {
-    let color = &SYS_COLORS[color_id as usize];
+    self.set_pixel_emphasized(x, y, color_id, 0);
+  }
+
+  // Blends the pixel halfway to the given color, for debug overlays
+  pub fn tint_pixel(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+    let idx = (y*self.width + x) * PIXEL_BYTES;
+    for (channel, tint) in self.buffer[idx..idx + 3].iter_mut().zip([r, g, b]) {
+      *channel = ((*channel as u16 + tint as u16) / 2) as u8;
+    }
+  }
+
+  // Emphasis (red, green and blue bits) darkens the channels which aren't emphasized
+  pub fn set_pixel_emphasized(&mut self, x: usize, y: usize, color_id: u8, emphasis: u8) {
+    let rgba = &RGBA_COLORS[(emphasis as usize & 0b111) << 6 | (color_id as usize & 0x3F)];
     let idx = (y*self.width + x) * PIXEL_BYTES;
-    self.buffer[idx + 0] = color.0;
-    self.buffer[idx + 1] = color.1;
-    self.buffer[idx + 2] = color.2;
-    self.buffer[idx + 3] = 255;
+    self.buffer[idx..idx + PIXEL_BYTES].copy_from_slice(rgba);
+  }
+
+  // Blends the picture with the previous frame, which is then updated with what the next blend needs
+  pub fn blend(&mut self, previous: &mut FrameBuffer, mode: FrameBlend) {
+    let pixels = self.buffer.chunks_exact_mut(PIXEL_BYTES)
+      .zip(previous.buffer.chunks_exact_mut(PIXEL_BYTES));
+
+    for (px, prev) in pixels {
+      for (val, prev) in px[..3].iter_mut().zip(&mut prev[..3]) {
+        match mode {
+          FrameBlend::Off => {}
+          FrameBlend::Mix => {
+            let raw = *val;
+            *val = ((raw as u16 + *prev as u16) / 2) as u8;
+            *prev = raw;
+          }
+          FrameBlend::Phosphor => {
+            *val = (*val).max((*prev as u16 * PHOSPHOR_DECAY / 256) as u8);
+            *prev = *val;
+          }
+        }
+      }
+    }
+  }
+
+  pub fn hash(&self) -> u64 {
+    fnv_hash(&self.buffer)
+  }
+
+  // True if every pixel has the same color
+  pub fn is_blank(&self) -> bool {
+    let first = &self.buffer[..PIXEL_BYTES];
+    self.buffer.chunks(PIXEL_BYTES).all(|px| px == first)
   }
 }
 
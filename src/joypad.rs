@@ -1,7 +1,9 @@
 use bitflags::bitflags;
 
+use crate::codec::{codec_bits, codec_fields};
+
 bitflags! {
-  #[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+  #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
   pub struct JoypadButton: u8 {
     const right  = 0b1000_0000;
     const left   = 0b0100_0000;
@@ -14,15 +16,67 @@ bitflags! {
     const b      = 0b0000_0001;
   }
 }
+codec_bits!(JoypadButton);
+
+// What the game reads when both Left and Right, or both Up and Down, are held.
+// A real controller can't press them together, and some games glitch badly on them, but keyboards easily do.
+// Allow keeps them, as TASes use them on purpose. Neutral drops both, LastPressed keeps the newest one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum OppositeDirections { #[default] Allow, Neutral, LastPressed }
+
+impl OppositeDirections {
+	// prev_held are the buttons held at the previous latch, prev_read what the game read then
+	fn filter(self, held: JoypadButton, prev_held: JoypadButton, prev_read: JoypadButton) -> JoypadButton {
+		let mut res = held;
+		for pair in [JoypadButton::left | JoypadButton::right, JoypadButton::up | JoypadButton::down] {
+			if !held.contains(pair) { continue; }
+
+			let kept = match self {
+				OppositeDirections::Allow => pair,
+				OppositeDirections::Neutral => JoypadButton::empty(),
+				// both were already held: the choice made then stays
+				OppositeDirections::LastPressed if prev_held.contains(pair) => prev_read & pair,
+				// the one which wasn't held before was just pressed; pressed together, none wins
+				OppositeDirections::LastPressed if prev_held.intersects(pair) => pair - prev_held,
+				OppositeDirections::LastPressed => JoypadButton::empty(),
+			};
+			res = (res - pair) | kept;
+		}
+		res
+	}
+}
+
+// Called when the game latches the controllers, returning the buttons held on both.
+// Frontends can read their input this late, instead of once per frame, to cut latency.
+pub type InputPoller = Box<dyn FnMut() -> (JoypadButton, JoypadButton) + Send>;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Joypad {
 	strobe: bool,
 	pub buttons1: JoypadButton,
 	pub buttons2: JoypadButton,
+	// the buttons copied in the shift registers when the strobe was last released, which is what the game reads
+	#[serde(default)]
+	latched1: JoypadButton,
+	#[serde(default)]
+	latched2: JoypadButton,
 	button_idx1: u8,
 	button_idx2: u8,
+	#[serde(skip)]
+	pub poller: Option<InputPoller>,
+	// times the game latched the controllers, for the input latency measurement
+	#[serde(skip)]
+	pub latches: u64,
+	// held by the input macros, on top of the frontend buttons
+	#[serde(skip)]
+	pub macro_buttons: [JoypadButton; 2],
+	#[serde(skip)]
+	pub opposite_directions: OppositeDirections,
+	// all the buttons held at the last latch, before the opposite directions filter
+	#[serde(default)]
+	held: [JoypadButton; 2],
 }
+codec_fields!(Joypad { strobe, buttons1, buttons2, latched1, latched2, button_idx1, button_idx2, held });
 
 impl Joypad {
 	pub fn new() -> Self {
@@ -32,23 +86,50 @@ impl Joypad {
 			button_idx2: 0,
 			buttons1: JoypadButton::empty(),
 			buttons2: JoypadButton::empty(),
+			latched1: JoypadButton::empty(),
+			latched2: JoypadButton::empty(),
+			poller: None,
+			latches: 0,
+			macro_buttons: [JoypadButton::empty(); 2],
+			opposite_directions: OppositeDirections::Allow,
+			held: [JoypadButton::empty(); 2],
 		}
 	}
 
 	pub fn write(&mut self, val: u8) {
-		self.strobe = (val & 1) != 0;
+		let strobe = (val & 1) != 0;
+		if self.strobe && !strobe {
+			self.latch();
+		}
+
+		self.strobe = strobe;
 		if self.strobe {
 			self.button_idx1 = 0;
 			self.button_idx2 = 0;
 		}
 	}
 
+	fn latch(&mut self) {
+		if let Some(poll) = &mut self.poller {
+			(self.buttons1, self.buttons2) = poll();
+		}
+		let held = [self.buttons1 | self.macro_buttons[0], self.buttons2 | self.macro_buttons[1]];
+		self.latched1 = self.opposite_directions.filter(held[0], self.held[0], self.latched1);
+		self.latched2 = self.opposite_directions.filter(held[1], self.held[1], self.latched2);
+		self.held = held;
+		self.latches += 1;
+	}
+
+	pub fn last_latched(&self) -> (JoypadButton, JoypadButton) {
+		(self.latched1, self.latched2)
+	}
+
 	pub fn read1(&mut self) -> u8 {
 		if self.strobe {
-			return self.buttons1.contains(JoypadButton::a) as u8;
+			return (self.buttons1 | self.macro_buttons[0]).contains(JoypadButton::a) as u8;
 		}
 
-		let res = (self.buttons1.bits() >> self.button_idx1) & 1;
+		let res = (self.latched1.bits() >> self.button_idx1) & 1;
 		self.button_idx1 = (self.button_idx1 + 1) % 8;
 		// some games expect the highest bit to best due to open bus
 		res | 0x40
@@ -56,12 +137,142 @@ impl Joypad {
 
 	pub fn read2(&mut self) -> u8 {
 		if self.strobe {
-			return self.buttons2.contains(JoypadButton::a) as u8;
+			return (self.buttons2 | self.macro_buttons[1]).contains(JoypadButton::a) as u8;
 		}
 
-		let res = (self.buttons2.bits() >> self.button_idx2) & 1;
+		let res = (self.latched2.bits() >> self.button_idx2) & 1;
 		self.button_idx2 = (self.button_idx2 + 1) % 8;
 		// some games expect the highest bit to best due to open bus
 		res | 0x40
 	}
-}
\ No newline at end of file
+}
+// A button change which is applied once the ppu reaches the given frame and scanline
+#[derive(Clone, Copy)]
+pub struct TimedInput {
+	pub frame: u64,
+	pub scanline: u16,
+	// controller port 0 or 1
+	pub port: usize,
+	pub button: JoypadButton,
+	pub pressed: bool,
+}
+
+// A sequence of buttons, each held for some frames, played on a controller when triggered.
+// Frames end when vblank starts, as counted by the ppu, so a macro plays the same on every run, whatever the speed of the frontend.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InputMacro {
+	pub steps: Vec<(JoypadButton, u32)>,
+}
+
+impl InputMacro {
+	// One entry per frame, consecutive equal ones are merged
+	pub fn from_frames(frames: &[JoypadButton]) -> Self {
+		let mut steps: Vec<(JoypadButton, u32)> = Vec::new();
+		for &buttons in frames {
+			match steps.last_mut() {
+				Some((last, count)) if *last == buttons => *count += 1,
+				_ => steps.push((buttons, 1)),
+			}
+		}
+		Self { steps }
+	}
+
+	pub fn len_frames(&self) -> u32 {
+		self.steps.iter().map(|(_, frames)| frames).sum()
+	}
+
+	fn buttons_at(&self, mut frame: u32) -> Option<JoypadButton> {
+		for &(buttons, frames) in &self.steps {
+			if frame < frames { return Some(buttons); }
+			frame -= frames;
+		}
+		None
+	}
+}
+
+struct PlayingMacro {
+	id: usize,
+	port: usize,
+	// frame it was triggered in
+	start: u64,
+}
+
+// Plays the triggered macros, and records the buttons of a controller into new ones.
+// The buttons of the macros are added to the frontend ones, so that the player can still press buttons meanwhile.
+// Frames are counted here, at every start of vblank, so that savestates and power cycles don't take the macros back.
+#[derive(Default)]
+pub(crate) struct MacroEngine {
+	macros: Vec<InputMacro>,
+	playing: Vec<PlayingMacro>,
+	recording: Option<(usize, Vec<JoypadButton>)>,
+	buttons: [JoypadButton; 2],
+	frame: u64,
+	in_vblank: bool,
+}
+
+impl MacroEngine {
+	pub fn add(&mut self, input_macro: InputMacro) -> usize {
+		self.macros.push(input_macro);
+		self.macros.len() - 1
+	}
+
+	// Starts right away, in the current frame, on controller port 0 or 1
+	pub fn trigger(&mut self, id: usize, port: usize) -> Result<(), String> {
+		if id >= self.macros.len() {
+			return Err(format!("No macro with id {id}"));
+		}
+		if port > 1 {
+			return Err(format!("No controller port {port}"));
+		}
+		self.playing.push(PlayingMacro { id, port, start: self.frame });
+		Ok(())
+	}
+
+	pub fn clear(&mut self) {
+		self.macros.clear();
+		self.playing.clear();
+	}
+
+	pub fn start_recording(&mut self, port: usize) {
+		self.recording = Some((port.min(1), Vec::new()));
+	}
+
+	pub fn stop_recording(&mut self) -> Option<InputMacro> {
+		self.recording.take().map(|(_, frames)| InputMacro::from_frames(&frames))
+	}
+
+	pub fn is_active(&self) -> bool {
+		!self.playing.is_empty() || self.recording.is_some() || self.buttons != [JoypadButton::empty(); 2]
+	}
+
+	pub fn sync_vblank(&mut self, in_vblank: bool) {
+		self.in_vblank = in_vblank;
+	}
+
+	// Called after every cpu step, with the buttons held by the frontend.
+	// When a frame ended, records them and returns the buttons the macros hold in the new one.
+	pub fn step(&mut self, in_vblank: bool, held: [JoypadButton; 2]) -> Option<[JoypadButton; 2]> {
+		let frame_ended = in_vblank && !self.in_vblank;
+		self.in_vblank = in_vblank;
+		if !frame_ended { return None; }
+
+		if let Some((port, frames)) = &mut self.recording {
+			frames.push(held[*port]);
+		}
+		self.frame += 1;
+		Some(self.buttons())
+	}
+
+	// The buttons held by the macros in the current frame
+	pub fn buttons(&mut self) -> [JoypadButton; 2] {
+		let mut buttons = [JoypadButton::empty(); 2];
+		let (macros, frame) = (&self.macros, self.frame);
+		self.playing.retain(|playing| {
+			let Some(pressed) = macros[playing.id].buttons_at((frame - playing.start) as u32) else { return false; };
+			buttons[playing.port] |= pressed;
+			true
+		});
+		self.buttons = buttons;
+		buttons
+	}
+}
@@ -1,16 +1,43 @@
+#[macro_use]
+mod logging;
+
+// Stable API, used by the frontends. Most of it is re-exported by the prelude.
+pub mod prelude;
 pub mod nes;
+pub mod config;
+pub mod cart;
+pub mod frame;
+pub mod joypad;
+pub mod events;
+pub mod savestate;
+pub mod save_bundle;
+pub mod batch;
+pub mod movie;
+pub mod overrides;
+#[cfg(feature = "debug-tools")]
+pub mod state_diff;
+#[cfg(feature = "debug-tools")]
+pub mod debugger;
+
+// Internals. They are public for the tests, the fuzzer and the debugging tools,
+// but their items can change with any refactor, so frontends shouldn't rely on them.
+// seeds the power on state of the fuzzer's cpus
+pub mod rng;
+// stepped on its own by the cpu test suites and the fuzzer
 pub mod cpu;
+// the opcode table, checked against the nestest log
 pub mod instr;
-
+// the Memory trait, to peek and poke the bus from tests and debuggers
 pub mod mem;
+// the cpu bus, driven directly by nestest
 pub mod bus;
-pub mod dma;
+// the banking helpers, unit tested against hand made roms
 pub mod mapper;
-
+// the render settings and layers of the debug views
 pub mod ppu;
-pub mod frame;
-
+// the filter and resampler settings, also in the prelude
 pub mod apu;
-pub mod joypad;
-
-pub mod cart;
\ No newline at end of file
+pub(crate) mod codec;
+pub(crate) mod dma;
+// only reachable through the prelude, which re-exports what Nes::current_banks() returns
+pub(crate) mod bank_map;
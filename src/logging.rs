@@ -0,0 +1,14 @@
+// Target scoped logging, through the log crate when the "logging" feature is enabled.
+// Without the feature the calls compile to nothing, but their arguments are still type checked.
+// Every subsystem logs to its own target (nen::cpu, nen::ppu, nen::apu, nen::cart, nen::mapper::mmc3...),
+// so a single one can be traced when reporting a bug, e.g. with env_logger: RUST_LOG=nen::ppu=trace
+
+#[cfg(feature = "logging")]
+macro_rules! nen_log {
+  ($level:ident, $target:expr, $($arg:tt)+) => { log::$level!(target: $target, $($arg)+) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! nen_log {
+  ($level:ident, $target:expr, $($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } };
+}
@@ -1,6 +1,6 @@
 use std::marker::{self, PhantomData};
 
-use crate::{cart::{CartBanking, CartHeader, Mirroring, PpuTarget, PrgTarget}, ppu::PpuState};
+use crate::{cart::{CartBanking, CartHeader, HeaderFormat, Mirroring, PpuTarget, PrgTarget}, codec::{codec_fields, NenCodec, Reader}};
 
 mod mmc1;
 mod mmc2;
@@ -10,15 +10,20 @@ mod konami_irq;
 mod vrc2_4;
 mod vrc3;
 mod vrc6;
+mod vrc7;
 mod sunsoft4;
 mod sunsoft_fme_7;
 mod namco129_163;
+mod namco175_340;
 mod bandai_fcg;
 mod unrom512;
 mod gtrom;
+mod flash;
+mod eeprom;
 
-use bandai_fcg::BandaiFCG;
+pub(crate) use bandai_fcg::BandaiFCG;
 use gtrom::GTROM;
+pub use flash::{FlashOp, FLASH_SECTOR_SIZE};
 use mmc1::MMC1;
 use mmc2::MMC2;
 use mmc3::MMC3;
@@ -27,40 +32,87 @@ use unrom512::UNROM512;
 use vrc2_4::VRC2_4;
 use vrc3::VRC3;
 use vrc6::VRC6;
+use vrc7::VRC7;
 use sunsoft4::Sunsoft4;
 use sunsoft_fme_7::SunsoftFME7;
 use namco129_163::Namco129_163;
+use namco175_340::Namco175_340;
+
+// Every implemented mapper, by ines number
+macro_rules! mappers {
+  ($($ids:pat => $mapper:ty),* $(,)?) => {
+    pub fn new_mapper(header: &CartHeader, banks: &mut CartBanking) -> Result<Box<dyn Mapper>, String> {
+      let mapper: Box<dyn Mapper> = match header.mapper {
+        $($ids => <$mapper>::new(header, banks),)*
+        _ => return Err(format!("Mapper {} not implemented", header.mapper))
+      };
+
+      Ok(mapper)
+    }
+
+    // Savestates before version 2 held the fields of the mapper as json, tagged with its type name.
+    // They are turned into what the mapper saves now.
+    pub(crate) fn mapper_state_from_json(id: u16, mut mapper: serde_json::Value) -> Result<Vec<u8>, String> {
+      if let Some(fields) = mapper.as_object_mut() {
+        fields.remove("mmu");
+        // mappers without fields are unit structs
+        if fields.is_empty() { mapper = serde_json::Value::Null; }
+      }
 
-pub fn new_mapper(header: &CartHeader, banks: &mut CartBanking) -> Result<Box<dyn Mapper>, String> {
-  let mapper: Box<dyn Mapper> = match header.mapper {
-    0 => NROM::new(header, banks),
-    1 => MMC1::new(header, banks),
-    2 | 180 => UxROM::new(header, banks),
-    3 => CNROM::new(header, banks),
-    4 => MMC3::new(header, banks),
-    5 => MMC5::new(header, banks),
-    7 => AxROM::new(header, banks),
-    9 | 10 => MMC2::new(header, banks),
-    11 => ColorDreams::new(header, banks),
-    16 => BandaiFCG::new(header, banks),
-    19 => Namco129_163::new(header, banks),
-    21 | 22 | 23 | 25 => VRC2_4::new(header, banks),
-    24 | 26 => VRC6::new(header, banks),
-    30 => UNROM512::new(header, banks),
-    31 => INesMapper031::new(header, banks),
-    66 => GxROM::new(header, banks),
-    68 => Sunsoft4::new(header, banks),
-    69 => SunsoftFME7::new(header, banks),
-    71 => Codemasters::new(header, banks),
-    73 => VRC3::new(header, banks),
-    75 => VRC1::new(header, banks),
-    78 => INesMapper078::new(header, banks),
-    87 => INesMapper087::new(header, banks),
-    111 => GTROM::new(header, banks),
-    206 => INesMapper206::new(header, banks),
-    _ => return Err(format!("Mapper {} not implemented", header.mapper))
+      match id {
+        $($ids => json_to_state::<$mapper>(mapper),)*
+        _ => Err(format!("Mapper {id} not implemented"))
+      }
+    }
   };
+}
+
+mappers! {
+  0 => NROM,
+  1 => MMC1,
+  2 | 180 => UxROM,
+  3 => CNROM,
+  4 => MMC3,
+  5 => MMC5,
+  7 => AxROM,
+  9 | 10 => MMC2,
+  11 => ColorDreams,
+  16 => BandaiFCG,
+  19 => Namco129_163,
+  21 | 22 | 23 | 25 => VRC2_4,
+  24 | 26 => VRC6,
+  30 => UNROM512,
+  31 => INesMapper031,
+  59 => INesMapper059,
+  66 => GxROM,
+  68 => Sunsoft4,
+  69 => SunsoftFME7,
+  71 => Codemasters,
+  73 => VRC3,
+  75 => VRC1,
+  78 => INesMapper078,
+  85 => VRC7,
+  87 => INesMapper087,
+  111 => GTROM,
+  185 => INesMapper185,
+  206 => INesMapper206,
+  210 => Namco175_340,
+}
+
+fn json_to_state<M: Mapper + serde::de::DeserializeOwned>(mapper: serde_json::Value) -> Result<Vec<u8>, String> {
+  let mapper: M = serde_json::from_value(mapper)
+    .map_err(|e| format!("Savestate is corrupted: {e}"))?;
+  let mut state = Vec::new();
+  mapper.save(&mut state);
+  Ok(state)
+}
 
+// Builds the mapper of the game, then loads the state it saved
+pub fn load_mapper(header: &CartHeader, state: &[u8]) -> Result<Box<dyn Mapper>, String> {
+  // the banks the mapper sets up when built are thrown away, they are part of the state too
+  let mut banks = CartBanking::new(header);
+  let mut mapper = new_mapper(header, &mut banks)?;
+  mapper.load(state)?;
   Ok(mapper)
 }
 
@@ -70,7 +122,90 @@ pub fn mapper_name(id: u16) -> &'static str {
     .map(|m| m.1)
     .unwrap_or("Not implemented")
 }
-const MAPPERS_TABLE: [(u16, &'static str); 37] = [
+
+// Some boards always come with battery backed ram, but lots of dumps omit the battery flag in the header.
+pub fn board_has_battery(header: &CartHeader) -> bool {
+  match header.mapper {
+    // NES 2.0 explicitly declares non volatile prg ram, Bandai FCG eeproms included
+    _ if header.eeprom_size > 0 => true,
+    // otherwise the NES 2.0 battery bit can be trusted, only iNes dumps are guessed
+    _ if header.format == HeaderFormat::Nes2_0 => false,
+    // MMC1 boards with chr ram and prg ram (SNROM, SOROM, SUROM, SXROM) are battery backed
+    1 => header.uses_chr_ram && header.prg_ram_size > 0,
+    _ => false,
+  }
+}
+
+// Self-flashing boards save by reprogramming their own prg rom, which is what has to be persisted.
+pub fn board_has_flash(header: &CartHeader) -> bool {
+  match header.mapper {
+    // on UNROM 512 the battery flag marks the flashable variant
+    30 => header.has_battery,
+    // GTROM always comes with a flash chip
+    111 => true,
+    _ => false,
+  }
+}
+
+// Discrete boards don't disable the rom when the mapper register is written,
+// so the rom drives the bus too and the written value is ANDed with the rom byte.
+// https://www.nesdev.org/wiki/Bus_conflict
+pub fn board_has_bus_conflicts(header: &CartHeader) -> bool {
+  match (header.mapper, header.submapper) {
+    // NES 2.0 submapper 1 declares boards without conflicts, 2 boards with them
+    (2 | 3 | 7, 1) => false,
+    (2 | 3 | 7, 2) => true,
+    // UxROM and CNROM boards have them, while most AxROM games are on ANROM, which doesn't
+    (2 | 3, _) => true,
+    // Color Dreams, GxROM, and CNROM with chr disable
+    (11 | 66 | 185, _) => true,
+    _ => false,
+  }
+}
+
+// Relative volume the expansion audio is mixed at, as it depends on the resistors on each board.
+// 1.0 is the level each chip's get_sample() is already scaled to.
+pub fn board_expansion_gain(header: &CartHeader) -> f32 {
+  match (header.mapper, header.submapper) {
+    // VRC6's output is scaled after rustico's recordings, a full volume square comes out at
+    // about 0.85 of a full volume 2A03 square
+    (24 | 26, _) => 1.0,
+    // MMC5 pulses, VRC7 fm and Sunsoft 5B audio aren't synthesized yet.
+    // They are left at the chip level until they can be compared against recordings,
+    // except for the MMC5 pulses, which are the 2A03 ones and sound as loud.
+    (5 | 85 | 69, _) => 1.0,
+    // Namco 163 submappers declare the mixing level: none, 11-13db, 16-17db, 18-19.5db
+    (19, 2) => 0.0,
+    (19, 4) => 1.7,
+    (19, 5) => 2.2,
+    _ => 1.0,
+  }
+}
+
+// Prg ram of the boards whose header doesn't declare it.
+pub fn board_prg_ram_size(header: &CartHeader) -> usize {
+  match (header.mapper, &header.format) {
+    // MMC5 boards come with up to 64kb, and iNes headers rarely tell how much.
+    // The most is given, games with less only use the first banks.
+    (5, HeaderFormat::INes) => 64 * 1024,
+    _ => 8 * 1024,
+  }
+}
+
+// iNes can't declare chr ram size, so boards with more than 8kb have to be recognized by mapper.
+pub fn board_chr_ram_size(header: &CartHeader) -> usize {
+  match header.mapper {
+    // CPROM
+    13 => 16 * 1024,
+    // UNROM 512 and GTROM (the upper half of GTROM's ram is used as nametables)
+    30 | 111 => 32 * 1024,
+    // RacerMate
+    168 => 64 * 1024,
+    _ => 8 * 1024,
+  }
+}
+
+const MAPPERS_TABLE: [(u16, &'static str); 40] = [
   (0, "NROM"),
   (1, "MMC1"),
   (2, "UxROM"),
@@ -93,6 +228,7 @@ const MAPPERS_TABLE: [(u16, &'static str); 37] = [
   (31, "NSF"),
   (34, "BNROM/NINA-001"),
   (48, "Taito TC0690"),
+  (59, "BMC-D1038 multicart"),
   (66, "GxROM"),
   (68, "Sunsoft4"),
   (69, "Sunsoft5 FME-7"),
@@ -100,16 +236,39 @@ const MAPPERS_TABLE: [(u16, &'static str); 37] = [
   (73, "Konami VRC3 (Salamander)"),
   (75, "Konami VRC1"),
   (78, "Irem 74HC161 (Holy Diver and Cosmo Carrier)"),
+  (85, "Konami VRC7"),
   (87, "Jaleco87"),
   (91, "J.Y. Company"),
   (94, "UNROM (Senjou no Ookami)"),
   (111, "GTROM (Cheapocabra)"),
   (163, "FC-001"),
   (180, "UNROM (Crazy Climber)"),
+  (185, "CNROM (copy protected)"),
   (206, "Namco 118/Tengen MIMIC-1"),
   (210, "Namco 175/340"),
 ];
 
+// Folds an index into data of the given size, as the address lines of the chips do.
+// Sizes which are a power of two are just masked. Others are made of several chips, say 256kb and 128kb,
+// where the lines of the smaller chip past its size are ignored, so the indices past the end repeat it.
+pub fn mirror(mut index: usize, mut size: usize) -> usize {
+  if size.is_power_of_two() { return index & (size - 1); }
+  if size == 0 { return 0; }
+
+  let mut base = 0;
+  let mut chip = 1 << (usize::BITS - 1);
+  while index >= size {
+    while index & chip == 0 { chip >>= 1; }
+    index -= chip;
+    if size > chip {
+      size -= chip;
+      base += chip;
+    }
+    chip >>= 1;
+  }
+  base + index
+}
+
 pub fn set_byte_hi(dst: u16, val: u8) -> u16 {
   (dst & 0x00FF) | ((val as u16) << 8)
 }
@@ -118,8 +277,18 @@ pub fn set_byte_lo(dst: u16, val: u8) -> u16 {
   (dst & 0xFF00) | val as u16
 }
 
-#[typetag::serde(tag = "mmu")]
-pub trait Mapper {
+// The fields of a mapper as json, which every mapper gets from its serde derive
+pub trait MapperFields {
+  fn fields_json(&self) -> serde_json::Value;
+}
+
+impl<T: serde::Serialize> MapperFields for T {
+  fn fields_json(&self) -> serde_json::Value {
+    serde_json::to_value(self).unwrap_or_default()
+  }
+}
+
+pub trait Mapper: Send + NenCodec + MapperFields {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> where Self: Sized;
 
   fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8);
@@ -144,19 +313,52 @@ pub trait Mapper {
   fn cart_read(&mut self, _addr: usize) -> u8 { 0xFF }
   fn cart_write(&mut self, _banks: &mut CartBanking, _addr: usize, _val: u8) {}
   fn poll_irq(&mut self) -> bool { false }
+  // Pending write to prg rom, for self-flashing boards
+  fn poll_flash(&mut self) -> Option<FlashOp> { None }
+  // External configuration bits (dip switches, solder pads) found on some boards.
+  // Mappers needing a default setting should set it in new().
+  fn set_dipswitches(&mut self, _mask: u8) {}
+  // Non volatile memory owned by the mapper chip itself (eeproms), persisted along with the battery ram
+  fn get_mapper_nvram(&self) -> Option<Vec<u8>> { None }
+  fn set_mapper_nvram(&mut self, _data: Vec<u8>) {}
   
   // Generic cpu cycle notify / apu extension clocking
   fn notify_cpu_cycle(&mut self) {}
   fn get_sample(&self) -> f32 { 0.0 }
 
-  // Mmc3 scanline notify
-  fn notify_mmc3_scanline(&mut self) {}
+  // What the mapper sees of the ppu. Scanline counters and the like are built on these,
+  // as the chips watching the ppu bus do, so they hold up however a game sets up the ppu.
+
+  // Every address put on the ppu bus: the rendering fetches, and the $2006 and $2007 accesses outside of rendering
+  fn notify_ppu_addr(&mut self, _ctx: &mut PpuCtx, _addr: u16) {}
+  // Writes to the ppu registers ($2000-$2007), which some chips snoop on
+  fn notify_ppu_reg(&mut self, _ctx: &mut PpuCtx, _reg: u16, _val: u8) {}
+  // Start of vblank
+  fn notify_frame_end(&mut self, _ctx: &mut PpuCtx) {}
+
+  // Registers, irq counters and mode flags, by the names the mapper gives them, for debuggers.
+  // Mappers holding memories (extended ram, eeproms) leave them out, they have viewers of their own.
+  fn debug_state(&self) -> serde_json::Value { self.fields_json() }
+
+  // State of the chip, for the fast savestates.
+  // The mapper is built from the header before loading, so only what changes while running is saved.
+  fn save(&self, out: &mut Vec<u8>) { self.encode(out) }
+  fn load(&mut self, bytes: &[u8]) -> Result<(), String> {
+    let mut input = Reader::new(bytes);
+    self.decode(&mut input)?;
+    input.finish()
+  }
+}
 
-  // Mmc5 ppu notify
-  fn notify_ppuctrl(&mut self, _val: u8) {}
-  fn notify_ppumask(&mut self, _val: u8) {}
-  fn notify_ppu_state(&mut self, _state: PpuState) {}
-  fn notify_mmc5_scanline(&mut self) {}
+// Where the ppu is when a mapper is notified, and the banks the mapper may switch in response
+pub struct PpuCtx<'a> {
+  pub banks: &'a mut CartBanking,
+  pub scanline: usize,
+  pub cycle: usize,
+  // dots since power on, to time the gaps between accesses
+  pub dot: u64,
+  // the access is a rendering fetch, and not one of the cpu through $2006 or $2007
+  pub rendering: bool,
 }
 
 #[derive(Debug, Default)]
@@ -180,15 +382,19 @@ pub struct Banking<T> {
 
 impl<T> Banking<T> {
   pub fn new(rom_size: usize, pages_start: usize, page_size: usize, pages_count: usize) -> Self {
+    // translate() relies on it to avoid divisions
+    assert!(page_size.is_power_of_two(), "page size should be a power of two");
     let bankings = vec![0; pages_count].into_boxed_slice();
     let bank_size = page_size;
-    let banks_count = rom_size / bank_size;
+    // data smaller than a bank is mirrored in it, and a last bank cut short counts as a bank
+    let banks_count = rom_size.div_ceil(bank_size).max(1);
     Self { bankings, data_size: rom_size, pages_start, bank_size, banks_count, kind: PhantomData::<T> }
   }
 
+  // Banks past the end wrap as the address lines of the chips do, see mirror()
   pub fn set_page(&mut self, page: usize, bank: usize) {
     let pages_count = self.bankings.len();
-    self.bankings[page % pages_count] = (bank % self.banks_count) * self.bank_size;
+    self.bankings[page % pages_count] = mirror(bank, self.banks_count) * self.bank_size;
   }
 
   pub fn swap_pages(&mut self, left: usize, right: usize) {
@@ -196,9 +402,22 @@ impl<T> Banking<T> {
   }
 
   pub fn set_page_to_last_bank(&mut self, page: usize) {
-    let pages_count = self.bankings.len();
-    let last_bank = self.banks_count-1;
-    self.bankings[page % pages_count] = last_bank * self.bank_size;
+    self.set_page_to_bank_from_end(page, 0);
+  }
+
+  // Boards fix the last banks by pulling all the high address lines up, so 0 is the last bank, 1 the one before.
+  // With fewer banks than that, they wrap like any other select.
+  pub fn set_page_to_bank_from_end(&mut self, page: usize, from_end: usize) {
+    self.set_page(page, !from_end);
+  }
+
+  // The bank mapped in each page
+  pub fn banks(&self) -> Vec<usize> {
+    (0..self.bankings.len()).map(|page| self.bank(page)).collect()
+  }
+
+  pub fn bank(&self, page: usize) -> usize {
+    self.bankings[page % self.bankings.len()] / self.bank_size
   }
 
   fn page_to_bank_addr(&self, page: usize, addr: usize) -> usize {
@@ -206,9 +425,32 @@ impl<T> Banking<T> {
     self.bankings[page % pages_count] + (addr % self.bank_size)
   }
 
+  // This is on the path of every prg and chr access.
+  // Pages hold the offset of their bank, so only shifts and masks are left to do here.
   pub fn translate(&self, addr: usize) -> usize {
-    let page = (addr - self.pages_start) / self.bank_size;
-    self.page_to_bank_addr(page, addr)
+    let page = (addr - self.pages_start) >> self.bank_size.trailing_zeros();
+    // addresses past the last page mirror the first ones, it is rare enough to pay the division
+    let bank_addr = match self.bankings.get(page) {
+      Some(bank_addr) => *bank_addr,
+      None => self.bankings[page % self.bankings.len()],
+    };
+    bank_addr + (addr & (self.bank_size - 1))
+  }
+}
+
+// The layout of the pages is set up by the mapper when built, only the banks they point to change
+impl<T> NenCodec for Banking<T> {
+  fn encode(&self, out: &mut Vec<u8>) {
+    self.bankings.encode(out);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let pages_count = self.bankings.len();
+    self.bankings.decode(input)?;
+    if self.bankings.len() != pages_count || self.bankings.iter().any(|&bank_addr| bank_addr >= self.banks_count * self.bank_size) {
+      return Err("Savestate is corrupted: the banks don't match the cartridge".to_string());
+    }
+    Ok(())
   }
 }
 
@@ -272,7 +514,7 @@ impl Banking<CiramBanking> {
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Dummy;
-#[typetag::serde]
+codec_fields!(Dummy {});
 impl Mapper for Dummy {
   fn new(_: &CartHeader, _: &mut CartBanking) -> Box<Self> {
     Box::new(Self)
@@ -284,8 +526,8 @@ impl Mapper for Dummy {
 // https://www.nesdev.org/wiki/NROM
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct NROM;
+codec_fields!(NROM {});
 
-#[typetag::serde]
 impl Mapper for NROM {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -308,8 +550,8 @@ impl Mapper for NROM {
 pub struct UxROM {
   banked_page: u8,
 }
+codec_fields!(UxROM { banked_page });
 
-#[typetag::serde]
 impl Mapper for UxROM {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -325,9 +567,10 @@ impl Mapper for UxROM {
     Box::new(Self { banked_page })
   }
 
+  // UNROM has 3 bits and UOROM 4, the whole register is taken for the oversize homebrew of up to 4mb.
+  // It is the same on the real boards, as their roms mask out the bits above.
   fn prg_write(&mut self, banks: &mut CartBanking, _: usize, val: u8) {
-    let select = val & 0b1111;
-    banks.prg.set_page(self.banked_page as usize, select as usize);
+    banks.prg.set_page(self.banked_page as usize, val as usize);
   }
 }
 
@@ -335,8 +578,8 @@ impl Mapper for UxROM {
 // https://www.nesdev.org/wiki/INES_Mapper_003
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct CNROM;
+codec_fields!(CNROM {});
 
-#[typetag::serde]
 impl Mapper for CNROM {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.chr = Banking::new_chr(header, 1);
@@ -348,12 +591,48 @@ impl Mapper for CNROM {
   }
 }
 
+// Mapper 185
+// https://www.nesdev.org/wiki/INES_Mapper_185
+// CNROM whose register enables the chr rom instead of banking it.
+// Games check that the chr reads back wrong with some values, and refuse to run on other boards.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct INesMapper185 {
+  submapper: u8,
+  chr_enabled: bool,
+}
+codec_fields!(INesMapper185 { chr_enabled });
+
+impl Mapper for INesMapper185 {
+  fn new(header: &CartHeader, _: &mut CartBanking)-> Box<Self> {
+    Box::new(Self { submapper: header.submapper, chr_enabled: true })
+  }
+
+  fn prg_write(&mut self, _: &mut CartBanking, _: usize, val: u8) {
+    self.chr_enabled = match self.submapper {
+      // NES 2.0 submappers 4 to 7 declare the value of the two low bits enabling the chr
+      4..=7 => val & 0b11 == self.submapper & 0b11,
+      // every known game disables it with one of these
+      _ => val & 0b1111 != 0 && val != 0x13,
+    };
+  }
+
+  fn map_ppu_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PpuTarget {
+    match addr {
+      // the pattern tables are open bus, most consoles read back $FF
+      0x0000..=0x1FFF if !self.chr_enabled => PpuTarget::Value(0xFF),
+      0x0000..=0x1FFF => PpuTarget::Chr(banks.chr.translate(addr)),
+      0x2000..=0x2FFF => PpuTarget::CiRam(banks.ciram.translate(addr)),
+      _ => unreachable!()
+    }
+  }
+}
+
 // Mapper 07
 // https://www.nesdev.org/wiki/AxROM
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct AxROM;
+codec_fields!(AxROM {});
 
-#[typetag::serde]
 impl Mapper for AxROM {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 1);
@@ -376,8 +655,8 @@ impl Mapper for AxROM {
 // TODO: ColorDreams and GxRom are basically the same, use PhantomData generics
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct ColorDreams;
+codec_fields!(ColorDreams {});
 
-#[typetag::serde]
 impl Mapper for ColorDreams {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 1);
@@ -394,12 +673,79 @@ impl Mapper for ColorDreams {
   }
 }
 
+// Mapper 59 (BMC-D1038 multicarts)
+// https://www.nesdev.org/wiki/INES_Mapper_059
+// The register is the address written to. A dip switch picks the title shown by the menu,
+// which reads it back from the rom space.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct INesMapper059 {
+  dipswitches: u8,
+  reads_dipswitches: bool,
+}
+codec_fields!(INesMapper059 { dipswitches, reads_dipswitches });
+
+impl Mapper for INesMapper059 {
+  fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
+    banks.prg = Banking::new_prg(header, 2);
+    banks.chr = Banking::new_chr(header, 1);
+    banks.prg.set_page(1, 1);
+    Box::new(Self { dipswitches: 0, reads_dipswitches: false })
+  }
+
+  fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, _: u8) {
+    let prg_bank = (addr >> 4) & 0b111;
+    if addr & 0x80 != 0 {
+      banks.prg.set_page(0, prg_bank);
+      banks.prg.set_page(1, prg_bank);
+    } else {
+      banks.prg.set_page(0, prg_bank & !1);
+      banks.prg.set_page(1, prg_bank | 1);
+    }
+    banks.chr.set_page(0, addr & 0b111);
+
+    let mirroring = match addr & 0b1000 != 0 {
+      false => Mirroring::Vertical,
+      true  => Mirroring::Horizontal,
+    };
+    banks.ciram.update(mirroring);
+    self.reads_dipswitches = addr & 0x100 != 0;
+  }
+
+  fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
+    match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
+      0x6000..=0x7FFF => PrgTarget::SRam(true, banks.sram.translate(addr)),
+      0x8000..=0xFFFF if self.reads_dipswitches => PrgTarget::Cart,
+      0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
+      _ => unreachable!()
+    }
+  }
+
+  fn cart_read(&mut self, addr: usize) -> u8 {
+    match addr {
+      0x8000..=0xFFFF => self.dipswitches,
+      _ => 0xFF,
+    }
+  }
+
+  // the register still latches while the dip switches are read back
+  fn cart_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
+    if addr >= 0x8000 {
+      self.prg_write(banks, addr, val);
+    }
+  }
+
+  fn set_dipswitches(&mut self, mask: u8) {
+    self.dipswitches = mask & 0b11;
+  }
+}
+
 // Mapper 66
 // https://www.nesdev.org/wiki/GxROM
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct GxROM;
+codec_fields!(GxROM {});
 
-#[typetag::serde]
 impl Mapper for GxROM {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 1);
@@ -421,8 +767,8 @@ impl Mapper for GxROM {
 // https://www.nesdev.org/wiki/INES_Mapper_071
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Codemasters;
+codec_fields!(Codemasters {});
 
-#[typetag::serde]
 impl Mapper for Codemasters {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -454,8 +800,8 @@ impl Mapper for Codemasters {
 pub struct INesMapper078 {
   uses_hv_mirroring: bool,
 }
+codec_fields!(INesMapper078 {});
 
-#[typetag::serde]
 impl Mapper for INesMapper078 {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     let uses_hv_mirroring = 
@@ -503,8 +849,8 @@ impl Mapper for INesMapper078 {
 // https://www.nesdev.org/wiki/INES_Mapper_031
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct INesMapper031;
+codec_fields!(INesMapper031 {});
 
-#[typetag::serde]
 impl Mapper for INesMapper031 {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 8);
@@ -527,8 +873,8 @@ impl Mapper for INesMapper031 {
 // https://www.nesdev.org/wiki/VRC1
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct VRC1;
+codec_fields!(VRC1 {});
 
-#[typetag::serde]
 impl Mapper for VRC1 {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     banks.prg = Banking::new_prg(header, 4);
@@ -549,16 +895,14 @@ impl Mapper for VRC1 {
         };
         banks.ciram.update(mirroring);
 
-        let bank0 = banks.chr.bankings[0];
-        let bank0_hi = (val as usize >> 1) & 1;
-        banks.chr.set_page(0, (bank0_hi << 5) | bank0);
-
-        let bank1 = banks.chr.bankings[1];
-        let bank1_hi = (val as usize >> 1) & 1;
-        banks.chr.set_page(1, (bank1_hi << 5) | bank1);
+        // bits 1 and 2 are the high bits of the two chr banks
+        for page in 0..2 {
+          let hi = (val as usize >> (page + 1)) & 1;
+          banks.chr.set_page(page, (hi << 4) | (banks.chr.bank(page) & 0b1111));
+        }
       }
-      0xE000..=0xEFFF => banks.chr.set_page(0, val as usize),
-      0xF000..=0xFFFF => banks.chr.set_page(1, val as usize),
+      0xE000..=0xEFFF => banks.chr.set_page(0, (banks.chr.bank(0) & 0b1_0000) | (val as usize & 0b1111)),
+      0xF000..=0xFFFF => banks.chr.set_page(1, (banks.chr.bank(1) & 0b1_0000) | (val as usize & 0b1111)),
       _ => {}
     }
   }
@@ -570,8 +914,8 @@ impl Mapper for VRC1 {
 pub struct INesMapper206 {
   mmc3: MMC3,
 }
+codec_fields!(INesMapper206 { mmc3 });
 
-#[typetag::serde]
 impl Mapper for INesMapper206 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     let mmc3 = *MMC3::new(header, banks);
@@ -588,8 +932,8 @@ impl Mapper for INesMapper206 {
 // https://www.nesdev.org/wiki/INES_Mapper_087
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct INesMapper087;
+codec_fields!(INesMapper087 {});
 
-#[typetag::serde]
 impl Mapper for INesMapper087 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -609,6 +953,7 @@ impl Mapper for INesMapper087 {
 
   fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
     match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
       0x6000..=0x7FFF => PrgTarget::Prg(addr),
       0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
       _ => unreachable!()
@@ -1,6 +1,6 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring, PrgTarget};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::{codec_fields, NenCodec}};
 
-use super::{set_byte_hi, set_byte_lo, Banking, Mapper};
+use super::{eeprom::{Eeprom24C02, EEPROM_24C02_SIZE}, set_byte_hi, set_byte_lo, Banking, Mapper, MapperFields};
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct BandaiFCG {
@@ -11,9 +11,23 @@ pub struct BandaiFCG {
   irq_count: u16,
   irq_latch: u16,
   irq_requested: Option<()>,
+  // savestates before version 4 didn't have it
+  #[serde(default)]
+  serial: Eeprom24C02,
+}
+codec_fields!(BandaiFCG { eeprom, irq_enabled, irq_count, irq_latch, irq_requested, serial });
+
+impl BandaiFCG {
+  // Mapper states of savestates before version 4 end before the serial state of the eeprom, which is idle then.
+  // The ones migrated from version 1 fields already have it.
+  pub(crate) fn add_serial_state(state: &mut Vec<u8>) {
+    let mut mapper = Self { eeprom: vec![0; EEPROM_24C02_SIZE].into_boxed_slice(), ..Default::default() };
+    if mapper.load(state).is_err() {
+      Eeprom24C02::default().encode(state);
+    }
+  }
 }
 
-#[typetag::serde]
 impl Mapper for BandaiFCG {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -21,7 +35,7 @@ impl Mapper for BandaiFCG {
 
     banks.chr = Banking::new_chr(header, 8);
 
-    let eeprom = vec![0; 256].into_boxed_slice();
+    let eeprom = vec![0; EEPROM_24C02_SIZE].into_boxed_slice();
     Box::new(Self{
       submapper: header.submapper,
       eeprom,
@@ -31,10 +45,6 @@ impl Mapper for BandaiFCG {
 
   fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
     match (addr, self.submapper) {
-      (0x6000..=0x7FFF, 5) => {
-        // submapper 5 eeprom read
-      }
-      
       (0x6000..=0x6007 | 0x8000..=0x8007, _) => {
         let page = addr & 0x07;
         banks.chr.set_page(page, val as usize);
@@ -67,21 +77,47 @@ impl Mapper for BandaiFCG {
       (0x800B, _) => self.irq_latch = set_byte_hi(self.irq_latch, val),
       (0x800C, _) => self.irq_latch = set_byte_lo(self.irq_latch, val),
 
-      (0x800D, _) => {
-        // submapper 5 eeprom ctrl
-      }
-        _ => {}
+      // the game drives the clock with bit 5 and the data with bit 6, unless bit 7 lets the eeprom drive it
+      (0x800D, 5) => self.serial.write(&mut self.eeprom, val & 0x20 != 0, val & 0xC0 != 0),
+      _ => {}
+    }
+  }
+
+  fn get_mapper_nvram(&self) -> Option<Vec<u8>> {
+    (self.submapper == 5).then(|| self.eeprom.to_vec())
+  }
+
+  fn set_mapper_nvram(&mut self, data: Vec<u8>) {
+    if data.len() == self.eeprom.len() {
+      self.eeprom = data.into_boxed_slice();
     }
   }
 
+  fn debug_state(&self) -> serde_json::Value {
+    let mut state = self.fields_json();
+    if let Some(fields) = state.as_object_mut() { fields.remove("eeprom"); }
+    state
+  }
+
   fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
     match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
+      // the LZ93D50 has no registers there, only the eeprom data line
+      0x6000..=0x7FFF if self.submapper == 5 => PrgTarget::Cart,
       0x6000..=0x7FFF => PrgTarget::Prg(addr),
       0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
       _ => unreachable!(),
     }
   }
 
+  // Bit 4 is the eeprom data line, the others are open bus
+  fn cart_read(&mut self, addr: usize) -> u8 {
+    match addr {
+      0x6000..=0x7FFF => 0xEF | (self.serial.output() as u8) << 4,
+      _ => 0xFF,
+    }
+  }
+
   fn notify_cpu_cycle(&mut self) {
     if !self.irq_enabled { return; }
 
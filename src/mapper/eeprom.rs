@@ -0,0 +1,233 @@
+use crate::codec::{codec_enum, codec_fields};
+
+// 24C02 serial eeprom, which Bandai FCG boards with the LZ93D50 save games in.
+// It speaks I²C: the game drives the clock and data lines through a mapper register, and reads the data line back.
+// https://www.nesdev.org/wiki/Bandai_FCG_board#Serial_EEPROM
+pub const EEPROM_24C02_SIZE: usize = 256;
+// Sequential writes wrap around in their page
+const PAGE_SIZE: u8 = 8;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+enum Phase {
+  #[default] Idle,
+  // receiving a byte: the device address, the word address, or data to write
+  Device, Address, Write,
+  // sending a byte of data
+  Read,
+  // the chip acknowledges the byte it received, or the game the one it read
+  AckOut, AckIn,
+}
+codec_enum!(Phase { Idle, Device, Address, Write, Read, AckOut, AckIn });
+
+// Only the serial state, the memory is kept by the mapper
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Eeprom24C02 {
+  phase: Phase,
+  // where the acknowledge leads
+  next: Phase,
+  scl: bool,
+  sda: bool,
+  shift: u8,
+  bits: u8,
+  addr: u8,
+  // the data line as driven by the chip, which lets it be pulled up when it has nothing to say
+  out: bool,
+}
+codec_fields!(Eeprom24C02 { phase, next, scl, sda, shift, bits, addr, out });
+
+impl Default for Eeprom24C02 {
+  fn default() -> Self {
+    Self { phase: Phase::Idle, next: Phase::Idle, scl: true, sda: true, shift: 0, bits: 0, addr: 0, out: true }
+  }
+}
+
+impl Eeprom24C02 {
+  // The data line, as the game reads it back
+  pub fn output(&self) -> bool {
+    self.out
+  }
+
+  pub fn write(&mut self, mem: &mut [u8], scl: bool, sda: bool) {
+    let (prev_scl, prev_sda) = (self.scl, self.sda);
+    self.scl = scl;
+    self.sda = sda;
+
+    // the data line only changes with the clock low, unless it is a start (falling) or a stop (rising)
+    if prev_scl && scl && prev_sda != sda {
+      (self.phase, self.shift, self.bits) = (if sda { Phase::Idle } else { Phase::Device }, 0, 0);
+      self.out = true;
+    } else if !prev_scl && scl {
+      self.clock_rise(sda);
+    } else if prev_scl && !scl {
+      self.clock_fall(mem);
+    }
+  }
+
+  // The receiving side samples the data line
+  fn clock_rise(&mut self, sda: bool) {
+    match self.phase {
+      Phase::Device | Phase::Address | Phase::Write => {
+        self.shift = (self.shift << 1) | sda as u8;
+        self.bits += 1;
+      }
+      // the game stops reading by not acknowledging
+      Phase::AckIn => self.next = if sda { Phase::Idle } else { Phase::Read },
+      _ => {}
+    }
+  }
+
+  // The sending side puts the next bit on the data line
+  fn clock_fall(&mut self, mem: &mut [u8]) {
+    match self.phase {
+      Phase::Device | Phase::Address | Phase::Write if self.bits == 8 => {
+        self.bits = 0;
+        self.next = match self.phase {
+          // the device type is 1010, the chip select pins are all grounded
+          Phase::Device if self.shift & 0xFE != 0xA0 => {
+            self.phase = Phase::Idle;
+            return;
+          }
+          Phase::Device if self.shift & 1 != 0 => Phase::Read,
+          Phase::Device => Phase::Address,
+          Phase::Address => {
+            self.addr = self.shift;
+            Phase::Write
+          }
+          _ => {
+            mem[self.addr as usize] = self.shift;
+            self.addr = (self.addr & !(PAGE_SIZE-1)) | (self.addr.wrapping_add(1) & (PAGE_SIZE-1));
+            Phase::Write
+          }
+        };
+        self.phase = Phase::AckOut;
+        self.out = false;
+      }
+      Phase::AckOut | Phase::AckIn => {
+        self.phase = self.next;
+        self.out = true;
+        if self.phase == Phase::Read {
+          (self.shift, self.bits) = (mem[self.addr as usize], 0);
+          self.send_bit();
+        }
+      }
+      Phase::Read if self.bits < 8 => self.send_bit(),
+      Phase::Read => {
+        self.addr = self.addr.wrapping_add(1);
+        self.phase = Phase::AckIn;
+        self.out = true;
+      }
+      _ => {}
+    }
+  }
+
+  fn send_bit(&mut self) {
+    self.out = self.shift & (0x80 >> self.bits) != 0;
+    self.bits += 1;
+  }
+}
+
+#[cfg(test)]
+mod eeprom_tests {
+  use super::*;
+
+  // Drives the lines as a game would, reading back the data line on every clock
+  struct Bus { chip: Eeprom24C02, mem: Vec<u8> }
+
+  impl Bus {
+    fn new() -> Self {
+      Self { chip: Eeprom24C02::default(), mem: vec![0; EEPROM_24C02_SIZE] }
+    }
+
+    fn lines(&mut self, scl: bool, sda: bool) {
+      self.chip.write(&mut self.mem, scl, sda);
+    }
+
+    fn start(&mut self) {
+      self.lines(false, true);
+      self.lines(true, true);
+      self.lines(true, false);
+      self.lines(false, false);
+    }
+
+    fn stop(&mut self) {
+      self.lines(false, false);
+      self.lines(true, false);
+      self.lines(true, true);
+    }
+
+    fn clock(&mut self, sda: bool) -> bool {
+      self.lines(false, sda);
+      self.lines(true, sda);
+      let bit = self.chip.output();
+      self.lines(false, sda);
+      bit
+    }
+
+    // Returns whether the chip acknowledged
+    fn send(&mut self, byte: u8) -> bool {
+      for i in (0..8).rev() {
+        self.clock(byte & (1 << i) != 0);
+      }
+      !self.clock(true)
+    }
+
+    fn receive(&mut self, ack: bool) -> u8 {
+      let byte = (0..8).fold(0, |byte, _| (byte << 1) | self.clock(true) as u8);
+      self.clock(!ack);
+      byte
+    }
+  }
+
+  #[test]
+  fn write_then_read() {
+    let mut bus = Bus::new();
+    bus.start();
+    assert!(bus.send(0xA0));
+    assert!(bus.send(0x10));
+    assert!(bus.send(0x12));
+    assert!(bus.send(0x34));
+    bus.stop();
+    assert_eq!(bus.mem[0x10..0x12], [0x12, 0x34]);
+
+    // random read: the address is written, then a repeated start reads from it
+    bus.start();
+    assert!(bus.send(0xA0));
+    assert!(bus.send(0x10));
+    bus.start();
+    assert!(bus.send(0xA1));
+    assert_eq!(bus.receive(true), 0x12);
+    assert_eq!(bus.receive(false), 0x34);
+    bus.stop();
+
+    // current address read, from where the last one stopped
+    bus.mem[0x12] = 0x56;
+    bus.start();
+    assert!(bus.send(0xA1));
+    assert_eq!(bus.receive(false), 0x56);
+    bus.stop();
+  }
+
+  #[test]
+  fn page_write_wraps() {
+    let mut bus = Bus::new();
+    bus.start();
+    bus.send(0xA0);
+    bus.send(0x06);
+    for byte in [1, 2, 3] {
+      bus.send(byte);
+    }
+    bus.stop();
+    assert_eq!((bus.mem[0x06], bus.mem[0x07], bus.mem[0x00], bus.mem[0x08]), (1, 2, 3, 0));
+  }
+
+  #[test]
+  fn other_devices_are_ignored() {
+    let mut bus = Bus::new();
+    bus.start();
+    assert!(!bus.send(0xB0));
+    assert!(!bus.send(0x00));
+    bus.stop();
+    assert!(bus.mem.iter().all(|&b| b == 0));
+    assert!(bus.chip.output());
+  }
+}
@@ -0,0 +1,126 @@
+use crate::codec::{codec_enum, codec_fields, NenCodec, Reader};
+
+// SST39SF0x0 flash chip, used by self-flashing homebrew boards to save games in their own prg rom
+// https://www.nesdev.org/wiki/UNROM_512#Flash_ROM_programming
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+enum FlashState { #[default] Idle, Unlock1, Unlock2, Program, Erase, EraseUnlock1, EraseUnlock2 }
+codec_enum!(FlashState { Idle, Unlock1, Unlock2, Program, Erase, EraseUnlock1, EraseUnlock2 });
+
+#[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum FlashOp { Program(usize, u8), EraseSector(usize), #[default] EraseChip }
+
+impl NenCodec for FlashOp {
+  fn encode(&self, out: &mut Vec<u8>) {
+    match self {
+      FlashOp::Program(addr, val) => { out.push(0); addr.encode(out); val.encode(out); }
+      FlashOp::EraseSector(addr) => { out.push(1); addr.encode(out); }
+      FlashOp::EraseChip => out.push(2),
+    }
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let (mut addr, mut val) = (0usize, 0u8);
+    *self = match input.take_array::<1>()?[0] {
+      0 => { addr.decode(input)?; val.decode(input)?; FlashOp::Program(addr, val) }
+      1 => { addr.decode(input)?; FlashOp::EraseSector(addr) }
+      2 => FlashOp::EraseChip,
+      tag => return Err(format!("Savestate is corrupted: {tag} isn't a flash operation")),
+    };
+    Ok(())
+  }
+}
+
+pub const FLASH_SECTOR_SIZE: usize = 4 * 1024;
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct SstFlash {
+  state: FlashState,
+}
+codec_fields!(SstFlash { state });
+
+impl SstFlash {
+  // Takes the address as seen by the chip, that is, the prg rom address.
+  pub fn write(&mut self, addr: usize, val: u8) -> Option<FlashOp> {
+    use FlashState::*;
+    // only the lower 15 bits are decoded for the command sequence
+    let cmd_addr = addr & 0x7FFF;
+
+    let (state, op) = match (self.state, cmd_addr, val) {
+      (Idle, 0x5555, 0xAA) => (Unlock1, None),
+      (Unlock1, 0x2AAA, 0x55) => (Unlock2, None),
+      (Unlock2, 0x5555, 0xA0) => (Program, None),
+      (Unlock2, 0x5555, 0x80) => (Erase, None),
+      (Program, _, _) => (Idle, Some(FlashOp::Program(addr, val))),
+      (Erase, 0x5555, 0xAA) => (EraseUnlock1, None),
+      (EraseUnlock1, 0x2AAA, 0x55) => (EraseUnlock2, None),
+      (EraseUnlock2, 0x5555, 0x10) => (Idle, Some(FlashOp::EraseChip)),
+      (EraseUnlock2, _, 0x30) => (Idle, Some(FlashOp::EraseSector(addr))),
+      // software id mode and reset commands (or garbage) bring the chip back to reading
+      _ => (Idle, None),
+    };
+
+    self.state = state;
+    op
+  }
+}
+
+#[cfg(test)]
+mod flash_tests {
+  use super::*;
+
+  fn run(flash: &mut SstFlash, writes: &[(usize, u8)]) -> Vec<Option<FlashOp>> {
+    writes.iter().map(|&(addr, val)| flash.write(addr, val)).collect()
+  }
+
+  const UNLOCK: [(usize, u8); 2] = [(0x5555, 0xAA), (0x2AAA, 0x55)];
+
+  #[test]
+  fn program() {
+    let mut flash = SstFlash::default();
+    let ops = run(&mut flash, &[UNLOCK[0], UNLOCK[1], (0x5555, 0xA0), (0x1_2345, 0x42)]);
+    assert_eq!(ops, [None, None, None, Some(FlashOp::Program(0x1_2345, 0x42))]);
+    // a single byte is programmed per sequence
+    assert_eq!(flash.write(0x1_2346, 0x42), None);
+  }
+
+  #[test]
+  fn erase() {
+    let mut flash = SstFlash::default();
+    let erase = [UNLOCK[0], UNLOCK[1], (0x5555, 0x80), UNLOCK[0], UNLOCK[1]];
+    run(&mut flash, &erase);
+    assert_eq!(flash.write(0x3_1000, 0x30), Some(FlashOp::EraseSector(0x3_1000)));
+    run(&mut flash, &erase);
+    assert_eq!(flash.write(0x5555, 0x10), Some(FlashOp::EraseChip));
+  }
+
+  #[test]
+  fn commands_ignore_the_upper_address_lines() {
+    let mut flash = SstFlash::default();
+    let ops = run(&mut flash, &[(0x1_D555, 0xAA), (0x4_AAAA, 0x55), (0x7_D555, 0xA0), (0x10, 0)]);
+    assert_eq!(ops[3], Some(FlashOp::Program(0x10, 0)));
+  }
+
+  #[test]
+  fn broken_sequences_go_back_to_reading() {
+    let sequences: [&[(usize, u8)]; 5] = [
+      // wrong address
+      &[(0x5555, 0xAA), (0x2AAB, 0x55), (0x5555, 0xA0), (0, 0)],
+      // wrong value
+      &[(0x5555, 0xAA), (0x2AAA, 0x56), (0x5555, 0xA0), (0, 0)],
+      // software id entry, then a write
+      &[UNLOCK[0], UNLOCK[1], (0x5555, 0x90), (0, 0)],
+      // reset in the middle of an erase
+      &[UNLOCK[0], UNLOCK[1], (0x5555, 0x80), (0x5555, 0xF0), UNLOCK[1], (0x5555, 0x10)],
+      // unknown erase command
+      &[UNLOCK[0], UNLOCK[1], (0x5555, 0x80), UNLOCK[0], UNLOCK[1], (0x5555, 0x20)],
+    ];
+
+    for writes in sequences {
+      let mut flash = SstFlash::default();
+      assert!(run(&mut flash, writes).iter().all(Option::is_none), "{writes:X?}");
+      // and a full sequence works right after
+      let ops = run(&mut flash, &[UNLOCK[0], UNLOCK[1], (0x5555, 0xA0), (0x20, 1)]);
+      assert_eq!(ops[3], Some(FlashOp::Program(0x20, 1)), "{writes:X?}");
+    }
+  }
+}
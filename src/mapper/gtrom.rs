@@ -1,11 +1,15 @@
-use crate::cart::{CartBanking, CartHeader, PpuTarget, PrgTarget};
+use crate::{cart::{CartBanking, CartHeader, PpuTarget, PrgTarget}, codec::codec_fields};
 
-use super::{Banking, Mapper};
+use super::{flash::{FlashOp, SstFlash}, Banking, Mapper};
 
 // Mapper 111
 // https://www.nesdev.org/wiki/GTROM
 #[derive(serde::Serialize, serde::Deserialize)]
-pub struct GTROM;
+pub struct GTROM {
+  flash: SstFlash,
+  flash_op: Option<FlashOp>,
+}
+codec_fields!(GTROM { flash, flash_op });
 impl GTROM {
   fn write(&mut self, banks: &mut CartBanking, val: u8) {
     banks.prg.set_page(0, val as usize & 0b1111);
@@ -15,19 +19,21 @@ impl GTROM {
   }
 }
 
-#[typetag::serde]
 impl Mapper for GTROM {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 1);
     banks.chr = Banking::new_chr(header, 1);
     banks.ciram = Banking::new(header.chr_real_size(), 0x2000, 8*1024, 1);
 
-    Box::new(Self)
+    Box::new(Self { flash: SstFlash::default(), flash_op: None })
   }
 
   fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
-    if (0x7000..=0x7FFF).contains(&addr) {
-      self.write(banks, val);
+    match addr {
+      0x7000..=0x7FFF => self.write(banks, val),
+      // the whole prg window is wired to the flash chip
+      0x8000..=0xFFFF => self.flash_op = self.flash.write(banks.prg.translate(addr), val),
+      _ => {}
     }
   }
   fn cart_write(&mut self, banks: &mut CartBanking, addr: usize, val:u8) {
@@ -36,8 +42,13 @@ impl Mapper for GTROM {
     }
   }
 
+  fn poll_flash(&mut self) -> Option<FlashOp> {
+    self.flash_op.take()
+  }
+
   fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
     match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
       0x6000..=0x7FFF => PrgTarget::Prg(addr),
       0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
       _ => unreachable!()
@@ -1,8 +1,11 @@
+use crate::codec::{codec_enum, codec_fields};
+
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub enum IrqMode { 
   #[default] Mode0, // Scanline 
   Mode1             // Cycle
 }
+codec_enum!(IrqMode { Mode0, Mode1 });
 
 // https://www.nesdev.org/wiki/VRC_IRQ
 #[derive(Default, serde::Serialize, serde::Deserialize)]
@@ -15,6 +18,7 @@ pub struct KonamiIrq {
   pub mode: IrqMode,
   pub requested: Option<()>,
 }
+codec_fields!(KonamiIrq { prescaler, count, latch, enabled_after_ack, enabled, mode, requested });
 
 impl KonamiIrq {
   pub fn write_ctrl(&mut self, val: u8) {
@@ -36,4 +40,27 @@ impl KonamiIrq {
     self.requested = None;
     self.enabled = self.enabled_after_ack;    
   }
+
+  // Clocked every cpu cycle. In scanline mode, the prescaler counts 113.67 cycles per scanline.
+  pub fn step(&mut self) {
+    if !self.enabled { return; }
+
+    match self.mode {
+      IrqMode::Mode1 => {
+        self.count += 1;
+      }
+      IrqMode::Mode0 => {
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+          self.prescaler += 341;
+          self.count += 1;
+        }
+      }
+    }
+
+    if self.count > 0xFF {
+      self.requested = Some(());
+      self.count = self.latch;
+    }
+  }
 }
\ No newline at end of file
@@ -1,11 +1,13 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::{codec_enum, codec_fields}};
 
 use super::{Banking, Mapper};
 
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum PrgMode { Bank32kb, FixFirstPage, #[default] FixLastPage }
+codec_enum!(PrgMode { Bank32kb, FixFirstPage, FixLastPage });
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum ChrMode { #[default] Bank8kb, Bank4kb }
+codec_enum!(ChrMode { Bank8kb, Bank4kb });
 
 // Mapper 01
 // https://www.nesdev.org/wiki/MMC1
@@ -24,7 +26,11 @@ pub struct MMC1 {
   write_lock_delay: u8,
   prg_mode: PrgMode,
   chr_mode: ChrMode,
+  // MMC1B and later only, the MMC1A always has its ram enabled
+  #[serde(default)]
+  sram_disabled: bool,
 }
+codec_fields!(MMC1 { prg_select, prg_256kb_bank, chr_select0, chr_select1, last_wrote_chr_select1, shift_reg, shift_writes, write_lock_delay, prg_mode, chr_mode, sram_disabled });
 
 impl MMC1 {
   fn write_ctrl(&mut self, banks: &mut CartBanking, val: u8) {
@@ -104,7 +110,6 @@ impl MMC1 {
   }
 }
 
-#[typetag::serde]
 impl Mapper for MMC1 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -169,6 +174,7 @@ impl Mapper for MMC1 {
         }
         0xE000..=0xFFFF => {
           self.prg_select  = self.shift_reg as usize & 0b1111;
+          self.sram_disabled = self.shift_reg & 0b1_0000 != 0;
           self.update_prg_banks(banks);
         }
         _ => {}
@@ -179,6 +185,15 @@ impl Mapper for MMC1 {
     }
   }
 
+  fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
+    match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
+      0x6000..=0x7FFF => PrgTarget::SRam(!self.sram_disabled, banks.sram.translate(addr)),
+      0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
+      _ => unreachable!()
+    }
+  }
+
   fn notify_cpu_cycle(&mut self) {
     if self.write_lock_delay > 0 {
       self.write_lock_delay -= 1;
@@ -1,4 +1,4 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring, PpuTarget};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PpuTarget}, codec::{codec_enum, codec_fields}};
 
 use super::{Banking, ChrBanking, Mapper};
 
@@ -7,6 +7,7 @@ use super::{Banking, ChrBanking, Mapper};
 // https://www.nesdev.org/wiki/MMC4 
 #[derive(Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
 enum Mmc2Latch { FD, #[default] FE }
+codec_enum!(Mmc2Latch { FD, FE });
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct MMC2 {
   mapper: u16,
@@ -15,8 +16,8 @@ pub struct MMC2 {
   latch0: Mmc2Latch,
   latch1: Mmc2Latch,
 }
+codec_fields!(MMC2 { chr_banks0, chr_banks1, latch0, latch1 });
 
-#[typetag::serde]
 impl Mapper for MMC2 {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self> {
     let chr_banks0 = Banking::new_chr(header, 2);
@@ -26,9 +27,9 @@ impl Mapper for MMC2 {
       9 => {
         // MMC2 - Three 8 KB PRG ROM banks, fixed to the last three banks
         banks.prg = Banking::new_prg(header, 4);
-        banks.prg.set_page(1, banks.prg.banks_count-3);
-        banks.prg.set_page(2, banks.prg.banks_count-2);
-        banks.prg.set_page(3, banks.prg.banks_count-1);
+        banks.prg.set_page_to_bank_from_end(1, 2);
+        banks.prg.set_page_to_bank_from_end(2, 1);
+        banks.prg.set_page_to_last_bank(3);
       }
       10 => {
         // MMC4 - 16 KB PRG ROM bank, fixed to the last bank
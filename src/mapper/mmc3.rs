@@ -1,11 +1,18 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::{codec_enum, codec_fields}};
 
-use super::{Banking, Mapper};
+use super::{Banking, Mapper, PpuCtx};
+
+// A rise of A12 is only counted after it has been low for about 3 cpu cycles, so that the quick toggles
+// between nametable and pattern fetches aren't seen. The 8 dots of nametable fetches around dot 0 must not pass it.
+// https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+const A12_FILTER_DOTS: u64 = 10;
 
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum PrgMode { #[default] FixLastPages, FixFirstPages }
+codec_enum!(PrgMode { FixLastPages, FixFirstPages });
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum ChrMode { #[default] BiggerFirst, BiggerLast }
+codec_enum!(ChrMode { BiggerFirst, BiggerLast });
 
 // Mapper 04
 // https://www.nesdev.org/wiki/MMC3
@@ -17,6 +24,9 @@ pub struct MMC3 {
   chr_mode: ChrMode,
   pub mirroring: Mirroring,
 
+  // The MMC6 has its own ram protection, which isn't emulated
+  #[serde(default)]
+  is_mmc6: bool,
   sram_read_enabled: bool,
   sram_write_enabled: bool,
 
@@ -26,9 +36,30 @@ pub struct MMC3 {
   pub irq_enabled: bool,
 
   pub irq_requested: Option<()>,
+
+  // Line A12 of the ppu bus, and the dot it last went low
+  #[serde(default)]
+  a12_high: bool,
+  #[serde(default)]
+  a12_low_since: u64,
 }
+codec_fields!(MMC3 { reg_select, prg_mode, chr_mode, mirroring, sram_read_enabled, sram_write_enabled, irq_count, irq_latch, irq_reload, irq_enabled, irq_requested, a12_high, a12_low_since });
 
 impl MMC3 {
+  fn clock_irq_counter(&mut self) {
+    if self.irq_count == 0 || self.irq_reload {
+      self.irq_count = self.irq_latch;
+      self.irq_reload = false;
+    } else {
+      self.irq_count -= 1;
+    }
+
+    if self.irq_enabled && self.irq_count == 0 {
+      nen_log!(trace, "nen::mapper::mmc3", "irq, latch = {}", self.irq_latch);
+      self.irq_requested = Some(());
+    }
+  }
+
   fn write_bank_select(&mut self, banks: &mut CartBanking, val: u8) {
     self.reg_select = val & 0b111;
 
@@ -117,7 +148,6 @@ impl MMC3 {
   }
 }
 
-#[typetag::serde]
 impl Mapper for MMC3 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 4);
@@ -125,12 +155,16 @@ impl Mapper for MMC3 {
 
     // bank second last page to second last bank by default
     // this page is never set by registers, so not setting it here fuck up everything
-    banks.prg.set_page(2, banks.prg.banks_count-2);
+    banks.prg.set_page_to_bank_from_end(2, 1);
     // last page always fixed to last bank
     banks.prg.set_page_to_last_bank(3);
 
     let mapper = Self {
       mirroring: header.mirroring,
+      is_mmc6: header.submapper == 1,
+      // the ram state at power on is undefined, some games use it without ever enabling it
+      sram_read_enabled: true,
+      sram_write_enabled: true,
      ..Default::default()
     };
 
@@ -155,7 +189,7 @@ impl Mapper for MMC3 {
           banks.ciram.update(self.mirroring);
         }
       }
-      (0xA001..=0xBFFF, false) => {
+      (0xA001..=0xBFFF, false) if !self.is_mmc6 => {
         self.sram_write_enabled = val & 0b0100_0000 == 0;
         self.sram_read_enabled  = val & 0b1000_0000 != 0;
       }
@@ -170,17 +204,31 @@ impl Mapper for MMC3 {
     }
   }
  
-  fn notify_mmc3_scanline(&mut self) {
-    if self.irq_count == 0 || self.irq_reload {
-      self.irq_count = self.irq_latch;
-      self.irq_reload = false;
-    } else {
-      self.irq_count -= 1;
+  fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
+    match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
+      0x6000..=0x7FFF => {
+        let mapped = banks.sram.translate(addr);
+        match (self.sram_read_enabled, self.sram_write_enabled) {
+          (true, true) => PrgTarget::SRam(true, mapped),
+          (true, false) => PrgTarget::SRamProtected(mapped),
+          // a disabled chip ignores writes too
+          (false, _) => PrgTarget::SRam(false, mapped),
+        }
+      }
+      0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
+      _ => unreachable!()
     }
+  }
 
-    if self.irq_enabled && self.irq_count == 0 {
-      self.irq_requested = Some(());
+  fn notify_ppu_addr(&mut self, ctx: &mut PpuCtx, addr: u16) {
+    let high = addr & 0x1000 != 0;
+    if high && !self.a12_high && ctx.dot.saturating_sub(self.a12_low_since) >= A12_FILTER_DOTS {
+      self.clock_irq_counter();
+    } else if !high && self.a12_high {
+      self.a12_low_since = ctx.dot;
     }
+    self.a12_high = high;
   }
 
   fn poll_irq(&mut self) -> bool {
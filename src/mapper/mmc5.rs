@@ -1,23 +1,40 @@
-use crate::{apu::pulse::Pulse, cart::{CartBanking, CartHeader, Mirroring, PpuTarget, PrgTarget}, ppu::PpuState};
-use super::{Banking, ChrBanking, Mapper};
+use crate::{apu::pulse::Pulse, cart::{CartBanking, CartHeader, Mirroring, PpuTarget, PrgTarget}, codec::{codec_enum, codec_fields}};
+use super::{Banking, ChrBanking, Mapper, MapperFields, PpuCtx};
+
+// What the ppu is fetching, as the mmc5 sees it
+#[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
+enum PpuState {
+  FetchBg,
+  FetchSpr,
+  #[default] Vblank
+}
+codec_enum!(PpuState { FetchBg, FetchSpr, Vblank });
+
+// The ppu is out of the frame once it stops reading for this many cpu cycles
+const PPU_IDLE_CYCLES: u8 = 3;
 
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum PrgMode { Bank32kb, Bank16kb, BankMixed, #[default] Bank8kb }
+codec_enum!(PrgMode { Bank32kb, Bank16kb, BankMixed, Bank8kb });
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 enum ChrMode { Bank8kb, Bank4kb, Bank2kb, #[default] Bank1kb }
+codec_enum!(ChrMode { Bank8kb, Bank4kb, Bank2kb, Bank1kb });
 
 #[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
 enum ExRamMode { Nametbl, NametblEx, CpuReadWrite, #[default] CpuReadOnly }
+codec_enum!(ExRamMode { Nametbl, NametblEx, CpuReadWrite, CpuReadOnly });
 
 #[derive(Copy, Clone, Default, Debug, serde::Serialize, serde::Deserialize)]
 enum NametblMapping { #[default] CiRam0, CiRam1, ExRam, FillMode }
+codec_enum!(NametblMapping { CiRam0, CiRam1, ExRam, FillMode });
 
 #[derive(Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
 enum AccessTarget { #[default] Prg, SRam }
+codec_enum!(AccessTarget { Prg, SRam });
 
 fn is_attribute(addr: usize) -> bool {
-  (addr % 1024) > 960
+  (addr % 1024) >= 960
 }
 
 // Mapper 5
@@ -45,14 +62,21 @@ pub struct MMC5 {
   exram: Box<[u8]>,
   // this is used for extended attributes mode
   exram_banks: Banking<ChrBanking>,
+  ex_palette: u8,
 
   nametbls_mapping: [NametblMapping; 4],
   fill_mode_tile_id: u8,
   fill_mode_palette_id: u8,
 
   vsplit_enabled: bool,
-  vsplit_side: bool,
+  vsplit_right_side: bool,
   vsplit_count: u8,
+  vsplit_scroll: u8,
+  vsplit_banks: Banking<ChrBanking>,
+  // the tile being fetched in the scanline, and the scanline it will be rendered on
+  vsplit_tile: u8,
+  vsplit_line: u8,
+  in_vsplit: bool,
 
   irq_enabled: bool,
   irq_pending: bool,
@@ -60,6 +84,13 @@ pub struct MMC5 {
   irq_count: u8,
   irq_requested: Option<()>,
   ppu_in_frame: bool,
+  // A scanline starts after 3 reads of the same nametable address, which only happen at its start
+  #[serde(default)]
+  last_nt_addr: u16,
+  #[serde(default)]
+  nt_reads: u8,
+  #[serde(default)]
+  ppu_idle: u8,
 
   mirroring: Mirroring,
 
@@ -69,6 +100,7 @@ pub struct MMC5 {
   pulse1: Pulse,
   pulse2: Pulse
 }
+codec_fields!(MMC5 { ppu_spr_16, ppu_data_sub, ppu_state, prg_mode, prg_selects, sram_write_lock1, sram_write_lock2, chr_mode, chr_selects, bg_banks, spr_banks, last_selected_bg_regs, chr_bank_hi, exram_mode, exram, exram_banks, ex_palette, nametbls_mapping, fill_mode_tile_id, fill_mode_palette_id, vsplit_enabled, vsplit_right_side, vsplit_count, vsplit_scroll, vsplit_banks, vsplit_tile, vsplit_line, in_vsplit, irq_enabled, irq_pending, irq_value, irq_count, irq_requested, ppu_in_frame, last_nt_addr, nt_reads, ppu_idle, mirroring, multiplicand, multiplier, pulse1, pulse2 });
 
 // https://github.com/SourMesen/Mesen2/blob/master/Core/NES/Mappers/Nintendo/MMC5.h
 impl MMC5 {
@@ -79,15 +111,54 @@ impl MMC5 {
     self.irq_count = 0;
   }
 
+  // https://www.nesdev.org/wiki/MMC5#Scanline_Detection_and_Scanline_IRQ
+  fn detect_scanline(&mut self, addr: u16) {
+    if self.nt_reads >= 2 {
+      self.nt_reads = 0;
+      self.scanline_start();
+    } else if (0x2000..=0x2FFF).contains(&addr) && addr == self.last_nt_addr {
+      self.nt_reads += 1;
+    } else {
+      self.nt_reads = 0;
+    }
+    self.last_nt_addr = addr;
+  }
+
+  fn scanline_start(&mut self) {
+    if self.ppu_in_frame {
+      self.irq_count += 1;
+
+      if self.irq_count == self.irq_value {
+        self.irq_pending = true;
+        if self.irq_enabled {
+          self.irq_requested = Some(());
+        }
+      }
+
+    } else {
+      self.irq_requested = None;
+      self.ppu_in_frame = true;
+      self.irq_count = 0;
+    }
+  }
+
   fn in_8x16_mode(&self) -> bool {
     self.ppu_spr_16 && self.ppu_data_sub
   }
 
+  // Boards have up to two ram chips of 32kb, selected by bit 2 of the bank.
+  // On those with two 8kb chips, it is the only bit that matters.
+  // https://www.nesdev.org/wiki/MMC5#PRG-RAM_configurations
+  fn set_sram_page(banks: &mut CartBanking, page: usize, bank: usize) {
+    let bank = if banks.sram.data_size == 16*1024 { (bank >> 2) & 1 } else { bank & 0b111 };
+    banks.sram.set_page(page, bank);
+  }
+
   fn set_prg_page(&self, banks: &mut CartBanking, reg: usize, page: usize) {
     let (target, bank) = self.prg_selects[reg];
     match target {
       AccessTarget::Prg => banks.prg.set_page(page, bank),
-      AccessTarget::SRam => banks.sram.set_page(page+1, bank),
+      AccessTarget::SRam => Self::set_sram_page(banks, page+1, bank),
     }
   }
 
@@ -100,15 +171,15 @@ impl MMC5 {
         banks.prg.set_page(page+1, bank | 1);
       }
       AccessTarget::SRam => {
-        banks.sram.set_page(page+1, bank);
-        banks.sram.set_page(page+2, bank | 1);
+        Self::set_sram_page(banks, page+1, bank);
+        Self::set_sram_page(banks, page+2, bank | 1);
       },
     }
   }
 
   fn update_prg_and_sram_banks(&mut self, banks: &mut CartBanking) {
     // this is always the same
-    banks.sram.set_page(0, self.prg_selects[0].1);
+    Self::set_sram_page(banks, 0, self.prg_selects[0].1);
 
     // Register 5114, only used in mode3
     if self.prg_mode == PrgMode::Bank8kb {
@@ -142,113 +213,13 @@ impl MMC5 {
   }
 
   fn update_chr_banks(&mut self) {
-    self.update_exram_banks();
-
-    let selector = !self.ppu_spr_16 
-      || (!self.ppu_in_frame && !self.last_selected_bg_regs);
-
-    if !selector {
-      match self.chr_mode {
-        ChrMode::Bank8kb => {
-          let bank = self.chr_selects[11] as usize;
-          for page in 0..8 {
-            self.bg_banks.set_page(page, bank + page);
-          }
-        }
-        ChrMode::Bank4kb => {
-          let bank = self.chr_selects[11] as usize;
-          for page in 0..4 {
-            self.bg_banks.set_page(page, bank + page);
-          }
-  
-          let bank = self.chr_selects[11] as usize;
-          for page in 4..8 {
-            self.bg_banks.set_page(page, bank + (page-4));
-          }
-        }
-        ChrMode::Bank2kb => {
-          let bank = self.chr_selects[9] as usize;
-          self.bg_banks.set_page(0, bank);
-          self.bg_banks.set_page(1, bank + 1);
-  
-  
-          let bank = self.chr_selects[11] as usize;
-          self.bg_banks.set_page(2, bank);
-          self.bg_banks.set_page(3, bank + 1);
-  
-          let bank = self.chr_selects[9] as usize;
-          self.bg_banks.set_page(4, bank);
-          self.bg_banks.set_page(5, bank + 1);
-  
-          let bank = self.chr_selects[11] as usize;
-          self.bg_banks.set_page(6, bank);
-          self.bg_banks.set_page(7, bank + 1);
-        }
-        ChrMode::Bank1kb => {
-          for i in 0..8 {
-            self.bg_banks.set_page(i, self.chr_selects[8 + (i % 4)] as usize);
-          }
-        }
-      }
-    } else {
-      match self.chr_mode {
-        ChrMode::Bank8kb => {
-          let bank = self.chr_selects[7] as usize;
-          for page in 0..8 {
-            self.spr_banks.set_page(page, bank + page);
-          }
-        }
-        ChrMode::Bank4kb => {
-          let bank = self.chr_selects[4] as usize;
-          for page in 0..4 {
-            self.spr_banks.set_page(page, bank + page);
-          }
-  
-          let bank = self.chr_selects[7] as usize;
-          for page in 4..8 {
-            self.spr_banks.set_page(page, bank + (page-4));
-          }
-        }
-        ChrMode::Bank2kb => {
-          let bank = self.chr_selects[1] as usize;
-          self.spr_banks.set_page(0, bank);
-          self.spr_banks.set_page(1, bank + 1);
-  
-  
-          let bank = self.chr_selects[3] as usize;
-          self.spr_banks.set_page(2, bank);
-          self.spr_banks.set_page(3, bank + 1);
-  
-          let bank = self.chr_selects[5] as usize;
-          self.spr_banks.set_page(4, bank);
-          self.spr_banks.set_page(5, bank + 1);
-  
-          let bank = self.chr_selects[7] as usize;
-          self.spr_banks.set_page(6, bank);
-          self.spr_banks.set_page(7, bank + 1);
-        }
-        ChrMode::Bank1kb => {
-          for i in 0..8 {
-            self.spr_banks.set_page(i, self.chr_selects[i] as usize);
-          }
-        }
-      }
-    }
-  }
-
-  fn update_exram_banks(&mut self) {
-    if self.exram_mode == ExRamMode::NametblEx {
-      // Extended attribute is always in 4kb chr mode
-      let bank = self.chr_selects[4] as usize;
-      for page in 0..4 {
-        self.exram_banks.set_page(page, bank + page);
-      }
+    // https://www.nesdev.org/wiki/MMC5#CHR_Bankswitching_($5120-$5130)
+    let regs = self.chr_selects.map(|reg| ((self.chr_bank_hi as usize) << 8) | reg as usize);
+    set_chr_pages(&mut self.spr_banks, &self.chr_mode, &regs[0..8]);
 
-      let bank = self.chr_selects[7] as usize;
-      for page in 4..8 {
-        self.exram_banks.set_page(page, bank + (page-4));
-      }
-    }
+    // the bg registers are mirrored in both pattern tables
+    let bg_regs = [regs[8], regs[9], regs[10], regs[11], regs[8], regs[9], regs[10], regs[11]];
+    set_chr_pages(&mut self.bg_banks, &self.chr_mode, &bg_regs);
   }
 
   fn exram_read(&self, addr: usize) -> u8 {
@@ -259,31 +230,91 @@ impl MMC5 {
     self.exram[addr % self.exram.len()] = val;
   }
 
-  fn ex_attribute_val(&self, addr: usize) -> PpuTarget {
-    // https://www.nesdev.org/wiki/MMC5#Extended_attributes
-    let ex_attribute = self.exram_read(addr - 0x2000);
+  // https://www.nesdev.org/wiki/MMC5#Vertical_Split_Mode_($5200)
+  fn vsplit_y(&self) -> usize {
+    (self.vsplit_line as usize + self.vsplit_scroll as usize) % 240
+  }
+
+  fn vsplit_nametbl_fetch(&mut self, addr: usize) -> Option<PpuTarget> {
+    if !self.vsplit_enabled
+      || self.ppu_state != PpuState::FetchBg
+      || !matches!(self.exram_mode, ExRamMode::Nametbl | ExRamMode::NametblEx)
+    {
+      return None;
+    }
+
+    let coarse_x = self.vsplit_tile as usize % 32;
+    let coarse_y = self.vsplit_y() / 8;
 
-    println!("ExAttribute Mode access");
+    if !is_attribute(addr) {
+      // each tile fetch starts with the nametable byte, this is where we decide if the tile is in the split region
+      let tile = self.vsplit_tile;
+      self.vsplit_tile = self.vsplit_tile.wrapping_add(1);
+      self.in_vsplit = match self.vsplit_right_side {
+        false => tile < self.vsplit_count,
+        true  => tile >= self.vsplit_count,
+      };
+
+      return self.in_vsplit
+        .then(|| PpuTarget::Value(self.exram_read(coarse_y * 32 + coarse_x)));
+    }
+
+    if !self.in_vsplit { return None; }
+    let attribute = self.exram_read(0x3C0 + (coarse_y / 4) * 8 + coarse_x / 4);
+    let shift = ((coarse_y & 2) << 1) | (coarse_x & 2);
+    let pal = (attribute >> shift) & 0b11;
+    Some(PpuTarget::Value((pal << 6) | (pal << 4) | (pal << 2) | pal))
+  }
+
+  fn vsplit_ptrn_fetch(&self, addr: usize) -> Option<PpuTarget> {
+    if !self.in_vsplit || self.ppu_state != PpuState::FetchBg { return None; }
+
+    // the fine y has to be the split one, not the ppu one
+    let addr = (addr & !0b111) | (self.vsplit_y() % 8);
+    Some(PpuTarget::Chr(self.vsplit_banks.translate(addr)))
+  }
+
+  // https://www.nesdev.org/wiki/MMC5#Extended_attributes
+  fn ex_attribute_fetch(&mut self, addr: usize) -> Option<PpuTarget> {
+    if self.exram_mode != ExRamMode::NametblEx || self.ppu_state != PpuState::FetchBg {
+      return None;
+    }
 
     if is_attribute(addr) {
-      let pal = ex_attribute >> 6;
-      let attribute = (pal << 6) | (pal << 4) | (pal << 2) | pal;
-      PpuTarget::Value(attribute)
-    } else {
-      let bank = ex_attribute as usize & 0b0011_1111;
-      let mapped = self.exram_banks.page_to_bank_addr(bank, addr);
-      PpuTarget::Chr(mapped)
+      let pal = self.ex_palette;
+      return Some(PpuTarget::Value((pal << 6) | (pal << 4) | (pal << 2) | pal));
     }
+
+    // the nametable fetch goes on as usual, but the exram byte at the same offset is latched
+    // to select the palette and 4kb chr bank for this tile
+    let ex_attribute = self.exram_read(addr - 0x2000);
+    self.ex_palette = ex_attribute >> 6;
+    let bank = ((self.chr_bank_hi as usize) << 6) | (ex_attribute as usize & 0b0011_1111);
+    self.exram_banks.set_page(0, bank);
+    self.exram_banks.set_page(1, bank);
+    None
+  }
+}
+
+fn set_chr_pages(chr: &mut Banking<ChrBanking>, mode: &ChrMode, regs: &[usize]) {
+  for page in 0..8 {
+    let bank = match mode {
+      ChrMode::Bank8kb => regs[7] * 8 + page,
+      ChrMode::Bank4kb => regs[3 + (page / 4) * 4] * 4 + page % 4,
+      ChrMode::Bank2kb => regs[1 + (page / 2) * 2] * 2 + page % 2,
+      ChrMode::Bank1kb => regs[page],
+    };
+    chr.set_page(page, bank);
   }
 }
 
-#[typetag::serde]
 impl Mapper for MMC5 {
   fn new(header: &CartHeader, banks: &mut CartBanking)-> Box<Self>  {
     banks.prg = Banking::new_prg(header, 4);
     let bg_banks = Banking::new_chr(header, 8);
     let spr_banks = Banking::new_chr(header, 8);
-    let exram_banks = Banking::new_chr(header, 8);
+    let exram_banks = Banking::new(header.chr_real_size(), 0, 4*1024, 2);
+    let vsplit_banks = Banking::new(header.chr_real_size(), 0, 4*1024, 2);
     banks.sram = Banking::new(header.sram_real_size(), 0x6000, 8*1024, 4);
 
     
@@ -294,11 +325,13 @@ impl Mapper for MMC5 {
       bg_banks,
       spr_banks,
       exram_banks,
+      vsplit_banks,
       
       ..Default::default()
     };
     
-    // 5117 is 0xFF at start
+    // 5113 always selects sram, 5117 is 0xFF at start
+    mapper.prg_selects[0].0 = AccessTarget::SRam;
     mapper.prg_selects[4].1 = 0xFF;
 
     mapper.update_prg_and_sram_banks(banks);
@@ -372,8 +405,6 @@ impl Mapper for MMC5 {
           0b10 => ExRamMode::CpuReadWrite,
           _    => ExRamMode::CpuReadOnly,
         };
-
-        self.update_exram_banks();
       }
 
       0x5105 => {
@@ -417,28 +448,26 @@ impl Mapper for MMC5 {
       0x5120..=0x512B => {
         // https://www.nesdev.org/wiki/MMC5#CHR_Bankswitching_($5120-$5130)
         
-        if !self.ppu_spr_16 && addr >= 0x5128 {
-          self.last_selected_bg_regs = false;
-          return;
-        }
-
         let reg = addr - 0x5120;
         self.chr_selects[reg] = val;
         self.last_selected_bg_regs = addr >= 0x5128;
         self.update_chr_banks();
       }
-      0x5130 => self.chr_bank_hi = val & 0b11,
-
-      // 0x5200 => {
-      //   self.vsplit_enabled = (val >> 7) != 0;
-      //   self.vsplit_region = match (val >> 6) & 1 != 0 {
-      //     false => VSplitRegion::Left,
-      //     true  => VSplitRegion::Right,
-      //   };
-      //   self.vsplit_count = val & 0b1_1111;
-      // }
-      // 0x5201 => self.vsplit_scroll = val,
-      // 0x5202 => self.vsplit_bank = val,
+      0x5130 => {
+        self.chr_bank_hi = val & 0b11;
+        self.update_chr_banks();
+      }
+
+      0x5200 => {
+        self.vsplit_enabled = (val >> 7) != 0;
+        self.vsplit_right_side = (val >> 6) & 1 != 0;
+        self.vsplit_count = val & 0b1_1111;
+      }
+      0x5201 => self.vsplit_scroll = val,
+      0x5202 => {
+        self.vsplit_banks.set_page(0, val as usize);
+        self.vsplit_banks.set_page(1, val as usize);
+      }
 
       0x5203 => self.irq_value = val,
       0x5204 => {
@@ -477,7 +506,10 @@ impl Mapper for MMC5 {
         let (target, _) = self.prg_selects[page];
         match target {
           AccessTarget::Prg => PrgTarget::Prg(banks.prg.translate(addr)),
-          AccessTarget::SRam => PrgTarget::SRam(true, banks.sram.translate(addr)),
+          // writes are allowed only once the unlock values are written to both $5102 and $5103
+          AccessTarget::SRam if self.sram_write_lock1 && self.sram_write_lock2 =>
+            PrgTarget::SRam(true, banks.sram.translate(addr)),
+          AccessTarget::SRam => PrgTarget::SRamProtected(banks.sram.translate(addr)),
         }
       }
       _ => unreachable!()
@@ -487,25 +519,32 @@ impl Mapper for MMC5 {
   fn map_ppu_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PpuTarget {  
     match addr {
       0x0000..=0x1FFF => {
+        if let Some(target) = self.vsplit_ptrn_fetch(addr) {
+          return target;
+        }
+
         // https://forums.nesdev.org/viewtopic.php?p=193069#p193069
         let mapped = match (&self.ppu_state, self.in_8x16_mode()) {
-          (_, false) => self.spr_banks.translate(addr),
+          (PpuState::FetchBg, _) if self.exram_mode == ExRamMode::NametblEx
+            => self.exram_banks.translate(addr),
 
           (PpuState::FetchBg, true)  => self.bg_banks.translate(addr),
           (PpuState::FetchSpr, true) => self.spr_banks.translate(addr),
-          (PpuState::Vblank, true) => {
-            if self.last_selected_bg_regs {
-              self.bg_banks.translate(addr)
-            } else {
-              self.spr_banks.translate(addr)
-            }
-          }
+          // with 8x8 sprites (or outside of rendering), the last written set of registers is used for everything
+          _ if self.last_selected_bg_regs => self.bg_banks.translate(addr),
+          _ => self.spr_banks.translate(addr),
         };
 
         PpuTarget::Chr(mapped)
       },
 
       0x2000..=0x2FFF => {
+        if let Some(target) = self.vsplit_nametbl_fetch(addr)
+          .or_else(|| self.ex_attribute_fetch(addr))
+        {
+          return target;
+        }
+
         let page = (addr - 0x2000) / 1024;
         let target = self.nametbls_mapping[page];
 
@@ -514,19 +553,17 @@ impl Mapper for MMC5 {
             => PpuTarget::CiRam(banks.ciram.translate(addr)),
 
           NametblMapping::ExRam => {
-            match (&self.exram_mode, self.ppu_in_frame, self.ppu_data_sub) {
-              (ExRamMode::Nametbl, false, _) | (ExRamMode::NametblEx, false, false,) 
+            match &self.exram_mode {
+              ExRamMode::Nametbl | ExRamMode::NametblEx
                 => PpuTarget::Value(self.exram_read(addr - 0x2000)),
-              (ExRamMode::NametblEx, _, true) => self.ex_attribute_val(addr),
               _ => PpuTarget::Value(0),
             }
           }
 
           NametblMapping::FillMode => {
-            match (&self.exram_mode, is_attribute(addr)) {
-              (_, false) => PpuTarget::Value(self.fill_mode_tile_id),
-              (ExRamMode::NametblEx, true) => self.ex_attribute_val(addr),
-              (_, true) => {
+            match is_attribute(addr) {
+              false => PpuTarget::Value(self.fill_mode_tile_id),
+              true => {
                 let pal = self.fill_mode_palette_id;
                 let attribute = (pal << 6) | (pal << 4) | (pal << 2) | pal;
                 PpuTarget::Value(attribute)
@@ -540,54 +577,58 @@ impl Mapper for MMC5 {
     }
   }
 
-  fn notify_ppuctrl(&mut self, val: u8) {
-    self.ppu_spr_16 = (val >> 5) != 0;
-  }
-
-  fn notify_ppumask(&mut self, val: u8) {
-    let data_sub = (val >> 3) & 0b11 != 0;
-    
-    if !self.ppu_data_sub && data_sub {
-      self.notify_nmi();
-    } else if !data_sub {
-      self.ppu_in_frame = false;
-    }
+  fn notify_ppu_reg(&mut self, _: &mut PpuCtx, reg: u16, val: u8) {
+    match reg {
+      0x2000 => self.ppu_spr_16 = (val >> 5) & 1 != 0,
+      0x2001 => {
+        let data_sub = (val >> 3) & 0b11 != 0;
 
-    self.ppu_data_sub = data_sub;
-  }
+        if !self.ppu_data_sub && data_sub {
+          self.notify_nmi();
+        } else if !data_sub {
+          self.ppu_in_frame = false;
+        }
 
-  fn notify_ppu_state(&mut self, state: PpuState) {
-    match state {
-      PpuState::Vblank => self.notify_nmi(),
+        self.ppu_data_sub = data_sub;
+      }
       _ => {}
     }
-
-    self.ppu_state = state;
   }
 
-  fn notify_mmc5_scanline(&mut self) {
-    // irq is ack when scanline 0 is detected
-    // nmi notify when scanline 241 is detected
-
-    if self.ppu_in_frame {
-      self.irq_count += 1;
+  fn notify_frame_end(&mut self, _: &mut PpuCtx) {
+    self.ppu_state = PpuState::Vblank;
+    self.notify_nmi();
+  }
 
-      if self.irq_count == self.irq_value {
-        self.irq_pending = true;
-        if self.irq_enabled {
-          self.irq_requested = Some(());
-        }
-      }
+  fn notify_ppu_addr(&mut self, ctx: &mut PpuCtx, addr: u16) {
+    if !ctx.rendering { return; }
+    self.ppu_idle = PPU_IDLE_CYCLES;
 
-    } else {
-      self.irq_requested = None;
-      self.ppu_in_frame = true;
-      self.irq_count = 0;
+    let state = match ctx.cycle {
+      257..=320 => PpuState::FetchSpr,
+      _ => PpuState::FetchBg,
+    };
+    if state == PpuState::FetchSpr && self.ppu_state != PpuState::FetchSpr {
+      // the next tiles fetched are the first two of the next scanline
+      self.vsplit_tile = 0;
+      self.vsplit_line = if self.ppu_in_frame { self.irq_count.wrapping_add(1) } else { 0 };
+      self.in_vsplit = false;
     }
+    self.ppu_state = state;
+
+    self.detect_scanline(addr);
   }
 
   fn notify_cpu_cycle(&mut self) {
-    
+    if self.ppu_idle > 0 {
+      self.ppu_idle -= 1;
+      if self.ppu_idle == 0 {
+        self.ppu_in_frame = false;
+        self.ppu_state = PpuState::Vblank;
+        self.last_nt_addr = 0;
+        self.nt_reads = 0;
+      }
+    }
   }
 
   fn get_sample(&self) -> f32 {
@@ -597,4 +638,128 @@ impl Mapper for MMC5 {
   fn poll_irq(&mut self) -> bool {
     self.irq_requested.is_some()
   }
-}
\ No newline at end of file
+
+  fn debug_state(&self) -> serde_json::Value {
+    let mut state = self.fields_json();
+    if let Some(fields) = state.as_object_mut() { fields.remove("exram"); }
+    state
+  }
+}
+
+#[cfg(test)]
+mod mmc5_tests {
+  use crate::cart::Cart;
+  use super::*;
+
+  // 32kb of prg and 512kb of chr rom, enough for every 4kb bank the extended attributes can select
+  fn mmc5_cart() -> (Cart, Box<MMC5>) {
+    let mut rom = vec![0; 16 + 32*1024 + 512*1024];
+    rom[0..4].copy_from_slice(b"NES\x1A");
+    rom[4] = 2;
+    rom[5] = 64;
+    rom[6] = 0x50;
+    let mut cart = Cart::new(&rom).unwrap();
+    let mapper = MMC5::new(&cart.header, &mut cart.banks);
+    (cart, mapper)
+  }
+
+  // Puts the address on the bus as the ppu does while rendering, then reads it
+  fn fetch(mapper: &mut MMC5, banks: &mut CartBanking, cycle: usize, addr: usize) -> PpuTarget {
+    let mut ctx = PpuCtx { banks, scanline: 0, cycle, dot: 0, rendering: true };
+    mapper.notify_ppu_addr(&mut ctx, addr as u16);
+    mapper.map_ppu_addr(banks, addr)
+  }
+
+  fn chr_addr(target: PpuTarget) -> usize {
+    match target {
+      PpuTarget::Chr(addr) => addr,
+      _ => panic!("not a chr fetch"),
+    }
+  }
+
+  fn value(target: PpuTarget) -> u8 {
+    match target {
+      PpuTarget::Value(val) => val,
+      _ => panic!("not a value fetch"),
+    }
+  }
+
+  #[test]
+  fn ex_attributes_per_tile() {
+    let (mut cart, mut mapper) = mmc5_cart();
+    let banks = &mut cart.banks;
+
+    // exram is filled while cpu writable, then switched to extended attributes
+    mapper.cart_write(banks, 0x5104, 2);
+    // palette 2 and 4kb bank 3 for tile 5, palette 1 and bank 0x3F for tile 6
+    mapper.cart_write(banks, 0x5C05, 0b10_000011);
+    mapper.cart_write(banks, 0x5C06, 0b01_111111);
+    mapper.cart_write(banks, 0x5104, 1);
+    mapper.cart_write(banks, 0x5130, 1);
+
+    // the nametable itself is still read from ciram
+    assert!(matches!(fetch(&mut mapper, banks, 1, 0x2005), PpuTarget::CiRam(_)));
+    assert_eq!(value(fetch(&mut mapper, banks, 3, 0x23C1)), 0b10_10_10_10);
+    // the upper chr bits come from $5130, and both pattern tables map to the same bank
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 5, 0x0013)), (64 + 3) * 4096 + 0x13);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 7, 0x101B)), (64 + 3) * 4096 + 0x1B);
+
+    fetch(&mut mapper, banks, 9, 0x2006);
+    assert_eq!(value(fetch(&mut mapper, banks, 11, 0x23C1)), 0b01_01_01_01);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 13, 0x0020)), (64 + 0x3F) * 4096 + 0x20);
+
+    // sprites aren't affected
+    mapper.cart_write(banks, 0x5127, 9);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 260, 0x1C00)), (256 + 9) * 1024);
+  }
+
+  #[test]
+  fn sprite_size_selects_chr_registers() {
+    let (mut cart, mut mapper) = mmc5_cart();
+    let banks = &mut cart.banks;
+
+    mapper.cart_write(banks, 0x5101, 3);
+    for reg in 0..8 {
+      mapper.cart_write(banks, 0x5120 + reg, 1 + reg as u8);
+    }
+    for reg in 0..4 {
+      mapper.cart_write(banks, 0x5128 + reg, 20 + reg as u8);
+    }
+
+    // 8x16 sprites: the background uses $5128-$512B in both pattern tables, sprites use $5120-$5127
+    mapper.ppu_spr_16 = true;
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 1, 0x0400)), 21 * 1024);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 3, 0x1400)), 21 * 1024);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 260, 0x1400)), 6 * 1024);
+
+    // 8x8 sprites: the last written set is used for everything
+    mapper.ppu_spr_16 = false;
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 1, 0x0400)), 21 * 1024);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 260, 0x1400)), 21 * 1024);
+    mapper.cart_write(banks, 0x5125, 6);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 1, 0x1400)), 6 * 1024);
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 260, 0x0400)), 2 * 1024);
+
+    // with rendering off, the 8x16 mode falls back to the last written set as well
+    mapper.ppu_spr_16 = true;
+    mapper.ppu_data_sub = false;
+    assert_eq!(chr_addr(fetch(&mut mapper, banks, 1, 0x0400)), 2 * 1024);
+  }
+
+  #[test]
+  fn chr_modes() {
+    let (mut cart, mut mapper) = mmc5_cart();
+    let banks = &mut cart.banks;
+    mapper.cart_write(banks, 0x5120, 1);
+    mapper.cart_write(banks, 0x5121, 2);
+    mapper.cart_write(banks, 0x5123, 3);
+    mapper.cart_write(banks, 0x5127, 4);
+
+    // only the last register of each page group is used
+    let modes = [(0, 4 * 8 * 1024), (1, 3 * 4 * 1024), (2, 2 * 2 * 1024), (3, 1024)];
+    for (mode, start) in modes {
+      mapper.cart_write(banks, 0x5101, mode);
+      assert_eq!(chr_addr(mapper.map_ppu_addr(banks, 0x0000)), start, "mode {mode}");
+    }
+  }
+}
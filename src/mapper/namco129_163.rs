@@ -1,10 +1,13 @@
-use crate::cart::{CartBanking, CartHeader, PpuTarget};
+use crate::{cart::{CartBanking, CartHeader, PpuTarget}, codec::{codec_enum, codec_fields}};
 
 use super::{set_byte_hi, set_byte_lo, Banking, Mapper};
 
 #[derive(Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
 enum ChrTarget { #[default] Chr, Ciram0, Ciram1 }
+codec_enum!(ChrTarget { Chr, Ciram0, Ciram1 });
 
+// Mapper 19
+// https://www.nesdev.org/wiki/Namco_163
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct Namco129_163 {
   irq_value: u16,
@@ -18,8 +21,8 @@ pub struct Namco129_163 {
 
   apu_enabled: bool,
 }
+codec_fields!(Namco129_163 { irq_value, irq_enabled, irq_requested, chr_selects, chrram0_enabled, chrram1_enabled, exram_write_enabled, apu_enabled });
 
-#[typetag::serde]
 impl Mapper for Namco129_163 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 4);
@@ -36,8 +39,8 @@ impl Mapper for Namco129_163 {
 
   fn cart_read(&mut self, addr: usize) -> u8 {
     match addr {
-      0x5000..=0x57FFF => self.irq_value as u8,
-      0x5800..=0x5FFFF => {
+      0x5000..=0x57FF => self.irq_value as u8,
+      0x5800..=0x5FFF => {
         let mut res = 0;
         res |= (self.irq_value >> 8) as u8;
         res |= (self.irq_enabled as u8) << 7;
@@ -49,11 +52,11 @@ impl Mapper for Namco129_163 {
 
   fn cart_write(&mut self, _: &mut CartBanking, addr: usize, val: u8) {
     match addr {
-      0x5000..=0x57FFF => {
+      0x5000..=0x57FF => {
         self.irq_value = set_byte_lo(self.irq_value, val);
         self.irq_requested = None;
       }
-      0x5800..=0x5FFFF => {
+      0x5800..=0x5FFF => {
         self.irq_value = 
           set_byte_hi(self.irq_value, val) & 0b0111_1111;
         self.irq_enabled = val >> 7 != 0;
@@ -65,40 +68,21 @@ impl Mapper for Namco129_163 {
 
   fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {    
     match addr {
-      0x8000..=0x9FFF => {
-        let page = (addr as usize - 0x8000) / 0x800;
-
-        if val >= 0xE0 && self.chrram0_enabled {
-          self.chr_selects[page] = ChrTarget::Ciram0;
-        } else {
-          self.chr_selects[page] = ChrTarget::Chr;
-        }
-
-        banks.chr.set_page(page, val as usize);
-      }
-      0xA000..=0xBFFF => {
-        let page = (addr as usize - 0x8000) / 0x800;
-
-        if val >= 0xE0 && self.chrram1_enabled {
-          self.chr_selects[page] = ChrTarget::Ciram1;
-        } else {
-          self.chr_selects[page] = ChrTarget::Chr;
-        }
-
-        banks.chr.set_page(page, val as usize);
-      }
-      0xC000..=0xDFFF => {
-        let page = (addr as usize - 0x8000) / 0x800;
-
-        if val >= 0xE0 {
-          if val % 2 == 0 {
-            self.chr_selects[page] = ChrTarget::Ciram0;
-          } else {
-            self.chr_selects[page] = ChrTarget::Ciram1;
-          }
-        } else {
-          self.chr_selects[page] = ChrTarget::Chr;
-        }
+      0x8000..=0xDFFF => {
+        // https://www.nesdev.org/wiki/Namco_163#CHR_and_NT_Select_($8000-$DFFF)_w
+        let page = (addr - 0x8000) / 0x800;
+        // pattern tables can use ciram only if enabled, nametables can use chr rom as well
+        let ciram_allowed = match page {
+          0..=3 => self.chrram0_enabled,
+          4..=7 => self.chrram1_enabled,
+          _ => true,
+        };
+
+        self.chr_selects[page] = match (val >= 0xE0 && ciram_allowed, val % 2 == 0) {
+          (false, _) => ChrTarget::Chr,
+          (true, true) => ChrTarget::Ciram0,
+          (true, false) => ChrTarget::Ciram1,
+        };
 
         banks.chr.set_page(page, val as usize);
       }
@@ -0,0 +1,74 @@
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::codec_fields};
+
+use super::{Banking, Mapper};
+
+// Mapper 210
+// https://www.nesdev.org/wiki/INES_Mapper_210
+// Stripped down Namco 163, without irq, sound and chr ram nametables.
+// Submapper 1 (175) has hardwired mirroring and prg ram, submapper 2 (340) controls mirroring.
+// Submapper 0 doesn't tell, the header is looked at instead.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct Namco175_340 {
+  is_340: bool,
+  sram_enabled: bool,
+}
+codec_fields!(Namco175_340 { sram_enabled });
+
+// Only 175 boards come with prg ram (battery backed in the Famista and Family Circuit games),
+// so boards without it are taken as 340s. A four screen header can't be a 175 either, as its mirroring is hardwired.
+fn is_340(header: &CartHeader) -> bool {
+  match header.submapper {
+    1 => false,
+    2 => true,
+    _ => header.mirroring == Mirroring::FourScreen
+      || (!header.has_battery && header.prg_ram_size == 0 && header.eeprom_size == 0),
+  }
+}
+
+impl Mapper for Namco175_340 {
+  fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
+    banks.prg = Banking::new_prg(header, 4);
+    banks.prg.set_page_to_last_bank(3);
+    banks.chr = Banking::new_chr(header, 8);
+
+    Box::new(Self {
+      is_340: is_340(header),
+      ..Default::default()
+    })
+  }
+
+  fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
+    match addr {
+      0x8000..=0xBFFF => {
+        let page = (addr - 0x8000) / 0x800;
+        banks.chr.set_page(page, val as usize);
+      }
+      0xC000..=0xC7FF if !self.is_340 => self.sram_enabled = val & 1 != 0,
+      0xE000..=0xE7FF => {
+        banks.prg.set_page(0, val as usize & 0b11_1111);
+
+        if self.is_340 {
+          let mirroring = match val >> 6 {
+            0 => Mirroring::SingleScreenA,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::Horizontal,
+            _ => Mirroring::SingleScreenB,
+          };
+          banks.ciram.update(mirroring);
+        }
+      }
+      0xE800..=0xEFFF => banks.prg.set_page(1, val as usize & 0b11_1111),
+      0xF000..=0xF7FF => banks.prg.set_page(2, val as usize & 0b11_1111),
+      _ => {}
+    }
+  }
+
+  fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
+    match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
+      0x6000..=0x7FFF => PrgTarget::SRam(self.sram_enabled, banks.sram.translate(addr)),
+      0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
+      _ => unreachable!()
+    }
+  }
+}
@@ -1,7 +1,9 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring, PrgTarget, PpuTarget};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget, PpuTarget}, codec::codec_fields};
 
 use super::{Banking, CiramBanking, Mapper};
 
+// Mapper 68
+// https://www.nesdev.org/wiki/INES_Mapper_068
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct Sunsoft4 {
   sram_enabled: bool,
@@ -12,6 +14,7 @@ pub struct Sunsoft4 {
   nametbl1: usize,
   timer: usize,
 }
+codec_fields!(Sunsoft4 { sram_enabled, chrrom_banked, chrrom_banks, mirroring, nametbl0, nametbl1, timer });
 
 impl Sunsoft4 {
   pub fn update_ciram_banks(&mut self) {
@@ -39,7 +42,6 @@ impl Sunsoft4 {
   }
 }
 
-#[typetag::serde]
 impl Mapper for Sunsoft4 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
@@ -48,11 +50,16 @@ impl Mapper for Sunsoft4 {
     banks.chr = Banking::new_chr(header, 4);
     let chrrom_banks = Banking::new(header.chr_real_size(), 0, 1024, 4);
 
-    Box::new(Self{ 
+    let mut mapper = Self {
       chrrom_banks,
       mirroring: header.mirroring,
+      nametbl0: 0b1000_0000,
+      nametbl1: 0b1000_0000,
       ..Default::default()
-    })
+    };
+    mapper.update_ciram_banks();
+    
+    Box::new(mapper)
   }
 
   fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
@@ -79,8 +86,10 @@ impl Mapper for Sunsoft4 {
           _ => Mirroring::SingleScreenB,
         };
         banks.ciram.update(self.mirroring);
+        // mirroring applies to the chr rom nametables as well
+        self.update_ciram_banks();
 
-        self.chrrom_banked = val >> 4 != 0;
+        self.chrrom_banked = (val >> 4) & 1 != 0;
       }
       0xF000..=0xFFFF => {
         banks.prg.set_page(0, val as usize & 0b1111);
@@ -92,6 +101,7 @@ impl Mapper for Sunsoft4 {
 
   fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
     match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
       0x6000..=0x7FFF => PrgTarget::SRam(self.sram_enabled, banks.sram.translate(addr)),
       0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
       _ => unreachable!()
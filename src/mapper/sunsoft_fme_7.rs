@@ -1,6 +1,6 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring, PrgTarget};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::{codec_fields, NenCodec, Reader}};
 
-use super::{set_byte_hi, set_byte_lo, Banking, Mapper};
+use super::{mirror, set_byte_hi, set_byte_lo, Banking, Mapper};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 enum Command { Chr(u8), Prg0, Prg1(u8), Nametbl, IrqCtrl, IrqLo, IrqHi }
@@ -8,6 +8,36 @@ impl Default for Command {
   fn default() -> Self { Self::Chr(0) }
 }
 
+impl NenCodec for Command {
+  fn encode(&self, out: &mut Vec<u8>) {
+    let (tag, reg) = match self {
+      Command::Chr(reg) => (0, *reg),
+      Command::Prg0 => (1, 0),
+      Command::Prg1(reg) => (2, *reg),
+      Command::Nametbl => (3, 0),
+      Command::IrqCtrl => (4, 0),
+      Command::IrqLo => (5, 0),
+      Command::IrqHi => (6, 0),
+    };
+    out.extend_from_slice(&[tag, reg]);
+  }
+
+  fn decode(&mut self, input: &mut Reader) -> Result<(), String> {
+    let [tag, reg] = input.take_array()?;
+    *self = match tag {
+      0 => Command::Chr(reg),
+      1 => Command::Prg0,
+      2 => Command::Prg1(reg),
+      3 => Command::Nametbl,
+      4 => Command::IrqCtrl,
+      5 => Command::IrqLo,
+      6 => Command::IrqHi,
+      _ => return Err(format!("Savestate is corrupted: {tag} isn't a fme-7 command")),
+    };
+    Ok(())
+  }
+}
+
 // Mapper 69
 // https://www.nesdev.org/wiki/Sunsoft_FME-7
 #[derive(Default, serde::Serialize, serde::Deserialize)]
@@ -24,8 +54,8 @@ pub struct SunsoftFME7 {
   irq_requested: Option<()>,
   irq_count: u16,
 }
+codec_fields!(SunsoftFME7 { command, sram_banked, sram_enabled, prg0_select, irq_enabled, irq_counter_enabled, irq_requested, irq_count });
 
-#[typetag::serde]
 impl Mapper for SunsoftFME7 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 4);
@@ -67,7 +97,7 @@ impl Mapper for SunsoftFME7 {
             if self.sram_banked {
               banks.sram.set_page(0, bank);
             } else {
-              self.prg0_select = (bank % banks.prg.banks_count) * banks.prg.bank_size;
+              self.prg0_select = mirror(bank, banks.prg.banks_count) * banks.prg.bank_size;
             }
           }
           Command::Prg1(page) => 
@@ -1,23 +1,37 @@
-use crate::cart::{CartBanking, CartHeader, Mirroring};
+use crate::{cart::{CartBanking, CartHeader, Mirroring}, codec::codec_fields};
 
-use super::{Banking, Mapper};
+use super::{flash::{FlashOp, SstFlash}, Banking, Mapper};
 
 
 // Mapper 30
 // https://www.nesdev.org/wiki/UNROM_512
 #[derive(serde::Serialize, serde::Deserialize)]
-pub struct UNROM512;
-#[typetag::serde]
+pub struct UNROM512 {
+  flashable: bool,
+  flash: SstFlash,
+  flash_op: Option<FlashOp>,
+}
+codec_fields!(UNROM512 { flash, flash_op });
 impl Mapper for UNROM512 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
     banks.prg.set_page_to_last_bank(1);
     banks.chr = Banking::new_chr(header, 1);
 
-    Box::new(Self)
+    Box::new(Self {
+      flashable: super::board_has_flash(header),
+      flash: SstFlash::default(),
+      flash_op: None,
+    })
   }
 
-  fn prg_write(&mut self, banks: &mut CartBanking, _: usize, val: u8) {
+  fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
+    // On the flashable board, $8000-$BFFF is wired to the flash chip instead of the bank register
+    if self.flashable && addr < 0xC000 {
+      self.flash_op = self.flash.write(banks.prg.translate(addr), val);
+      return;
+    }
+
     banks.prg.set_page(0, val as usize & 0b1_1111);
     banks.chr.set_page(0, (val >> 5) as usize & 0b11);
     let mirroring = match (val >> 7) != 0 {
@@ -26,4 +40,8 @@ impl Mapper for UNROM512 {
     };
     banks.ciram.update(mirroring);
   }
+
+  fn poll_flash(&mut self) -> Option<FlashOp> {
+    self.flash_op.take()
+  }
 }
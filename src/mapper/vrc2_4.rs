@@ -1,7 +1,7 @@
 use bitfield_struct::bitfield;
 
-use crate::cart::{CartBanking, CartHeader, Mirroring, PrgTarget};
-use super::{konami_irq::{IrqMode, KonamiIrq}, Banking, Mapper};
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::{codec_bitfield, codec_fields}};
+use super::{konami_irq::KonamiIrq, Banking, Mapper};
 
 #[bitfield(u16, order = Lsb)]
 struct ChrSelectByte {
@@ -13,6 +13,7 @@ struct ChrSelectByte {
   #[bits(7)]
   __: u8
 }
+codec_bitfield!(ChrSelectByte);
 
 impl serde::Serialize for ChrSelectByte {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -39,16 +40,47 @@ pub struct VRC2_4 {
   chr_selects: [ChrSelectByte; 8],
 
   mapper: u16,
+  // the cpu address lines wired to the chip's A0 and A1, if known from the NES 2.0 submapper
+  addr_lines: Option<(usize, usize)>,
+  is_vrc2: bool,
   swap_mode: bool,
   sram_ctrl: bool,
   latch: bool,
 
   irq: KonamiIrq,
 }
+codec_fields!(VRC2_4 { prg_select0, prg_select1, chr_selects, swap_mode, sram_ctrl, latch, irq });
+
+// https://www.nesdev.org/wiki/NES_2.0_submappers#021.2C_023.2C_025:_Konami_VRC2.2FVRC4
+fn submapper_addr_lines(mapper: u16, submapper: u8) -> Option<(usize, usize)> {
+  match (mapper, submapper) {
+    // Vrc4a
+    (21, 1) => Some((1, 2)),
+    // Vrc4c
+    (21, 2) => Some((6, 7)),
+    // Vrc2a
+    (22, _) => Some((1, 0)),
+    // Vrc4f, Vrc2b
+    (23, 1) | (23, 3) => Some((0, 1)),
+    // Vrc4e
+    (23, 2) => Some((2, 3)),
+    // Vrc4b, Vrc2c
+    (25, 1) | (25, 3) => Some((1, 0)),
+    // Vrc4d
+    (25, 2) => Some((3, 2)),
+    _ => None,
+  }
+}
 
 impl VRC2_4 {
-  // iNes compatibility
   fn translate_addr(&self, addr: usize) -> usize {
+    if let Some((line0, line1)) = self.addr_lines {
+      let a0 = (addr >> line0) & 1;
+      let a1 = (addr >> line1) & 1;
+      return (addr & 0xFF00 | (a1 << 1) | a0) & 0xF00F;
+    }
+
+    // iNes compatibility
     // Taken from Mesen emulator source, this trick makes it work without discriminating submapper
     // https://github.com/SourMesen/Mesen2/blob/master/Core/NES/Mappers/Konami/VRC2_4.h
     
@@ -100,10 +132,10 @@ impl VRC2_4 {
     match self.swap_mode {
       false => {
         banks.prg.set_page(0, self.prg_select0 as usize);
-        banks.prg.set_page(2, banks.prg.banks_count-2);
+        banks.prg.set_page_to_bank_from_end(2, 1);
       }
       true  => {
-        banks.prg.set_page(0, banks.prg.banks_count-2);
+        banks.prg.set_page_to_bank_from_end(0, 1);
         banks.prg.set_page(2, self.prg_select0 as usize);
       }
     }
@@ -144,22 +176,25 @@ impl VRC2_4 {
         self.chr_selects[reg].set_lo(val & 0b1111);
       }
 
-      banks.chr.set_page(reg, self.chr_selects[reg].0 as usize);
+      // Vrc2a ignores the lowest bit of the chr bank
+      let bank = self.chr_selects[reg].0 as usize >> (self.mapper == 22) as usize;
+      banks.chr.set_page(reg, bank);
     }
   }
 }
 
-#[typetag::serde]
 impl Mapper for VRC2_4 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 4);
     banks.chr = Banking::new_chr(header, 8);
 
-    banks.prg.set_page(2, banks.prg.banks_count-2);
-    banks.prg.set_page(3, banks.prg.banks_count-1);
+    banks.prg.set_page_to_bank_from_end(2, 1);
+    banks.prg.set_page_to_last_bank(3);
 
     let mapper = Self {
       mapper: header.mapper,
+      addr_lines: submapper_addr_lines(header.mapper, header.submapper),
+      is_vrc2: header.mapper == 22 || header.submapper == 3,
       ..Default::default()
     };
 
@@ -171,7 +206,7 @@ impl Mapper for VRC2_4 {
     match addr {
       0x9002 => {
         self.sram_ctrl = val & 0b01 != 0;
-        self.swap_mode = val & 0b10 != 0 && self.mapper != 22;
+        self.swap_mode = val & 0b10 != 0 && !self.is_vrc2;
         self.update_prg_banks(banks);
       }
 
@@ -217,28 +252,74 @@ impl Mapper for VRC2_4 {
   }
 
   fn notify_cpu_cycle(&mut self) {
-    if !self.irq.enabled || self.mapper == 22 { return; }
-
-    match self.irq.mode {
-      IrqMode::Mode1 => {
-        self.irq.count += 1;
-      }
-      IrqMode::Mode0 => {
-        self.irq.prescaler -= 3;
-        if self.irq.prescaler <= 0 {
-          self.irq.prescaler += 341;
-          self.irq.count += 1;
-        }
-      }
-    }
-
-    if self.irq.count > 0xFF {
-      self.irq.requested = Some(());
-      self.irq.count = self.irq.latch;
+    // the vrc2 has no irq
+    if !self.is_vrc2 {
+      self.irq.step();
     }
   }
 
   fn poll_irq(&mut self) -> bool {
     self.irq.requested.is_some()
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod vrc2_4_tests {
+  use super::*;
+
+  // The register the chip sees, with only the given cpu address lines high
+  fn register(mapper: u16, submapper: u8, lines: &[usize]) -> usize {
+    let vrc = VRC2_4 { mapper, addr_lines: submapper_addr_lines(mapper, submapper), ..Default::default() };
+    let addr = lines.iter().fold(0x9000, |addr, line| addr | (1 << line));
+    vrc.translate_addr(addr) & 0b11
+  }
+
+  #[test]
+  fn addr_lines_of_every_submapper() {
+    // the A0 and A1 lines of each board, which iNes dumps (submapper 0) are allowed any of
+    let boards: [(u16, u8, &[usize], &[usize]); 9] = [
+      (21, 1, &[1], &[2]),
+      (21, 2, &[6], &[7]),
+      (22, 0, &[1], &[0]),
+      (23, 1, &[0], &[1]),
+      (23, 2, &[2], &[3]),
+      (23, 3, &[0], &[1]),
+      (25, 1, &[1], &[0]),
+      (25, 2, &[3], &[2]),
+      (25, 3, &[1], &[0]),
+    ];
+    let ines: [(u16, &[usize], &[usize]); 4] = [
+      (21, &[1, 6], &[2, 7]),
+      (22, &[1], &[0]),
+      (23, &[0, 2], &[1, 3]),
+      (25, &[1, 3], &[0, 2]),
+    ];
+
+    for mapper in [21, 22, 23, 25] {
+      for submapper in 0..16 {
+        // VRC2a has a single wiring, whatever the submapper
+        let board = boards.iter().find(|b| b.0 == mapper && (b.1 == submapper || mapper == 22));
+        let (a0, a1) = match board {
+          Some(&(_, _, a0, a1)) => {
+            assert_eq!(submapper_addr_lines(mapper, submapper), Some((a0[0], a1[0])), "{mapper}.{submapper}");
+            (a0, a1)
+          }
+          None => {
+            assert_eq!(submapper_addr_lines(mapper, submapper), None, "{mapper}.{submapper}");
+            let &(_, a0, a1) = ines.iter().find(|b| b.0 == mapper).unwrap();
+            (a0, a1)
+          }
+        };
+
+        for &line in a0 {
+          assert_eq!(register(mapper, submapper, &[line]), 0b01, "{mapper}.{submapper} A0 on line {line}");
+        }
+        for &line in a1 {
+          assert_eq!(register(mapper, submapper, &[line]), 0b10, "{mapper}.{submapper} A1 on line {line}");
+        }
+        assert_eq!(register(mapper, submapper, &[a0[0], a1[0]]), 0b11, "{mapper}.{submapper}");
+        assert_eq!(register(mapper, submapper, &[]), 0b00, "{mapper}.{submapper}");
+      }
+    }
+  }
+}
@@ -1,4 +1,4 @@
-use crate::cart::{CartBanking, CartHeader};
+use crate::{cart::{CartBanking, CartHeader}, codec::codec_fields};
 use super::{konami_irq::{self, KonamiIrq}, Banking, Mapper};
 
 // Mapper 73
@@ -7,8 +7,8 @@ use super::{konami_irq::{self, KonamiIrq}, Banking, Mapper};
 pub struct VRC3 {
   irq: KonamiIrq,
 }
+codec_fields!(VRC3 { irq });
 
-#[typetag::serde]
 impl Mapper for VRC3 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 2);
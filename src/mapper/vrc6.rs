@@ -1,10 +1,12 @@
-use crate::{apu::{ApuDivider, Channel}, cart::{CartBanking, CartHeader, Mirroring, PpuTarget}};
-use super::{konami_irq::{IrqMode, KonamiIrq}, Banking, Mapper, CiramBanking};
+use crate::{apu::{ApuDivider, Channel}, cart::{CartBanking, CartHeader, Mirroring, PpuTarget}, codec::{codec_enum, codec_fields}};
+use super::{konami_irq::KonamiIrq, Banking, Mapper, CiramBanking};
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 enum ChrMode { #[default] Bank1kb, Bank2kb, BankMixed }
+codec_enum!(ChrMode { Bank1kb, Bank2kb, BankMixed });
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 enum NametblSrc { #[default] CiRam, ChrRom }
+codec_enum!(NametblSrc { CiRam, ChrRom });
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct VRC6 {
@@ -30,6 +32,7 @@ pub struct VRC6 {
   pulse2: PulseVRC6,
   sawtooth: SawtoothVRC6,
 }
+codec_fields!(VRC6 { vram_chrrom_banks, vram_ciram_banks, chr_selects, irq, chr_mode, nametbl_src, nametbl_mode, chr_latch, sram_enabled, apu_halted, apu_freq16, apu_freq256, pulse1, pulse2, sawtooth });
 
 impl VRC6 {
   fn update_chr_banks(&self, banks: &mut CartBanking) {
@@ -138,28 +141,6 @@ impl VRC6 {
     }
   }
 
-  fn handle_irq(&mut self) {
-    if !self.irq.enabled { return; }
-
-    match self.irq.mode {
-      IrqMode::Mode1 => {
-        self.irq.count += 1;
-      }
-      IrqMode::Mode0 => {
-        self.irq.prescaler -= 3;
-        if self.irq.prescaler <= 0 {
-          self.irq.prescaler += 341;
-          self.irq.count += 1;
-        }
-      }
-    }
-
-    if self.irq.count > 0xFF {
-      self.irq.requested = Some(());
-      self.irq.count = self.irq.latch;
-    }
-  }
-
   fn handle_apu(&mut self) {
     if self.apu_halted { return; }
 
@@ -169,7 +150,6 @@ impl VRC6 {
   }
 }
 
-#[typetag::serde]
 impl Mapper for VRC6 {
   fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
     banks.prg = Banking::new_prg(header, 4);
@@ -229,12 +209,12 @@ impl Mapper for VRC6 {
       },
 
       0xD000..=0xD003 => {
-        let reg = addr - 0xD000;
+        let reg = addr & 0b11;
         self.chr_selects[reg] = val as usize;
         self.update_chr_banks(banks);
       }
       0xE000..=0xE003 => {
-        let reg = addr - 0xE000;
+        let reg = addr & 0b11;
         self.chr_selects[reg + 4] = val as usize;
         self.update_chr_banks(banks);
       }
@@ -292,7 +272,7 @@ impl Mapper for VRC6 {
   }
 
   fn notify_cpu_cycle(&mut self) {
-    self.handle_irq();
+    self.irq.step();
     self.handle_apu();
   }
 
@@ -322,6 +302,7 @@ struct PulseVRC6 {
   ignore_duty: bool,
   enabled: bool,
 }
+codec_fields!(PulseVRC6 { timer, freq_shift, volume, duty_idx, duty_cycle, ignore_duty, enabled });
 impl PulseVRC6 {
   pub fn set_ctrl(&mut self, val: u8) {
     self.volume = val & 0b1111;
@@ -377,6 +358,7 @@ struct SawtoothVRC6 {
   duty: u8,
   enabled: bool,
 }
+codec_fields!(SawtoothVRC6 { timer, freq_shift, acc_rate, acc, duty, enabled });
 impl SawtoothVRC6 {
   pub fn set_acc(&mut self, val: u8) {
     self.acc_rate = val & 0b11_1111;
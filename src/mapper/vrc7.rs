@@ -0,0 +1,96 @@
+use crate::{cart::{CartBanking, CartHeader, Mirroring, PrgTarget}, codec::codec_fields};
+use super::{konami_irq::KonamiIrq, Banking, Mapper};
+
+// Mapper 85
+// https://www.nesdev.org/wiki/VRC7
+// The FM audio expansion is not emulated.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct VRC7 {
+  // the cpu address line used to select the odd registers:
+  // A4 on Vrc7a (Tiny Toon Adventures 2), A3 on Vrc7b (Lagrange Point)
+  reg_line: Option<usize>,
+  sram_enabled: bool,
+  irq: KonamiIrq,
+}
+codec_fields!(VRC7 { reg_line, sram_enabled, irq });
+
+impl VRC7 {
+  fn translate_addr(&self, addr: usize) -> usize {
+    let odd = match self.reg_line {
+      Some(line) => (addr >> line) & 1,
+      // iNes compatibility, both lines are checked
+      None => ((addr >> 4) | (addr >> 3)) & 1,
+    };
+
+    (addr & 0xF000) | (odd << 4)
+  }
+}
+
+impl Mapper for VRC7 {
+  fn new(header: &CartHeader, banks: &mut CartBanking) -> Box<Self> {
+    banks.prg = Banking::new_prg(header, 4);
+    banks.chr = Banking::new_chr(header, 8);
+    banks.prg.set_page_to_last_bank(3);
+
+    // https://www.nesdev.org/wiki/NES_2.0_submappers#085:_Konami_VRC7
+    let reg_line = match header.submapper {
+      1 => Some(3),
+      2 => Some(4),
+      _ => None,
+    };
+
+    Box::new(Self { reg_line, ..Default::default() })
+  }
+
+  fn prg_write(&mut self, banks: &mut CartBanking, addr: usize, val: u8) {
+    match self.translate_addr(addr) {
+      0x8000 => banks.prg.set_page(0, val as usize & 0b11_1111),
+      0x8010 => banks.prg.set_page(1, val as usize & 0b11_1111),
+      0x9000 => banks.prg.set_page(2, val as usize & 0b11_1111),
+      // audio registers
+      0x9010 => {}
+
+      0xA000 => banks.chr.set_page(0, val as usize),
+      0xA010 => banks.chr.set_page(1, val as usize),
+      0xB000 => banks.chr.set_page(2, val as usize),
+      0xB010 => banks.chr.set_page(3, val as usize),
+      0xC000 => banks.chr.set_page(4, val as usize),
+      0xC010 => banks.chr.set_page(5, val as usize),
+      0xD000 => banks.chr.set_page(6, val as usize),
+      0xD010 => banks.chr.set_page(7, val as usize),
+
+      0xE000 => {
+        let mirroring = match val & 0b11 {
+          0 => Mirroring::Vertical,
+          1 => Mirroring::Horizontal,
+          2 => Mirroring::SingleScreenA,
+          _ => Mirroring::SingleScreenB,
+        };
+        banks.ciram.update(mirroring);
+        self.sram_enabled = (val >> 7) & 1 != 0;
+      }
+
+      0xE010 => self.irq.latch = val as u16,
+      0xF000 => self.irq.write_ctrl(val),
+      0xF010 => self.irq.write_ack(),
+      _ => {}
+    }
+  }
+
+  fn map_prg_addr(&mut self, banks: &mut CartBanking, addr: usize) -> PrgTarget {
+    match addr {
+      0x4020..=0x5FFF => PrgTarget::Cart,
+      0x6000..=0x7FFF => PrgTarget::SRam(self.sram_enabled, banks.sram.translate(addr)),
+      0x8000..=0xFFFF => PrgTarget::Prg(banks.prg.translate(addr)),
+      _ => unreachable!()
+    }
+  }
+
+  fn notify_cpu_cycle(&mut self) {
+    self.irq.step();
+  }
+
+  fn poll_irq(&mut self) -> bool {
+    self.irq.requested.is_some()
+  }
+}
@@ -13,6 +13,18 @@ pub struct Ram64Kb {
   pub mem: [u8; 64*1024]
 }
 
+impl Ram64Kb {
+  pub fn new() -> Self {
+    Self { mem: [0; 64*1024] }
+  }
+}
+
+impl Default for Ram64Kb {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Memory for Ram64Kb {
   fn read(&mut self, addr: u16) -> u8 {
     self.mem[addr as usize]
@@ -0,0 +1,171 @@
+use crate::{frame::fnv_hash, joypad::JoypadButton, nes::Nes};
+
+// Input movies recorded by other emulators, to check that a TAS plays out the same way here.
+// Only FCEUX .fm2 text movies with standard controllers are supported.
+// https://fceux.com/web/help/fm2.html
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieCommand { SoftReset, PowerCycle }
+
+#[derive(Debug, Clone, Copy)]
+pub struct MovieFrame {
+  pub command: Option<MovieCommand>,
+  pub buttons1: JoypadButton,
+  pub buttons2: JoypadButton,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Movie {
+  pub rom_filename: Option<String>,
+  // base64 md5 of the rom, as written by FCEUX
+  pub rom_checksum: Option<String>,
+  pub pal: bool,
+  pub rerecords: u32,
+  pub frames: Vec<MovieFrame>,
+}
+
+// What a movie left behind, to be compared with the results of other emulators.
+// The screen and ram hashes don't depend on how the emulator is built, unlike the state hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieReport {
+  pub frames: usize,
+  pub screen_hash: u64,
+  pub ram_hash: u64,
+  pub state_hash: u64,
+}
+
+// fm2 buttons, from left to right
+const FM2_BUTTONS: [JoypadButton; 8] = [
+  JoypadButton::right, JoypadButton::left, JoypadButton::down, JoypadButton::up,
+  JoypadButton::start, JoypadButton::select, JoypadButton::b, JoypadButton::a,
+];
+
+fn parse_fm2_buttons(field: &str) -> Result<JoypadButton, String> {
+  if field.is_empty() { return Ok(JoypadButton::empty()); }
+  if field.len() != 8 {
+    return Err(format!("Invalid controller input: {field:?}"));
+  }
+
+  // anything but a dot or a space is a held button
+  Ok(field.chars().zip(FM2_BUTTONS)
+    .filter(|(c, _)| *c != '.' && *c != ' ')
+    .fold(JoypadButton::empty(), |acc, (_, button)| acc | button))
+}
+
+impl Movie {
+  pub fn from_fm2(text: &str) -> Result<Self, String> {
+    let mut movie = Movie::default();
+
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim_end();
+      if line.is_empty() { continue; }
+
+      if let Some(input) = line.strip_prefix('|') {
+        // |commands|port0|port1|port2|
+        let fields: Vec<&str> = input.split('|').collect();
+        if fields.len() < 3 {
+          return Err(format!("Invalid input on line {}", i + 1));
+        }
+
+        let commands: u8 = fields[0].parse()
+          .map_err(|_| format!("Invalid command on line {}", i + 1))?;
+        let command = if commands & 2 != 0 {
+          Some(MovieCommand::PowerCycle)
+        } else if commands & 1 != 0 {
+          Some(MovieCommand::SoftReset)
+        } else { None };
+
+        movie.frames.push(MovieFrame {
+          command,
+          buttons1: parse_fm2_buttons(fields[1])?,
+          buttons2: parse_fm2_buttons(fields[2])?,
+        });
+        continue;
+      }
+
+      let (key, val) = line.split_once(' ').unwrap_or((line, ""));
+      match key {
+        "binary" if val == "1" => return Err("Binary fm2 movies are not supported".to_string()),
+        "fourscore" if val == "1" => return Err("Four score movies are not supported".to_string()),
+        "port0" | "port1" if val != "0" && val != "1" => return Err("Only standard controllers are supported".to_string()),
+        "romFilename" => movie.rom_filename = Some(val.to_string()),
+        "romChecksum" => movie.rom_checksum = Some(val.trim_start_matches("base64:").to_string()),
+        "palFlag" => movie.pal = val == "1",
+        "rerecordCount" => movie.rerecords = val.parse().unwrap_or(0),
+        _ => {}
+      }
+    }
+
+    Ok(movie)
+  }
+
+  // Sets up the emulator for the given frame of the movie, before it is run
+  pub fn apply_frame(&self, i: usize, nes: &mut Nes) {
+    let Some(frame) = self.frames.get(i) else { return };
+
+    match frame.command {
+      Some(MovieCommand::SoftReset) => nes.reset(),
+      // movies usually start with a power cycle, the emulator is already in its power on state then
+      Some(MovieCommand::PowerCycle) if i > 0 => nes.power_cycle(),
+      _ => {}
+    }
+
+    let joypad = nes.get_joypad();
+    joypad.buttons1 = frame.buttons1;
+    joypad.buttons2 = frame.buttons2;
+  }
+
+  // Plays the whole movie on a freshly booted emulator, one frame of input per ppu frame
+  pub fn play(&self, nes: &mut Nes) -> MovieReport {
+    for i in 0..self.frames.len() {
+      self.apply_frame(i, nes);
+      nes.step_until_vblank();
+      nes.consume_samples();
+    }
+
+    let ram: Vec<u8> = (0..0x800).map(|addr| nes.peek(addr)).collect();
+    MovieReport {
+      frames: self.frames.len(),
+      screen_hash: nes.get_screen().hash(),
+      ram_hash: fnv_hash(&ram),
+      state_hash: nes.state_hash(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod movie_tests {
+  use super::*;
+
+  const FM2: &str = "version 3\nemuVersion 22020\nrerecordCount 42\npalFlag 0\nromFilename game\nromChecksum base64:AAAA\nport0 1\nport1 1\nport2 0\n\
+    |2|........|........||\n|0|R..U...A|.L......||\n|1|........|........||\n|0|RLDUTSBA|........||\n";
+
+  #[test]
+  fn parse_fm2() {
+    let movie = Movie::from_fm2(FM2).unwrap();
+    assert_eq!(movie.rerecords, 42);
+    assert_eq!(movie.rom_checksum.as_deref(), Some("AAAA"));
+    assert_eq!(movie.frames.len(), 4);
+    assert_eq!(movie.frames[0].command, Some(MovieCommand::PowerCycle));
+    assert_eq!(movie.frames[1].buttons1, JoypadButton::right | JoypadButton::up | JoypadButton::a);
+    assert_eq!(movie.frames[1].buttons2, JoypadButton::left);
+    assert_eq!(movie.frames[2].command, Some(MovieCommand::SoftReset));
+    assert_eq!(movie.frames[3].buttons1, JoypadButton::all());
+
+    assert!(Movie::from_fm2("binary 1\n").is_err());
+    assert!(Movie::from_fm2("port0 2\n").is_err());
+  }
+
+  #[test]
+  fn play_is_reproducible() {
+    let mut rom = vec![0; 16 + 16 * 1024 + 8 * 1024];
+    rom[0..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+    // jmp $c000
+    rom[16..19].copy_from_slice(&[0x4C, 0x00, 0xC0]);
+
+    let movie = Movie::from_fm2(FM2).unwrap();
+    let report = movie.play(&mut Nes::boot_from_bytes(&rom).unwrap());
+    assert_eq!(report.frames, 4);
+    assert_eq!(report, movie.play(&mut Nes::boot_from_bytes(&rom).unwrap()));
+  }
+}
@@ -1,10 +1,51 @@
-use crate::{apu::Apu, bus::Bus, cart::{Cart, CartHeader}, cpu::Cpu, frame::FrameBuffer, joypad::{Joypad, JoypadButton}, ppu::Ppu};
+use crate::{apu::{Apu, ApuState, Playback}, bank_map::BankMap, overrides::GameOverride, events::{EmuEvent, EventTracker, InputLatency}, mapper, overrides, savestate, save_bundle::SaveBundle, config::EmuConfig, bus::Bus, cart::{Cart, CartHeader, ConsoleTiming, RegionSource}, cpu::Cpu, frame::{fnv_hash, AspectMode, DisplayRect, FrameBlend, FrameBuffer}, joypad::{InputMacro, InputPoller, Joypad, JoypadButton, MacroEngine, TimedInput}, ppu::{Layer, MemoryChanges, Overlays, Ppu, ScanlineScroll, ScanlineSprites}, rng::Rng};
 use wasm_bindgen::prelude::wasm_bindgen;
 
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome { Ok, Jammed }
+
+// Called with the visible scanline the ppu just started, and the emulator itself.
+// The hook is taken out of the emulator while it runs, so it can do anything with it, even step it.
+pub type ScanlineHook = Box<dyn FnMut(u16, &mut Nes) + Send>;
+
+// A plain value, with no self references: the cart lives on the heap, and the devices share a pointer to it.
+// It can be moved freely, and kept on the stack, in an Arc<Mutex<_>>, or in any other storage.
 #[wasm_bindgen]
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Nes {
   cpu: Cpu<Bus>,
+  // settings belong to the frontend, they are not part of savestates
+  #[serde(skip)]
+  config: EmuConfig,
+  #[serde(skip)]
+  timed_inputs: Vec<TimedInput>,
+  // creation time of the game's save bundle
+  #[serde(skip)]
+  bundle_created_at: Option<u64>,
+  #[serde(skip)]
+  events: EventTracker,
+  // achievements safe mode, anything that lets the player tamper with the game is refused
+  #[serde(skip)]
+  hardcore: bool,
+  // set by the player, the board default otherwise. Kept when the game is swapped or power cycled.
+  #[serde(skip)]
+  dipswitches: Option<u8>,
+  // compatibility overrides of this instance, for the games it boots
+  #[serde(skip)]
+  overrides: Vec<GameOverride>,
+  #[serde(skip)]
+  scanline_hook: Option<ScanlineHook>,
+  #[serde(skip)]
+  macros: MacroEngine,
+  // what get_samples() does with the sound, while paused or in slow motion
+  #[serde(skip)]
+  playback: Playback,
+  // the scanline the hook was last called for
+  #[serde(skip)]
+  hooked_scanline: usize,
+  #[serde(skip)]
+  scanline_hook_replaced: bool,
 }
 
 #[wasm_bindgen]
@@ -14,27 +55,108 @@ impl Nes {
     Ok(Nes::boot_from_cart(cart))
   }
 
-  pub fn boot_empty() -> Self {
-    Self {
-      cpu: Cpu::with_cart(Cart::default()),
+  // The file name hints the region of games whose header doesn't tell it, e.g. "(E)" or "(Europe)" runs them as PAL
+  pub fn boot_from_file(rom: &[u8], file_name: &str) -> Result<Self, String> {
+    let cart = Cart::new_from_file(rom, file_name)?;
+    Ok(Nes::boot_from_cart(cart))
+  }
+
+  // Swaps the loaded cartridge with a new one, while keeping this instance (and the frontend resources built around it) alive.
+  // Returns the battery ram of the previous game, so that it can be persisted before being thrown away.
+  // If the new rom is invalid, the current game keeps running untouched.
+  pub fn swap_rom(&mut self, rom: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    self.swap_rom_from_file(rom, "")
+  }
+
+  // As swap_rom(), with the file name hinting the region, as in boot_from_file()
+  pub fn swap_rom_from_file(&mut self, rom: &[u8], file_name: &str) -> Result<Option<Vec<u8>>, String> {
+    let cart = Cart::new_with_overrides(rom, file_name, &self.overrides)?;
+    let old_sram = self.save_sram();
+    // the old bus (and its cart, with the mapper state) is dropped here.
+    // The new game boots from scratch, so hardcore mode simply goes on.
+    self.rebuild_keeping_frontend_state(Nes::boot_with_config(cart, self.config));
+    // a bundle of the old game says nothing about this one
+    self.bundle_created_at = None;
+    Ok(old_sram)
+  }
+
+  // Replace the chr or prg data of the running game in place, without resetting.
+  // Meant for homebrew development, to see the changes of a rebuilt rom live. The new data must be as big as the old one.
+  pub fn replace_chr(&mut self, chr: &[u8]) -> Result<(), String> {
+    self.refuse_in_hardcore("Replacing chr")?;
+    let cart = self.get_cart();
+    if chr.len() != cart.chr.len() {
+      return Err(format!("Chr size mismatch: expected {} bytes, got {}", cart.chr.len(), chr.len()));
+    }
+    cart.chr.copy_from_slice(chr);
+    Ok(())
+  }
+
+  pub fn replace_prg(&mut self, prg: &[u8]) -> Result<(), String> {
+    self.refuse_in_hardcore("Replacing prg")?;
+    let cart = self.get_cart();
+    if prg.len() != cart.prg.len() {
+      return Err(format!("Prg size mismatch: expected {} bytes, got {}", cart.prg.len(), prg.len()));
     }
+    cart.prg.copy_from_slice(prg);
+    Ok(())
+  }
+
+  pub fn boot_empty() -> Self {
+    Nes::boot_from_cart(Cart::default())
   }
 
-  pub fn step(&mut self) {
+  // A Jammed outcome means the game crashed, and the emulator will be stuck until a reset
+  pub fn step(&mut self) -> StepOutcome {
+    if !self.timed_inputs.is_empty() {
+      self.apply_timed_inputs();
+    }
+
+    let was_jammed = self.cpu.jammed;
     self.get_cpu().step();
+
+    let mut events = core::mem::take(&mut self.events);
+    events.track(self, was_jammed);
+    self.events = events;
+
+    if self.scanline_hook.is_some() {
+      self.run_scanline_hook();
+    }
+    if self.macros.is_active() {
+      let joypad = &self.cpu.bus.joypad;
+      if let Some(buttons) = self.macros.step(self.in_vblank(), [joypad.buttons1, joypad.buttons2]) {
+        self.get_joypad().macro_buttons = buttons;
+      }
+    }
+
+    if self.cpu.jammed { StepOutcome::Jammed } else { StepOutcome::Ok }
   }
 
-  pub fn step_until_vblank(&mut self) {
+  pub fn step_until_vblank(&mut self) -> StepOutcome {
     loop {
-      if self.get_bus().poll_vblank() { break; }
-      self.step();
+      if self.get_bus().poll_vblank() { break StepOutcome::Ok; }
+      if self.step() == StepOutcome::Jammed { break StepOutcome::Jammed; }
     }
   }
 
+  // As the reset button, the mapper registers are kept: multicarts stay on the selected game
   pub fn reset(&mut self) {
-    self.get_cpu().reset();
+    nen_log!(info, "nen::nes", "reset");
     self.get_ppu().reset();
     self.get_apu().reset();
+    // the cpu runs its reset sequence with the ppu and apu already reset
+    self.get_cpu().reset();
+    self.events.push(EmuEvent::Reset);
+  }
+
+  // Turns the console off and on, with the same cartridge. Multicarts go back to their menu.
+  // Battery backed ram survives, as do the settings and the frontend hooks.
+  pub fn power_cycle(&mut self) {
+    nen_log!(info, "nen::nes", "power cycle");
+    let mut cart = core::mem::take(self.get_cart());
+    cart.power_cycle();
+    self.rebuild_keeping_frontend_state(Nes::boot_with_config(cart, self.config));
+    self.events.push(EmuEvent::PowerCycle);
   }
 
   pub fn get_raw_screen(&self) -> *const u8 {
@@ -61,7 +183,48 @@ impl Nes {
   pub fn button_released(&mut self, button: u8) {
     self.get_joypad().buttons1.remove(JoypadButton::from_bits_retain(button));
   }
+
+  // Presses or releases a button of controller port 0 or 1 when the ppu reaches the given scanline,
+  // in the current frame or in the next one if it was already passed.
+  // Light gun and paddle games sample the input mid frame, and miss changes that only happen between frames.
+  pub fn set_joypad_btn_at(&mut self, port: usize, button: u8, pressed: bool, scanline: u16) -> Result<(), String> {
+    if port > 1 {
+      return Err(format!("No controller port {port}"));
+    }
+    let (frame, current_scanline, _) = self.ppu_position();
+    let frame = if scanline <= current_scanline { frame + 1 } else { frame };
+
+    self.timed_inputs.push(TimedInput {
+      frame,
+      scanline,
+      port,
+      button: JoypadButton::from_bits_retain(button),
+      pressed,
+    });
+    Ok(())
+  }
   
+  // The frontend stopped running the emulator: get_samples() fades to silence, and the frames advanced meanwhile fade out
+  pub fn set_paused(&mut self, paused: bool) {
+    self.playback.set_paused(paused);
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.playback.is_paused()
+  }
+
+  // The frontend runs the emulator at this fraction of real time, get_samples() stretches the sound to match.
+  // Slow motion is a speed change, refused in hardcore mode.
+  pub fn set_speed(&mut self, speed: f32) -> Result<(), String> {
+    self.refuse_in_hardcore("Changing the speed")?;
+    self.playback.set_speed(speed);
+    Ok(())
+  }
+
+  pub fn get_speed(&self) -> f32 {
+    self.playback.speed()
+  }
+
   pub fn get_fps(&self) -> f32 {
     self.get_cart_header().timing.fps()
   }
@@ -74,6 +237,37 @@ impl Nes {
     self.get_bus().cart.as_mut().set_sram(data);
   }
 
+  // Bundles all the persistent data of the game in a single .nensave file.
+  // `now` is the current unix time in seconds. Returns None if the game has nothing to persist.
+  pub fn save_bundle(&mut self, now: u64) -> Option<Vec<u8>> {
+    let created_at = *self.bundle_created_at.get_or_insert(now);
+    SaveBundle::from_cart(self.get_cart(), created_at, now)
+      .map(|bundle| bundle.to_bytes())
+  }
+
+  pub fn load_bundle(&mut self, bytes: &[u8]) -> Result<(), String> {
+    let bundle = SaveBundle::from_bytes(bytes)?;
+    let created_at = bundle.created_at;
+    bundle.apply_to_cart(self.get_cart())?;
+    self.bundle_created_at = Some(created_at);
+    Ok(())
+  }
+
+  // Overrides the battery flag, so that sram is (or isn't) handed out by save_sram()
+  pub fn force_battery(&mut self, has_battery: bool) {
+    self.get_cart().header.has_battery = has_battery;
+  }
+
+  pub fn set_dipswitch(&mut self, mask: u8) {
+    self.dipswitches = Some(mask);
+    self.get_cart().mapper.set_dipswitches(mask);
+  }
+
+  pub fn set_expansion_gain(&mut self, gain: f32) {
+    self.config.expansion_gain = Some(gain);
+    self.get_apu().set_expansion_gain(gain);
+  }
+
   pub fn toggle_sprite_limit(&mut self) {
     let limit = &mut self.get_ppu().oam_sprite_limit;
     if *limit == 8 {
@@ -81,16 +275,73 @@ impl Nes {
     } else {
       *limit = 8;
     }
+    self.config.sprite_limit = self.cpu.bus.ppu.oam_sprite_limit == 8;
+  }
+
+  pub fn save_state(&self) -> Vec<u8> {
+    savestate::serialize(self)
+  }
+
+  // Savestates from older versions are migrated to the current layout, when possible
+  pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+    let other = savestate::deserialize(bytes)?;
+    nen_log!(info, "nen::nes", "loaded a savestate of {} bytes", bytes.len());
+    self.load_from_emu(other)
+  }
+
+  // Quicker and smaller than save_state(), but only loadable by this same build of the emulator
+  pub fn save_state_fast(&self) -> Vec<u8> {
+    savestate::serialize_fast(&self.cpu, &self.cpu.bus.cart.as_ref().header)
+  }
+
+  // A state which fails to load leaves the emulator as it was
+  pub fn load_state_fast(&mut self, bytes: &[u8]) -> Result<(), String> {
+    self.refuse_in_hardcore("Loading savestates")?;
+
+    let backup = self.save_state_fast();
+    let header = self.get_cart_header().clone();
+    if let Err(e) = savestate::deserialize_fast(&mut self.cpu, &header, bytes) {
+      savestate::deserialize_fast(&mut self.cpu, &header, &backup).expect("backup state should always load");
+      return Err(e);
+    }
+
+    self.apply_config(self.config);
+    self.resume_macros();
+    self.touch_video_memory(self.cpu.bus.ppu.changes);
+    self.events.push(EmuEvent::StateLoaded);
+    Ok(())
+  }
+
+  // Hardcore mode is for achievement systems: savestate loading and any other way to alter the game return an error.
+  // Turning it on resets the game, so that nothing done before carries over, and goes back to real time.
+  pub fn set_hardcore(&mut self, enabled: bool) {
+    if enabled && !self.hardcore {
+      self.playback.set_speed(1.0);
+      self.apply_config(EmuConfig { overclock_pre_nmi: 0, overclock_post_nmi: 0, ..self.config });
+      self.reset();
+    }
+    self.hardcore = enabled;
+  }
+
+  pub fn is_hardcore(&self) -> bool {
+    self.hardcore
   }
 
-  pub fn load_from_emu(&mut self, other: Nes) {
+  pub fn load_from_emu(&mut self, mut other: Nes) -> Result<(), String> {
+    self.refuse_in_hardcore("Loading savestates")?;
+
     // save prg and chr in temp values
     let old_cart = self.get_bus().cart.as_mut();
     let prg = core::mem::take(&mut old_cart.prg);
     let chr = core::mem::take(&mut old_cart.chr);
 
-    // copy the new emulator
-    *self = other;
+    // copy the new emulator, keeping our settings
+    let timing_source = self.get_cart_header().timing_source;
+    // before the rebuild, which looks the game's overrides up
+    other.get_cart().header.rom_crc32 = self.get_cart_header().rom_crc32;
+    self.rebuild_keeping_frontend_state(other);
+    self.get_cart().header.timing_source = timing_source;
+    self.events.push(EmuEvent::StateLoaded);
 
     // the new emulator is missing prg and chr; we take the temp ones
     let new_cart = self.get_bus().cart.as_mut();
@@ -106,14 +357,210 @@ impl Nes {
     self.get_ppu().wire_cart(ppu_cart);
     let apu_cart = self.cpu.bus.cart.clone();
     self.get_apu().wire_cart(apu_cart);
+    Ok(())
   }
 }
 
 impl Nes {
   pub fn boot_from_cart(cart: Cart) -> Self {
-    Self {
+    Self::boot_with_config(cart, EmuConfig::default())
+  }
+
+  // Boots with the settings already in place, so that the power on state follows them
+  pub fn new_with_config(rom: &[u8], config: EmuConfig) -> Result<Self, String> {
+    let cart = Cart::new(rom)?;
+    Ok(Nes::boot_with_config(cart, config))
+  }
+
+  pub fn new_from_file(rom: &[u8], file_name: &str, config: EmuConfig) -> Result<Self, String> {
+    let cart = Cart::new_from_file(rom, file_name)?;
+    Ok(Nes::boot_with_config(cart, config))
+  }
+
+  // As new_from_file(), with compatibility overrides on top of the builtin ones.
+  // They are kept for the games swapped in later, other instances don't see them.
+  pub fn new_with_overrides(rom: &[u8], file_name: &str, config: EmuConfig, overrides: Vec<GameOverride>) -> Result<Self, String> {
+    let cart = Cart::new_with_overrides(rom, file_name, &overrides)?;
+    let mut nes = Nes::boot_with_config(cart, config);
+    nes.overrides = overrides;
+    nes.apply_config(config);
+    Ok(nes)
+  }
+
+  // Adds an override for the games this instance loads from now on, replacing any previous one for the same rom.
+  // The running game keeps its header, until it is loaded again.
+  pub fn add_override(&mut self, entry: GameOverride) {
+    self.remove_override(entry.rom_hash);
+    self.overrides.push(entry);
+  }
+
+  pub fn remove_override(&mut self, rom_hash: u64) {
+    self.overrides.retain(|e| e.rom_hash != rom_hash);
+  }
+
+  pub fn get_overrides(&self) -> &[GameOverride] {
+    &self.overrides
+  }
+
+  pub fn boot_with_config(mut cart: Cart, config: EmuConfig) -> Self {
+    cart.rng = Rng::new(config.rng_seed);
+    if let Some(region) = config.region {
+      cart.header.timing = region;
+      cart.header.timing_source = RegionSource::Config;
+    }
+    nen_log!(info, "nen::nes", "running as {:?}, from {:?}", cart.header.timing, cart.header.timing_source);
+    let mut nes = Self {
       cpu: Cpu::with_cart(cart),
+      config,
+      timed_inputs: Vec::new(),
+      bundle_created_at: None,
+      events: EventTracker::default(),
+      hardcore: false,
+      dipswitches: None,
+      overrides: Vec::new(),
+      scanline_hook: None,
+      playback: Playback::default(),
+      macros: MacroEngine::default(),
+      hooked_scanline: 0,
+      scanline_hook_replaced: false,
+    };
+    nes.apply_config(config);
+    nes.get_bus().init_ram(config.ram_init);
+    nes
+  }
+
+  // The hook runs at the first instruction boundary of each visible scanline, so up to a few dots after it started
+  fn run_scanline_hook(&mut self) {
+    let scanline = self.cpu.bus.ppu.scanline;
+    if scanline == self.hooked_scanline { return; }
+    self.hooked_scanline = scanline;
+    if scanline >= 240 { return; }
+
+    if let Some(mut hook) = self.scanline_hook.take() {
+      self.scanline_hook_replaced = false;
+      hook(scanline as u16, self);
+      // unless the hook replaced or removed itself
+      if !self.scanline_hook_replaced {
+        self.scanline_hook = Some(hook);
+      }
+    }
+  }
+
+  // Puts the new console in place of this one, carrying over what belongs to the frontend:
+  // settings, input, hooks, playback, macros, events, dip switches, overrides and the debug view.
+  fn rebuild_keeping_frontend_state(&mut self, mut new: Nes) {
+    let config = self.config;
+    let (frame, _, _) = self.ppu_position();
+    let mut timed_inputs = core::mem::take(&mut self.timed_inputs);
+    new.get_joypad().poller = self.get_joypad().poller.take();
+    new.scanline_hook = self.scanline_hook.take();
+    new.playback = core::mem::take(&mut self.playback);
+    new.macros = core::mem::take(&mut self.macros);
+    new.events = core::mem::take(&mut self.events);
+    new.hardcore = self.hardcore;
+    new.dipswitches = self.dipswitches;
+    new.overrides = core::mem::take(&mut self.overrides);
+    if let Some(mask) = self.dipswitches {
+      new.get_cart().mapper.set_dipswitches(mask);
     }
+    new.bundle_created_at = self.bundle_created_at;
+    new.get_ppu().view = self.cpu.bus.ppu.view;
+    let changes = self.cpu.bus.ppu.changes;
+
+    *self = new;
+    // the random generator comes from the new console, it must not be reseeded
+    self.config = config;
+    self.apply_config(config);
+    self.resume_macros();
+    self.touch_video_memory(changes);
+
+    // pending timed inputs stay as many frames ahead on the new console
+    let (new_frame, _, _) = self.ppu_position();
+    for input in &mut timed_inputs {
+      input.frame = (input.frame + new_frame).saturating_sub(frame);
+    }
+    self.timed_inputs = timed_inputs;
+  }
+
+  // The video memory was replaced as a whole, the counters go on from the old ones so that viewers notice
+  fn touch_video_memory(&mut self, mut changes: MemoryChanges) {
+    changes.touch_all();
+    self.get_ppu().changes = changes;
+  }
+
+  // Frames of the macros end with the picture
+  fn in_vblank(&self) -> bool {
+    let ppu = &self.cpu.bus.ppu;
+    ppu.scanline >= 241 && ppu.scanline < ppu.last_scanline
+  }
+
+  // The ppu may have moved without the macros following it: after loads, or while they were idle
+  fn resume_macros(&mut self) {
+    self.macros.sync_vblank(self.in_vblank());
+    self.get_joypad().macro_buttons = self.macros.buttons();
+  }
+
+  fn apply_timed_inputs(&mut self) {
+    let (frame, scanline, _) = self.ppu_position();
+    let mut inputs = core::mem::take(&mut self.timed_inputs);
+
+    inputs.retain(|input| {
+      if (input.frame, input.scanline) > (frame, scanline) { return true; }
+
+      let joypad = &mut self.cpu.bus.joypad;
+      let buttons = if input.port == 0 { &mut joypad.buttons1 } else { &mut joypad.buttons2 };
+      if input.pressed {
+        buttons.insert(input.button);
+      } else {
+        buttons.remove(input.button);
+      }
+      false
+    });
+
+    self.timed_inputs = inputs;
+  }
+
+  // Overclocking is a speed change, refused in hardcore mode
+  pub fn set_config(&mut self, config: EmuConfig) -> Result<(), String> {
+    if config.overclock_pre_nmi != 0 || config.overclock_post_nmi != 0 {
+      self.refuse_in_hardcore("Overclocking")?;
+    }
+    self.apply_config(config);
+    Ok(())
+  }
+
+  fn apply_config(&mut self, config: EmuConfig) {
+    if config.rng_seed != self.config.rng_seed {
+      self.get_cart().rng = Rng::new(config.rng_seed);
+    }
+    self.config = config;
+    self.cpu.unstable_magic = config.unstable_magic;
+    self.cpu.bus.apu.dmc.reduce_pops = config.dmc_reduce_pops;
+    self.get_apu().set_mute_ultrasonic_triangle(config.mute_ultrasonic_triangle);
+    self.get_apu().set_sample_rate(config.sample_rate, config.resample_quality);
+    self.get_apu().set_filter_profile(config.filter_profile);
+    let expansion_gain = config.expansion_gain
+      .unwrap_or_else(|| mapper::board_expansion_gain(self.get_cart_header()));
+    self.get_apu().set_expansion_gain(expansion_gain);
+    self.get_ppu().accurate_oam = config.accurate_oam;
+    self.get_ppu().render_accuracy = config.render_accuracy;
+    self.get_ppu().overclock = (config.overclock_pre_nmi, config.overclock_post_nmi);
+    self.get_joypad().opposite_directions = config.opposite_directions;
+    let sprite_limit = overrides::find(self.get_cart_header(), &self.overrides)
+      .and_then(|entry| entry.sprite_limit)
+      .unwrap_or(config.sprite_limit);
+    self.get_ppu().oam_sprite_limit = if sprite_limit { 8 } else { u8::MAX };
+  }
+
+  fn refuse_in_hardcore(&self, what: &str) -> Result<(), String> {
+    if self.hardcore {
+      return Err(format!("{what} is disabled in hardcore mode"));
+    }
+    Ok(())
+  }
+
+  pub fn get_config(&self) -> &EmuConfig {
+    &self.config
   }
 
   pub fn get_bus(&mut self) -> &mut Bus {
@@ -132,6 +579,12 @@ impl Nes {
     &mut self.cpu.bus.apu
   }
 
+  // The region the game runs as, and how it was decided. To force another one, boot with EmuConfig::region.
+  pub fn region(&self) -> (ConsoleTiming, RegionSource) {
+    let header = self.get_cart_header();
+    (header.timing, header.timing_source)
+  }
+
   pub fn get_cart_header(&self) -> &CartHeader {
     &self.cpu.bus.cart.as_ref().header
   }
@@ -140,14 +593,233 @@ impl Nes {
     self.get_bus().cart.as_mut()
   }
 
+  // Hash of the whole serialized state, two emulators with the same hash are in sync
+  pub fn state_hash(&self) -> u64 {
+    let state = serde_json::to_vec(self).expect("state should always be serializable");
+    fnv_hash(&state)
+  }
+
+  // Every field that differs between this emulator's state and another's, to find where two runs desynced
+  #[cfg(feature = "debug-tools")]
+  pub fn diff_state(&self, other: &Nes) -> Vec<crate::state_diff::FieldDiff> {
+    crate::state_diff::diff(self, other)
+  }
+
+  // Reads cpu memory without side effects, io registers read as 0
+  pub fn peek(&mut self, addr: u16) -> u8 {
+    self.get_bus().peek(addr)
+  }
+
+  // Which bank every page of the cpu and ppu address space is currently mapped to
+  pub fn current_banks(&self) -> BankMap {
+    BankMap::new(self.cpu.bus.cart.as_ref())
+  }
+
+  // Hides the background or the sprites from the screen, for debugging and screenshots. The game isn't affected.
+  pub fn set_layer_visible(&mut self, layer: Layer, visible: bool) {
+    self.get_ppu().set_layer_visible(layer, visible);
+  }
+
+  // Draws the tile grid, the attribute grid or the sprite boxes over the picture
+  pub fn set_overlay(&mut self, overlays: Overlays, enabled: bool) {
+    self.get_ppu().view.overlays.set(overlays, enabled);
+  }
+
+  // Blends every frame with the previous one, to smooth out the flicker of games which alternate their sprites every frame
+  pub fn set_frame_blend(&mut self, mode: FrameBlend) {
+    self.get_ppu().set_frame_blend(mode);
+  }
+
+  // Extra scanlines of cpu time before and after the nmi, to reduce slowdown (see EmuConfig).
+  // Refused in hardcore mode, as any other speed change.
+  pub fn set_overclock(&mut self, extra_scanlines_pre_nmi: u16, extra_scanlines_post_nmi: u16) -> Result<(), String> {
+    self.set_config(EmuConfig {
+      overclock_pre_nmi: extra_scanlines_pre_nmi,
+      overclock_post_nmi: extra_scanlines_post_nmi,
+      ..self.config
+    })
+  }
+
+  // The scrolling every visible scanline was rendered with in the last frame, to inspect split screens and status bars
+  pub fn debug_scroll_log(&self) -> &[ScanlineScroll] {
+    self.cpu.bus.ppu.scroll_log()
+  }
+
+  // The controllers state the game read the last time it latched them, for input displays.
+  // It can differ from what the frontend sent, if the input changed after the game polled it.
+  pub fn last_latched_input(&self) -> (JoypadButton, JoypadButton) {
+    self.cpu.bus.joypad.last_latched()
+  }
+
+  // Asks the frontend for the input right when the game latches the controllers, instead of once per frame
+  pub fn set_input_poller(&mut self, poller: Option<InputPoller>) {
+    self.get_joypad().poller = poller;
+  }
+
+  // Adds a macro, played with trigger_macro(id) with the returned id
+  pub fn add_macro(&mut self, input_macro: InputMacro) -> usize {
+    self.macros.add(input_macro)
+  }
+
+  // Plays a macro on controller port 0 or 1, from the current frame. Several macros can play at once.
+  pub fn trigger_macro(&mut self, id: usize, port: usize) -> Result<(), String> {
+    self.macros.trigger(id, port)?;
+    self.resume_macros();
+    Ok(())
+  }
+
+  // Removes every macro, and stops the ones playing
+  pub fn clear_macros(&mut self) {
+    self.macros.clear();
+    self.resume_macros();
+  }
+
+  // Records the buttons held on a controller port every frame, until stop_macro_recording()
+  pub fn start_macro_recording(&mut self, port: usize) {
+    self.macros.start_recording(port);
+    self.resume_macros();
+  }
+
+  pub fn stop_macro_recording(&mut self) -> Option<InputMacro> {
+    self.macros.stop_recording()
+  }
+
+  // Runs the hook at the start of every visible scanline, for mid-frame overlays, palette debugging or data capture.
+  // To remove the hook from inside it, call set_scanline_hook(None) there.
+  pub fn set_scanline_hook(&mut self, hook: Option<ScanlineHook>) {
+    self.scanline_hook = hook;
+    self.scanline_hook_replaced = true;
+    self.hooked_scanline = self.cpu.bus.ppu.scanline;
+  }
+
+  // What every apu channel is doing, for apu viewers
+  pub fn apu_state(&self) -> ApuState {
+    self.cpu.bus.apu.debug_state()
+  }
+
+  // The oam entries in range of a visible scanline, to see which sprites the 8 sprites limit drops
+  pub fn debug_scanline_sprites(&self, scanline: usize) -> Option<&ScanlineSprites> {
+    self.cpu.bus.ppu.scanline_sprites(scanline)
+  }
+
+  // The screen with the sprites dropped by the 8 sprites limit tinted in red
+  pub fn debug_sprite_overlay(&self) -> FrameBuffer {
+    self.cpu.bus.ppu.sprite_limit_overlay()
+  }
+
+  // The nametable ram of the console, 2KB, or 4KB for four screen boards
+  pub fn debug_nametables(&self) -> &[u8] {
+    &self.cpu.bus.cart.as_ref().ciram
+  }
+
+  pub fn debug_palettes(&self) -> &[u8] {
+    self.cpu.bus.ppu.palette_ram()
+  }
+
+  pub fn debug_oam(&self) -> &[u8] {
+    self.cpu.bus.ppu.oam_ram()
+  }
+
+  // What the mapper chip holds (registers, irq counter, mode flags), with the banks mapped in each page.
+  // It is meant for display: the fields are the mapper's own, and can change between versions.
+  pub fn mapper_debug_state(&self) -> serde_json::Value {
+    let cart = self.cpu.bus.cart.as_ref();
+    serde_json::json!({
+      "mapper": cart.header.mapper,
+      "name": cart.header.mapper_name,
+      "state": cart.mapper.debug_state(),
+      "banks": {
+        "prg": cart.banks.prg.banks(),
+        "chr": cart.banks.chr.banks(),
+        "sram": cart.banks.sram.banks(),
+        "ciram": cart.banks.ciram.banks(),
+      },
+    })
+  }
+
+  // Counts of writes to the nametables, palettes and oam, for viewers to skip redrawing the unchanged ones
+  pub fn memory_changes(&self) -> MemoryChanges {
+    self.cpu.bus.ppu.changes
+  }
+
+  // The cpu page copied to oam by the last $4014 write
+  pub fn oam_dma_page(&self) -> Option<u8> {
+    self.cpu.bus.oam_dma.last_page
+  }
+
+  // Delays between the input changes and the game reading them, to measure the effect of run-ahead or late polling
+  pub fn input_latency(&self) -> InputLatency {
+    self.events.input_latency
+  }
+
+  pub fn reset_input_latency(&mut self) {
+    self.events.input_latency = InputLatency::default();
+  }
+
+  // Events that happened since the last call, oldest first
+  pub fn poll_events(&mut self) -> Vec<EmuEvent> {
+    self.events.take()
+  }
+
+  // (frame, scanline, dot) the ppu is currently at
+  pub fn ppu_position(&self) -> (u64, u16, u16) {
+    let ppu = &self.cpu.bus.ppu;
+    (ppu.frame, ppu.scanline as u16, ppu.cycle as u16)
+  }
+
   pub fn get_resolution(&mut self) -> (usize, usize) { (32*8, 30*8) }
 
+  // Width of a pixel relative to its height, as seen on a tv of the game's region (8:7 on ntsc)
+  pub fn pixel_aspect_ratio(&self) -> f32 {
+    self.get_cart_header().timing.pixel_aspect_ratio()
+  }
+
+  // The part of the screen left after cropping the overscan of the config
+  pub fn visible_area(&self) -> DisplayRect {
+    let screen = self.get_screen();
+    let overscan = self.config.overscan;
+    let (left, right) = (overscan.left as u32, overscan.right as u32);
+    let (top, bottom) = (overscan.top as u32, overscan.bottom as u32);
+    DisplayRect {
+      x: left,
+      y: top,
+      width: (screen.width as u32).saturating_sub(left + right),
+      height: (screen.height as u32).saturating_sub(top + bottom),
+    }
+  }
+
+  // Where to draw the visible area in a window of the given size, following the aspect mode of the config
+  pub fn display_rect(&self, window_width: u32, window_height: u32) -> DisplayRect {
+    let visible = self.visible_area();
+    let pixel_aspect_ratio = match self.config.aspect_mode {
+      AspectMode::Correct => self.pixel_aspect_ratio(),
+      AspectMode::SquarePixels => 1.0,
+    };
+    DisplayRect::fit((window_width, window_height), (visible.width, visible.height), pixel_aspect_ratio)
+  }
+
   pub fn get_screen(&self) -> &FrameBuffer {
     &self.cpu.bus.ppu.screen
   }
 
+  // Runs headlessly for the given number of frames, returning the hash of each rendered frame
+  pub fn run_and_hash(&mut self, frames: usize) -> Vec<u64> {
+    (0..frames).map(|_| {
+      self.step_until_vblank();
+      self.get_screen().hash()
+    }).collect()
+  }
+
+  // Meant to be called once per frame shown. While paused it gives a frame of silence, and in slow motion the stretched samples.
   pub fn get_samples(&mut self) -> Vec<f32> {
-    self.get_apu().consume_samples()
+    let samples = self.get_apu().consume_samples();
+    let frame_samples = self.get_sample_rate() as f64 / self.get_fps() as f64;
+    self.playback.process(samples, frame_samples)
+  }
+
+  // Samples per second of get_samples(), as set by the config
+  pub fn get_sample_rate(&self) -> u32 {
+    self.cpu.bus.apu.sample_rate()
   }
 
   pub fn get_joypad(&mut self) -> &mut Joypad {
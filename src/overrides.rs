@@ -0,0 +1,94 @@
+use crate::cart::{CartHeader, ConsoleTiming, HeaderDiagnostic, Mirroring, RegionSource};
+use crate::mapper;
+
+// Fixes for games whose header is wrong, or which need a setting to run correctly.
+// Games are told apart by CartHeader::rom_hash (rom_crc32 for the builtin ones), so a fix applies whatever the file name or header of the dump.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameOverride {
+  pub rom_hash: u64,
+  pub name: String,
+  pub mapper: Option<u16>,
+  pub submapper: Option<u8>,
+  pub mirroring: Option<Mirroring>,
+  pub battery: Option<bool>,
+  // Some(false) lifts the 8 sprites per scanline limit, for games which flicker a lot
+  pub sprite_limit: Option<bool>,
+  // for PAL games with an iNes header, which would run too fast as NTSC
+  pub timing: Option<ConsoleTiming>,
+}
+
+impl GameOverride {
+  pub fn new(rom_hash: u64, name: &str) -> Self {
+    Self { rom_hash, name: name.to_string(), ..Default::default() }
+  }
+
+  pub fn apply_to_header(&self, header: &mut CartHeader) {
+    if let Some(mapper) = self.mapper {
+      header.mapper = mapper;
+      header.mapper_name = mapper::mapper_name(mapper).to_string();
+    }
+    if let Some(submapper) = self.submapper {
+      header.submapper = submapper;
+    }
+    if let Some(mirroring) = self.mirroring {
+      header.mirroring = mirroring;
+      header.has_alt_mirroring = mirroring == Mirroring::FourScreen;
+    }
+    if let Some(battery) = self.battery {
+      header.has_battery = battery;
+    }
+    if let Some(timing) = self.timing {
+      header.timing = timing;
+      header.timing_source = RegionSource::Database;
+    }
+    header.diagnostics.push(HeaderDiagnostic::Overridden(self.name.clone()));
+  }
+}
+
+// (crc32, name, mapper, submapper, mirroring, battery, sprite limit, timing)
+type BuiltinEntry = (u32, &'static str, Option<u16>, Option<u8>, Option<Mirroring>, Option<bool>, Option<bool>, Option<ConsoleTiming>);
+
+// Known problem games, by the crc32 of their prg and chr (CartHeader::rom_crc32), as rom databases list them.
+// Both are logged when a game is loaded, and shown by the debugger.
+const BUILTIN: &[BuiltinEntry] = &[
+  // discrete boards with hardwired mirroring, commonly dumped with the wrong one
+  (0x9EA1DC76, "Rainbow Islands", Some(2), None, Some(Mirroring::Horizontal), None, None, None),
+  (0x6D65CAC6, "Terra Cresta", Some(2), None, Some(Mirroring::Horizontal), None, None, None),
+  (0xE1B260DA, "Argos no Senshi", Some(2), None, Some(Mirroring::Vertical), None, None, None),
+  (0xDBF90772, "Alpha Mission", Some(3), None, Some(Mirroring::Horizontal), None, None, None),
+  (0xCF322BB3, "John Elway's Quarterback", Some(3), None, Some(Mirroring::Vertical), None, None, None),
+];
+
+// The overrides of an instance (see Nes::add_override()) take precedence over the builtin ones
+pub fn find(header: &CartHeader, registered: &[GameOverride]) -> Option<GameOverride> {
+  registered.iter().find(|e| e.rom_hash == header.rom_hash).cloned()
+    .or_else(|| BUILTIN.iter()
+      .find(|e| e.0 == header.rom_crc32)
+      .map(|&(_, name, mapper, submapper, mirroring, battery, sprite_limit, timing)| GameOverride {
+        rom_hash: header.rom_hash, name: name.to_string(), mapper, submapper, mirroring, battery, sprite_limit, timing,
+      })
+    )
+}
+
+#[cfg(test)]
+mod overrides_tests {
+  use crate::frame::crc32;
+  use super::*;
+
+  #[test]
+  fn builtin_overrides() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+    let mut header = CartHeader { rom_hash: 42, rom_crc32: BUILTIN[0].0, ..Default::default() };
+    let entry = find(&header, &[]).unwrap();
+    assert_eq!((entry.rom_hash, entry.name.as_str()), (42, BUILTIN[0].1));
+    assert_eq!(entry.mirroring, BUILTIN[0].4);
+
+    let mut registered = GameOverride::new(42, "Registered");
+    registered.sprite_limit = Some(false);
+    assert_eq!(find(&header, &[registered.clone()]), Some(registered));
+
+    header.rom_crc32 = 0;
+    assert_eq!(find(&header, &[]), None);
+  }
+}
@@ -1,9 +1,12 @@
-use crate::{cart::{ConsoleTiming, SharedCart}, frame::FrameBuffer};
+use crate::{cart::{ConsoleTiming, SharedCart}, codec::{codec_bitfield, codec_bits, codec_enum, codec_fields}, frame::{FrameBlend, FrameBuffer, FrameInfo}, mapper::{Mapper, PpuCtx}};
 use bitfield_struct::bitfield;
 use bitflags::bitflags;
 use render::Fetcher;
 
 mod render;
+mod overlay;
+
+pub use overlay::{Overlays, ViewOptions};
 
 bitflags! {
 	#[derive(Default, serde::Serialize, serde::Deserialize)]
@@ -26,9 +29,10 @@ bitflags! {
 		const bg_enabled     = 0b0000_1000;
 
 		const spr_enabled = 0b0001_0000;
-		const red_boost   = 0b0010_0000;
-		const blue_boost  = 0b0100_0000;
-		const green_boost = 0b1000_0000;
+		// named after the ntsc ppu, pal and dendy swap red and green
+		const emph_red    = 0b0010_0000;
+		const emph_green  = 0b0100_0000;
+		const emph_blue   = 0b1000_0000;
 	}
 
 	#[derive(Default, serde::Serialize, serde::Deserialize)]
@@ -39,6 +43,9 @@ bitflags! {
 		const vblank       = 0b1000_0000;
 	}
 }
+codec_bits!(Ctrl);
+codec_bits!(Mask);
+codec_bits!(Stat);
 
 impl Ctrl {
 	pub fn vram_addr_incr(&self) -> u16 {
@@ -80,6 +87,7 @@ struct LoopyReg {
 	#[bits(1)]
 	__: u8,
 }
+codec_bitfield!(LoopyReg);
 impl LoopyReg {
 	pub fn nametbl(&self) -> u8 {
 		(self.nametbl_y() << 1) | self.nametbl_x()
@@ -110,6 +118,7 @@ enum WriteLatch {
 	#[default] FirstWrite,
 	SecondWrite,
 }
+codec_enum!(WriteLatch { FirstWrite, SecondWrite });
 
 enum VramDst {
 	Patterntbl,
@@ -118,16 +127,88 @@ enum VramDst {
 	Unused,
 }
 
-#[derive(Default, PartialEq, serde::Serialize, serde::Deserialize)]
-pub enum PpuState {
-	FetchBg,
-	FetchSpr,
-	#[default] Vblank
-}
-
 pub const NAMETABLES: u16 = 0x2000;
 pub const ATTRIBUTES: u16 = 0x23C0;
 pub const PALETTES: u16 = 0x3F00;
+// oam rows decay after about 3000 cpu cycles without being refreshed
+const OAM_DECAY_DOTS: u64 = 3000 * 3;
+// what a decayed row reads back. The bits lost differ on every console, with all of them set the sprites go off screen.
+pub const OAM_DECAY_VALUE: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer { Bg, Spr }
+
+// How closely rendering follows the dots of the real ppu. The background is always fetched dot by dot.
+// Scanline evaluates and fetches the sprites of a scanline all at once, which is faster and enough for most games.
+// Dot evaluates them over the visible dots and fetches each one at its own dots, so that oam writes,
+// $2004 reads, the buggy overflow flag and chr bank switches during hblank behave as on the console.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RenderAccuracy { #[default] Scanline, Dot }
+
+// Writes to every video memory, so that viewers only refresh what changed since they last drew.
+// Writes of the same value count too, and savestate loads count as a write to everything.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryChanges {
+	pub nametables: u64,
+	pub palettes: u64,
+	pub oam: u64,
+}
+
+impl MemoryChanges {
+	pub fn touch_all(&mut self) {
+		self.nametables += 1;
+		self.palettes += 1;
+		self.oam += 1;
+	}
+}
+
+// The real ppu draws at most this many sprites per scanline
+pub const HW_SPRITE_LIMIT: usize = 8;
+
+// A sprite found in range of a scanline by the sprite evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvaluatedSprite {
+	// oam entry, 0 to 63
+	pub index: u8,
+	pub x: u8,
+	pub y: u8,
+}
+
+// Every sprite in range of a scanline, in oam order.
+// Past the 8th, the real console drops them, which is why games flicker sprites around.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanlineSprites {
+	pub sprites: Vec<EvaluatedSprite>,
+}
+
+impl ScanlineSprites {
+	pub fn drawn(&self) -> &[EvaluatedSprite] {
+		&self.sprites[..self.sprites.len().min(HW_SPRITE_LIMIT)]
+	}
+
+	pub fn dropped(&self) -> &[EvaluatedSprite] {
+		&self.sprites[self.sprites.len().min(HW_SPRITE_LIMIT)..]
+	}
+}
+
+// Where the background of a scanline starts, once the scrolling registers are applied.
+// Games with a status bar or a split screen change it mid frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanlineScroll {
+	pub x: u8,
+	pub y: u8,
+	// 0 to 3, from $2000 to $2C00
+	pub nametable: u8,
+}
+
+impl ScanlineScroll {
+	// Position in the 512x480 plane made by the 4 nametables
+	pub fn plane_position(&self) -> (usize, usize) {
+		let x = self.x as usize + (self.nametable as usize & 1) * 256;
+		let y = self.y as usize + (self.nametable as usize >> 1) * 240;
+		(x, y)
+	}
+}
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct Ppu {
@@ -154,11 +235,46 @@ pub struct Ppu {
 	palettes: [u8; 32],
 	oam: Box<[u8]>,
 	pub oam_sprite_limit: u8,
+	#[serde(skip)]
+	pub view: ViewOptions,
+	// last frame, as the frame blending needs it
+	#[serde(skip)]
+	previous_frame: Option<FrameBuffer>,
+	// scrolling of every visible scanline in the last frame
+	#[serde(skip)]
+	scroll_log: Vec<ScanlineScroll>,
+	// result of the last sprite evaluation of every visible scanline, for debuggers
+	#[serde(skip)]
+	sprite_eval: Vec<ScanlineSprites>,
+	// Optional emulation of oam dram decay and oamaddr corruption, off by default as few games need it
+	#[serde(skip)]
+	pub accurate_oam: bool,
+	#[serde(skip)]
+	pub render_accuracy: RenderAccuracy,
+	// dot at which each 8 bytes row of oam was last refreshed
+	#[serde(default)]
+	oam_rows_refresh: [u64; 32],
+	#[serde(skip)]
+	pub changes: MemoryChanges,
 	
 	pub scanline: usize,
 	pub last_scanline: usize,
 	pub cycle: usize,
+	// number of the frame being rendered, counted from power on. A reset does not clear it
+	#[serde(default)]
+	pub frame: u64,
 	in_odd_frame: bool,
+	// dots run since power on, for the emulated time of the frames
+	#[serde(default)]
+	dots: u64,
+	// extra scanlines before and after the nmi, and the dots left in the current ones
+	#[serde(skip)]
+	pub overclock: (u16, u16),
+	#[serde(default)]
+	overclock_dots: u32,
+	// After a reset, writes to ctrl, mask, scroll and addr are ignored until the pre-render scanline
+	#[serde(default)]
+	reset_latch: bool,
 	
 	pub nmi_tmp: Option<()>,
 	pub nmi_requested: Option<()>,
@@ -166,6 +282,7 @@ pub struct Ppu {
 	nmi_suppress: bool,
 	pub frame_ready: Option<()>,
 }
+codec_fields!(Ppu { renderer, v, t, x, w, ctrl, mask, mask_tmp, mask_write_delay, stat, oam_addr, data_buf, palettes, oam, oam_sprite_limit, oam_rows_refresh, scanline, last_scanline, cycle, frame, in_odd_frame, dots, overclock_dots, reset_latch, nmi_tmp, nmi_requested, vblank_suppress, nmi_suppress, frame_ready });
 
 impl Ppu {
 	pub fn new(cart: SharedCart) -> Self {
@@ -206,18 +323,41 @@ impl Ppu {
 		self.x = 0;
 		self.data_buf = 0;
 		self.in_odd_frame = false;
+		self.mask_write_delay = 0;
+		// with ctrl cleared, a pending nmi never reaches the cpu
+		self.nmi_tmp = None;
+		self.nmi_requested = None;
 
+		// the ppu restarts from the top of the picture, so a frame passes before writes are accepted again
+		// https://www.nesdev.org/wiki/PPU_power_up_state
 		self.cycle = 0;
-		self.scanline = self.last_scanline;
+		self.scanline = 0;
+		self.reset_latch = true;
 	}
 
 	pub fn step(&mut self) {
+		if self.overclock_dots > 0 {
+			self.overclock_dots -= 1;
+			return;
+		}
+
 		if (0..=239).contains(&self.scanline) {
 			self.render_step();
 		} if self.scanline == 241 {
-			self.cart.as_mut().mapper.notify_ppu_state(PpuState::Vblank);
-
 			if self.cycle == 1 {
+				self.notify_mapper(false, |mapper, ctx| mapper.notify_frame_end(ctx));
+				// overlays are drawn after, so that they don't leave trails
+				if self.view.frame_blend != FrameBlend::Off {
+					self.blend_frame();
+				}
+				if !self.view.overlays.is_empty() {
+					self.draw_overlays();
+				}
+				self.screen.info = FrameInfo {
+					index: self.frame,
+					time_ns: (self.dots as f64 * 1e9 / self.cart.as_ref().header.timing.ppu_hz()) as u64,
+					odd_field: self.in_odd_frame,
+				};
 				self.frame_ready = Some(());
 				self.stat.set(Stat::vblank, !self.vblank_suppress);
 
@@ -230,9 +370,14 @@ impl Ppu {
 
 			if self.cycle == 1 {
 				self.stat = Stat::empty();
+				self.reset_latch = false;
+				if self.accurate_oam && self.rendering_enabled() {
+					self.corrupt_oam();
+				}
 				self.oam_addr = 0;
 			} else if self.cycle == 304 {
 				self.reset_render_y();
+				self.log_scroll(0);
 			} else if self.cart.as_mut().header.timing != ConsoleTiming::PAL 
 				&& self.cycle == 339 && self.in_odd_frame
 				&& self.rendering_enabled()
@@ -246,16 +391,28 @@ impl Ppu {
 		if self.mask_write_delay > 0 {
 			self.mask_write_delay -= 1;
 			if self.mask_write_delay == 0 {
+				let was_rendering = self.is_rendering();
 				self.mask = Mask::from_bits_retain(self.mask_tmp);
+				// rendering turned off mid frame gives the bus back to v, whose fine y can hold A12 high
+				if was_rendering { self.drive_bus_from_v(); }
 			}
 		}
 
+		self.dots += 1;
 		self.cycle += 1;
 		if self.cycle > 340 {
 			self.cycle = 0;
 			self.scanline += 1;
+
+			// the ppu waits right before setting vblank, and right before the pre-render scanline
+			if self.scanline == 241 {
+				self.overclock_dots = self.overclock.0 as u32 * 341;
+			} else if self.scanline == self.last_scanline {
+				self.overclock_dots = self.overclock.1 as u32 * 341;
+			}
 			if self.scanline > self.last_scanline {
 				self.scanline = 0;
+				self.frame += 1;
 				self.in_odd_frame = !self.in_odd_frame;
 				
 				self.nmi_suppress = false;
@@ -264,6 +421,143 @@ impl Ppu {
 		}
 	}
 
+	pub fn set_layer_visible(&mut self, layer: Layer, visible: bool) {
+		match layer {
+			Layer::Bg => self.view.hide_bg = !visible,
+			Layer::Spr => self.view.hide_spr = !visible,
+		}
+	}
+
+	pub fn set_frame_blend(&mut self, mode: FrameBlend) {
+		self.view.frame_blend = mode;
+		// blending starts over from the next frame, instead of a stale one
+		self.previous_frame = None;
+	}
+
+	fn blend_frame(&mut self) {
+		let previous = self.previous_frame.get_or_insert_with(|| {
+			let mut previous = FrameBuffer::new(self.screen.width, self.screen.height);
+			previous.buffer.copy_from_slice(&self.screen.buffer);
+			previous
+		});
+		self.screen.blend(previous, self.view.frame_blend);
+	}
+
+	// The scrolling the given scanline was rendered with, in the last frame
+	pub fn scanline_scroll(&self, scanline: usize) -> Option<ScanlineScroll> {
+		self.scroll_log.get(scanline).copied()
+	}
+
+	// The cpu is running extra scanlines, which the rest of the console doesn't see
+	pub fn is_overclocking(&self) -> bool {
+		self.overclock_dots > 0
+	}
+
+	pub fn palette_ram(&self) -> &[u8] {
+		&self.palettes
+	}
+
+	pub fn oam_ram(&self) -> &[u8] {
+		&self.oam
+	}
+
+	pub fn scroll_log(&self) -> &[ScanlineScroll] {
+		&self.scroll_log
+	}
+
+	// The current vram address (v), which $2007 accesses and rendering both move
+	pub fn vram_addr(&self) -> u16 {
+		self.v.0
+	}
+
+	// The sprites in range of a visible scanline, as seen by its last evaluation
+	pub fn scanline_sprites(&self, scanline: usize) -> Option<&ScanlineSprites> {
+		self.sprite_eval.get(scanline)
+	}
+
+	// A copy of the screen, with the sprites dropped by the 8 sprites limit tinted in red.
+	// Dropped sprites are drawn anyway if the limit is lifted, so the overlay shows what the real console would hide.
+	pub fn sprite_limit_overlay(&self) -> FrameBuffer {
+		let mut overlay = FrameBuffer::new(self.screen.width, self.screen.height);
+		overlay.buffer.copy_from_slice(&self.screen.buffer);
+
+		for (y, line) in self.sprite_eval.iter().enumerate() {
+			for sprite in line.dropped() {
+				let start = sprite.x as usize;
+				for x in start..(start + 8).min(overlay.width) {
+					overlay.tint_pixel(x, y, (255, 0, 0));
+				}
+			}
+		}
+
+		overlay
+	}
+
+	fn dots_count(&self) -> u64 {
+		(self.frame * (self.last_scanline as u64 + 1) + self.scanline as u64) * 341 + self.cycle as u64
+	}
+
+	// Runs a mapper hook, telling it where the ppu is
+	fn notify_mapper(&mut self, rendering: bool, notify: impl FnOnce(&mut dyn Mapper, &mut PpuCtx)) {
+		let (scanline, cycle, dot) = (self.scanline, self.cycle, self.dots_count());
+		let cart = self.cart.as_mut();
+		let mut ctx = PpuCtx { banks: &mut cart.banks, scanline, cycle, dot, rendering };
+		notify(cart.mapper.as_mut(), &mut ctx);
+	}
+
+	// Puts an address on the ppu bus, for the mappers watching it
+	fn drive_bus(&mut self, addr: u16, rendering: bool) {
+		self.notify_mapper(rendering, |mapper, ctx| mapper.notify_ppu_addr(ctx, addr));
+	}
+
+	// Outside of rendering, the bus holds v, so that $2006 and $2007 accesses are seen by the mapper too
+	fn drive_bus_from_v(&mut self) {
+		if !self.is_rendering() {
+			self.drive_bus(self.v.0, false);
+		}
+	}
+
+	fn is_rendering(&self) -> bool {
+		let rendering_line = (0..=239).contains(&self.scanline) || self.scanline == self.last_scanline;
+		rendering_line && self.rendering_enabled()
+	}
+
+	// https://www.nesdev.org/wiki/PPU_OAM#Dynamic_RAM_decay
+	// Oam is dynamic ram, which loses its content when it isn't refreshed (read) for a while.
+	pub(self) fn refresh_oam_row(&mut self, row: usize) {
+		let now = self.dots_count();
+		// the pal ppu refreshes oam during its long vblank, so it never decays
+		let decays = self.cart.as_ref().header.timing != ConsoleTiming::PAL;
+		// a reset moves the ppu back to the top of the picture, which can be before the last refresh
+		if decays && now.saturating_sub(self.oam_rows_refresh[row]) > OAM_DECAY_DOTS {
+			self.oam[row*8..row*8 + 8].fill(OAM_DECAY_VALUE);
+			self.changes.oam += 1;
+		}
+		self.oam_rows_refresh[row] = now;
+	}
+
+	// https://www.nesdev.org/wiki/PPU_registers#OAMADDR
+	// If oamaddr isn't less than 8 when rendering starts, the 8 bytes at oamaddr & 0xF8 are copied to the first 8 bytes of oam.
+	fn corrupt_oam(&mut self) {
+		if self.oam_addr >= 8 {
+			let src = (self.oam_addr & 0xF8) as usize;
+			self.oam.copy_within(src..src + 8, 0);
+		}
+	}
+
+	// Color emphasis from ppumask, as red, green and blue bits
+	// https://www.nesdev.org/wiki/PPU_registers#Color_control
+	pub(self) fn emphasis(&self) -> u8 {
+		let (red, green) = match self.cart.as_ref().header.timing {
+			ConsoleTiming::PAL | ConsoleTiming::Dendy => (Mask::emph_green, Mask::emph_red),
+			_ => (Mask::emph_red, Mask::emph_green),
+		};
+
+		self.mask.contains(red) as u8
+		| (self.mask.contains(green) as u8) << 1
+		| (self.mask.contains(Mask::emph_blue) as u8) << 2
+	}
+
 	pub(self) fn rendering_enabled(&self) -> bool {
 		self.mask.contains(Mask::bg_enabled)
 		|| self.mask.contains(Mask::spr_enabled)
@@ -272,9 +566,10 @@ impl Ppu {
 	fn map_address(&self, addr: u16) -> (VramDst, usize) {
 		match addr {
 			0x0000..=0x1FFF => (VramDst::Patterntbl, addr as usize),
-			0x2000..=0x2FFF => {
+			0x2000..=0x3EFF => {
 				// let mirrored = self.mirror_nametbl(addr);
-				(VramDst::Nametbl, addr as usize)
+				// 0x3000..0x3EFF mirrors the nametables
+				(VramDst::Nametbl, (addr & 0x2FFF) as usize)
 			}
 			0x3F00..=0x3FFF => {
 				let palette = self.mirror_palette(addr);
@@ -296,38 +591,54 @@ impl Ppu {
 
 	fn increase_vram_address(&mut self) {
 		// https://www.nesdev.org/wiki/PPU_scrolling#$2007_(PPUDATA)_reads_and_writes
-		if (0..=239).contains(&self.scanline) || self.scanline == self.last_scanline {
+		// While rendering, the access glitches into a coarse x and a y increment at the same time,
+		// instead of the usual 1 or 32 increment (Young Indiana Jones Chronicles relies on it).
+		if self.is_rendering() {
 			self.increase_coarse_x();
 			self.increase_coarse_y();
+		} else {
+			self.v.0 = self.v.0.wrapping_add(self.ctrl.vram_addr_incr());
 		}
-		
-		self.v.0 = self.v.0.wrapping_add(self.ctrl.vram_addr_incr());
 	}
 
 	pub fn read_vram(&mut self) -> u8 {
-		// palettes shouldn't be buffered
-		let res = if self.v.0 >= PALETTES {
-			self.peek_vram(self.v.0)
+		self.drive_bus_from_v();
+		let addr = self.v.0 & 0x3FFF;
+		// palettes shouldn't be buffered, but the buffer still gets filled, with the nametable "underneath" them
+		let res = if addr >= PALETTES {
+			self.data_buf = self.peek_vram(addr - 0x1000);
+			self.peek_vram(addr)
 		} else {
-			self.data_buf
+			let res = self.data_buf;
+			self.data_buf = self.peek_vram(addr);
+			res
 		};
 
-		self.data_buf = self.peek_vram(self.v.0);
 		self.increase_vram_address();
+		// the incremented address is left on the bus, so crossing $1000 raises A12 too
+		self.drive_bus_from_v();
 		
 		res
 	}
 
 	pub fn write_vram(&mut self, val: u8) {
+		self.drive_bus_from_v();
 		let (dst, addr) = self.map_address(self.v.0);
 		match dst {
-			VramDst::Patterntbl | VramDst::Nametbl => self.cart.as_mut()
-				.vram_write(addr, val),
-			VramDst::Palettes => self.palettes[addr] = val & 0b0011_1111,
+			VramDst::Patterntbl => self.cart.as_mut().vram_write(addr, val),
+			VramDst::Nametbl => {
+				self.cart.as_mut().vram_write(addr, val);
+				self.changes.nametables += 1;
+			}
+			VramDst::Palettes => {
+				self.palettes[addr] = val & 0b0011_1111;
+				self.changes.palettes += 1;
+			}
 			VramDst::Unused => {}
 		}
 
 		self.increase_vram_address();
+		self.drive_bus_from_v();
 	}
 
 	pub fn read_reg(&mut self, addr: u16) -> u8 {
@@ -351,19 +662,30 @@ impl Ppu {
 				self.stat.remove(Stat::vblank);
 				old_stat
 			}
-			0x2004 => self.oam[self.oam_addr as usize],
+			0x2004 => {
+				if let Some(val) = self.sprite_eval_bus() { return val; }
+				if self.accurate_oam { self.refresh_oam_row(self.oam_addr as usize / 8); }
+				self.oam[self.oam_addr as usize]
+			}
 			0x2007 => self.read_vram(),
 			_ => 0,
 		}
 	}
 
 	pub fn write_reg(&mut self, addr: u16, val: u8) {
+		if self.reset_latch && matches!(addr, 0x2000 | 0x2001 | 0x2005 | 0x2006) {
+			nen_log!(trace, "nen::ppu", "write to ${addr:04X} ignored after reset");
+			return;
+		}
+		self.notify_mapper(false, |mapper, ctx| mapper.notify_ppu_reg(ctx, addr, val));
+
 		match addr {
 			0x2000 => {
 				// TODO: bit 0 race condition
 
 				let was_nmi_off = !self.ctrl.contains(Ctrl::nmi_enabled);
 				self.ctrl = Ctrl::from_bits_retain(val);
+				nen_log!(trace, "nen::ppu", "ctrl = {val:08b} at {}:{}", self.scanline, self.cycle);
 
 				self.t.set_nametbl_x(val & 0b01);
 				self.t.set_nametbl_y((val & 0b10) >> 1);
@@ -374,18 +696,18 @@ impl Ppu {
 				{
 					self.nmi_tmp = Some(());
 				}
-
-				self.cart.as_mut().mapper.notify_ppuctrl(self.ctrl.bits());
 			}
 			0x2001 => {
+				nen_log!(trace, "nen::ppu", "mask = {val:08b} at {}:{}", self.scanline, self.cycle);
 				self.mask_tmp = val;
 				self.mask_write_delay = 3;
-				self.cart.as_mut().mapper.notify_ppumask(val);
 			}
 			0x2003 => self.oam_addr = val,
 			0x2004 => {
+				if self.accurate_oam { self.refresh_oam_row(self.oam_addr as usize / 8); }
 				self.oam[self.oam_addr as usize] = val;
 				self.oam_addr = self.oam_addr.wrapping_add(1);
+				self.changes.oam += 1;
 			}
 			0x2005 => {
 				match self.w {
@@ -416,6 +738,7 @@ impl Ppu {
 						// val is set to high byte of t
 						self.t.0 = (self.t.0 & 0xFF00) | (val as u16);
 						self.v.0 = self.t.0;
+						self.drive_bus_from_v();
 
 						self.w = WriteLatch::FirstWrite;
 					}
@@ -0,0 +1,70 @@
+use bitflags::bitflags;
+
+use crate::frame::FrameBlend;
+
+use super::Ppu;
+
+bitflags! {
+	// Debug overlays drawn over the picture at the end of every frame
+	#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+	pub struct Overlays: u8 {
+		// 8x8 background tiles
+		const tile_grid    = 0b001;
+		// 16x16 attribute areas, which share a palette
+		const attr_grid    = 0b010;
+		const sprite_boxes = 0b100;
+	}
+}
+
+// How the frontend wants the picture, none of it is seen by the game
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ViewOptions {
+	pub hide_bg: bool,
+	pub hide_spr: bool,
+	pub overlays: Overlays,
+	pub frame_blend: FrameBlend,
+}
+
+const TILE_GRID_COLOR: (u8, u8, u8) = (255, 255, 255);
+const ATTR_GRID_COLOR: (u8, u8, u8) = (0, 128, 255);
+const SPRITE_BOX_COLOR: (u8, u8, u8) = (0, 255, 0);
+
+impl Ppu {
+	pub(super) fn draw_overlays(&mut self) {
+		let overlays = self.view.overlays;
+		let width = self.screen.width;
+
+		for (y, scroll) in self.scroll_log.iter().enumerate() {
+			let (origin_x, origin_y) = scroll.plane_position();
+			for x in 0..width {
+				let (bg_x, bg_y) = (origin_x + x, origin_y);
+
+				if overlays.contains(Overlays::attr_grid) && (bg_x % 16 == 0 || bg_y % 16 == 0) {
+					self.screen.tint_pixel(x, y, ATTR_GRID_COLOR);
+				} else if overlays.contains(Overlays::tile_grid) && (bg_x % 8 == 0 || bg_y % 8 == 0) {
+					self.screen.tint_pixel(x, y, TILE_GRID_COLOR);
+				}
+			}
+		}
+
+		if overlays.contains(Overlays::sprite_boxes) {
+			let height = self.ctrl.spr_height();
+
+			for (y, line) in self.sprite_eval.iter().enumerate() {
+				for sprite in line.drawn() {
+					let left = sprite.x as usize;
+					let right = (left + 7).min(width - 1);
+					// sprites are drawn one line below their y
+					let top = sprite.y as usize + 1;
+
+					if y == top || y == top + height - 1 {
+						for x in left..=right { self.screen.tint_pixel(x, y, SPRITE_BOX_COLOR); }
+					} else {
+						self.screen.tint_pixel(left, y, SPRITE_BOX_COLOR);
+						self.screen.tint_pixel(right, y, SPRITE_BOX_COLOR);
+					}
+				}
+			}
+		}
+	}
+}
@@ -1,6 +1,8 @@
 use std::collections::VecDeque;
 
-use super::{Mask, Ppu, PpuState, Stat, ATTRIBUTES, NAMETABLES, PALETTES};
+use crate::codec::{codec_enum, codec_fields};
+
+use super::{EvaluatedSprite, Mask, Ppu, RenderAccuracy, ScanlineScroll, ScanlineSprites, Stat, ATTRIBUTES, HW_SPRITE_LIMIT, NAMETABLES, PALETTES};
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub(super) struct Fetcher {
@@ -9,7 +11,13 @@ pub(super) struct Fetcher {
   bg_fifo: VecDeque<(u8, u8)>,
   oam_tmp: Vec<OamEntry>,
   spr_scanline: Box<[Option<SprData>]>,
+  // pattern address of each of the 8 sprite slots of the scanline, for the address bus
+  #[serde(default)]
+  spr_addrs: [u16; 8],
+  #[serde(default)]
+  spr_eval: SpriteEval,
 }
+codec_fields!(Fetcher { state, data, bg_fifo, oam_tmp, spr_scanline, spr_addrs, spr_eval });
 
 impl Fetcher {
   pub fn new() -> Self {
@@ -20,14 +28,36 @@ impl Fetcher {
       bg_fifo: VecDeque::from([(0,0)].repeat(9)),
       oam_tmp: Vec::new(),
       spr_scanline: vec![None; 256].into(),
+      spr_addrs: [0; 8],
+      spr_eval: SpriteEval::default(),
     }
   }
 }
 
+// State of the dot by dot sprite evaluation, in RenderAccuracy::Dot
+// https://www.nesdev.org/wiki/PPU_sprite_evaluation
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct SpriteEval {
+  secondary_oam: [u8; 32],
+  // oam entry and byte being read
+  n: usize,
+  m: usize,
+  // sprites copied to secondary oam
+  found: usize,
+  done: bool,
+  // the hardware only knows whether sprite 0 went in the first slot
+  spr0_found: bool,
+  // last byte read or written, seen by $2004 reads
+  bus: u8,
+  spr_plane0: u8,
+}
+codec_fields!(SpriteEval { secondary_oam, n, m, found, done, spr0_found, bus, spr_plane0 });
+
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 enum FetcherState {
   #[default] Nametbl, Attribute, PtrnLow, PtrnHigh
 }
+codec_enum!(FetcherState { Nametbl, Attribute, PtrnLow, PtrnHigh });
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub (super) struct FetcherData {
@@ -37,6 +67,7 @@ pub (super) struct FetcherData {
 	pub tile_plane0: u8,
 	pub tile_plane1: u8,
 }
+codec_fields!(FetcherData { tile_id, palette_id, tile_addr, tile_plane0, tile_plane1 });
 
 #[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SprData {
@@ -45,6 +76,7 @@ pub struct SprData {
 	pub priority: SpritePriority,
 	pub is_sprite0: bool,
 }
+codec_fields!(SprData { pixel, palette_id, priority, is_sprite0 });
 
 #[derive(Debug, PartialEq, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum SpritePriority {
@@ -52,6 +84,7 @@ pub enum SpritePriority {
     #[default]
     Behind,
 }
+codec_enum!(SpritePriority { Front, Behind });
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub(super) struct OamEntry {
@@ -64,6 +97,7 @@ pub(super) struct OamEntry {
     pub flip_vertical: bool,
     pub x: usize,
 }
+codec_fields!(OamEntry { index, y, tile_id, palette_id, priority, flip_horizontal, flip_vertical, x });
 impl Default for OamEntry {
     fn default() -> Self {
         Self { index: Default::default(), y: Default::default(), tile_id: 0xFF, palette_id: Default::default(), priority: Default::default(), flip_horizontal: Default::default(), flip_vertical: Default::default(), x: Default::default() }
@@ -105,30 +139,61 @@ fn pixel_from_planes(bit: u8, plane0: u8, plane1: u8) -> u8 {
 impl Ppu {
   pub(super) fn render_step(&mut self) {
     if (1..=256).contains(&self.cycle) || (321..=336).contains(&self.cycle) {
-      if self.cycle == 1 || self.cycle == 321 {
-  			self.cart.as_mut().mapper.notify_ppu_state(PpuState::FetchBg);
-      }
       self.fetch_bg_step();
+      if self.render_accuracy == RenderAccuracy::Dot && self.cycle <= 256 {
+        self.sprite_eval_step();
+      }
 
-    } else if self.cycle == 257 {
-      self.increase_coarse_y();
-      self.reset_render_x();
+    } else if (257..=320).contains(&self.cycle) {
+      if self.cycle == 257 {
+        self.increase_coarse_y();
+        self.reset_render_x();
+        // v now points to where the next scanline starts
+        if self.scanline < 239 {
+          self.log_scroll(self.scanline + 1);
+        }
+      }
+
+      // In Scanline accuracy sprites are fetched all at once, but their addresses still go on the bus at the right dots.
+      // The first one is a nametable fetch, so the mapper sees the sprite fetches begin before they are read.
+      if self.cycle % 2 == 1 && self.rendering_enabled() {
+        let slot = (self.cycle - 257) / 8;
+        let dot = self.render_accuracy == RenderAccuracy::Dot;
+        match (self.cycle - 257) % 8 {
+          // garbage nametable fetches
+          0 | 2 => self.drive_bus(NAMETABLES + self.v.nametbl_idx(), true),
+          4 if dot => self.fetch_sprite_plane(slot, false),
+          6 if dot => self.fetch_sprite_plane(slot, true),
+          4 => self.drive_bus(self.renderer.spr_addrs[slot], true),
+          _ => self.drive_bus(self.renderer.spr_addrs[slot] + 8, true),
+        }
+      }
 
-      // we just render all sprites in one go
-			self.cart.as_mut().mapper.notify_ppu_state(PpuState::FetchSpr);
-      self.evaluate_sprites();
-      self.fetch_sprites();
+      if self.cycle == 257 {
+        self.evaluate_sprites();
+        match self.render_accuracy {
+          // we just render all sprites in one go
+          RenderAccuracy::Scanline => self.fetch_sprites(),
+          RenderAccuracy::Dot => {
+            self.load_secondary_oam();
+            self.clear_sprite_fetches();
+          }
+        }
+      } else if self.cycle == 320 && self.render_accuracy == RenderAccuracy::Dot {
+        self.fetch_extra_sprites();
+      }
+    } else if matches!(self.cycle, 337 | 339) && self.rendering_enabled() {
+      // the unused nametable fetches, of the same tile as the first fetch of the next scanline
+      self.drive_bus(NAMETABLES + self.v.nametbl_idx(), true);
     }
+  }
 
-    if self.cycle == 3
-      && self.rendering_enabled()
-    {
-      self.cart.as_mut().mapper.notify_mmc5_scanline();
-    } else if self.cycle == 260
-      && self.rendering_enabled()
-    {
-      self.cart.as_mut().mapper.notify_mmc3_scanline();
+  // A read of the background fetcher, which also drives the address bus
+  fn fetch_vram(&mut self, addr: u16) -> u8 {
+    if self.rendering_enabled() {
+      self.drive_bus(addr, true);
     }
+    self.peek_vram(addr)
   }
 
   fn render_pixel(&mut self) {
@@ -139,7 +204,7 @@ impl Ppu {
       || !self.mask.contains(Mask::bg_strip_show) && x < 8
     {
       let color = self.color_from_palette(0, 0);
-      self.screen.set_pixel(x, y, color);
+      self.screen.set_pixel_emphasized(x, y, color, self.emphasis());
       return;
     }
 
@@ -148,10 +213,13 @@ impl Ppu {
     let sprite = self.renderer.spr_scanline[x]
       .take().unwrap_or_default();
 
+    // hidden layers are only left out of the picture, the game sees no difference (e.g. sprite 0 hits still happen)
+    let shown_bg_pixel = if self.view.hide_bg { 0 } else { bg_pixel };
     let mut sprite_in_left_strip = false;
     let pixel_color = if self.mask.contains(Mask::spr_enabled) 
-      && (sprite.priority == SpritePriority::Front || bg_pixel == 0)
+      && (sprite.priority == SpritePriority::Front || shown_bg_pixel == 0)
       && sprite.pixel != 0
+      && !self.view.hide_spr
     {
       if !self.mask.contains(Mask::spr_strip_show) && x < 8 {
         sprite_in_left_strip = true;
@@ -159,7 +227,7 @@ impl Ppu {
       } else {
         self.color_from_palette(sprite.pixel, sprite.palette_id)
       }
-    } else if self.mask.contains(Mask::bg_enabled) {
+    } else if self.mask.contains(Mask::bg_enabled) && !self.view.hide_bg {
       self.color_from_palette(bg_pixel, bg_palette_id)
     } else {
       self.color_from_palette(0, 0)
@@ -176,7 +244,7 @@ impl Ppu {
       self.stat.insert(Stat::spr0_hit);
     }
 
-    self.screen.set_pixel(x, y, pixel_color);
+    self.screen.set_pixel_emphasized(x, y, pixel_color, self.emphasis());
   }
 
 
@@ -201,7 +269,7 @@ impl Ppu {
           } 
 
           let tile_addr = NAMETABLES + self.v.nametbl_idx();
-          self.renderer.data.tile_id = self.peek_vram(tile_addr);
+          self.renderer.data.tile_id = self.fetch_vram(tile_addr);
           self.renderer.state = FetcherState::Attribute;
         }
 
@@ -211,7 +279,7 @@ impl Ppu {
             + ((self.v.coarse_y() as u16) / 4) * 8
             + ((self.v.coarse_x() as u16) / 4);
 
-          let attribute = self.peek_vram(attribute_addr);
+          let attribute = self.fetch_vram(attribute_addr);
           let palette_id = self.palette_from_attribute(attribute);
 
           self.renderer.data.palette_id = palette_id;
@@ -223,7 +291,7 @@ impl Ppu {
             + (self.renderer.data.tile_id as u16) * 16
             + self.v.fine_y() as u16;
 
-          let plane0 = self.peek_vram(tile_addr);
+          let plane0 = self.fetch_vram(tile_addr);
           self.renderer.data.tile_addr = tile_addr;
           self.renderer.data.tile_plane0 = plane0;
           self.renderer.state = FetcherState::PtrnHigh;
@@ -231,7 +299,7 @@ impl Ppu {
 
         FetcherState::PtrnHigh => {
           let plane1 = self
-            .peek_vram(self.renderer.data.tile_addr + 8);
+            .fetch_vram(self.renderer.data.tile_addr + 8);
           self.renderer.data.tile_plane1 = plane1;
           self.renderer.state = FetcherState::Nametbl;
 
@@ -272,6 +340,19 @@ impl Ppu {
 		if !self.rendering_enabled() { return; }
     self.renderer.oam_tmp.clear();
 
+		// sprite evaluation reads the whole oam, refreshing it
+		if self.accurate_oam {
+			for row in 0..32 { self.refresh_oam_row(row); }
+		}
+
+		// the sprites found now are drawn on the next scanline
+		let next_scanline = self.scanline + 1;
+		if self.sprite_eval.len() < 240 {
+			self.sprite_eval.resize(240, ScanlineSprites::default());
+		}
+		let mut eval = self.sprite_eval.get_mut(next_scanline).map(core::mem::take);
+		if let Some(eval) = &mut eval { eval.sprites.clear(); }
+
 		let mut visible_sprites = 0;
 		for i in (0..256).step_by(4) {
 			let spr_y = self.oam[i] as isize;
@@ -283,73 +364,220 @@ impl Ppu {
 					self.renderer.oam_tmp
 						.push(OamEntry::from_bytes(&self.oam[i..i + 4], i));
 				}
+				if let Some(eval) = &mut eval {
+					eval.sprites.push(EvaluatedSprite { index: (i / 4) as u8, x: self.oam[i + 3], y: self.oam[i] });
+				}
 				visible_sprites += 1;
 			}
 		}
 
-		let spr_overflow = self.stat.contains(Stat::spr_overflow)
-			|| (self.rendering_enabled() && visible_sprites > 8);
-		self.stat.set(Stat::spr_overflow, spr_overflow);
+		if let Some(eval) = eval {
+			self.sprite_eval[next_scanline] = eval;
+		}
+
+		// in Dot accuracy the evaluation already set it
+		if self.render_accuracy == RenderAccuracy::Scanline {
+			let spr_overflow = self.stat.contains(Stat::spr_overflow)
+				|| (self.rendering_enabled() && visible_sprites > HW_SPRITE_LIMIT);
+			self.stat.set(Stat::spr_overflow, spr_overflow);
+		}
+	}
+
+	// One dot of the sprite evaluation of the next scanline, in Dot accuracy
+	fn sprite_eval_step(&mut self) {
+		if !self.rendering_enabled() { return; }
+		if self.cycle == 1 {
+			self.renderer.spr_eval = SpriteEval::default();
+		}
+		// there is no evaluation on the pre-render scanline
+		if self.scanline == self.last_scanline { return; }
+
+		let (scanline, height) = (self.scanline, self.ctrl.spr_height());
+		let in_range = |y: u8| scanline.wrapping_sub(y as usize) < height;
+		let eval = &mut self.renderer.spr_eval;
+
+		match self.cycle {
+			// secondary oam is cleared a byte every 2 dots, and reads see $ff
+			1..=64 => {
+				eval.bus = 0xFF;
+				eval.secondary_oam[(self.cycle - 1) / 2] = 0xFF;
+			}
+			// odd dots read from oam, even dots write to secondary oam
+			_ if self.cycle % 2 == 1 => eval.bus = self.oam[(eval.n * 4 + eval.m) & 0xFF],
+			_ if eval.done => {}
+			_ if eval.found < HW_SPRITE_LIMIT => {
+				// the y is always copied, the other bytes only when it is in range
+				eval.secondary_oam[eval.found * 4 + eval.m] = eval.bus;
+				if eval.m > 0 || in_range(eval.bus) {
+					if eval.n == 0 { eval.spr0_found = true; }
+					eval.m += 1;
+					if eval.m == 4 {
+						eval.m = 0;
+						eval.found += 1;
+						eval.n += 1;
+					}
+				} else {
+					eval.n += 1;
+				}
+				eval.done = eval.n == 64;
+			}
+			_ => {
+				// with secondary oam full m is wrongly increased along with n, so other bytes are taken as y
+				if in_range(eval.bus) {
+					self.stat.insert(Stat::spr_overflow);
+					eval.done = true;
+				} else {
+					eval.n += 1;
+					eval.m = (eval.m + 1) % 4;
+					eval.done = eval.n == 64;
+				}
+			}
+		}
+	}
+
+	// What $2004 reads while sprites are evaluated and fetched, in Dot accuracy
+	pub(super) fn sprite_eval_bus(&self) -> Option<u8> {
+		if self.render_accuracy != RenderAccuracy::Dot
+			|| !self.is_rendering()
+			|| self.scanline == self.last_scanline
+		{ return None; }
+
+		let eval = &self.renderer.spr_eval;
+		match self.cycle {
+			1..=256 => Some(eval.bus),
+			257..=320 => {
+				let (slot, byte) = ((self.cycle - 257) / 8, (self.cycle - 257) % 8);
+				Some(eval.secondary_oam[slot * 4 + byte.min(3)])
+			}
+			_ => Some(eval.secondary_oam[0]),
+		}
+	}
+
+	// The first sprites to fetch become the ones in secondary oam.
+	// Those past the 8th, when the sprite limit is lifted, are still taken from the whole oam scan.
+	fn load_secondary_oam(&mut self) {
+		let renderer = &mut self.renderer;
+		let extra = renderer.oam_tmp.split_off(renderer.oam_tmp.len().min(HW_SPRITE_LIMIT));
+		renderer.oam_tmp.clear();
+
+		let eval = &renderer.spr_eval;
+		for slot in 0..eval.found {
+			let index = if slot == 0 && eval.spr0_found { 0 } else { usize::MAX };
+			renderer.oam_tmp.push(OamEntry::from_bytes(&eval.secondary_oam[slot * 4..slot * 4 + 4], index));
+		}
+		renderer.oam_tmp.extend(extra);
+	}
+
+	// One of the two pattern fetches of a sprite slot, read at its dot in Dot accuracy
+	fn fetch_sprite_plane(&mut self, slot: usize, high: bool) {
+		let has_sprite = slot < self.renderer.oam_tmp.len().min(HW_SPRITE_LIMIT);
+
+		if !high {
+			if has_sprite {
+				self.renderer.spr_addrs[slot] = self.sprite_pattern_addr(&self.renderer.oam_tmp[slot]);
+			}
+			self.renderer.spr_eval.spr_plane0 = self.fetch_vram(self.renderer.spr_addrs[slot]);
+		} else {
+			let plane1 = self.fetch_vram(self.renderer.spr_addrs[slot] + 8);
+			if has_sprite {
+				self.draw_sprite(slot, self.renderer.spr_eval.spr_plane0, plane1);
+			}
+		}
+	}
+
+	// Sprites past the 8th, with the sprite limit lifted. They are read without going on the bus.
+	fn fetch_extra_sprites(&mut self) {
+		if !self.rendering_enabled() { return; }
+		for slot in HW_SPRITE_LIMIT..self.renderer.oam_tmp.len() {
+			let addr = self.sprite_pattern_addr(&self.renderer.oam_tmp[slot]);
+			let (plane0, plane1) = (self.peek_vram(addr), self.peek_vram(addr + 8));
+			self.draw_sprite(slot, plane0, plane1);
+		}
 	}
 
   pub fn fetch_sprites(&mut self) {
+		if !self.clear_sprite_fetches() { return; }
+
+		for slot in 0..self.renderer.oam_tmp.len() {
+			let spr_addr = self.sprite_pattern_addr(&self.renderer.oam_tmp[slot]);
+			if let Some(addr) = self.renderer.spr_addrs.get_mut(slot) {
+				*addr = spr_addr;
+			}
+
+			let plane0 = self.peek_vram(spr_addr);
+			let plane1 = self.peek_vram(spr_addr + 8);
+			self.draw_sprite(slot, plane0, plane1);
+		}
+	}
+
+	// Clears the sprites of the next scanline, returns false if rendering is off
+	fn clear_sprite_fetches(&mut self) -> bool {
     self.renderer.spr_scanline.fill(None);
-		if !self.rendering_enabled() { return; }
+		if !self.rendering_enabled() { return false; }
+
+		// empty slots fetch tile $FF, which is in the second pattern table for 8x16 sprites
+		self.renderer.spr_addrs.fill(match self.ctrl.spr_height() {
+			8 => self.ctrl.spr_ptrntbl_addr() + 0xFF * 16,
+			_ => 0x1000 + 0xFE * 16,
+		});
+		true
+	}
 
-		for sprite in self.renderer.oam_tmp.iter() {
-			let vertical_start: usize = if sprite.flip_vertical { 7 } else { 0 };
-			let dist_from_scanline = self.scanline - sprite.y;
+	fn sprite_pattern_addr(&self, sprite: &OamEntry) -> u16 {
+		let vertical_start: usize = if sprite.flip_vertical { 7 } else { 0 };
+		let dist_from_scanline = self.scanline - sprite.y;
 
-			let spr_addr = match self.ctrl.spr_height() {
-				8 => {
-					self.ctrl.spr_ptrntbl_addr()
-						+ sprite.tile_id as u16 * 16
-						+ (dist_from_scanline).abs_diff(vertical_start) as u16
-				}
-				16 => {
-					let tbl = (sprite.tile_id & 1) as u16;
-					let mut tile_id = sprite.tile_id as u16 & 0b1111_1110;
-					tile_id += match sprite.flip_vertical {
-						false => if dist_from_scanline >= 8 { 1 } else { 0 },
-						true  => if dist_from_scanline >= 8 { 0 } else { 1 },
-					};
-
-					(tbl << 12)
-						+ tile_id * 16
-						+ (dist_from_scanline % 8).abs_diff(vertical_start) as u16
-				}
-				_ => unreachable!("sprite heights are either 8 or 16"),
-			};
+		match self.ctrl.spr_height() {
+			8 => {
+				self.ctrl.spr_ptrntbl_addr()
+					+ sprite.tile_id as u16 * 16
+					+ (dist_from_scanline).abs_diff(vertical_start) as u16
+			}
+			16 => {
+				let tbl = (sprite.tile_id & 1) as u16;
+				let mut tile_id = sprite.tile_id as u16 & 0b1111_1110;
+				tile_id += match sprite.flip_vertical {
+					false => if dist_from_scanline >= 8 { 1 } else { 0 },
+					true  => if dist_from_scanline >= 8 { 0 } else { 1 },
+				};
+
+				(tbl << 12)
+					+ tile_id * 16
+					+ (dist_from_scanline % 8).abs_diff(vertical_start) as u16
+			}
+			_ => unreachable!("sprite heights are either 8 or 16"),
+		}
+	}
 
-			let mut plane0 = self.peek_vram(spr_addr);
-			let mut plane1 = self.peek_vram(spr_addr + 8);
+	// Puts a fetched sprite in the next scanline, behind the sprites of the slots before it
+	fn draw_sprite(&mut self, slot: usize, mut plane0: u8, mut plane1: u8) {
+		let sprite = &self.renderer.oam_tmp[slot];
+
+		// this works in reverse
+		if !sprite.flip_horizontal {
+			plane0 = plane0.reverse_bits();
+			plane1 = plane1.reverse_bits();
+		}
 
-			// this works in reverse
-			if !sprite.flip_horizontal {
-				plane0 = plane0.reverse_bits();
-				plane1 = plane1.reverse_bits();
+		for i in (0..8usize).rev() {
+			if sprite.x + i >= 32 * 8 {
+				continue;
 			}
 
-			for i in (0..8usize).rev() {
-				if sprite.x + i >= 32 * 8 {
+			// sprite with higher priority already there
+			if let Some(current_pixel) = &self.renderer.spr_scanline[sprite.x + i] {
+				if current_pixel.pixel != 0 {
 					continue;
 				}
-
-				// sprite with higher priority already there
-				if let Some(current_pixel) = &self.renderer.spr_scanline[sprite.x + i] {
-					if current_pixel.pixel != 0 {
-						continue;
-					}
-				}
-
-				let pixel = pixel_from_planes(i as u8, plane0, plane1);
-				self.renderer.spr_scanline[sprite.x + i] = Some(SprData {
-					pixel,
-					palette_id: sprite.palette_id,
-					priority: sprite.priority,
-					is_sprite0: sprite.index == 0,
-				});
 			}
+
+			let pixel = pixel_from_planes(i as u8, plane0, plane1);
+			self.renderer.spr_scanline[sprite.x + i] = Some(SprData {
+				pixel,
+				palette_id: sprite.palette_id,
+				priority: sprite.priority,
+				is_sprite0: sprite.index == 0,
+			});
 		}
 	}
 }
@@ -410,6 +638,18 @@ impl Ppu {
 	}
 
 	// https://forums.nesdev.org/viewtopic.php?p=5578#p5578
+	pub(super) fn log_scroll(&mut self, scanline: usize) {
+		if self.scroll_log.len() < 240 {
+			self.scroll_log.resize(240, ScanlineScroll::default());
+		}
+
+		self.scroll_log[scanline] = ScanlineScroll {
+			x: self.v.coarse_x() * 8 + self.x,
+			y: self.v.coarse_y() * 8 + self.v.fine_y(),
+			nametable: self.v.nametbl(),
+		};
+	}
+
 	fn reset_render_x(&mut self) {
 		if !self.rendering_enabled() { return; }
 
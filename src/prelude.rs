@@ -0,0 +1,15 @@
+// The types a frontend needs, with `use nen_emulator::prelude::*`.
+// They are the stable part of the library: changes to them are listed in the changelog of a release,
+// while the modules below the "Internals" line of lib.rs can change with any refactor.
+pub use crate::nes::{Nes, ScanlineHook, StepOutcome};
+pub use crate::config::{EmuConfig, Overscan, RamInit};
+pub use crate::cart::{CartHeader, ConsoleTiming, EmuError, HeaderDiagnostic, Mirroring, RegionSource};
+pub use crate::frame::{AspectMode, DisplayRect, FrameBlend, FrameBuffer, FrameInfo, RGBColor};
+pub use crate::joypad::{InputMacro, InputPoller, JoypadButton, OppositeDirections, TimedInput};
+pub use crate::events::{EmuEvent, InputLatency};
+pub use crate::movie::{Movie, MovieReport};
+pub use crate::save_bundle::{SaveBundle, SAVE_BUNDLE_EXTENSION};
+pub use crate::overrides::GameOverride;
+pub use crate::apu::{FilterProfile, ResampleQuality};
+pub use crate::ppu::{Layer, MemoryChanges, Overlays, RenderAccuracy};
+pub use crate::bank_map::{BankMap, BankTarget, PageMapping};
@@ -0,0 +1,55 @@
+use crate::codec::codec_fields;
+
+// Seeded random numbers, for the behaviours which are random on real hardware
+// (oam decay, uninitialized ram, ...).
+// There is a single generator, stored in the cart so every subsystem can reach it, and saved with the state,
+// so that runs with the same seed and inputs are reproducible, also across savestates.
+// splitmix64: https://prng.di.unimi.it/splitmix64.c
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Rng {
+  state: u64,
+}
+codec_fields!(Rng { state });
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    Self { state: seed }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+  }
+
+  pub fn next_u8(&mut self) -> u8 {
+    (self.next_u64() >> 56) as u8
+  }
+
+  pub fn fill(&mut self, buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+      let bytes = self.next_u64().to_le_bytes();
+      chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod rng_tests {
+  use super::*;
+
+  #[test]
+  fn same_seed_same_numbers() {
+    let mut a = Rng::new(1234);
+    let mut b = a.clone();
+    let mut buf_a = [0; 13];
+    let mut buf_b = [0; 13];
+    a.fill(&mut buf_a);
+    b.fill(&mut buf_b);
+    assert_eq!(buf_a, buf_b);
+    assert_eq!(a.next_u64(), b.next_u64());
+    assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+  }
+}
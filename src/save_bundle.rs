@@ -0,0 +1,105 @@
+use crate::cart::Cart;
+
+// A single file holding everything a game persists, so that frontends can sync one file per game.
+// It is stored as json, with the `.nensave` extension.
+pub const SAVE_BUNDLE_EXTENSION: &str = "nensave";
+const SAVE_BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SaveBundle {
+  pub version: u32,
+  // the game this bundle belongs to, see CartHeader::rom_hash
+  pub rom_hash: u64,
+  pub game_title: String,
+  // unix timestamps in seconds, given by the frontend (there's no clock on wasm)
+  pub created_at: u64,
+  pub modified_at: u64,
+
+  // battery backed prg ram, or the whole prg rom for self-flashing boards
+  pub sram: Option<Vec<u8>>,
+  // eeproms inside the mapper chip
+  pub mapper_nvram: Option<Vec<u8>>,
+  // offset of the cartridge clock from the host clock, in seconds. No emulated board has a clock yet
+  pub rtc_offset: Option<i64>,
+}
+
+impl SaveBundle {
+  // Collects the persistent data of the cart. Returns None if the game has nothing to persist.
+  pub fn from_cart(cart: &Cart, created_at: u64, modified_at: u64) -> Option<Self> {
+    let sram = cart.get_sram();
+    let mapper_nvram = cart.mapper.get_mapper_nvram();
+    if sram.is_none() && mapper_nvram.is_none() {
+      return None;
+    }
+
+    Some(Self {
+      version: SAVE_BUNDLE_VERSION,
+      rom_hash: cart.header.rom_hash,
+      game_title: cart.header.game_title.clone(),
+      created_at,
+      modified_at,
+      sram,
+      mapper_nvram,
+      rtc_offset: None,
+    })
+  }
+
+  // Restores the persistent data into the cart, if the bundle belongs to its game
+  pub fn apply_to_cart(self, cart: &mut Cart) -> Result<(), String> {
+    if self.rom_hash != cart.header.rom_hash {
+      return Err(format!("Save bundle belongs to another game ({})", self.game_title));
+    }
+
+    if let Some(sram) = self.sram {
+      cart.set_sram(sram);
+    }
+    if let Some(nvram) = self.mapper_nvram {
+      cart.mapper.set_mapper_nvram(nvram);
+    }
+    Ok(())
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    serde_json::to_vec(self).expect("save bundle should always be serializable")
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+    let bundle: Self = serde_json::from_slice(bytes)
+      .map_err(|e| format!("Save bundle is corrupted: {e}"))?;
+
+    if bundle.version > SAVE_BUNDLE_VERSION {
+      return Err(format!("Save bundle is from a newer version of the emulator ({})", bundle.version));
+    }
+    Ok(bundle)
+  }
+}
+
+#[cfg(test)]
+mod save_bundle_tests {
+  use super::*;
+
+  fn battery_rom(prg_fill: u8) -> Vec<u8> {
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0b10, 0];
+    rom.resize(16, 0);
+    rom.extend(vec![prg_fill; 16 * 1024]);
+    rom.extend(vec![0; 8 * 1024]);
+    rom
+  }
+
+  #[test]
+  fn bundle_roundtrip() {
+    let mut cart = Cart::new(&battery_rom(0)).unwrap();
+    cart.sram[0] = 0x42;
+
+    let bundle = SaveBundle::from_cart(&cart, 10, 20).unwrap();
+    let bytes = bundle.to_bytes();
+    assert_eq!(SaveBundle::from_bytes(&bytes), Ok(bundle.clone()));
+
+    let mut new_cart = Cart::new(&battery_rom(0)).unwrap();
+    assert!(bundle.clone().apply_to_cart(&mut new_cart).is_ok());
+    assert_eq!(new_cart.sram[0], 0x42);
+
+    let mut other_game = Cart::new(&battery_rom(1)).unwrap();
+    assert!(bundle.apply_to_cart(&mut other_game).is_err());
+  }
+}
@@ -0,0 +1,213 @@
+use serde_json::{json, Value};
+
+use crate::{cart::CartHeader, codec::{NenCodec, Reader}, mapper};
+
+// Savestates are stored as json, along with the version of their layout.
+// Every time a change to the emulator breaks the layout of the state, SAVESTATE_VERSION has to be bumped,
+// and a step from the previous version added to migrate_step(), so that older savestates can still be loaded.
+// Added fields don't need a migration, as long as they are marked #[serde(default)].
+// Mappers are the exception: they store the bytes of Mapper::save(), so any change to their fields needs one.
+pub const SAVESTATE_VERSION: u32 = 4;
+
+// Before versioning, the native frontend wrote savestates with the pot crate, in a binary format we can't decode
+const LEGACY_MAGIC: &[u8; 3] = b"Pot";
+
+// Savestates made before versioning, which can't be loaded anymore.
+// Frontends can tell them apart from corrupted ones, to keep them around instead of overwriting them.
+pub fn is_legacy(bytes: &[u8]) -> bool {
+  bytes.starts_with(LEGACY_MAGIC)
+}
+
+#[derive(serde::Serialize)]
+struct SavestateRef<'a, T> {
+  version: u32,
+  state: &'a T,
+}
+
+pub fn serialize<T: serde::Serialize>(state: &T) -> Vec<u8> {
+  let savestate = SavestateRef { version: SAVESTATE_VERSION, state };
+  serde_json::to_vec(&savestate).expect("state should always be serializable")
+}
+
+// Returns the state, migrated to the current layout
+pub fn deserialize<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+  if is_legacy(bytes) {
+    return Err("Savestate was made by an older version of the emulator, whose format can't be loaded anymore".to_string());
+  }
+  let mut savestate: Value = serde_json::from_slice(bytes)
+    .map_err(|e| format!("Savestate is corrupted: {e}"))?;
+
+  // savestates made before versioning don't have the version field
+  let version = savestate["version"].as_u64().unwrap_or(0) as u32;
+  let state = savestate.get_mut("state").map(Value::take)
+    .ok_or("Savestate is corrupted: state is missing")?;
+  let state = migrate_value(version, state)?;
+
+  serde_json::from_value(state)
+    .map_err(|e| format!("Savestate doesn't match the emulator state: {e}"))
+}
+
+// Migrates a json state from an older layout to the current one
+pub fn migrate(from_version: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+  let state = serde_json::from_slice(bytes)
+    .map_err(|e| format!("Savestate is corrupted: {e}"))?;
+  let state = migrate_value(from_version, state)?;
+  Ok(serde_json::to_vec(&state).expect("json value should always be serializable"))
+}
+
+fn migrate_value(from_version: u32, mut state: Value) -> Result<Value, String> {
+  if from_version > SAVESTATE_VERSION {
+    return Err(format!("Savestate is from a newer version of the emulator ({from_version}, current is {SAVESTATE_VERSION})"));
+  }
+
+  for version in from_version..SAVESTATE_VERSION {
+    state = migrate_step(version, state)?;
+  }
+  Ok(state)
+}
+
+// Migrates a state from `version` to `version + 1`
+fn migrate_step(version: u32, state: Value) -> Result<Value, String> {
+  match version {
+    // unversioned savestates were written with a different format by the frontends
+    0 => Err("Savestate is too old to be loaded".to_string()),
+    1 => mapper_fields_to_state(state),
+    2 => ppu_dots_from_position(state),
+    3 => bandai_eeprom_serial_state(state),
+    _ => Ok(state),
+  }
+}
+
+// Version 1 stored the mapper as its fields, version 2 as its id and the bytes it saves
+fn mapper_fields_to_state(mut state: Value) -> Result<Value, String> {
+  let cart = state.pointer_mut("/cpu/bus/cart")
+    .ok_or("Savestate is corrupted: cart is missing")?;
+  let id = cart.pointer("/header/mapper").and_then(Value::as_u64)
+    .ok_or("Savestate is corrupted: mapper id is missing")? as u16;
+  let fields = cart.get_mut("mapper").map(Value::take)
+    .ok_or("Savestate is corrupted: mapper is missing")?;
+
+  let mapper_state = mapper::mapper_state_from_json(id, fields)?;
+  cart["mapper"] = json!({ "id": id, "state": mapper_state });
+  Ok(state)
+}
+
+// Version 3 counts the dots run by the ppu, for the time of the frames.
+// Older states only have its position, the dots are rebuilt from it as if rendering was always on,
+// which is off by a dot for every odd frame spent with rendering off.
+fn ppu_dots_from_position(mut state: Value) -> Result<Value, String> {
+  let timing = state.pointer("/cpu/bus/cart/header/timing").and_then(Value::as_str).map(str::to_owned);
+  let ppu = state.pointer_mut("/cpu/bus/ppu")
+    .ok_or("Savestate is corrupted: ppu is missing")?;
+  let field = |name: &str| ppu.get(name).and_then(Value::as_u64)
+    .ok_or(format!("Savestate is corrupted: ppu {name} is missing"));
+  let (frame, scanline, last_scanline, cycle) = (field("frame")?, field("scanline")?, field("last_scanline")?, field("cycle")?);
+
+  // the ppu powers on at the start of the pre-render scanline of frame 0
+  let position = (frame * (last_scanline + 1) + scanline) * 341 + cycle;
+  let skipped = if timing.as_deref() == Some("PAL") { 0 } else { frame / 2 };
+  ppu["dots"] = json!(position.saturating_sub(last_scanline * 341 + skipped));
+  Ok(state)
+}
+
+// Version 4 saves the serial state of the Bandai FCG eeprom, at the end of the mapper
+fn bandai_eeprom_serial_state(mut state: Value) -> Result<Value, String> {
+  let mapper = state.pointer_mut("/cpu/bus/cart/mapper")
+    .ok_or("Savestate is corrupted: mapper is missing")?;
+  if mapper["id"] != 16 { return Ok(state); }
+
+  let mut bytes: Vec<u8> = serde_json::from_value(mapper["state"].take())
+    .map_err(|e| format!("Savestate is corrupted: {e}"))?;
+  mapper::BandaiFCG::add_serial_state(&mut bytes);
+  mapper["state"] = json!(bytes);
+  Ok(state)
+}
+
+// Fast savestates skip json, for quick slots and rewind, where a state is taken every few frames.
+// They hold the raw state in the codec layout, which has no migrations: only the build that made one can load it,
+// and only on the same game. The header is checked first, so that a mismatch is an error and not a garbled state.
+const FAST_MAGIC: &[u8; 4] = b"NENF";
+
+fn fast_header(header: &CartHeader, out: &mut Vec<u8>) {
+  out.extend_from_slice(FAST_MAGIC);
+  env!("CARGO_PKG_VERSION").as_bytes().to_vec().encode(out);
+  header.rom_hash.encode(out);
+  header.mapper.encode(out);
+  header.submapper.encode(out);
+}
+
+pub fn serialize_fast<T: NenCodec>(state: &T, header: &CartHeader) -> Vec<u8> {
+  let mut out = Vec::new();
+  fast_header(header, &mut out);
+  state.encode(&mut out);
+  out
+}
+
+// Decodes the state in place. On error, the state may be left partially loaded.
+pub fn deserialize_fast<T: NenCodec>(state: &mut T, header: &CartHeader, bytes: &[u8]) -> Result<(), String> {
+  let mut input = Reader::new(bytes);
+  if input.take(FAST_MAGIC.len()).ok() != Some(&FAST_MAGIC[..]) {
+    return Err("Savestate is corrupted: not a fast savestate".to_string());
+  }
+
+  let mut version = Vec::<u8>::new();
+  version.decode(&mut input)?;
+  if version != env!("CARGO_PKG_VERSION").as_bytes() {
+    return Err(format!("Savestate is from another version of the emulator ({})", String::from_utf8_lossy(&version)));
+  }
+
+  let (mut rom_hash, mut mapper, mut submapper) = (0u64, 0u16, 0u8);
+  rom_hash.decode(&mut input)?;
+  mapper.decode(&mut input)?;
+  submapper.decode(&mut input)?;
+  if rom_hash != header.rom_hash {
+    return Err("Savestate is from another game".to_string());
+  }
+  if (mapper, submapper) != (header.mapper, header.submapper) {
+    return Err(format!("Savestate is from another mapper ({mapper}.{submapper})"));
+  }
+
+  state.decode(&mut input)?;
+  input.finish()
+}
+
+#[cfg(test)]
+mod savestate_tests {
+  use super::*;
+
+  #[test]
+  fn versions() {
+    let state = serialize(&[1u8, 2, 3]);
+    assert_eq!(deserialize::<[u8; 3]>(&state), Ok([1, 2, 3]));
+
+    let newer = format!(r#"{{"version":{},"state":[1,2,3]}}"#, SAVESTATE_VERSION + 1);
+    assert!(deserialize::<[u8; 3]>(newer.as_bytes()).is_err());
+
+    let unversioned = br#"{"state":[1,2,3]}"#;
+    assert!(deserialize::<[u8; 3]>(unversioned).is_err());
+    assert!(deserialize::<[u8; 3]>(b"[1,2,3]").is_err());
+
+    let legacy = b"Pot\x00\x02...";
+    assert!(is_legacy(legacy) && !is_legacy(&state));
+    assert!(deserialize::<[u8; 3]>(legacy).unwrap_err().contains("older version"));
+
+    assert_eq!(migrate(SAVESTATE_VERSION, b"[1,2,3]"), Ok(b"[1,2,3]".to_vec()));
+  }
+
+  #[test]
+  fn bandai_eeprom_serial_state() {
+    // eeprom, irq enabled, count, latch and requested
+    let mut old = Vec::new();
+    vec![0x42u8; 256].into_boxed_slice().encode(&mut old);
+    (true, (0x1234u16, 0x5678u16)).encode(&mut old);
+    Some(()).encode(&mut old);
+    let state = json!({ "cpu": { "bus": { "cart": { "mapper": { "id": 16, "state": old } } } } });
+
+    let migrated = migrate_step(3, state).unwrap();
+    let bytes: Vec<u8> = serde_json::from_value(migrated["cpu"]["bus"]["cart"]["mapper"]["state"].clone()).unwrap();
+    assert!(bytes.len() > old.len() && bytes.starts_with(&old));
+    // already there, as in the states migrated from version 1
+    let again = migrate_step(3, json!({ "cpu": { "bus": { "cart": { "mapper": { "id": 16, "state": bytes } } } } })).unwrap();
+    assert_eq!(again["cpu"]["bus"]["cart"]["mapper"]["state"], json!(bytes));
+  }
+}
@@ -0,0 +1,133 @@
+use serde_json::Value;
+
+use crate::savestate;
+
+// A single field that differs between two emulator states.
+// The component is the part of the console the field belongs to (cpu, ppu, apu, cart, ram...),
+// the field is its path inside the component, with array indices in brackets (e.g. "oam[12]").
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+  pub component: String,
+  pub field: String,
+  pub old: Value,
+  pub new: Value,
+}
+
+impl std::fmt::Display for FieldDiff {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}.{}: {} -> {}", self.component, self.field, self.old, self.new)
+  }
+}
+
+// Diffs two serializable states
+pub fn diff<T: serde::Serialize>(old: &T, new: &T) -> Vec<FieldDiff> {
+  let old = serde_json::to_value(old).expect("state should always be serializable");
+  let new = serde_json::to_value(new).expect("state should always be serializable");
+  diff_values(&old, &new)
+}
+
+// Diffs two savestates, as returned by Nes::save_state()
+pub fn diff_savestates(old: &[u8], new: &[u8]) -> Result<Vec<FieldDiff>, String> {
+  let old: Value = savestate::deserialize(old)?;
+  let new: Value = savestate::deserialize(new)?;
+  Ok(diff_values(&old, &new))
+}
+
+pub fn diff_values(old: &Value, new: &Value) -> Vec<FieldDiff> {
+  let mut path = Vec::new();
+  let mut diffs = Vec::new();
+  walk(&mut path, old, new, &mut diffs);
+  diffs
+}
+
+enum PathSegment<'a> {
+  Key(&'a str),
+  Index(usize),
+}
+
+fn walk<'a>(path: &mut Vec<PathSegment<'a>>, old: &'a Value, new: &'a Value, diffs: &mut Vec<FieldDiff>) {
+  match (old, new) {
+    (Value::Object(old_map), Value::Object(new_map)) => {
+      for (key, old_val) in old_map {
+        path.push(PathSegment::Key(key));
+        walk(path, old_val, new_map.get(key).unwrap_or(&Value::Null), diffs);
+        path.pop();
+      }
+
+      for (key, new_val) in new_map.iter().filter(|(key, _)| !old_map.contains_key(*key)) {
+        path.push(PathSegment::Key(key));
+        walk(path, &Value::Null, new_val, diffs);
+        path.pop();
+      }
+    }
+    (Value::Array(old_arr), Value::Array(new_arr)) if old_arr.len() == new_arr.len() => {
+      for (i, (old_val, new_val)) in old_arr.iter().zip(new_arr).enumerate() {
+        path.push(PathSegment::Index(i));
+        walk(path, old_val, new_val, diffs);
+        path.pop();
+      }
+    }
+    _ => if old != new {
+      let (component, field) = split_path(path);
+      diffs.push(FieldDiff { component, field, old: old.clone(), new: new.clone() });
+    }
+  }
+}
+
+// The Nes state is laid out as cpu -> bus -> components, the cpu registers are directly in the cpu object.
+// Everything under the bus is grouped by the bus field it belongs to.
+fn split_path(path: &[PathSegment]) -> (String, String) {
+  let component_len = match path {
+    [PathSegment::Key("cpu"), PathSegment::Key("bus"), PathSegment::Key(_), _, ..] => 3,
+    [PathSegment::Key("cpu"), _, ..] => 1,
+    [PathSegment::Key(_), _, ..] => 1,
+    _ => 0,
+  };
+
+  let component = match path[..component_len].last() {
+    Some(PathSegment::Key(key)) => key.to_string(),
+    _ => String::new(),
+  };
+
+  let mut field = String::new();
+  for segment in &path[component_len..] {
+    match segment {
+      PathSegment::Key(key) => {
+        if !field.is_empty() { field.push('.'); }
+        field.push_str(key);
+      }
+      PathSegment::Index(i) => field.push_str(&format!("[{i}]")),
+    }
+  }
+
+  (component, field)
+}
+
+#[cfg(test)]
+mod state_diff_tests {
+  use serde_json::json;
+  use super::*;
+
+  #[test]
+  fn diff_nested() {
+    let old = json!({
+      "cpu": { "pc": 0x8000, "a": 1, "bus": { "ram": [0, 0, 0], "ppu": { "v": 0, "oam": [1, 2] } } }
+    });
+    let new = json!({
+      "cpu": { "pc": 0x8002, "a": 1, "bus": { "ram": [0, 5, 0], "ppu": { "v": 0, "oam": [1, 3] } } }
+    });
+
+    let mut diffs: Vec<_> = diff_values(&old, &new).iter()
+      .map(|d| (d.component.clone(), d.field.clone(), d.old.clone(), d.new.clone()))
+      .collect();
+    diffs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    assert_eq!(diffs, vec![
+      ("cpu".to_string(), "pc".to_string(), json!(0x8000), json!(0x8002)),
+      ("ppu".to_string(), "oam[1]".to_string(), json!(2), json!(3)),
+      ("ram".to_string(), "[1]".to_string(), json!(0), json!(5)),
+    ]);
+
+    assert!(diff_values(&old, &old).is_empty());
+  }
+}
@@ -0,0 +1,40 @@
+use nen_emulator::nes::Nes;
+
+mod common;
+use common::RomBuilder;
+
+// Starts a constant volume pulse, and a looping dmc sample
+const CODE: &[u8] = &[
+  0xA9, 0x3F, 0x8D, 0x00, 0x40,       // C000: pulse 1: halted length, constant volume 15
+  0xA9, 0x1F, 0x8D, 0x15, 0x40,       // C005: enable every channel
+  0xA9, 0xFD, 0x8D, 0x02, 0x40,       // C00A: period low
+  0xA9, 0x08, 0x8D, 0x03, 0x40,       // C00F: period high 0, length 254
+  0xA9, 0x4F, 0x8D, 0x10, 0x40,       // C014: dmc: loop, fastest rate
+  0xA9, 0x00, 0x8D, 0x12, 0x40,       // C019: sample at $c000
+  0xA9, 0x01, 0x8D, 0x13, 0x40,       // C01E: 17 bytes long
+  0xA9, 0x1F, 0x8D, 0x15, 0x40,       // C023: start the sample
+  0x4C, 0x28, 0xC0,                   // C028: jmp $c028
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).reset(0xC000).build()
+}
+
+#[test]
+fn apu_snapshot() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  let state = emu.apu_state();
+
+  assert!(state.pulse1.enabled);
+  assert_eq!(state.pulse1.period, 0xFD);
+  assert_eq!(state.pulse1.length_counter, 254);
+  assert_eq!(state.pulse1.envelope, Some(15));
+  assert_eq!(state.pulse2.length_counter, 0);
+  assert_eq!(state.triangle.envelope, None);
+
+  assert!(state.dmc.enabled);
+  assert!(state.dmc.loop_enabled);
+  assert!((0xC000..=0xC010).contains(&state.dmc.address));
+  assert!(state.dmc.remaining <= 17);
+}
@@ -0,0 +1,142 @@
+use std::{hint::black_box, time::Instant};
+use nen_emulator::{mapper::{mirror, Banking, ChrBanking, PrgBanking}, mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+#[test]
+fn translate_pages() {
+  // 128kb of prg in 4 pages of 8kb
+  let mut prg = Banking::<PrgBanking>::new(128 * 1024, 0x8000, 8 * 1024, 4);
+  prg.set_page(0, 3);
+  prg.set_page(1, 17);
+  prg.set_page_to_last_bank(3);
+  assert_eq!(prg.translate(0x8000), 3 * 8 * 1024);
+  assert_eq!(prg.translate(0x9FFF), 4 * 8 * 1024 - 1);
+  // banks past the end wrap around
+  assert_eq!(prg.translate(0xA123), 8 * 1024 + 0x123);
+  assert_eq!(prg.translate(0xFFFF), 128 * 1024 - 1);
+
+  // 12 pages of 1kb, for chr and nametables
+  let mut chr = Banking::<ChrBanking>::new(256 * 1024, 0, 1024, 12);
+  chr.set_page(11, 200);
+  assert_eq!(chr.translate(0x2C05), 200 * 1024 + 5);
+}
+
+#[test]
+fn out_of_range_banks() {
+  // 1mb of prg in 8kb banks, as on the biggest mmc5 boards, and 1mb of chr in 1kb banks
+  let mut prg = Banking::<PrgBanking>::new(1024 * 1024, 0x8000, 8 * 1024, 4);
+  prg.set_page(0, 127);
+  prg.set_page(1, 128 + 5);
+  assert_eq!(prg.banks(), [127, 5, 0, 0]);
+  let mut chr = Banking::<ChrBanking>::new(1024 * 1024, 0, 1024, 8);
+  chr.set_page(0, 1023);
+  chr.set_page(1, 1024 + 1023);
+  assert_eq!(chr.translate(0x0400), 1023 * 1024);
+
+  // 384kb are a 256kb and a 128kb chip: past the end, the second chip repeats
+  let mut prg = Banking::<PrgBanking>::new(384 * 1024, 0x8000, 8 * 1024, 4);
+  prg.set_page(0, 47);
+  prg.set_page(1, 50);
+  prg.set_page(2, 255);
+  prg.set_page_to_bank_from_end(3, 1);
+  assert_eq!(prg.banks(), [47, 34, 47, 46]);
+  assert_eq!(mirror(300 * 1024, 384 * 1024), 300 * 1024);
+  assert_eq!(mirror(400 * 1024, 384 * 1024), 272 * 1024);
+  assert_eq!(mirror(520 * 1024, 384 * 1024), 8 * 1024);
+
+  // a last bank cut short is still there, and mirrored in itself
+  let mut prg = Banking::<PrgBanking>::new(24 * 1024, 0x8000, 16 * 1024, 2);
+  prg.set_page_to_last_bank(1);
+  assert_eq!(prg.translate(0xC000), 16 * 1024);
+  assert_eq!(mirror(prg.translate(0xE010), 24 * 1024), 16 * 1024 + 0x10);
+
+  // fixed banks of roms with fewer banks than that
+  let mut prg = Banking::<PrgBanking>::new(8 * 1024, 0x8000, 8 * 1024, 4);
+  prg.set_page_to_bank_from_end(0, 2);
+  prg.set_page_to_bank_from_end(1, 1);
+  assert_eq!(prg.banks(), [0, 0, 0, 0]);
+}
+
+// Every 16kb bank of prg starts with its number, the rest is $FF so that bus conflicts keep the written values
+fn build_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(mapper)
+    .prg_banks(prg_banks as usize, 0xFF).bank_numbers()
+    .chr_banks(chr_banks as usize)
+    .build()
+}
+
+#[test]
+fn oversize_uxrom() {
+  // 2mb, past the 4 bits of the real boards
+  let mut emu = Nes::boot_from_bytes(&build_rom(2, 128, 0)).unwrap();
+  let bus = emu.get_bus();
+  for bank in [0, 15, 16, 100, 127, 128 + 5] {
+    bus.write(0x8001, bank);
+    assert_eq!(bus.read(0x8000), bank & 127);
+  }
+}
+
+#[test]
+fn vrc1_chr_high_bits() {
+  // 128kb of chr in 4kb banks, bits 1 and 2 of $9000 are their high bits
+  let mut emu = Nes::boot_from_bytes(&build_rom(75, 2, 16)).unwrap();
+  let bus = emu.get_bus();
+  bus.write(0xE000, 3);
+  bus.write(0xF000, 0x1F);
+  bus.write(0x9000, 0b010);
+  assert_eq!(emu.mapper_debug_state()["banks"]["chr"], serde_json::json!([19, 15]));
+
+  let bus = emu.get_bus();
+  bus.write(0x9000, 0b100);
+  bus.write(0xE000, 4);
+  assert_eq!(emu.mapper_debug_state()["banks"]["chr"], serde_json::json!([4, 31]));
+}
+
+#[test]
+fn vrc7_register_line() {
+  // $8000 selects the first 8kb prg page, the odd register $8010/$8008 the second one
+  let prg_banks = |submapper: u8, addr: u16| {
+    let rom = RomBuilder::new().mapper(85).submapper(submapper).prg_banks(8, 0xFF).build();
+    let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+    emu.get_bus().write(addr, 5);
+    emu.mapper_debug_state()["banks"]["prg"].clone()
+  };
+
+  // Vrc7b selects it with A3, Vrc7a with A4
+  assert_eq!(prg_banks(1, 0x8008), serde_json::json!([0, 5, 0, 15]));
+  assert_eq!(prg_banks(1, 0x8010), serde_json::json!([5, 0, 0, 15]));
+  assert_eq!(prg_banks(2, 0x8010), serde_json::json!([0, 5, 0, 15]));
+  assert_eq!(prg_banks(2, 0x8008), serde_json::json!([5, 0, 0, 15]));
+  // iNes dumps take both
+  assert_eq!(prg_banks(0, 0x8008), serde_json::json!([0, 5, 0, 15]));
+  assert_eq!(prg_banks(0, 0x8010), serde_json::json!([0, 5, 0, 15]));
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release --test banking -- --ignored --nocapture`"]
+fn translate_throughput() {
+  const ACCESSES: usize = 100_000_000;
+  let mut prg = Banking::<PrgBanking>::new(256 * 1024, 0x8000, 8 * 1024, 4);
+  let mut chr = Banking::<ChrBanking>::new(256 * 1024, 0, 1024, 8);
+  for page in 0..8 {
+    prg.set_page(page, page * 3);
+    chr.set_page(page, page * 5);
+  }
+
+  // so that the sizes aren't known at compile time, as in the emulator
+  let (prg, chr) = black_box((prg, chr));
+
+  let start = Instant::now();
+  let mut sum = 0usize;
+  for i in 0..ACCESSES {
+    let i = black_box(i);
+    sum = sum.wrapping_add(prg.translate(0x8000 + (i & 0x7FFF)));
+    sum = sum.wrapping_add(chr.translate(i & 0x1FFF));
+  }
+  black_box(sum);
+
+  let elapsed = start.elapsed().as_secs_f64();
+  println!("{:.2} ns per translate", elapsed * 1e9 / (2 * ACCESSES) as f64);
+}
@@ -0,0 +1,35 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+const BANKS: usize = 4;
+// in the fixed last bank, so that the rom byte under it is the same whatever bank is selected
+const REGISTER: u16 = 0xC100;
+
+// UxROM with the number of each bank at its start, and 1 under REGISTER
+fn build_rom(submapper: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(2).submapper(submapper)
+    .prg_banks(BANKS, 0).bank_numbers()
+    .put(REGISTER, &[1])
+    .build()
+}
+
+fn select_bank(submapper: u8, bank: u8) -> u8 {
+  let mut emu = Nes::boot_from_bytes(&build_rom(submapper)).unwrap();
+  let bus = emu.get_bus();
+  bus.write(REGISTER, bank);
+  bus.read(0x8000)
+}
+
+#[test]
+fn uxrom_bus_conflicts() {
+  // the written value is ANDed with the 1 in rom
+  assert_eq!(select_bank(2, 3), 1);
+  assert_eq!(select_bank(2, 2), 0);
+  // boards of unknown submapper have them too
+  assert_eq!(select_bank(0, 3), 1);
+  // submapper 1 declares boards without them
+  assert_eq!(select_bank(1, 3), 3);
+  assert_eq!(select_bank(1, 2), 2);
+}
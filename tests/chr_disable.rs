@@ -0,0 +1,36 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+const CHR_BYTE: u8 = 0x5A;
+
+// Mapper 185 with a NES 2.0 header, for the submapper. The prg is all $FF, so that writes don't conflict.
+fn build_rom(submapper: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(185).submapper(submapper)
+    .prg_banks(2, 0xFF)
+    .chr(vec![CHR_BYTE; 8 * 1024])
+    .build()
+}
+
+fn chr_after_write(submapper: u8, val: u8) -> u8 {
+  let mut emu = Nes::boot_from_bytes(&build_rom(submapper)).unwrap();
+  emu.get_bus().write(0x8000, val);
+  emu.get_ppu().peek_vram(0x0100)
+}
+
+#[test]
+fn chr_disabled_by_submapper() {
+  // submapper 5 enables the chr when the low bits are 1
+  assert_eq!(chr_after_write(5, 0b01), CHR_BYTE);
+  assert_eq!(chr_after_write(5, 0b10), 0xFF);
+  assert_eq!(chr_after_write(4, 0b00), CHR_BYTE);
+  assert_eq!(chr_after_write(4, 0b11), 0xFF);
+}
+
+#[test]
+fn chr_disabled_without_submapper() {
+  assert_eq!(chr_after_write(0, 0x0F), CHR_BYTE);
+  assert_eq!(chr_after_write(0, 0x00), 0xFF);
+  assert_eq!(chr_after_write(0, 0x13), 0xFF);
+}
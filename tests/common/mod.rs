@@ -0,0 +1,162 @@
+// The roms the tests run, built in place of real games.
+// Each test crate uses a part of the builder only.
+#![allow(dead_code)]
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+// An iNES rom, with a NES 2.0 header when one of its fields is set.
+// Prg is placed as the cpu sees it at boot: the last bank ends at $FFFF, where NROM
+// and the boards fixing their last bank put it.
+pub struct RomBuilder {
+  mapper: u8,
+  nes2: bool,
+  submapper: u8,
+  timing: u8,
+  prg_ram_shift: u8,
+  prg_nvram_shift: u8,
+  prg_ram_units: u8,
+  battery: bool,
+  vertical_mirroring: bool,
+  pub prg: Vec<u8>,
+  pub chr: Vec<u8>,
+}
+
+impl RomBuilder {
+  // NROM with 16kb of prg and 8kb of chr, all zero
+  pub fn new() -> Self {
+    Self {
+      mapper: 0,
+      nes2: false,
+      submapper: 0,
+      timing: 0,
+      prg_ram_shift: 0,
+      prg_nvram_shift: 0,
+      prg_ram_units: 0,
+      battery: false,
+      vertical_mirroring: false,
+      prg: vec![0; PRG_BANK_SIZE],
+      chr: vec![0; CHR_BANK_SIZE],
+    }
+  }
+
+  pub fn mapper(mut self, mapper: u8) -> Self {
+    self.mapper = mapper;
+    self
+  }
+
+  pub fn nes2(mut self) -> Self {
+    self.nes2 = true;
+    self
+  }
+
+  pub fn submapper(mut self, submapper: u8) -> Self {
+    self.submapper = submapper;
+    self.nes2()
+  }
+
+  // 0 ntsc, 1 pal, 2 multiple regions, 3 dendy
+  pub fn timing(mut self, timing: u8) -> Self {
+    self.timing = timing;
+    self.nes2()
+  }
+
+  // Volatile prg ram of 64 << shift bytes
+  pub fn prg_ram_shift(mut self, shift: u8) -> Self {
+    self.prg_ram_shift = shift;
+    self.nes2()
+  }
+
+  // Battery backed prg ram of 64 << shift bytes
+  pub fn prg_nvram_shift(mut self, shift: u8) -> Self {
+    self.prg_nvram_shift = shift;
+    self.nes2()
+  }
+
+  // Prg ram in 8kb units, as iNES byte 8 counts it. NES 2.0 headers use the shift counts instead
+  pub fn prg_ram_units(mut self, units: u8) -> Self {
+    self.prg_ram_units = units;
+    self
+  }
+
+  pub fn battery(mut self) -> Self {
+    self.battery = true;
+    self
+  }
+
+  pub fn vertical_mirroring(mut self) -> Self {
+    self.vertical_mirroring = true;
+    self
+  }
+
+  // That many 16kb banks, filled with the byte
+  pub fn prg_banks(mut self, banks: usize, fill: u8) -> Self {
+    self.prg = vec![fill; banks * PRG_BANK_SIZE];
+    self
+  }
+
+  // The first byte of every 16kb bank is its number
+  pub fn bank_numbers(mut self) -> Self {
+    for (bank, prg) in self.prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+      prg[0] = bank as u8;
+    }
+    self
+  }
+
+  // That many 8kb banks, all zero
+  pub fn chr_banks(mut self, banks: usize) -> Self {
+    self.chr = vec![0; banks * CHR_BANK_SIZE];
+    self
+  }
+
+  pub fn chr(mut self, chr: Vec<u8>) -> Self {
+    self.chr = chr;
+    self
+  }
+
+  // Bytes at the address the cpu finds them at boot
+  pub fn put(mut self, addr: u16, bytes: &[u8]) -> Self {
+    let start = self.prg.len() - (0x10000 - addr as usize);
+    self.prg[start..start + bytes.len()].copy_from_slice(bytes);
+    self
+  }
+
+  // The program, at $C000
+  pub fn code(self, code: &[u8]) -> Self {
+    self.put(0xC000, code)
+  }
+
+  pub fn nmi(self, addr: u16) -> Self {
+    self.put(0xFFFA, &addr.to_le_bytes())
+  }
+
+  pub fn reset(self, addr: u16) -> Self {
+    self.put(0xFFFC, &addr.to_le_bytes())
+  }
+
+  pub fn irq(self, addr: u16) -> Self {
+    self.put(0xFFFE, &addr.to_le_bytes())
+  }
+
+  pub fn build(&self) -> Vec<u8> {
+    let flags6 = (self.mapper << 4) | ((self.battery as u8) << 1) | self.vertical_mirroring as u8;
+    let flags7 = (self.mapper & 0xF0) | if self.nes2 { 0b1000 } else { 0 };
+    let mut rom = vec![
+      b'N', b'E', b'S', 0x1A,
+      (self.prg.len() / PRG_BANK_SIZE) as u8, (self.chr.len() / CHR_BANK_SIZE) as u8,
+      flags6, flags7,
+    ];
+    rom.resize(16, 0);
+    if self.nes2 {
+      rom[8] = self.submapper << 4;
+      rom[10] = (self.prg_nvram_shift << 4) | self.prg_ram_shift;
+      rom[12] = self.timing;
+    } else {
+      rom[8] = self.prg_ram_units;
+    }
+
+    rom.extend(&self.prg);
+    rom.extend(&self.chr);
+    rom
+  }
+}
@@ -0,0 +1,56 @@
+use nen_emulator::{cpu::{Cpu, CpuFlags}, mem::{Memory, Ram64Kb}};
+
+const ADC: u8 = 0x69;
+const SBC: u8 = 0xE9;
+
+// Runs one immediate adc or sbc in decimal mode, returning the accumulator and the carry
+fn run(opcode: u8, a: u8, val: u8, carry: bool) -> (u8, bool) {
+  let mut cpu: Cpu<Ram64Kb> = Cpu::with_ram64kb();
+  cpu.decimal_enabled = true;
+  cpu.write(0x0200, opcode);
+  cpu.write(0x0201, val);
+  cpu.pc = 0x0200;
+  cpu.a = a;
+  cpu.p.insert(CpuFlags::decimal);
+  cpu.p.set(CpuFlags::carry, carry);
+  cpu.step();
+  (cpu.a, cpu.p.contains(CpuFlags::carry))
+}
+
+#[test]
+fn decimal_arithmetic() {
+  // (opcode, a, operand, carry in, result, carry out)
+  let cases = [
+    (ADC, 0x09, 0x01, false, 0x10, false),
+    (ADC, 0x58, 0x46, true, 0x05, true),
+    (ADC, 0x99, 0x01, false, 0x00, true),
+    (ADC, 0x99, 0x00, true, 0x00, true),
+    (SBC, 0x00, 0x01, true, 0x99, false),
+    (SBC, 0x46, 0x12, true, 0x34, true),
+    (SBC, 0x40, 0x13, false, 0x26, true),
+    // invalid nibbles go through the same adjust as the nmos 6502 does
+    (ADC, 0x0A, 0x00, false, 0x10, false),
+    (ADC, 0x0F, 0x01, false, 0x16, false),
+    (ADC, 0xFF, 0xFF, true, 0x55, true),
+    (SBC, 0x1F, 0x0A, true, 0x15, true),
+    (SBC, 0x20, 0x0F, true, 0x1B, true),
+  ];
+
+  for (opcode, a, val, carry, res, carry_out) in cases {
+    let name = if opcode == ADC { "adc" } else { "sbc" };
+    assert_eq!(run(opcode, a, val, carry), (res, carry_out), "${a:02X} {name} ${val:02X}, carry {carry}");
+  }
+}
+
+#[test]
+fn binary_without_decimal_support() {
+  // the 2A03 ignores the flag
+  let mut cpu: Cpu<Ram64Kb> = Cpu::with_ram64kb();
+  cpu.write(0x0200, ADC);
+  cpu.write(0x0201, 0x01);
+  cpu.pc = 0x0200;
+  cpu.a = 0x09;
+  cpu.p.insert(CpuFlags::decimal);
+  cpu.step();
+  assert_eq!(cpu.a, 0x0A);
+}
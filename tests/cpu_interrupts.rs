@@ -0,0 +1,169 @@
+use nen_emulator::{cpu::{Cpu, CpuFlags}, mem::{Memory, Ram64Kb}};
+
+const PROGRAM: u16 = 0x0200;
+const IRQ_HANDLER: u16 = 0x0300;
+const NMI_HANDLER: u16 = 0x0400;
+
+// A bare 6502 whose nmi line rises at a chosen cycle
+struct Board {
+  ram: Ram64Kb,
+  ticks: usize,
+  nmi_at: Option<usize>,
+  nmi: bool,
+}
+
+impl Memory for Board {
+  fn read(&mut self, addr: u16) -> u8 { self.ram.read(addr) }
+  fn write(&mut self, addr: u16, val: u8) { self.ram.write(addr, val) }
+
+  fn tick(&mut self) {
+    self.ticks += 1;
+    if self.nmi_at == Some(self.ticks) { self.nmi = true; }
+  }
+
+  fn nmi_poll(&mut self) -> bool {
+    core::mem::take(&mut self.nmi)
+  }
+}
+
+// Everything not in the program is a nop, handlers included
+fn boot(program: &[u8]) -> Cpu<Board> {
+  let mut ram = Ram64Kb { mem: [0xEA; 64 * 1024] };
+  ram.mem[PROGRAM as usize..PROGRAM as usize + program.len()].copy_from_slice(program);
+  ram.mem[0xFFFA..0xFFFC].copy_from_slice(&NMI_HANDLER.to_le_bytes());
+  ram.mem[0xFFFE..].copy_from_slice(&IRQ_HANDLER.to_le_bytes());
+
+  let mut cpu = Cpu::with_memory(Board { ram, ticks: 0, nmi_at: None, nmi: false });
+  cpu.pc = PROGRAM;
+  cpu
+}
+
+// The nmi rises while the return address is pushed, on the third cycle of the sequence
+fn nmi_during_push(cpu: &mut Cpu<Board>) {
+  cpu.bus.nmi_at = Some(cpu.bus.ticks + 3);
+}
+
+fn pushed_status(cpu: &mut Cpu<Board>) -> CpuFlags {
+  let addr = 0x0100 + cpu.sp.wrapping_add(1) as u16;
+  CpuFlags::from_bits_retain(cpu.bus.read(addr))
+}
+
+#[test]
+fn nmi_hijacks_brk() {
+  let mut cpu = boot(&[0x00, 0x00]);
+  nmi_during_push(&mut cpu);
+  cpu.step();
+
+  assert_eq!(cpu.pc, NMI_HANDLER);
+  // the brk flag tells the nmi handler what happened
+  assert!(pushed_status(&mut cpu).contains(CpuFlags::brk));
+  // the nmi was consumed by the hijack
+  cpu.step();
+  assert_eq!(cpu.pc, NMI_HANDLER + 1);
+}
+
+#[test]
+fn nmi_hijacks_irq() {
+  let mut cpu = boot(&[0x58, 0xEA]);
+  cpu.set_irq(true);
+  cpu.step();
+  cpu.step();
+
+  nmi_during_push(&mut cpu);
+  cpu.step();
+  assert_eq!(cpu.pc, NMI_HANDLER + 1);
+  assert!(!pushed_status(&mut cpu).contains(CpuFlags::brk));
+}
+
+#[test]
+fn hardware_interrupts_push_brk_clear() {
+  let mut cpu = boot(&[]);
+  cpu.set_nmi();
+  cpu.step();
+  assert_eq!(cpu.pc, NMI_HANDLER + 1);
+  let status = pushed_status(&mut cpu);
+  assert!(!status.contains(CpuFlags::brk));
+  assert!(status.contains(CpuFlags::unused));
+
+  // brk and php push it set
+  let mut cpu = boot(&[0x08]);
+  cpu.step();
+  assert!(pushed_status(&mut cpu).contains(CpuFlags::brkpush));
+}
+
+#[test]
+fn irq_delayed_after_cli() {
+  let mut cpu = boot(&[0x58, 0xEA, 0xEA]);
+  cpu.set_irq(true);
+  cpu.step();
+  // the nop after cli still runs
+  cpu.step();
+  assert_eq!(cpu.pc, PROGRAM + 2);
+  cpu.step();
+  assert_eq!(cpu.pc, IRQ_HANDLER + 1);
+  // the pushed return address is the instruction the irq replaced
+  let ret = u16::from_le_bytes([cpu.bus.read(0x01FC), cpu.bus.read(0x01FD)]);
+  assert_eq!(ret, PROGRAM + 2);
+}
+
+#[test]
+fn irq_taken_after_sei() {
+  let mut cpu = boot(&[0xEA, 0x78, 0xEA]);
+  cpu.p.remove(CpuFlags::irq_off);
+  cpu.step();
+  cpu.step();
+  // raised after sei, which had polled with interrupts enabled
+  cpu.set_irq(true);
+  cpu.step();
+  assert_eq!(cpu.pc, IRQ_HANDLER + 1);
+  assert!(pushed_status(&mut cpu).contains(CpuFlags::irq_off));
+}
+
+#[test]
+fn irq_delayed_after_plp() {
+  // lda #0, pha, plp, nop
+  let mut cpu = boot(&[0xA9, 0x00, 0x48, 0x28, 0xEA, 0xEA]);
+  cpu.set_irq(true);
+  for _ in 0..3 { cpu.step(); }
+  assert!(!cpu.p.contains(CpuFlags::irq_off));
+  cpu.step();
+  assert_eq!(cpu.pc, PROGRAM + 5);
+  cpu.step();
+  assert_eq!(cpu.pc, IRQ_HANDLER + 1);
+}
+
+#[test]
+fn external_lines_on_plain_ram() {
+  let mut ram = Ram64Kb { mem: [0xEA; 64 * 1024] };
+  // both handlers are a nop and a rti
+  ram.mem[NMI_HANDLER as usize + 1] = 0x40;
+  ram.mem[IRQ_HANDLER as usize + 1] = 0x40;
+  ram.mem[0xFFFA..0xFFFC].copy_from_slice(&NMI_HANDLER.to_le_bytes());
+  ram.mem[0xFFFE..].copy_from_slice(&IRQ_HANDLER.to_le_bytes());
+  let mut cpu = Cpu::with_memory(ram);
+  cpu.pc = PROGRAM;
+
+  // it powers on with irqs masked
+  cpu.set_irq(true);
+  cpu.step();
+  assert_eq!(cpu.pc, PROGRAM + 1);
+
+  // with both lines up, the nmi goes first
+  cpu.p.remove(CpuFlags::irq_off);
+  cpu.set_nmi();
+  cpu.step();
+  assert_eq!(cpu.pc, NMI_HANDLER + 1);
+  // the nmi handler isn't interrupted, and returns
+  cpu.step();
+  assert_eq!(cpu.pc, PROGRAM + 1);
+
+  // the irq line is still held, then it is served
+  cpu.step();
+  assert_eq!(cpu.pc, IRQ_HANDLER + 1);
+  cpu.set_irq(false);
+  cpu.step();
+  assert_eq!(cpu.pc, PROGRAM + 1);
+  // nothing is left pending
+  cpu.step();
+  assert_eq!(cpu.pc, PROGRAM + 2);
+}
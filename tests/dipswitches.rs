@@ -0,0 +1,53 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// BMC-D1038 multicart, with 8 banks of 16kb whose first byte is their number
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().mapper(59).prg_banks(8, 0xEA).bank_numbers().chr_banks(8).build()
+}
+
+// the menu sets the read back bit, and reads the switches from the rom space
+fn read_dipswitches(emu: &mut Nes) -> u8 {
+  let bus = emu.get_bus();
+  bus.write(0x8100, 0);
+  let val = bus.read(0x8000);
+  bus.write(0x8000, 0);
+  val
+}
+
+#[test]
+fn multicart_banking() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  let bus = emu.get_bus();
+  assert_eq!((bus.read(0x8000), bus.read(0xC000)), (0, 1));
+
+  // 16kb mode, bank 5 in both halves
+  bus.write(0x80D0, 0);
+  assert_eq!((bus.read(0x8000), bus.read(0xC000)), (5, 5));
+  // 32kb mode, bank pair 2-3
+  bus.write(0x8020, 0);
+  assert_eq!((bus.read(0x8000), bus.read(0xC000)), (2, 3));
+}
+
+#[test]
+fn dipswitches_read_back() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  assert_eq!(read_dipswitches(&mut emu), 0);
+
+  emu.set_dipswitch(2);
+  assert_eq!(read_dipswitches(&mut emu), 2);
+  // the rom is back once the bit is cleared
+  assert_eq!(emu.get_bus().read(0x8000), 0);
+
+  // the setting belongs to the player, it outlives the console
+  emu.power_cycle();
+  assert_eq!(read_dipswitches(&mut emu), 2);
+  emu.swap_rom(&build_rom()).unwrap();
+  assert_eq!(read_dipswitches(&mut emu), 2);
+  let state = emu.save_state();
+  emu.set_dipswitch(1);
+  emu.load_state(&state).unwrap();
+  assert_eq!(read_dipswitches(&mut emu), 1);
+}
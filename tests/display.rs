@@ -0,0 +1,40 @@
+use nen_emulator::{config::{EmuConfig, Overscan}, frame::{AspectMode, DisplayRect}, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// NES 2.0 header, with the given timing
+fn build_rom(timing: u8) -> Vec<u8> {
+  RomBuilder::new().timing(timing).reset(0xC000).build()
+}
+
+#[test]
+fn pixel_aspect_ratio_follows_region() {
+  let ntsc = Nes::boot_from_bytes(&build_rom(0)).unwrap();
+  assert_eq!(ntsc.pixel_aspect_ratio(), 8.0 / 7.0);
+
+  for timing in [1, 3] {
+    let pal = Nes::boot_from_bytes(&build_rom(timing)).unwrap();
+    assert!((pal.pixel_aspect_ratio() - 1.3862).abs() < 0.001, "timing {timing}: {}", pal.pixel_aspect_ratio());
+  }
+}
+
+#[test]
+fn display_rects() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(0)).unwrap();
+  assert_eq!(emu.visible_area(), DisplayRect { x: 0, y: 0, width: 256, height: 240 });
+
+  // 256 * 8/7 = 292.57 wide for 240 high, pillarboxed in a 4:3 window
+  assert_eq!(emu.display_rect(1024, 768), DisplayRect { x: 44, y: 0, width: 936, height: 768 });
+  // letterboxed in a tall window
+  assert_eq!(emu.display_rect(585, 1000), DisplayRect { x: 0, y: 260, width: 585, height: 480 });
+
+  emu.set_config(EmuConfig { aspect_mode: AspectMode::SquarePixels, ..*emu.get_config() }).unwrap();
+  assert_eq!(emu.display_rect(1024, 768), DisplayRect { x: 102, y: 0, width: 819, height: 768 });
+
+  // the cropped overscan isn't shown
+  let overscan = Overscan { top: 8, bottom: 8, left: 0, right: 0 };
+  emu.set_config(EmuConfig { overscan, ..*emu.get_config() }).unwrap();
+  assert_eq!(emu.visible_area(), DisplayRect { x: 0, y: 8, width: 256, height: 224 });
+  assert_eq!(emu.display_rect(512, 448), DisplayRect { x: 0, y: 0, width: 512, height: 448 });
+}
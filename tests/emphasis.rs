@@ -0,0 +1,84 @@
+use std::{hint::black_box, time::Instant};
+use nen_emulator::{frame::{FrameBuffer, SYS_COLORS}, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// White backdrop, with rendering off and the first emphasis bit of ppumask set
+const CODE: &[u8] = &[
+  0xA9, 0x3F, 0x8D, 0x06, 0x20,       // C000: ppu address $3f00
+  0xA9, 0x00, 0x8D, 0x06, 0x20,       // C005
+  0xA9, 0x30, 0x8D, 0x07, 0x20,       // C00A: white
+  0xA9, 0x20, 0x8D, 0x01, 0x20,       // C00F: ppumask bit 5
+  0x4C, 0x14, 0xC0,                   // C014: jmp $c014
+];
+
+// NES 2.0 header, with the given timing
+fn build_rom(timing: u8) -> Vec<u8> {
+  RomBuilder::new().timing(timing).code(CODE).reset(0xC000).build()
+}
+
+fn first_pixel(timing: u8) -> [u8; 3] {
+  let mut emu = Nes::boot_from_bytes(&build_rom(timing)).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  emu.get_screen().buffer[..3].try_into().unwrap()
+}
+
+#[test]
+fn emphasis_follows_region() {
+  let [r, g, b] = first_pixel(0);
+  assert!(r > g && g == b, "ntsc bit 5 emphasizes red: {r} {g} {b}");
+
+  // pal and dendy swap red and green
+  for timing in [1, 3] {
+    let [r, g, b] = first_pixel(timing);
+    assert!(g > r && r == b, "bit 5 emphasizes green on timing {timing}: {r} {g} {b}");
+  }
+}
+
+#[test]
+fn emphasized_colors() {
+  // no emphasis leaves the colors as they are, otherwise the channels not emphasized are darkened
+  let mut screen = FrameBuffer::new(64, 8);
+  for emphasis in 0..8 {
+    for color in 0..64 {
+      screen.set_pixel_emphasized(color, emphasis, color as u8, emphasis as u8);
+    }
+  }
+
+  for emphasis in 0..8 {
+    for (color, sys) in SYS_COLORS.iter().enumerate() {
+      let idx = (emphasis * 64 + color) * 4;
+      let expected: Vec<u8> = [sys.0, sys.1, sys.2].iter().enumerate()
+        .map(|(channel, &val)| {
+          if emphasis == 0 || emphasis & (1 << channel) != 0 { val } else { (val as f32 * 0.816328) as u8 }
+        })
+        .chain([255])
+        .collect();
+      assert_eq!(screen.buffer[idx..idx + 4], expected, "color {color:02X} emphasis {emphasis:03b}");
+    }
+  }
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release --test emphasis -- --ignored --nocapture`"]
+fn pixel_throughput() {
+  const FRAMES: usize = 2000;
+  let mut screen = FrameBuffer::nes_screen();
+  let (width, height) = (screen.width, screen.height);
+
+  let start = Instant::now();
+  for frame in 0..FRAMES {
+    // half of the frames emphasized, as games which use it usually do it on a part of the screen
+    let emphasis = black_box((frame & 1) as u8 * 0b101);
+    for y in 0..height {
+      for x in 0..width {
+        screen.set_pixel_emphasized(x, y, black_box((x ^ y ^ frame) as u8 & 0x3F), emphasis);
+      }
+    }
+    black_box(&screen.buffer);
+  }
+
+  let elapsed = start.elapsed().as_secs_f64();
+  println!("{:.1} us per frame", elapsed * 1e6 / FRAMES as f64);
+}
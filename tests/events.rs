@@ -0,0 +1,37 @@
+use nen_emulator::{events::EmuEvent, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// Writes to the battery ram every frame, then jams the cpu after a few frames
+const CODE: &[u8] = &[
+  0xEE, 0x00, 0x60,                   // C000: inc $6000
+  0x2C, 0x02, 0x20, 0x10, 0xFB,       // C003: wait vblank
+  0xAD, 0x00, 0x60, 0xC9, 0x03,       // C008: lda $6000, cmp #3
+  0xD0, 0xF1,                         // C00D: bne $c000
+  0x02,                               // C00F: jam
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().battery().code(CODE).reset(0xC000).build()
+}
+
+#[test]
+fn event_stream() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  let state = emu.save_state();
+
+  for _ in 0..6 { emu.step_until_vblank(); }
+  let events = emu.poll_events();
+
+  assert!(events.iter().filter(|e| **e == EmuEvent::VBlank).count() >= 3);
+  assert!(events.contains(&EmuEvent::FrameStart(1)));
+  assert!(events.contains(&EmuEvent::SramChanged));
+  assert_eq!(events.iter().filter(|e| **e == EmuEvent::CpuJammed).count(), 1);
+  assert!(!events.contains(&EmuEvent::MapperIrq));
+  assert!(emu.poll_events().is_empty());
+
+  emu.reset();
+  emu.load_state(&state).unwrap();
+  assert_eq!(emu.poll_events(), vec![EmuEvent::Reset, EmuEvent::StateLoaded]);
+}
@@ -0,0 +1,67 @@
+use nen_emulator::{apu::FilterProfile, config::EmuConfig, mapper::board_expansion_gain, cart::CartHeader, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+fn sta(addr: u16, val: u8) -> Vec<u8> {
+  let [lo, hi] = addr.to_le_bytes();
+  vec![0xA9, val, 0x8D, lo, hi]
+}
+
+#[derive(PartialEq)]
+enum Square { None, Apu, Vrc6 }
+
+// Plays a full volume square on the 2A03, or on the VRC6
+fn build_rom(square: Square) -> Vec<u8> {
+  let mut code = match square {
+    Square::None => vec![],
+    Square::Apu => [sta(0x4000, 0xBF), sta(0x4015, 0x01), sta(0x4002, 0xFF), sta(0x4003, 0x08)].concat(),
+    // the duty is ignored, so the square stays high
+    Square::Vrc6 => [sta(0x9000, 0x8F), sta(0x9001, 0xFF), sta(0x9002, 0x80)].concat(),
+  };
+  code.extend([0x4C, code.len() as u8, 0xE0]);
+
+  let mapper = if square == Square::Vrc6 { 24 } else { 0 };
+  RomBuilder::new().mapper(mapper)
+    .prg_banks(2, 0)
+    .put(0xE000, &code)
+    .reset(0xE000)
+    .build()
+}
+
+// The loudest the mixer gets, without the console filters in the way
+fn peak(square: Square, expansion_gain: Option<f32>) -> f32 {
+  let config = EmuConfig { filter_profile: FilterProfile::Off, expansion_gain, ..Default::default() };
+  let mut emu = Nes::new_with_config(&build_rom(square), config).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  emu.get_samples().into_iter().fold(0.0, f32::max)
+}
+
+// How much the square adds to the mixer output, over the idle triangle's level
+fn level(square: Square, expansion_gain: Option<f32>) -> f32 {
+  peak(square, expansion_gain) - peak(Square::None, None)
+}
+
+#[test]
+fn vrc6_against_the_2a03() {
+  let apu = level(Square::Apu, None);
+  let vrc6 = level(Square::Vrc6, None);
+  // a full volume 2A03 square is 95.52 / (8128 / 15 + 100)
+  assert!((apu - 0.1494).abs() < 0.001, "{apu}");
+  assert!((vrc6 / apu - 0.85).abs() < 0.01, "{}", vrc6 / apu);
+
+  // the gain of the config goes over the one of the board
+  let half = level(Square::Vrc6, Some(0.5));
+  assert!((half - vrc6 / 2.0).abs() < 0.001, "{half}");
+  assert!(level(Square::Vrc6, Some(0.0)).abs() < 0.001);
+}
+
+#[test]
+fn board_levels() {
+  let header = |mapper, submapper| {
+    CartHeader::new(&RomBuilder::new().mapper(mapper).submapper(submapper).build()).unwrap()
+  };
+  for (mapper, submapper, gain) in [(24, 0, 1.0), (26, 0, 1.0), (5, 0, 1.0), (85, 0, 1.0), (69, 0, 1.0), (19, 2, 0.0), (19, 4, 1.7), (19, 5, 2.2)] {
+    assert_eq!(board_expansion_gain(&header(mapper, submapper)), gain, "mapper {mapper} submapper {submapper}");
+  }
+}
@@ -0,0 +1,66 @@
+use nen_emulator::{frame::FrameBlend, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// Rendering off, with the backdrop switching between black and white every frame
+const CODE: &[u8] = &[
+  0xA9, 0x0F, 0x85, 0x00,             // C000: black in $00
+  0xA9, 0x80, 0x8D, 0x00, 0x20,       // C004: enable nmi
+  0x4C, 0x09, 0xC0,                   // C009: jmp $c009
+  // nmi
+  0xA9, 0x3F, 0x8D, 0x06, 0x20,       // C00C: ppu address $3f00
+  0xA9, 0x00, 0x8D, 0x06, 0x20,       // C011
+  0xA5, 0x00, 0x49, 0x3F, 0x85, 0x00, // C016: swap black ($0f) and white ($30)
+  0x8D, 0x07, 0x20,                   // C01C
+  0x40,                               // C01F: rti
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).nmi(0xC00C).reset(0xC000).build()
+}
+
+// Red channel of the first pixel of the next few frames
+fn next_frames(emu: &mut Nes, count: usize) -> Vec<u8> {
+  (0..count).map(|_| {
+    emu.step_until_vblank();
+    emu.get_screen().buffer[0]
+  }).collect()
+}
+
+fn boot(mode: FrameBlend) -> Nes {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_frame_blend(mode);
+  next_frames(&mut emu, 4);
+  emu
+}
+
+#[test]
+fn blend_modes() {
+  let frames = next_frames(&mut boot(FrameBlend::Off), 4);
+  let (black, white) = (*frames.iter().min().unwrap(), *frames.iter().max().unwrap());
+  assert!(frames.windows(2).all(|pair| pair[0] != pair[1]), "the backdrop flickers: {frames:?}");
+
+  // every frame is halfway between the two
+  let frames = next_frames(&mut boot(FrameBlend::Mix), 4);
+  let gray = ((black as u16 + white as u16) / 2) as u8;
+  assert!(frames.iter().all(|&val| val == gray), "mixed frames: {frames:?}, expected {gray}");
+
+  // white fades, but never goes all the way to black
+  let frames = next_frames(&mut boot(FrameBlend::Phosphor), 4);
+  assert!(frames.contains(&white), "phosphor frames: {frames:?}");
+  assert!(frames.iter().all(|&val| val > black && val <= white), "phosphor frames: {frames:?}, between {black} and {white}");
+}
+
+#[test]
+fn blending_is_only_seen_by_the_frontend() {
+  let mut emu = boot(FrameBlend::Off);
+  let mut blended = boot(FrameBlend::Mix);
+  next_frames(&mut emu, 10);
+  next_frames(&mut blended, 10);
+  assert_eq!(emu.state_hash(), blended.state_hash());
+
+  // turning it off brings back the raw frames
+  blended.set_frame_blend(FrameBlend::Off);
+  assert_eq!(next_frames(&mut blended, 4), next_frames(&mut emu, 4));
+}
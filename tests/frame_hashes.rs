@@ -0,0 +1,126 @@
+// Golden framebuffer hashes, to catch rendering regressions across mapper changes.
+// The roms are tiny homebrew programs built here, so no third party rom has to be checked in:
+// each one sets up its mapper's chr banks, fills the palette and the first nametable, and enables rendering.
+
+mod common;
+use common::RomBuilder;
+
+fn sta(addr: u16, val: u8) -> Vec<u8> {
+  let [lo, hi] = addr.to_le_bytes();
+  vec![0xA9, val, 0x8D, lo, hi]
+}
+
+fn mmc1_write(addr: u16, val: u8) -> Vec<u8> {
+  (0..5).flat_map(|i| sta(addr, (val >> i) & 1)).collect()
+}
+
+fn build_rom(mapper: u8, mapper_init: &[u8]) -> Vec<u8> {
+  let mut code = vec![
+    0x78, 0xD8, 0xA2, 0xFF, 0x9A,             // sei, cld, ldx #$ff, txs
+  ];
+  code.extend(mapper_init);
+  code.extend([
+    0xA9, 0x00, 0x8D, 0x00, 0x20, 0x8D, 0x01, 0x20, // disable nmi and rendering
+    0x2C, 0x02, 0x20, 0x10, 0xFB,             // wait vblank
+    0x2C, 0x02, 0x20, 0x10, 0xFB,             // wait vblank
+    0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+    0xA2, 0x00,                               // ldx #0
+    0x8A, 0x8D, 0x07, 0x20, 0xE8, 0xE0, 0x20, 0xD0, 0xF7, // palette = 0..32
+    0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+    0xA0, 0x04, 0xA2, 0x00,                   // ldy #4, ldx #0
+    0x8A, 0x8D, 0x07, 0x20, 0xE8, 0xD0, 0xF9, 0x88, 0xD0, 0xF6, // nametable and attributes = 0..256, 4 times
+    0xA9, 0x00, 0x8D, 0x05, 0x20, 0x8D, 0x05, 0x20, // scroll 0, 0
+    0xA9, 0x1E, 0x8D, 0x01, 0x20,             // enable rendering
+    0xB8, 0x50, 0xFE,                         // clv, bvc self
+  ]);
+
+  // 32kb prg, 32kb chr. The code lives in the last 8kb, which is fixed at boot on all the tested mappers.
+  RomBuilder::new().mapper(mapper).vertical_mirroring()
+    .prg_banks(2, 0)
+    .put(0xE000, &code)
+    .nmi(0xE000).reset(0xE000).irq(0xE000)
+    // every chr byte is different enough that a wrong bank shows up in the hash
+    .chr((0..32 * 1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect())
+    .build()
+}
+
+fn frame_hash(rom: &[u8], frames: usize) -> u64 {
+  let mut emu = nen_emulator::nes::Nes::boot_from_bytes(rom).unwrap();
+  let hashes = emu.run_and_hash(frames);
+  assert!(!emu.get_screen().is_blank(), "screen is blank");
+  *hashes.last().unwrap()
+}
+
+#[test]
+fn nrom_frame() {
+  let rom = build_rom(0, &[]);
+  assert_eq!(frame_hash(&rom, 10), 14587380975766136609);
+}
+
+#[test]
+fn mmc1_frame() {
+  let mut init = sta(0x8000, 0x80);
+  // chr 4kb mode, prg mode 3, vertical mirroring
+  init.extend(mmc1_write(0x8000, 0b1_1110));
+  init.extend(mmc1_write(0xA000, 3));
+  let rom = build_rom(1, &init);
+  assert_eq!(frame_hash(&rom, 10), 11056348593558234346);
+}
+
+#[test]
+fn mmc3_frame() {
+  let init = [sta(0x8000, 0), sta(0x8001, 4), sta(0x8000, 1), sta(0x8001, 10), sta(0xA000, 0)].concat();
+  let rom = build_rom(4, &init);
+  assert_eq!(frame_hash(&rom, 10), 1714144752838305755);
+}
+
+#[test]
+fn vrc6_frame() {
+  let mut init = sta(0xB003, 0x20);
+  for (i, addr) in (0xD000..=0xD003).enumerate() {
+    init.extend(sta(addr, 8 + i as u8 * 3));
+  }
+  let rom = build_rom(24, &init);
+  assert_eq!(frame_hash(&rom, 10), 12636448332349184228);
+}
+
+#[test]
+fn mmc5_frame() {
+  // 8kb prg, 1kb chr banks, vertical mirroring
+  let mut init = [sta(0x5100, 3), sta(0x5117, 0xFF), sta(0x5101, 3), sta(0x5105, 0x44)].concat();
+  for (i, addr) in (0x5120..=0x512B).enumerate() {
+    init.extend(sta(addr, 30 - i as u8));
+  }
+  let rom = build_rom(5, &init);
+  assert_eq!(frame_hash(&rom, 10), 7031956834118193842);
+}
+
+#[test]
+fn sunsoft4_chr_nametables_frame() {
+  // the nametables are chr rom banks 5 and 6, vertically mirrored, so what the program writes to them is ignored
+  let init = [sta(0xC000, 5), sta(0xD000, 6), sta(0xE000, 0x10)].concat();
+  let rom = build_rom(68, &init);
+  let hash = frame_hash(&rom, 10);
+  assert_eq!(hash, 1145302647851238645);
+
+  // same banks, on ciram
+  let init = [sta(0xC000, 5), sta(0xD000, 6), sta(0xE000, 0x00)].concat();
+  assert_ne!(frame_hash(&build_rom(68, &init), 10), hash);
+}
+
+#[test]
+fn hot_reload_chr() {
+  let rom = build_rom(0, &[]);
+  let mut emu = nen_emulator::nes::Nes::boot_from_bytes(&rom).unwrap();
+  emu.run_and_hash(5);
+
+  // same rom, with the chr inverted
+  let mut new_rom = rom.clone();
+  let chr_start = new_rom.len() - 32 * 1024;
+  new_rom[chr_start..].iter_mut().for_each(|b| *b = !*b);
+
+  assert!(emu.replace_chr(&new_rom[chr_start..chr_start + 1024]).is_err());
+  emu.replace_chr(&new_rom[chr_start..]).unwrap();
+  emu.replace_prg(&new_rom[16..chr_start]).unwrap();
+  assert_eq!(emu.run_and_hash(5)[4], frame_hash(&new_rom, 10));
+}
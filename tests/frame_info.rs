@@ -0,0 +1,62 @@
+use nen_emulator::{cart::ConsoleTiming, config::EmuConfig, frame::FrameInfo, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+const CODE: &[u8] = &[
+  0xA9, 0x18, 0x8D, 0x01, 0x20, // C000: enable rendering
+  0x4C, 0x05, 0xC0,             // C005: jmp $c005
+];
+
+fn build_rom(rendering: bool) -> Vec<u8> {
+  let start: u16 = if rendering { 0xC000 } else { 0xC005 };
+  RomBuilder::new().code(CODE).reset(start).build()
+}
+
+fn next_frames(emu: &mut Nes, count: usize) -> Vec<FrameInfo> {
+  (0..count).map(|_| {
+    emu.step_until_vblank();
+    emu.get_screen().info
+  }).collect()
+}
+
+// Dots between the end of each frame and the next one
+fn frame_dots(frames: &[FrameInfo], timing: ConsoleTiming) -> Vec<u64> {
+  frames.windows(2)
+    .map(|pair| ((pair[1].time_ns - pair[0].time_ns) as f64 * timing.ppu_hz() / 1e9).round() as u64)
+    .collect()
+}
+
+#[test]
+fn counts_frames_and_time() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(false)).unwrap();
+  let frames = next_frames(&mut emu, 4);
+
+  assert!(frames.windows(2).all(|pair| pair[1].index == pair[0].index + 1), "{frames:?}");
+  assert!(frames.windows(2).all(|pair| pair[1].odd_field != pair[0].odd_field), "{frames:?}");
+  // 262 scanlines of 341 dots, about 16.6ms
+  assert_eq!(frame_dots(&frames, ConsoleTiming::NTSC), [341 * 262; 3]);
+}
+
+#[test]
+fn odd_fields_are_shorter_while_rendering() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(true)).unwrap();
+  next_frames(&mut emu, 2);
+  let frames = next_frames(&mut emu, 5);
+
+  // the pre-render scanline after an odd field skips a dot
+  for (pair, dots) in frames.windows(2).zip(frame_dots(&frames, ConsoleTiming::NTSC)) {
+    let expected = if pair[0].odd_field { 341 * 262 - 1 } else { 341 * 262 };
+    assert_eq!(dots, expected, "{pair:?}");
+  }
+}
+
+#[test]
+fn pal_frames() {
+  let config = EmuConfig { region: Some(ConsoleTiming::PAL), ..Default::default() };
+  let mut emu = Nes::new_from_file(&build_rom(true), "", config).unwrap();
+  let frames = next_frames(&mut emu, 4);
+
+  // no dot skipped, 312 scanlines, about 20ms
+  assert_eq!(frame_dots(&frames, ConsoleTiming::PAL), [341 * 312; 3]);
+}
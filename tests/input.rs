@@ -0,0 +1,222 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+mod common;
+use common::RomBuilder;
+
+use nen_emulator::{config::EmuConfig, joypad::{InputMacro, JoypadButton, OppositeDirections}, nes::Nes};
+
+// Latches and reads the first controller once per frame, storing the bits at $00
+const CODE: &[u8] = &[
+  0xA9, 0x01, 0x8D, 0x16, 0x40,       // C000: strobe on
+  0xA9, 0x00, 0x8D, 0x16, 0x40,       // C005: strobe off, latches the buttons
+  0xA2, 0x08,                         // C00A: ldx #8
+  0xAD, 0x16, 0x40, 0x4A, 0x26, 0x00, // C00C: lda $4016, lsr, rol $00
+  0xCA, 0xD0, 0xF7,                   // C012: dex, bne $c00c
+  0x2C, 0x02, 0x20, 0x10, 0xFB,       // C015: wait vblank
+  0x4C, 0x00, 0xC0,                   // C01A: jmp $c000
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).reset(0xC000).build()
+}
+
+#[test]
+fn input_poller() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  let polls = Arc::new(AtomicUsize::new(0));
+  let counter = polls.clone();
+  emu.set_input_poller(Some(Box::new(move || {
+    counter.fetch_add(1, Ordering::Relaxed);
+    (JoypadButton::a | JoypadButton::start, JoypadButton::b)
+  })));
+
+  for _ in 0..3 { emu.step_until_vblank(); }
+  assert!(polls.load(Ordering::Relaxed) >= 3);
+
+  let pressed = JoypadButton::a | JoypadButton::start;
+  assert_eq!(emu.last_latched_input(), (pressed, JoypadButton::b));
+  // the first bit read ends up in bit 7
+  assert_eq!(emu.peek(0x00), pressed.bits().reverse_bits());
+
+  // without a poller, changes after the latch aren't seen until the next one
+  emu.set_input_poller(None);
+  emu.get_joypad().buttons1 = JoypadButton::up;
+  assert_eq!(emu.last_latched_input().0, pressed);
+  emu.step_until_vblank();
+  assert_eq!(emu.last_latched_input().0, JoypadButton::up);
+}
+
+#[test]
+fn input_latency() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+
+  // the game latches right after vblank
+  emu.button_pressed(JoypadButton::a.bits());
+  emu.step_until_vblank();
+  let latency = emu.input_latency();
+  assert_eq!(latency.count, 1);
+  assert!(latency.last_cycles < 100 && latency.last_frames == 0, "{latency:?}");
+
+  // released at scanline 100, it waits for the next vblank
+  emu.set_joypad_btn_at(0, JoypadButton::a.bits(), false, 100).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+  let latency = emu.input_latency();
+  assert_eq!(latency.count, 2);
+  let expected = (241 - 100) * 341 / 3;
+  assert!(latency.last_cycles.abs_diff(expected) < 200, "{latency:?}, expected {expected} cycles");
+  assert_eq!(latency.max_cycles, latency.last_cycles);
+  assert!(latency.average_cycles() < latency.max_cycles as f64);
+
+  // polled when latched, the game sees the input right away
+  emu.reset_input_latency();
+  emu.set_input_poller(Some(Box::new(|| (JoypadButton::b, JoypadButton::empty()))));
+  for _ in 0..2 { emu.step_until_vblank(); }
+  let latency = emu.input_latency();
+  assert_eq!((latency.count, latency.max_cycles), (1, 0), "{latency:?}");
+}
+
+#[test]
+fn input_latency_with_altered_input() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_config(EmuConfig { opposite_directions: OppositeDirections::Neutral, ..EmuConfig::default() }).unwrap();
+  let hold = emu.add_macro(InputMacro { steps: vec![(JoypadButton::select, 10)] });
+  emu.trigger_macro(hold, 0).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+
+  // the game reads neither the buttons held nor the filtered ones, the change still counts at the next latch
+  emu.button_pressed((JoypadButton::left | JoypadButton::right).bits());
+  emu.step_until_vblank();
+  assert_eq!(emu.last_latched_input().0, JoypadButton::select);
+  let latency = emu.input_latency();
+  assert_eq!(latency.count, 1);
+  assert!(latency.last_cycles < 100 && latency.last_frames == 0, "{latency:?}");
+
+  emu.button_released(JoypadButton::left.bits());
+  emu.step_until_vblank();
+  let latency = emu.input_latency();
+  assert_eq!(latency.count, 2);
+  assert!(latency.max_cycles < 100, "{latency:?}");
+}
+
+#[test]
+fn input_macros() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+
+  let combo = emu.add_macro(InputMacro { steps: vec![
+    (JoypadButton::a, 2), (JoypadButton::empty(), 1), (JoypadButton::b | JoypadButton::start, 1),
+  ]});
+  assert!(emu.trigger_macro(combo + 1, 0).is_err());
+  assert!(emu.trigger_macro(combo, 2).is_err());
+
+  // played on top of what the player holds
+  emu.get_joypad().buttons1 = JoypadButton::up;
+  emu.trigger_macro(combo, 0).unwrap();
+  let frames: Vec<_> = (0..5).map(|_| {
+    emu.step_until_vblank();
+    emu.last_latched_input().0
+  }).collect();
+  let up = JoypadButton::up;
+  assert_eq!(frames, [up | JoypadButton::a, up | JoypadButton::a, up, up | JoypadButton::b | JoypadButton::start, up]);
+
+  // clearing stops the ones playing
+  emu.trigger_macro(combo, 0).unwrap();
+  emu.step_until_vblank();
+  emu.clear_macros();
+  emu.step_until_vblank();
+  assert_eq!(emu.last_latched_input().0, up);
+  assert!(emu.trigger_macro(combo, 0).is_err());
+}
+
+#[test]
+fn recorded_macros_replay() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+
+  let inputs = [JoypadButton::right, JoypadButton::right, JoypadButton::right | JoypadButton::a, JoypadButton::empty(), JoypadButton::left];
+  emu.start_macro_recording(0);
+  for buttons in inputs {
+    emu.get_joypad().buttons1 = buttons;
+    emu.step_until_vblank();
+  }
+  let recorded = emu.stop_macro_recording().unwrap();
+  assert_eq!(recorded.steps, [
+    (JoypadButton::right, 2), (JoypadButton::right | JoypadButton::a, 1), (JoypadButton::empty(), 1), (JoypadButton::left, 1),
+  ]);
+
+  emu.get_joypad().buttons1 = JoypadButton::empty();
+  let id = emu.add_macro(recorded);
+  emu.trigger_macro(id, 0).unwrap();
+  let replayed: Vec<_> = (0..inputs.len()).map(|_| {
+    emu.step_until_vblank();
+    emu.last_latched_input().0
+  }).collect();
+  assert_eq!(replayed, inputs);
+}
+
+#[test]
+fn macros_survive_savestates() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+  let state = emu.save_state();
+  for _ in 0..5 { emu.step_until_vblank(); }
+
+  let hold = emu.add_macro(InputMacro { steps: vec![(JoypadButton::select, 4)] });
+  emu.trigger_macro(hold, 1).unwrap();
+  emu.step_until_vblank();
+  // the frame count goes back, the macro keeps playing from where it was
+  emu.load_state(&state).unwrap();
+  let frames: Vec<_> = (0..3).map(|_| {
+    emu.step_until_vblank();
+    emu.get_joypad().macro_buttons[1]
+  }).collect();
+  assert_eq!(frames, [JoypadButton::select, JoypadButton::select, JoypadButton::empty()]);
+}
+
+#[test]
+fn opposite_directions_filter() {
+  let (left, right, up, down) = (JoypadButton::left, JoypadButton::right, JoypadButton::up, JoypadButton::down);
+  let inputs = [right, left | right | up | down, left | right, left, left | right | up];
+  let read = |filter| {
+    let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+    emu.set_config(EmuConfig { opposite_directions: filter, ..EmuConfig::default() }).unwrap();
+    inputs.iter().map(|&buttons| {
+      emu.get_joypad().buttons1 = buttons;
+      emu.step_until_vblank();
+      emu.last_latched_input().0
+    }).collect::<Vec<_>>()
+  };
+
+  assert_eq!(read(OppositeDirections::Allow), inputs);
+  assert_eq!(read(OppositeDirections::Neutral), [right, JoypadButton::empty(), JoypadButton::empty(), left, up]);
+  // left was pressed while right was held, then right while left was held
+  assert_eq!(read(OppositeDirections::LastPressed), [right, left, left, left, right | up]);
+}
+
+#[test]
+fn timed_inputs() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..2 { emu.step_until_vblank(); }
+  let a = JoypadButton::a.bits();
+
+  // vblank already passed scanline 100, so it happens in the next frame, and only on the second controller
+  emu.set_joypad_btn_at(1, a, true, 100).unwrap();
+  assert!(emu.set_joypad_btn_at(2, a, true, 100).is_err());
+  while emu.ppu_position().1 != 99 { emu.step(); }
+  assert!(emu.get_joypad().buttons2.is_empty());
+  while emu.ppu_position().1 != 101 { emu.step(); }
+  assert_eq!(emu.get_joypad().buttons2, JoypadButton::a);
+  assert!(emu.get_joypad().buttons1.is_empty());
+
+  // loading a state keeps the pending ones, as far ahead of the loaded frame
+  emu.step_until_vblank();
+  let state = emu.save_state();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  emu.set_joypad_btn_at(0, a, true, 100).unwrap();
+  emu.load_state(&state).unwrap();
+  let (frame, _, _) = emu.ppu_position();
+  while emu.ppu_position().1 != 101 { emu.step(); }
+  assert_eq!(emu.ppu_position().0, frame + 1);
+  assert_eq!(emu.get_joypad().buttons1, JoypadButton::a);
+}
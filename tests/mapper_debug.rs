@@ -0,0 +1,61 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+fn build_rom(mapper: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(mapper)
+    .prg_banks(4, 0xEA)
+    .put(0xFFF0, &[0x4C, 0xEA, 0xEA])
+    .chr_banks(2)
+    .build()
+}
+
+#[test]
+fn mmc3_debug_state() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(4)).unwrap();
+  let bus = emu.get_bus();
+  // prg bank 3 at $8000, then the irq latch
+  bus.write(0x8000, 6);
+  bus.write(0x8001, 3);
+  bus.write(0xC000, 42);
+  bus.write(0xE001, 0);
+
+  let state = emu.mapper_debug_state();
+  assert_eq!(state["mapper"], 4);
+  assert_eq!(state["name"], "MMC3");
+  assert_eq!(state["state"]["irq_latch"], 42);
+  assert_eq!(state["state"]["irq_enabled"], true);
+  assert_eq!(state["state"]["prg_mode"], "FixLastPages");
+  // 8kb pages, R7 is still 0 and the last two are fixed to the last banks
+  assert_eq!(state["banks"]["prg"], serde_json::json!([3, 0, 6, 7]));
+  assert_eq!(state["banks"]["chr"].as_array().unwrap().len(), 8);
+}
+
+#[test]
+fn mapper_memories_left_out() {
+  let emu = Nes::boot_from_bytes(&build_rom(5)).unwrap();
+  let state = emu.mapper_debug_state();
+  assert!(state["state"]["irq_enabled"].is_boolean());
+  assert!(state["state"].get("exram").is_none());
+
+  // mappers without registers
+  let emu = Nes::boot_from_bytes(&build_rom(0)).unwrap();
+  assert_eq!(emu.mapper_debug_state()["state"], serde_json::Value::Null);
+}
+
+#[test]
+fn current_banks_from_the_prelude() {
+  use nen_emulator::prelude::{BankMap, BankTarget};
+
+  let mut emu = Nes::boot_from_bytes(&build_rom(4)).unwrap();
+  let bus = emu.get_bus();
+  bus.write(0x8000, 6);
+  bus.write(0x8001, 3);
+
+  let banks: BankMap = emu.current_banks();
+  assert_eq!(banks.cpu[3].addr, 0x8000);
+  assert_eq!(banks.cpu[3].target, BankTarget::Prg(3 * 0x2000));
+  assert_eq!(banks.cpu[3].bank(), Some(6));
+  assert_eq!(banks.ppu.len(), 12);
+}
@@ -0,0 +1,100 @@
+use nen_emulator::{frame::SYS_COLORS, mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+const BLACK: u8 = 0x0F;
+const WHITE: u8 = 0x30;
+
+// MMC5 with 8kb chr banks. The background is tile 0, blank in every bank,
+// while tile 1 is solid color 3 in the second 4kb bank, which the split is given.
+// The program just loops, the tests set the mapper and the ppu up through the bus.
+fn build_rom() -> Vec<u8> {
+  let mut chr = vec![0; 32 * 1024];
+  chr[0x1010..0x1020].fill(0xFF);
+
+  RomBuilder::new().mapper(5)
+    .prg_banks(2, 0)
+    .put(0xE000, &[0x4C, 0x00, 0xE0])
+    .reset(0xE000)
+    .chr(chr)
+    .build()
+}
+
+// The split nametable shows tile 1 in its top 15 rows, and tile 0 in the bottom ones
+fn boot(split: u8, scroll: u8) -> Nes {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.step_until_vblank();
+
+  let bus = emu.get_bus();
+  for (addr, val) in [(0x5100, 3), (0x5101, 0), (0x5105, 0), (0x5104, 2)] {
+    bus.write(addr, val);
+  }
+  for offset in 0..1024 {
+    bus.write(0x5C00 + offset, (offset < 15 * 32) as u8);
+  }
+  for (addr, val) in [(0x5104, 0), (0x5200, split), (0x5201, scroll), (0x5202, 1)] {
+    bus.write(addr, val);
+  }
+
+  bus.write(0x2006, 0x20);
+  bus.write(0x2006, 0x00);
+  for _ in 0..1024 { bus.write(0x2007, 0); }
+  bus.write(0x2006, 0x3F);
+  bus.write(0x2006, 0x00);
+  for color in [BLACK, BLACK, BLACK, WHITE] { bus.write(0x2007, color); }
+  bus.write(0x2000, 0);
+  bus.write(0x2005, 0);
+  bus.write(0x2005, 0);
+  bus.write(0x2001, 0x0A);
+
+  for _ in 0..2 { emu.step_until_vblank(); }
+  emu
+}
+
+fn pixel(emu: &Nes, x: usize, y: usize) -> [u8; 3] {
+  let idx = (y * emu.get_screen().width + x) * 4;
+  emu.get_screen().buffer[idx..idx + 3].try_into().unwrap()
+}
+
+fn color(id: u8) -> [u8; 3] {
+  let color = &SYS_COLORS[id as usize];
+  [color.0, color.1, color.2]
+}
+
+// Checks every pixel of the frame, white where the split shows tile 1
+fn assert_split(emu: &Nes, in_split: impl Fn(usize, usize) -> bool) {
+  for y in 0..240 {
+    for x in 0..256 {
+      let expected = color(if in_split(x, y) { WHITE } else { BLACK });
+      assert_eq!(pixel(emu, x, y), expected, "pixel ({x}, {y})");
+    }
+  }
+}
+
+#[test]
+fn left_split() {
+  // the first 8 tiles of each line
+  let emu = boot(0x80 | 8, 0);
+  assert_split(&emu, |x, y| x < 64 && y < 120);
+}
+
+#[test]
+fn right_split() {
+  // the tiles from the 24th on
+  let emu = boot(0xC0 | 24, 0);
+  assert_split(&emu, |x, y| x >= 192 && y < 120);
+}
+
+#[test]
+fn split_scroll() {
+  // the split scrolls on its own, and wraps around at 240 lines as the nametable does
+  let emu = boot(0x80 | 8, 64);
+  assert_split(&emu, |x, y| x < 64 && (y < 56 || y >= 176));
+}
+
+#[test]
+fn split_disabled() {
+  let emu = boot(8, 0);
+  assert_split(&emu, |_, _| false);
+}
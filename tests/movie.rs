@@ -0,0 +1,32 @@
+use nen_emulator::{events::EmuEvent, mem::Memory, movie::Movie, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// jmp $c000
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(&[0x4C, 0x00, 0xC0]).reset(0xC000).build()
+}
+
+#[test]
+fn power_cycle_mid_movie() {
+  let movie = Movie::from_fm2("version 3\n|2|........|........||\n|0|........|........||\n|2|........|........||\n|1|........|........||\n").unwrap();
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+
+  // the one on the first frame finds the emulator already powered on
+  movie.apply_frame(0, &mut emu);
+  assert!(emu.poll_events().is_empty());
+  emu.step_until_vblank();
+
+  emu.poll_events();
+  emu.get_bus().write(0x0300, 0x42);
+  movie.apply_frame(2, &mut emu);
+  assert_eq!(emu.peek(0x0300), 0);
+  assert_eq!(emu.poll_events(), [EmuEvent::PowerCycle]);
+
+  // a soft reset keeps the ram
+  emu.get_bus().write(0x0300, 0x42);
+  movie.apply_frame(3, &mut emu);
+  assert_eq!(emu.peek(0x0300), 0x42);
+  assert_eq!(emu.poll_events(), [EmuEvent::Reset]);
+}
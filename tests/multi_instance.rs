@@ -0,0 +1,193 @@
+use std::{sync::{Arc, Mutex}, thread, time::Instant};
+use nen_emulator::{batch::{run_batch, BatchJob}, joypad::JoypadButton, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// Many emulators running at the same time, each on its own thread, must not interfere with each other.
+// Every instance is checked against a run of the same rom done alone.
+
+const INSTANCES: usize = 36;
+const FRAMES: usize = 5;
+const MAPPERS: &[u8] = &[0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 19, 21, 23, 24, 25, 66, 69, 85];
+
+// Keeps writing random values to random mapper registers, and to the ppu.
+// Every 4kb of prg holds the same code, so whatever bank gets switched in, execution goes on.
+const CODE: &[u8] = &[
+  0x78, 0xD8, 0xA2, 0xFF, 0x9A,       // F000: sei, cld, ldx #$ff, txs
+  0xA0, 0x00,                         // F005: ldy #0
+  0xA9, 0x01, 0x85, 0x00,             // F007: lda #1, sta $00 (rng seed)
+  0xA9, 0x1E, 0x8D, 0x01, 0x20,       // F00B: enable rendering
+  0x20, 0x2B, 0xF0,                   // F010: jsr rng
+  0x30, 0x04,                         // F013: bmi +4
+  0x29, 0x0F, 0x09, 0x50,             // F015: and #$0f, ora #$50 (registers at $5000..$5FFF)
+  0x85, 0x02,                         // F019: sta $02
+  0x20, 0x2B, 0xF0, 0x85, 0x01,       // F01B: jsr rng, sta $01
+  0x20, 0x2B, 0xF0, 0x91, 0x01,       // F020: jsr rng, sta ($01),y
+  0x8D, 0x07, 0x20,                   // F025: sta $2007
+  0x4C, 0x10, 0xF0,                   // F028: jmp $f010
+  // F02B: rng, 8 bit galois lfsr
+  0xA5, 0x00, 0x0A, 0x90, 0x02, 0x49, 0x1D, 0x85, 0x00, 0x60,
+];
+
+fn build_rom(mapper: u8) -> Vec<u8> {
+  // 128kb prg, 64kb chr
+  let mut rom = RomBuilder::new().mapper(mapper).battery()
+    .prg_banks(8, 0)
+    .chr((0..64 * 1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect());
+  for chunk in rom.prg.chunks_mut(4 * 1024) {
+    chunk[..CODE.len()].copy_from_slice(CODE);
+    for vector in [0xFFA, 0xFFC, 0xFFE] {
+      chunk[vector..vector+2].copy_from_slice(&0xF000u16.to_le_bytes());
+    }
+  }
+  rom.build()
+}
+
+// (screen hash after every frame, state hash at the end)
+fn run(rom: &[u8], frames: usize) -> (Vec<u64>, u64) {
+  let mut emu = Nes::boot_from_bytes(rom).unwrap();
+  let screens = (0..frames).map(|_| {
+    emu.step_until_vblank();
+    emu.consume_samples();
+    emu.get_screen().hash()
+  }).collect();
+  (screens, emu.state_hash())
+}
+
+#[test]
+fn concurrent_instances() {
+  let roms: Vec<_> = MAPPERS.iter().map(|&mapper| build_rom(mapper)).collect();
+  let expected: Vec<_> = roms.iter().map(|rom| run(rom, FRAMES)).collect();
+
+  let results: Vec<_> = thread::scope(|s| {
+    let handles: Vec<_> = (0..INSTANCES)
+      .map(|i| {
+        let rom = &roms[i % roms.len()];
+        s.spawn(move || run(rom, FRAMES))
+      })
+      .collect();
+
+    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+  });
+
+  for (i, result) in results.iter().enumerate() {
+    let mapper = MAPPERS[i % MAPPERS.len()];
+    assert!(result == &expected[i % MAPPERS.len()], "instance {i} (mapper {mapper}) desynced");
+  }
+}
+
+#[test]
+fn instances_can_move_between_threads() {
+  let rom = build_rom(4);
+  let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+  emu.step_until_vblank();
+
+  let mut emu = thread::spawn(move || {
+    emu.step_until_vblank();
+    emu
+  }).join().unwrap();
+  emu.step_until_vblank();
+  emu.consume_samples();
+
+  let expected = run(&rom, 3);
+  assert_eq!(emu.state_hash(), expected.1);
+}
+
+fn assert_movable<T: Send + Unpin + 'static>() {}
+
+// Embedders keep the emulator wherever they like: in a mutex shared with a worker thread, in a vec that reallocates...
+#[test]
+fn instances_can_live_anywhere() {
+  assert_movable::<Nes>();
+  let rom = build_rom(4);
+
+  let shared = Arc::new(Mutex::new(Nes::boot_from_bytes(&rom).unwrap()));
+  for _ in 0..FRAMES {
+    let emu = Arc::clone(&shared);
+    thread::spawn(move || {
+      let mut emu = emu.lock().unwrap();
+      emu.step_until_vblank();
+      emu.consume_samples();
+    }).join().unwrap();
+  }
+
+  assert_eq!(shared.lock().unwrap().state_hash(), run(&rom, FRAMES).1);
+
+  // every push moves the ones already running
+  let mut emus = Vec::with_capacity(1);
+  for _ in 0..FRAMES {
+    emus.push(Nes::boot_from_bytes(&rom).unwrap());
+    for emu in emus.iter_mut() {
+      emu.step_until_vblank();
+      emu.consume_samples();
+    }
+  }
+  assert_eq!(emus[0].state_hash(), run(&rom, FRAMES).1);
+}
+
+// Every frame, shows a blue backdrop if any button is held, a grey one otherwise
+const JOYPAD_CODE: &[u8] = &[
+  0x78, 0xD8,                                     // sei, cld
+  0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, // strobe the joypad
+  0xAD, 0x16, 0x40, 0x0D, 0x16, 0x40, 0x0D, 0x16, 0x40, 0x0D, 0x16, 0x40, // or together the 8 buttons
+  0x0D, 0x16, 0x40, 0x0D, 0x16, 0x40, 0x0D, 0x16, 0x40, 0x0D, 0x16, 0x40,
+  0x29, 0x01, 0xAA,                               // tax
+  0x2C, 0x02, 0x20, 0x10, 0xFB,                   // wait vblank
+  0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+  0x8E, 0x07, 0x20,                               // backdrop color = x
+  0xA9, 0x00, 0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20, // point v back to the pattern tables
+  0x4C, 0x02, 0xC0,                               // jmp loop
+];
+
+#[test]
+fn batch_runner() {
+  let rom = RomBuilder::new().code(JOYPAD_CODE).reset(0xC000).build();
+
+  let pressed = vec![JoypadButton::a; 4];
+  let jobs: Vec<_> = (0..12)
+    .map(|i| match i % 3 {
+      0 => BatchJob::new(rom.clone()),
+      1 => BatchJob::with_inputs(rom.clone(), pressed.clone()),
+      _ => BatchJob::new(build_rom(MAPPERS[i % MAPPERS.len()])),
+    })
+    .chain([BatchJob::new(vec![0; 16])])
+    .collect();
+
+  let results = run_batch(&jobs, 6, 4);
+  assert_eq!(results, run_batch(&jobs, 6, 1));
+  assert!(results.last().unwrap().is_err());
+
+  let released = results[0].as_ref().unwrap();
+  let held = results[1].as_ref().unwrap();
+  // the input of a frame shows up on the next one
+  assert_ne!(held[2..5], released[2..5]);
+  assert_eq!(held[5], released[5]);
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release --test multi_instance -- --ignored --nocapture`"]
+fn throughput() {
+  const BENCH_FRAMES: usize = 300;
+  let roms: Vec<_> = MAPPERS.iter().map(|&mapper| build_rom(mapper)).collect();
+  let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+  for instances in [1, threads, INSTANCES] {
+    let start = Instant::now();
+    thread::scope(|s| {
+      for i in 0..instances {
+        let rom = &roms[i % roms.len()];
+        s.spawn(move || {
+          let mut emu = Nes::boot_from_bytes(rom).unwrap();
+          // the samples are never consumed, as a headless user would do
+          for _ in 0..BENCH_FRAMES { emu.step_until_vblank(); }
+          assert!(emu.get_samples_count() <= 44_100.0, "samples buffer keeps growing");
+        });
+      }
+    });
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let fps = (instances * BENCH_FRAMES) as f64 / elapsed;
+    println!("{instances:>3} instances: {fps:>8.0} frames/s total, {:>6.0} frames/s each", fps / instances as f64);
+  }
+}
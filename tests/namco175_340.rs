@@ -0,0 +1,97 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// 128kb of prg and chr, every 8kb prg bank and 1kb chr bank starting with its number
+fn builder() -> RomBuilder {
+  let mut builder = RomBuilder::new().mapper(210).prg_banks(8, 0).chr_banks(16);
+  for (bank, prg) in builder.prg.chunks_mut(8 * 1024).enumerate() { prg[0] = bank as u8; }
+  for (bank, chr) in builder.chr.chunks_mut(1024).enumerate() { chr[0] = bank as u8; }
+  builder
+}
+
+fn nametable_pages(emu: &mut Nes) -> Vec<usize> {
+  emu.get_cart().banks.ciram.banks()
+}
+
+fn sram_roundtrip(emu: &mut Nes, val: u8) -> u8 {
+  let bus = emu.get_bus();
+  bus.write(0x6000, val);
+  bus.read(0x6000)
+}
+
+#[test]
+fn banks() {
+  for builder in [builder().submapper(1), builder().submapper(2)] {
+    let mut emu = Nes::boot_from_bytes(&builder.build()).unwrap();
+    let bus = emu.get_bus();
+    assert_eq!(bus.read(0xE000), 15);
+
+    for (addr, bank) in [(0xE000, 3), (0xE800, 5), (0xF000, 7)] {
+      bus.write(addr, bank);
+    }
+    assert_eq!([bus.read(0x8000), bus.read(0xA000), bus.read(0xC000), bus.read(0xE000)], [3, 5, 7, 15]);
+
+    // a register every $800, for each 1kb chr page
+    for page in 0..8 {
+      bus.write(0x8000 + page * 0x800, 100 + page as u8);
+    }
+    let ppu = emu.get_ppu();
+    for page in 0..8 {
+      assert_eq!(ppu.peek_vram(page * 0x400), (100 + page as u8) % 128, "page {page}");
+    }
+  }
+}
+
+#[test]
+fn namco175() {
+  let mut emu = Nes::boot_from_bytes(&builder().submapper(1).vertical_mirroring().build()).unwrap();
+  assert_eq!(nametable_pages(&mut emu), [0, 1, 0, 1]);
+
+  // the mirroring bits of the prg register are ignored
+  emu.get_bus().write(0xE000, 0x80);
+  assert_eq!(nametable_pages(&mut emu), [0, 1, 0, 1]);
+
+  // prg ram is enabled by $c000
+  assert_ne!(sram_roundtrip(&mut emu, 0x11), 0x11);
+  emu.get_bus().write(0xC000, 1);
+  assert_eq!(sram_roundtrip(&mut emu, 0x11), 0x11);
+  emu.get_bus().write(0xC000, 0);
+  assert_ne!(emu.get_bus().read(0x6000), 0x11);
+}
+
+#[test]
+fn namco340() {
+  let mut emu = Nes::boot_from_bytes(&builder().submapper(2).vertical_mirroring().build()).unwrap();
+
+  for (val, pages) in [(0x00, [0, 0, 0, 0]), (0x40, [0, 1, 0, 1]), (0x80, [0, 0, 1, 1]), (0xC0, [1, 1, 1, 1])] {
+    emu.get_bus().write(0xE000, val | 2);
+    assert_eq!(nametable_pages(&mut emu), pages, "{val:02X}");
+    assert_eq!(emu.get_bus().read(0x8000), 2);
+  }
+
+  // there is no prg ram to enable
+  emu.get_bus().write(0xC000, 1);
+  assert_ne!(sram_roundtrip(&mut emu, 0x11), 0x11);
+}
+
+#[test]
+fn submapper_0_from_header() {
+  // battery backed prg ram, as the Famista games
+  let mut emu = Nes::boot_from_bytes(&builder().battery().vertical_mirroring().build()).unwrap();
+  emu.get_bus().write(0xE000, 0x80);
+  assert_eq!(nametable_pages(&mut emu), [0, 1, 0, 1]);
+  emu.get_bus().write(0xC000, 1);
+  assert_eq!(sram_roundtrip(&mut emu, 0x11), 0x11);
+
+  // NES 2.0 declaring prg ram, without saying the submapper
+  let mut emu = Nes::boot_from_bytes(&builder().prg_nvram_shift(7).vertical_mirroring().build()).unwrap();
+  emu.get_bus().write(0xE000, 0x80);
+  assert_eq!(nametable_pages(&mut emu), [0, 1, 0, 1]);
+
+  // no prg ram, as Splatterhouse
+  let mut emu = Nes::boot_from_bytes(&builder().vertical_mirroring().build()).unwrap();
+  emu.get_bus().write(0xE000, 0x80);
+  assert_eq!(nametable_pages(&mut emu), [0, 0, 1, 1]);
+}
@@ -1,14 +1,52 @@
 #[cfg(test)]
 pub mod nes_test {
-use core::panic;
-use std::{fs, io::{BufWriter, Write}, path::Path};
-use circular_buffer::CircularBuffer;
-use log::info;
+  use std::{env, fs, io::{BufWriter, Write}};
+  use circular_buffer::CircularBuffer;
+  use log::info;
+
+  use nen_emulator::{bus::Bus, cart::Cart, cpu::{Cpu, CpuFlags}, instr::{AddressingMode, INSTRUCTIONS}, mem::Memory, nes::Nes};
+  use prettydiff::{diff_lines, diff_words};
+
+  // Get it from https://www.qmtpro.com/~nes/misc/nestest.nes, or point NESTEST_ROM to it
+  const NESTEST_ROM: &str = "tests/nestest/nestest.nes";
+  // nestest's automated mode starts here, instead of the reset vector
+  const NESTEST_START: u16 = 0xC000;
+  // The cpu takes 7 cycles to run the reset sequence, the log starts after it
+  const RESET_CYCLES: usize = 7;
+
+  // The test roms aren't distributed with the repository, tests which need them are skipped when missing
+  fn load_cart(path: &str) -> Option<Cart> {
+    let Ok(rom) = fs::read(path) else {
+      println!("{path} not found, skipping");
+      return None;
+    };
+    Some(Cart::new(&rom).unwrap())
+  }
 
-use nen_emulator::{bus::Bus, cart::Cart, cpu::{Cpu, CpuFlags}, instr::{AddressingMode, INSTRUCTIONS}, mem::Memory, nes::Nes};
-use prettydiff::{diff_lines, diff_words};
+  fn nestest_rom() -> String {
+    env::var("NESTEST_ROM").unwrap_or(NESTEST_ROM.to_string())
+  }
+
+  // Our own log goes in cargo's scratch directory, or where NESTEST_LOG points to
+  fn nestest_log() -> String {
+    env::var("NESTEST_LOG").unwrap_or(format!("{}/mylog.log", env!("CARGO_TARGET_TMPDIR")))
+  }
 
-  #[derive(Debug, Eq, Clone)]
+  // Boots nestest in the same state as the first line of the log
+  fn boot_nestest(cart: Cart) -> Nes {
+    let mut emu = Nes::boot_from_cart(cart);
+    // the log counts scanlines from the first visible one, while we power on in the pre-render one
+    emu.get_ppu().scanline = 0;
+
+    let cpu = emu.get_cpu();
+    cpu.pc = NESTEST_START;
+    cpu.p = CpuFlags::from_bits_retain(0x24);
+    // fetching the reset vector already took some of them
+    while cpu.cycles < RESET_CYCLES { cpu.tick(); }
+    emu
+  }
+
+  #[derive(Debug, PartialEq, Eq, Clone)]
   struct CpuMock {
     pc: u16,
     sp: u8,
@@ -20,19 +58,11 @@ use prettydiff::{diff_lines, diff_words};
     ppu_cycles: usize,
     cpu_cycles: usize
   }
-  impl PartialEq for CpuMock {
-    fn eq(&self, other: &Self) -> bool {
-        self.pc == other.pc && self.sp == other.sp && self.a == other.a && self.x == other.x && self.y == other.y && self.p == other.p 
-        && self.cpu_cycles == other.cpu_cycles
-        && self.ppu_cycles == other.ppu_cycles
-        && self.scanlines == other.scanlines
-    }
-  }
 
   impl CpuMock {
     fn from_cpu(cpu: &Cpu<Bus>) -> Self {
       CpuMock {
-        pc: cpu.pc, sp: cpu.sp, a: cpu.a, x: cpu.x, y: cpu.y, p: cpu.p.bits(), 
+        pc: cpu.pc, sp: cpu.sp, a: cpu.a, x: cpu.x, y: cpu.y, p: cpu.p.bits(),
         cpu_cycles: cpu.cycles,
         ppu_cycles: cpu.bus.ppu.cycle,
         scanlines: cpu.bus.ppu.scanline,
@@ -41,25 +71,25 @@ use prettydiff::{diff_lines, diff_words};
 
     fn from_log(line: &str) -> Self {
       let mut tokens = line.split_whitespace();
-      
+
       let pc = u16::from_str_radix(tokens.next().unwrap(), 16).unwrap();
-  
+
       let mut tokens = tokens.skip_while(|token| !token.contains("A:"));
-          
+
       let a = u8::from_str_radix(tokens.next().unwrap().split(':').last().unwrap(), 16).unwrap();
       let x = u8::from_str_radix(tokens.next().unwrap().split(':').last().unwrap(), 16).unwrap();
       let y = u8::from_str_radix(tokens.next().unwrap().split(':').last().unwrap(), 16).unwrap();
       let p = u8::from_str_radix(tokens.next().unwrap().split(':').last().unwrap(), 16).unwrap();
       let sp = u8::from_str_radix(tokens.next().unwrap().split(':').last().unwrap(), 16).unwrap();
-      
+
       let ppu_data = tokens.clone()
         .take_while(|token| !token.contains("CYC:"))
         .collect::<String>();
 
-      let scanlines = usize::from_str_radix(ppu_data.split(',').nth(0).unwrap().trim_start_matches("PPU:").trim(), 10).unwrap();
-      let ppu_cycles = usize::from_str_radix(ppu_data.split(',').nth(1).unwrap().trim(), 10).unwrap();
-      let cpu_cycles = usize::from_str_radix(tokens.skip_while(|token| !token.contains("CYC:")).next().unwrap().split(':').last().unwrap(), 10).unwrap();
-      
+      let scanlines = ppu_data.split(',').next().unwrap().trim_start_matches("PPU:").trim().parse().unwrap();
+      let ppu_cycles = ppu_data.split(',').nth(1).unwrap().trim().parse().unwrap();
+      let cpu_cycles = tokens.find(|token| token.contains("CYC:")).unwrap().split(':').last().unwrap().parse().unwrap();
+
       CpuMock {
         pc, a, x, y, sp, p, cpu_cycles, ppu_cycles, scanlines
       }
@@ -69,52 +99,48 @@ use prettydiff::{diff_lines, diff_words};
   #[test]
   fn parse_log_line() {
     let log_str = include_str!("nestest/nestest.log");
-    let mut test_log = log_str
-      .lines();
+    let cpu = CpuMock::from_log(log_str.lines().next().unwrap());
 
-    let cpu = CpuMock::from_log(test_log.next().unwrap());
-    println!("{:?}", cpu);
+    assert_eq!(cpu.pc, NESTEST_START);
+    assert_eq!((cpu.scanlines, cpu.ppu_cycles, cpu.cpu_cycles), (0, 21, RESET_CYCLES));
   }
 
   #[test]
   fn open_rom() {
-    let rom_path = Path::new("roms/Donkey Kong.nes");
-    let cart = Cart::from_file(rom_path).unwrap();
+    let Some(cart) = load_cart("roms/Donkey Kong.nes") else { return };
     println!("{:?}", cart.header);
   }
 
+  // Formats a line as nestest.log does. Memory is peeked, so that the emulator isn't ticked.
   fn debug_line(mock: &CpuMock, cpu: &mut Cpu<Bus>) -> String {
-    let opcode = cpu.read(mock.pc);
+    let bus = &mut cpu.bus;
+    let opcode = bus.peek(mock.pc);
     let instr = &INSTRUCTIONS[opcode as usize];
-    
-    let operand8 = cpu.read(mock.pc.wrapping_add(1));
-    let operand16 = u16::from_le_bytes([operand8, cpu.read(mock.pc.wrapping_add(2))]);
+
+    let operand8 = bus.peek(mock.pc.wrapping_add(1));
+    let operand16 = u16::from_le_bytes([operand8, bus.peek(mock.pc.wrapping_add(2))]);
 
     use AddressingMode::*;
     let desc = match instr.addressing {
       Implicit | Accumulator => String::new(),
       Immediate => format!("#${operand8:02X}"),
       Relative => format!("${:04X}", (mock.pc.wrapping_add(instr.bytes as u16)).wrapping_add_signed((operand8 as i8) as i16)),
-      ZeroPage | ZeroPageX | ZeroPageY => format!("${operand8:02X} = ${:04X}", cpu.read(operand8 as u16)),
-      Absolute => format!("${operand16:04X} = ${:02X}", cpu.read(operand16)),
-      AbsoluteX => format!("${:04X} = ${:02X}", operand16.wrapping_add(mock.x as u16), cpu.read(operand16.wrapping_add(mock.x as u16))),
-      AbsoluteY => format!("${:04X} = ${:02X}", operand16.wrapping_add(mock.y as u16), cpu.read(operand16.wrapping_add(mock.y as u16))),
-      Indirect => format!("${operand16:04X} = {:04X}", cpu.read(operand16)),
-      IndirectX => format!("IndX ${:04X} @ {:04X}", operand8.wrapping_add(mock.x), cpu.read((operand8.wrapping_add(mock.x)) as u16)),
-      IndirectY => format!("IndY ${:04X} @ {:04X}", operand8, cpu.read((operand8 as u16).wrapping_add(mock.y as u16))),
+      ZeroPage | ZeroPageX | ZeroPageY => format!("${operand8:02X} = ${:04X}", bus.peek(operand8 as u16)),
+      Absolute => format!("${operand16:04X} = ${:02X}", bus.peek(operand16)),
+      AbsoluteX => format!("${:04X} = ${:02X}", operand16.wrapping_add(mock.x as u16), bus.peek(operand16.wrapping_add(mock.x as u16))),
+      AbsoluteY => format!("${:04X} = ${:02X}", operand16.wrapping_add(mock.y as u16), bus.peek(operand16.wrapping_add(mock.y as u16))),
+      Indirect => format!("${operand16:04X} = {:04X}", bus.peek(operand16)),
+      IndirectX => format!("IndX ${:04X} @ {:04X}", operand8.wrapping_add(mock.x), bus.peek((operand8.wrapping_add(mock.x)) as u16)),
+      IndirectY => format!("IndY ${:04X} @ {:04X}", operand8, bus.peek((operand8 as u16).wrapping_add(mock.y as u16))),
     };
-    // let desc = " ".repeat(27);
-    
+
     let mem_data = match instr.bytes {
       1 => format!("{:02X}      ", opcode),
       2 => format!("{:02X} {:02X}   ", opcode, operand8),
-      3 => format!("{:02X} {:02X} {:02X}", opcode, operand8, cpu.read(mock.pc.wrapping_add(2))),
+      3 => format!("{:02X} {:02X} {:02X}", opcode, operand8, bus.peek(mock.pc.wrapping_add(2))),
       _ => unreachable!()
     };
-    let is_illegal = match instr.illegal {
-      true => '*',
-      false => ' ',
-    };
+    let is_illegal = if instr.illegal { '*' } else { ' ' };
 
     format!(
       "{:04X}  {mem_data} {is_illegal}{instr} {desc:27} \
@@ -126,114 +152,91 @@ use prettydiff::{diff_lines, diff_words};
     )
   }
 
+  // nestest writes the code of the first failed test in these, 0 means everything passed
+  fn results(cpu: &mut Cpu<Bus>) -> (u8, u8) {
+    (cpu.bus.peek(0x2), cpu.bus.peek(0x3))
+  }
+
   const LINES_RANGE: usize = 8;
 
+  // The cpu timing regression gate: registers, cpu cycles and the ppu scanline and dot
+  // are compared with the reference log, before every instruction
   #[test]
   fn nestest() {
-    let mut builder = colog::basic_builder();
-    builder.filter_level(log::LevelFilter::Info);
-    builder.init();
+    let _ = colog::basic_builder().filter_level(log::LevelFilter::Info).try_init();
 
     let log_str = include_str!("nestest/nestest.log");
-    let mut test_log = log_str
-      .lines();
-
-    let rom_path = Path::new("./tests/nestest/nestest.nes");
-    let rom = Cart::from_file(rom_path).unwrap();
-    let mut emu = Nes::with_cart(rom);
-
-    emu.get_cpu().pc = 0xC000;
-    emu.get_cpu().p = CpuFlags::from_bits_retain(0x24);
-    //emu.write_data(0x8000, &cart.prg_rom[..0x4000]);
-    //emu.write_data(0xC000, &cart.prg_rom[..0x4000]);
-    
+
+    let Some(cart) = load_cart(&nestest_rom()) else {
+      // the workflow downloads it, a missing rom must not pass silently there
+      assert!(env::var_os("CI").is_none(), "nestest.nes is required on CI");
+      return;
+    };
+    let mut emu = boot_nestest(cart);
+
     let mut most_recent_instr = CircularBuffer::<LINES_RANGE, (CpuMock, CpuMock)>::new();
-    let mut line_count = 1;
-
-    loop {
-      let next_line = test_log.next();
-      
-      if let None = next_line {
-        info!("Reached end of input!!");
-        print_last_diffs(&most_recent_instr, &mut emu.get_cpu(), line_count);
-        info!("Errors: ${:02X}", &emu.get_cpu().read(0x2));
-        info!("Results: ${:04X}", &emu.get_cpu().read16(0x2));
-
-        break;
-      }
 
-      let line = next_line.unwrap();
-      let my_cpu = CpuMock::from_cpu(&emu.get_cpu());
+    for (i, line) in log_str.lines().enumerate() {
+      let line_count = i + 1;
+      let my_cpu = CpuMock::from_cpu(emu.get_cpu());
       let log_cpu = CpuMock::from_log(line);
 
       if my_cpu != log_cpu {
-        print_last_diffs(&most_recent_instr, &mut emu.get_cpu(), line_count);
-        
-        let (my_line, log_line) = print_diff(&my_cpu, &log_cpu, &mut emu.get_cpu(), line_count);
-        
+        print_last_diffs(&most_recent_instr, emu.get_cpu(), line_count);
+
+        let (my_line, log_line) = print_diff(&my_cpu, &log_cpu, emu.get_cpu(), line_count);
+
         info!("{}", "-".repeat(50));
         info!("Incosistency at line {line_count}\n{}", diff_words(&my_line, &log_line));
-        
+
         let my_p = format!("{:?}", CpuFlags::from_bits_retain(my_cpu.p));
         let log_p = format!("{:?}", CpuFlags::from_bits_retain(log_cpu.p));
-        info!("Stack: {}", &emu.get_cpu().stack_trace());
-        
+        info!("Stack: {}", emu.get_cpu().stack_trace());
         info!("Flags: {}", diff_lines(&my_p, &log_p));
-        info!("Errors: ${:02X}", &emu.get_cpu().read(0x2));
-        info!("Results: ${:04X}", &emu.get_cpu().read16(0x2));
-        
+        info!("Results: {:02X?}", results(emu.get_cpu()));
         info!("{}", "-".repeat(50));
-        
-        panic!("Instruction inconsistency")
+
+        panic!("Inconsistency at line {line_count}:\nmine: {my_line}\nlog:  {log_line}")
       }
-      
-      most_recent_instr.push_back((my_cpu, log_cpu));
 
-      line_count+=1;
+      most_recent_instr.push_back((my_cpu, log_cpu));
       emu.step();
     }
+
+    assert_eq!(results(emu.get_cpu()), (0, 0), "nestest reported a failure");
   }
 
-#[test]
-fn nestest_to_file() {
-  let file = fs::File::create("tests/nestest/mylog.log").expect("Couldn't create log file");
-  let mut buf = BufWriter::new(file);
-
-  let rom_path = Path::new("tests/nestest/nestest.nes");
-  let rom = Cart::from_file(rom_path).unwrap();
-  let mut emu = Cpu::with_cart(rom);
-  emu.pc = 0xC000;
-  emu.write(0x2, 0);
-  emu.write(0x3, 0);
-
-
-  for _ in 0..8992 {
-    let snapshot = CpuMock::from_cpu(&emu);
-    let mut line = debug_line(&snapshot, &mut emu);
-    line.push('\n');
-    
-    buf.write(line.as_bytes()).expect("Couldn't write line to file");
-    emu.step();
+  // Writes our own log in the nestest.log format, to be diffed with the reference one
+  #[test]
+  fn nestest_to_file() {
+    let Some(cart) = load_cart(&nestest_rom()) else { return };
+    let file = fs::File::create(nestest_log()).expect("Couldn't create log file");
+    let mut buf = BufWriter::new(file);
+
+    let mut emu = boot_nestest(cart);
+    let lines = include_str!("nestest/nestest.log").lines().count();
+
+    for _ in 0..lines {
+      let snapshot = CpuMock::from_cpu(emu.get_cpu());
+      let line = debug_line(&snapshot, emu.get_cpu());
+      writeln!(buf, "{line}").expect("Couldn't write line to file");
+      emu.step();
+    }
   }
-}
 
-fn print_diff(my_cpu: &CpuMock, log_cpu: &CpuMock, cpu: &mut Cpu<Bus>, line_count: usize) -> (String, String) {
+  fn print_diff(my_cpu: &CpuMock, log_cpu: &CpuMock, cpu: &mut Cpu<Bus>, line_count: usize) -> (String, String) {
     let my_line = debug_line(my_cpu, cpu);
     let log_line = debug_line(log_cpu, cpu);
     info!("{}|Mine -> {my_line}", line_count);
     info!("{}|Log  -> {log_line}", line_count);
-    info!("Errors: ${:02X}", &cpu.read(0x2));
     info!("");
     (my_line, log_line)
   }
-  
-  fn print_last_diffs(most_recent_instr: &CircularBuffer<8, (CpuMock, CpuMock)>, cpu: &mut Cpu<Bus>, line_count: usize) {
-    let mut trace: Vec<(usize, &(CpuMock, CpuMock))> = most_recent_instr.iter().enumerate().collect::<Vec<_>>();
-    trace.sort_by(|a, b| a.0.cmp(&b.0));
-    
-    for (i, (mine, log)) in trace {
+
+  fn print_last_diffs(most_recent_instr: &CircularBuffer<LINES_RANGE, (CpuMock, CpuMock)>, cpu: &mut Cpu<Bus>, line_count: usize) {
+    for (i, (mine, log)) in most_recent_instr.iter().enumerate() {
       let line = line_count.max(LINES_RANGE) - LINES_RANGE + i;
-      print_diff(&mine, &log, cpu, line);
+      print_diff(mine, log, cpu, line);
     }
   }
-}
\ No newline at end of file
+}
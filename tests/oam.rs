@@ -0,0 +1,80 @@
+use nen_emulator::{config::EmuConfig, mem::Memory, nes::Nes, ppu::OAM_DECAY_VALUE};
+
+mod common;
+use common::RomBuilder;
+
+const ROW: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+// The program loops with rendering off, the tests drive the ppu through the bus
+fn boot(timing: u8, accurate_oam: bool) -> Nes {
+  let rom = RomBuilder::new().timing(timing).code(&[0x4C, 0x00, 0xC0]).reset(0xC000).build();
+  let config = EmuConfig { accurate_oam, ..Default::default() };
+  let mut emu = Nes::new_with_config(&rom, config).unwrap();
+  emu.step_until_vblank();
+  emu
+}
+
+fn write_oam(emu: &mut Nes, addr: u8, data: &[u8]) {
+  let bus = emu.get_bus();
+  bus.write(0x2003, addr);
+  for &val in data { bus.write(0x2004, val); }
+}
+
+// Reading $2004 doesn't move oamaddr, so it is set before every byte
+fn read_oam(emu: &mut Nes, addr: u8) -> Vec<u8> {
+  let bus = emu.get_bus();
+  (addr..addr + 8).map(|addr| {
+    bus.write(0x2003, addr);
+    bus.read(0x2004)
+  }).collect()
+}
+
+#[test]
+fn unrefreshed_rows_decay() {
+  let mut emu = boot(0, true);
+  write_oam(&mut emu, 8, &ROW);
+  assert_eq!(read_oam(&mut emu, 8), ROW);
+
+  // a frame is way more than 3000 cpu cycles
+  emu.step_until_vblank();
+  assert_eq!(read_oam(&mut emu, 8), [OAM_DECAY_VALUE; 8]);
+
+  // rows refreshed in time keep their content
+  write_oam(&mut emu, 16, &ROW);
+  for _ in 0..200 { emu.step(); }
+  assert_eq!(read_oam(&mut emu, 16), ROW);
+}
+
+#[test]
+fn pal_never_decays() {
+  let mut emu = boot(1, true);
+  write_oam(&mut emu, 8, &ROW);
+  for _ in 0..3 { emu.step_until_vblank(); }
+  assert_eq!(read_oam(&mut emu, 8), ROW);
+}
+
+#[test]
+fn decay_off_by_default() {
+  let mut emu = boot(0, false);
+  write_oam(&mut emu, 8, &ROW);
+  emu.step_until_vblank();
+  assert_eq!(read_oam(&mut emu, 8), ROW);
+}
+
+#[test]
+fn oamaddr_corrupts_the_first_row() {
+  for accurate_oam in [true, false] {
+    let mut emu = boot(0, accurate_oam);
+    write_oam(&mut emu, 0, &[0; 8]);
+    write_oam(&mut emu, 0x10, &ROW);
+    // rendering starts with oamaddr pointing in the third row
+    emu.get_bus().write(0x2003, 0x13);
+    emu.get_bus().write(0x2001, 0x18);
+    emu.step_until_vblank();
+    emu.get_bus().write(0x2001, 0);
+
+    let first_row = if accurate_oam { ROW } else { [0; 8] };
+    assert_eq!(read_oam(&mut emu, 0), first_row, "accurate oam: {accurate_oam}");
+    assert_eq!(read_oam(&mut emu, 0x10), ROW);
+  }
+}
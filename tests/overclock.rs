@@ -0,0 +1,82 @@
+use nen_emulator::nes::Nes;
+
+mod common;
+use common::RomBuilder;
+
+// The main loop counts its iterations, the nmi stores the count of the last frame in $02-$03
+const CODE: &[u8] = &[
+  0xA9, 0x80, 0x8D, 0x00, 0x20,       // C000: enable nmi
+  0xE6, 0x00, 0xD0, 0x02, 0xE6, 0x01, // C005: 16 bits increment of $00-$01, 11 cycles per loop
+  0x4C, 0x05, 0xC0,                   // C00B: jmp $c005
+  // nmi
+  0xA5, 0x00, 0x85, 0x02,             // C00E
+  0xA5, 0x01, 0x85, 0x03,             // C012
+  0xA9, 0x00, 0x85, 0x00, 0x85, 0x01, // C016
+  0x40,                               // C01C: rti
+];
+const LOOP_CYCLES: f32 = 11.0;
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).nmi(0xC00E).reset(0xC000).build()
+}
+
+// Loops run by the game in a frame, and samples produced
+fn run(pre_nmi: u16, post_nmi: u16) -> (f32, usize) {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_overclock(pre_nmi, post_nmi).unwrap();
+  for _ in 0..5 { emu.step_until_vblank(); }
+  emu.get_samples();
+
+  emu.step_until_vblank();
+  let loops = u16::from_le_bytes([emu.peek(0x02), emu.peek(0x03)]) as f32;
+  let samples = (0..10).map(|_| {
+    emu.step_until_vblank();
+    emu.get_samples().len()
+  }).sum();
+  (loops, samples)
+}
+
+#[test]
+fn extra_scanlines_give_cpu_time() {
+  let (loops, samples) = run(0, 0);
+  // a scanline is 341 dots, a third of them cpu cycles
+  let scanline_loops = 341.0 / 3.0 / LOOP_CYCLES;
+
+  for (pre, post) in [(20, 0), (0, 20), (50, 50)] {
+    let (overclocked_loops, overclocked_samples) = run(pre, post);
+    let expected = loops + (pre + post) as f32 * scanline_loops;
+    assert!((overclocked_loops - expected).abs() < 2.0, "{pre}/{post}: {overclocked_loops} loops, expected {expected}");
+    // the sound isn't slowed down
+    assert!(overclocked_samples.abs_diff(samples) <= 1, "{pre}/{post}: {overclocked_samples} samples, expected {samples}");
+  }
+}
+
+#[test]
+fn frame_time_is_unchanged() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_overclock(30, 10).unwrap();
+  emu.step_until_vblank();
+  let start = emu.get_screen().info.time_ns;
+  emu.step_until_vblank();
+  let frame_ns = emu.get_screen().info.time_ns - start;
+  // 262 scanlines of 341 dots, rendering is off
+  let expected = (341.0 * 262.0 * 1e9 / 5_369_318.0f64) as u64;
+  assert!(frame_ns.abs_diff(expected) <= 1, "{frame_ns}ns, expected {expected}ns");
+}
+
+#[test]
+fn refused_in_hardcore() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_overclock(30, 10).unwrap();
+  // turning hardcore on stops overclocking
+  emu.set_hardcore(true);
+  assert_eq!((emu.get_config().overclock_pre_nmi, emu.get_config().overclock_post_nmi), (0, 0));
+
+  assert!(emu.set_overclock(30, 10).is_err());
+  let config = nen_emulator::config::EmuConfig { overclock_post_nmi: 10, ..*emu.get_config() };
+  assert!(emu.set_config(config).is_err());
+  assert_eq!(emu.get_config().overclock_post_nmi, 0);
+  // the other settings still change
+  let config = nen_emulator::config::EmuConfig { sprite_limit: true, ..*emu.get_config() };
+  assert!(emu.set_config(config).is_ok());
+}
@@ -0,0 +1,51 @@
+use nen_emulator::{cart::{HeaderDiagnostic, Mirroring}, config::EmuConfig, frame::fnv_hash, nes::Nes, overrides::GameOverride};
+
+mod common;
+use common::RomBuilder;
+
+fn build_rom(signature: &[u8]) -> Vec<u8> {
+  RomBuilder::new().code(signature).reset(0xC000).build()
+}
+
+#[test]
+fn instance_overrides() {
+  let rom = build_rom(b"OVRD");
+  let rom_hash = fnv_hash(&rom[16..]);
+  let mut entry = GameOverride::new(rom_hash, "Test Game");
+  entry.mirroring = Some(Mirroring::Vertical);
+  entry.battery = Some(true);
+  entry.sprite_limit = Some(true);
+
+  let mut emu = Nes::new_with_overrides(&rom, "", EmuConfig::default(), vec![entry.clone()]).unwrap();
+  let header = emu.get_cart_header();
+  assert_eq!(header.mirroring, Mirroring::Vertical);
+  assert!(header.has_battery);
+  assert!(header.diagnostics.contains(&HeaderDiagnostic::Overridden("Test Game".to_string())));
+  assert_eq!(emu.get_ppu().oam_sprite_limit, 8);
+
+  // other instances boot the game as it is
+  let mut other = Nes::boot_from_bytes(&rom).unwrap();
+  assert_eq!(other.get_cart_header().mirroring, Mirroring::Horizontal);
+  assert_eq!(other.get_ppu().oam_sprite_limit, u8::MAX);
+
+  // the overrides stay with the instance across swaps
+  emu.swap_rom(&build_rom(b"OTHR")).unwrap();
+  assert_eq!(emu.get_cart_header().mirroring, Mirroring::Horizontal);
+  emu.swap_rom(&rom).unwrap();
+  assert_eq!(emu.get_cart_header().mirroring, Mirroring::Vertical);
+  emu.power_cycle();
+  assert_eq!(emu.get_ppu().oam_sprite_limit, 8);
+  let crc32 = emu.get_cart_header().rom_crc32;
+  let state = emu.save_state();
+  emu.load_state(&state).unwrap();
+  assert_eq!((emu.get_cart_header().rom_crc32, emu.get_ppu().oam_sprite_limit), (crc32, 8));
+
+  emu.remove_override(rom_hash);
+  assert!(emu.get_overrides().is_empty());
+  emu.swap_rom(&rom).unwrap();
+  assert_eq!(emu.get_cart_header().mirroring, Mirroring::Horizontal);
+
+  other.add_override(entry);
+  other.swap_rom(&rom).unwrap();
+  assert_eq!(other.get_cart_header().mirroring, Mirroring::Vertical);
+}
@@ -0,0 +1,86 @@
+use nen_emulator::nes::Nes;
+
+mod common;
+use common::RomBuilder;
+
+// A square wave at full volume
+const CODE: &[u8] = &[
+  0xA9, 0x01, 0x8D, 0x15, 0x40, // C000: enable pulse 1
+  0xA9, 0xBF, 0x8D, 0x00, 0x40, // C005: constant volume 15
+  0xA9, 0xFF, 0x8D, 0x02, 0x40, // C00A: period $0ff
+  0xA9, 0x00, 0x8D, 0x03, 0x40, // C00F
+  0x4C, 0x14, 0xC0,             // C014: jmp $c014
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).reset(0xC000).build()
+}
+
+fn boot() -> Nes {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..5 { emu.step_until_vblank(); }
+  emu.get_samples();
+  emu
+}
+
+fn frame_samples(emu: &mut Nes) -> Vec<f32> {
+  emu.step_until_vblank();
+  emu.get_samples()
+}
+
+fn max_jump(samples: &[f32]) -> f32 {
+  samples.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max)
+}
+
+#[test]
+fn silence_while_paused() {
+  let mut emu = boot();
+  let playing = frame_samples(&mut emu);
+  let last = *playing.last().unwrap();
+  assert!(max_jump(&playing) > 0.05, "the square wave is playing");
+
+  emu.set_paused(true);
+  let frames: Vec<Vec<f32>> = (0..60).map(|_| emu.get_samples()).collect();
+
+  // 60 frames of samples, fading out from where the sound was
+  let expected = (60.0 * emu.get_sample_rate() as f32 / emu.get_fps()) as usize;
+  assert!(frames.iter().map(Vec::len).sum::<usize>().abs_diff(expected) <= 1);
+  assert!((frames[0][0] - last).abs() < 0.01, "{} after {last}", frames[0][0]);
+  assert!(max_jump(&frames[0]) < 0.01);
+  assert!(frames[1..].iter().flatten().all(|&sample| sample == 0.0));
+
+  // and back in when resuming
+  emu.set_paused(false);
+  let resumed = frame_samples(&mut emu);
+  assert!(resumed[0].abs() < 0.01, "{}", resumed[0]);
+  assert!(max_jump(&resumed[256..]) > 0.05, "the square wave plays again");
+}
+
+#[test]
+fn frame_advance_fades_out() {
+  let mut emu = boot();
+  emu.set_paused(true);
+  emu.get_samples();
+
+  let advanced = frame_samples(&mut emu);
+  assert!(advanced.len() > 700, "a frame of sound: {}", advanced.len());
+  assert!(advanced[0].abs() < 0.01 && advanced.last().unwrap().abs() < 0.01);
+  assert!(max_jump(&advanced) > 0.05);
+}
+
+#[test]
+fn slow_motion_stretches() {
+  let mut emu = boot();
+  let normal = frame_samples(&mut emu).len() as f32;
+
+  emu.set_speed(0.5).unwrap();
+  let slow = (0..4).map(|_| frame_samples(&mut emu).len()).sum::<usize>() as f32 / 4.0;
+  assert!((slow / normal - 2.0).abs() < 0.01, "{slow} samples per frame, {normal} at normal speed");
+  assert_eq!(emu.get_speed(), 0.5);
+
+  // hardcore mode goes back to real time, and refuses slow motion
+  emu.set_hardcore(true);
+  assert_eq!(emu.get_speed(), 1.0);
+  assert!(emu.set_speed(0.5).is_err());
+  assert_eq!(emu.get_speed(), 1.0);
+}
@@ -0,0 +1,49 @@
+use nen_emulator::{events::EmuEvent, mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// UxROM with 4 banks, whose first byte is their number, and a battery
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().mapper(2).battery()
+    .prg_banks(4, 0).bank_numbers()
+    .chr_banks(0)
+    // the bank numbers in the fixed bank, to write them without bus conflicts
+    .put(0xC010, &[0, 1, 2, 3])
+    .reset(0xC000)
+    .build()
+}
+
+fn selected_bank(emu: &mut Nes) -> u8 {
+  emu.get_bus().read(0x8000)
+}
+
+#[test]
+fn power_cycle_clears_the_mapper() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  let bus = emu.get_bus();
+  bus.write(0xC013, 3);
+  bus.write(0x6000, 0x42);
+  bus.write(0x0000, 0x24);
+  assert_eq!(selected_bank(&mut emu), 3);
+
+  // the reset button leaves the mapper alone
+  emu.reset();
+  assert_eq!(selected_bank(&mut emu), 3);
+
+  emu.power_cycle();
+  assert_eq!(selected_bank(&mut emu), 0);
+  let bus = emu.get_bus();
+  assert_eq!(bus.read(0x0000), 0, "the internal ram is lost");
+  assert_eq!(bus.read(0x6000), 0x42, "the battery backed ram is kept");
+  assert_eq!(emu.poll_events().last(), Some(&EmuEvent::PowerCycle));
+
+  // it powers on as a freshly booted console
+  let mut booted = Nes::boot_from_bytes(&build_rom()).unwrap();
+  booted.get_bus().write(0x6000, 0x42);
+  for _ in 0..10 {
+    emu.step_until_vblank();
+    booted.step_until_vblank();
+  }
+  assert_eq!(emu.state_hash(), booted.state_hash());
+}
@@ -0,0 +1,81 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// Reads and writes $2007 with rendering off, as games do in vblank
+fn boot() -> Nes {
+  let rom = RomBuilder::new().code(&[0x4C, 0x00, 0xC0]).reset(0xC000).build();
+  let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+  emu.step_until_vblank();
+  emu
+}
+
+fn set_addr(emu: &mut Nes, addr: u16) {
+  let bus = emu.get_bus();
+  bus.write(0x2006, (addr >> 8) as u8);
+  bus.write(0x2006, addr as u8);
+}
+
+fn write(emu: &mut Nes, addr: u16, val: u8) {
+  set_addr(emu, addr);
+  emu.get_bus().write(0x2007, val);
+}
+
+// The first read only fills the buffer
+fn buffered_read(emu: &mut Nes, addr: u16) -> u8 {
+  set_addr(emu, addr);
+  emu.get_bus().read(0x2007);
+  emu.get_bus().read(0x2007)
+}
+
+#[test]
+fn palette_reads_fill_the_buffer_with_the_nametable_underneath() {
+  let mut emu = boot();
+  write(&mut emu, 0x2F00, 0x5A);
+  write(&mut emu, 0x3F00, 0x21);
+
+  set_addr(&mut emu, 0x3F00);
+  // the palette comes back at once
+  assert_eq!(emu.get_bus().read(0x2007), 0x21);
+  // and the next buffered read returns the nametable byte
+  set_addr(&mut emu, 0x2000);
+  assert_eq!(emu.get_bus().read(0x2007), 0x5A);
+}
+
+#[test]
+fn upper_nametables_mirror_the_lower_ones() {
+  let mut emu = boot();
+  write(&mut emu, 0x2123, 0x77);
+  assert_eq!(buffered_read(&mut emu, 0x3123), 0x77);
+
+  write(&mut emu, 0x3456, 0x42);
+  assert_eq!(buffered_read(&mut emu, 0x2456), 0x42);
+}
+
+#[test]
+fn reads_while_rendering_glitch_the_address() {
+  let mut emu = boot();
+  emu.get_bus().write(0x2001, 0x0A);
+  while emu.get_ppu().scanline != 100 { emu.step(); }
+
+  // fine y 2, coarse y 5, coarse x 31 of the first nametable.
+  // The bus doesn't tick the ppu, so rendering doesn't move the address in between.
+  set_addr(&mut emu, 0x20BF);
+  emu.get_bus().read(0x2007);
+  // coarse x wraps into the next nametable, and fine y is incremented, instead of adding 1
+  assert_eq!(emu.get_ppu().vram_addr(), 0x34A0);
+
+  // the 32 increment isn't used either
+  emu.get_bus().write(0x2000, 0x04);
+  set_addr(&mut emu, 0x3BA5);
+  emu.get_bus().read(0x2007);
+  // fine y 3 to 4, coarse y 29 stays, coarse x 5 to 6
+  assert_eq!(emu.get_ppu().vram_addr(), 0x4BA6);
+
+  // in vblank the usual increment is back
+  emu.step_until_vblank();
+  set_addr(&mut emu, 0x3BA5);
+  emu.get_bus().read(0x2007);
+  assert_eq!(emu.get_ppu().vram_addr(), 0x3BA5 + 32);
+}
@@ -0,0 +1,135 @@
+use nen_emulator::{nes::Nes, ppu::{Layer, Overlays}};
+
+mod common;
+use common::RomBuilder;
+
+// Puts 10 sprites on the same line through oam dma, gives them a color, then turns rendering on
+const CODE: &[u8] = &[
+  0x78, 0xA2, 0x00,                   // C000: sei, ldx #0
+  0xA9, 0xFF, 0x9D, 0x00, 0x02,       // C003: lda #$ff, sta $0200,x (hide every sprite)
+  0xE8, 0xD0, 0xF8,                   // C008: inx, bne $c003
+  0xA0, 0x00,                         // C00B: ldy #0
+  0xA9, 0x32, 0x9D, 0x00, 0x02,       // C00D: lda #50, sta $0200,x (y)
+  0x8A, 0x9D, 0x03, 0x02,             // C012: txa, sta $0203,x (x)
+  0xE8, 0xE8, 0xE8, 0xE8,             // C016: inx * 4
+  0xC8, 0xC0, 0x0A, 0xD0, 0xEE,       // C01A: iny, cpy #10, bne $c00d
+  0xA9, 0x02, 0x8D, 0x14, 0x40,       // C01F: oam dma from $0200
+  0xA9, 0x3F, 0x8D, 0x06, 0x20,       // C024: ppu address $3f1f (color 3 of sprite palette 3)
+  0xA9, 0x1F, 0x8D, 0x06, 0x20,       // C029
+  0xA9, 0x16, 0x8D, 0x07, 0x20,       // C02E: red
+  0xA9, 0x18, 0x8D, 0x01, 0x20,       // C033: enable rendering
+  0x4C, 0x38, 0xC0,                   // C038: jmp $c038
+];
+
+fn build_rom() -> Vec<u8> {
+  // the background (tile 0) is transparent, sprites (tile $ff) are solid
+  let mut chr = vec![0xFF; 8 * 1024];
+  chr[..16].fill(0);
+  RomBuilder::new().code(CODE).reset(0xC000).chr(chr).build()
+}
+
+#[test]
+fn dropped_sprites() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+
+  assert_eq!(emu.oam_dma_page(), Some(0x02));
+
+  // sprites are drawn one line below their y
+  let line = emu.debug_scanline_sprites(51).unwrap();
+  assert_eq!(line.sprites.len(), 10);
+  assert_eq!(line.drawn().len(), 8);
+  let dropped: Vec<_> = line.dropped().iter().map(|s| (s.index, s.x)).collect();
+  assert_eq!(dropped, [(8, 32), (9, 36)]);
+  assert!(emu.debug_scanline_sprites(50).unwrap().sprites.is_empty());
+  assert!(emu.debug_scanline_sprites(240).is_none());
+
+  assert_ne!(emu.debug_sprite_overlay().hash(), emu.get_screen().hash());
+}
+
+#[test]
+fn layer_toggles() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  let full = emu.get_screen().hash();
+  assert!(!emu.get_screen().is_blank());
+
+  emu.set_layer_visible(Layer::Bg, false);
+  emu.step_until_vblank();
+  assert_eq!(emu.get_screen().hash(), full);
+
+  emu.set_layer_visible(Layer::Spr, false);
+  emu.step_until_vblank();
+  assert!(emu.get_screen().is_blank());
+
+  emu.set_layer_visible(Layer::Spr, true);
+  emu.set_layer_visible(Layer::Bg, true);
+  emu.step_until_vblank();
+  assert_eq!(emu.get_screen().hash(), full);
+}
+
+#[test]
+fn overlays() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  let plain = emu.get_screen().buffer.to_vec();
+  let pixel = |buf: &[u8], x: usize, y: usize| buf[(y * 256 + x) * 4..(y * 256 + x) * 4 + 4].to_vec();
+
+  emu.set_overlay(Overlays::sprite_boxes, true);
+  emu.step_until_vblank();
+  let boxes = emu.get_screen().buffer.to_vec();
+  // top left corner of the first sprite, and inside of it
+  assert_ne!(pixel(&boxes, 0, 51), pixel(&plain, 0, 51));
+  assert_eq!(pixel(&boxes, 3, 54), pixel(&plain, 3, 54));
+  // the dropped sprites have no box
+  assert_eq!(pixel(&boxes, 39, 51), pixel(&plain, 39, 51));
+
+  emu.set_overlay(Overlays::sprite_boxes, false);
+  emu.set_overlay(Overlays::tile_grid | Overlays::attr_grid, true);
+  emu.step_until_vblank();
+  let grid = emu.get_screen().buffer.to_vec();
+  assert_ne!(pixel(&grid, 8, 3), pixel(&grid, 16, 3));
+  assert_ne!(pixel(&grid, 8, 3), pixel(&grid, 9, 3));
+  assert_eq!(pixel(&grid, 9, 3), pixel(&plain, 9, 3));
+
+  emu.set_overlay(Overlays::all(), false);
+  emu.step_until_vblank();
+  assert_eq!(emu.get_screen().buffer.to_vec(), plain);
+}
+
+#[test]
+fn scroll_log() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+
+  // the last $2006 write left the scrolling at $3f1f: fine y 3, nametable 3, coarse y 24, coarse x 31
+  let log = emu.debug_scroll_log();
+  assert_eq!(log.len(), 240);
+  assert_eq!((log[0].x, log[0].y, log[0].nametable), (248, 195, 3));
+  assert_eq!((log[44].y, log[44].nametable), (239, 3));
+  // past the bottom of the nametable, rendering wraps to the one above
+  assert_eq!((log[45].y, log[45].nametable), (0, 1));
+  assert_eq!(log[45].plane_position(), (256 + 248, 0));
+}
+
+#[test]
+fn memory_views() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+
+  assert_eq!(emu.debug_nametables().len(), 2 * 1024);
+  assert_eq!(emu.debug_palettes()[0x1F], 0x16);
+  assert_eq!(&emu.debug_oam()[..4], [50, 0xFF, 0xFF, 0]);
+
+  // the game is idle, nothing changes from one frame to the next
+  let changes = emu.memory_changes();
+  assert_eq!((changes.nametables, changes.palettes, changes.oam), (0, 1, 256));
+  emu.step_until_vblank();
+  assert_eq!(emu.memory_changes(), changes);
+
+  // a savestate replaces all of them
+  let state = emu.save_state();
+  emu.load_state(&state).unwrap();
+  let loaded = emu.memory_changes();
+  assert!(loaded.nametables > changes.nametables && loaded.palettes > changes.palettes && loaded.oam > changes.oam);
+}
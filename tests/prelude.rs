@@ -0,0 +1,26 @@
+use nen_emulator::prelude::*;
+
+mod common;
+use common::RomBuilder;
+
+// What a frontend does in a frame, with the prelude only
+const CODE: &[u8] = &[
+  0x4C, 0x00, 0xC0, // C000: jmp $c000
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).reset(0xC000).build()
+}
+
+#[test]
+fn runs_a_frame_with_the_prelude() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_config(EmuConfig { aspect_mode: AspectMode::SquarePixels, ..*emu.get_config() }).unwrap();
+  emu.get_joypad().buttons1.insert(JoypadButton::start);
+
+  assert_eq!(emu.step_until_vblank(), StepOutcome::Ok);
+  let screen: &FrameBuffer = emu.get_screen();
+  assert_eq!(screen.buffer.len(), screen.width * screen.height * 4);
+  assert!(!emu.get_samples().is_empty());
+  assert_eq!(emu.region(), (ConsoleTiming::Unknown, RegionSource::Default));
+}
@@ -0,0 +1,125 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// NROM over NES 2.0, with the prg ram shift count of byte 10 (64 << shift bytes)
+fn build_rom(ram_shift: u8) -> Vec<u8> {
+  RomBuilder::new().battery().prg_nvram_shift(ram_shift).build()
+}
+
+// Writes a different value at every mirror of the ram, and reads back the first address
+fn detect_ram_size(emu: &mut Nes) -> usize {
+  let bus = emu.get_bus();
+  for (i, size) in [8 * 1024, 4 * 1024, 2 * 1024].into_iter().enumerate() {
+    bus.write(0x6000 + size as u16 / 2, 0);
+    bus.write(0x6000, 0);
+    bus.write(0x6000 + size as u16 / 2, i as u8 + 1);
+    if bus.read(0x6000) == 0 {
+      return size;
+    }
+  }
+  1024
+}
+
+#[test]
+fn small_prg_ram_is_mirrored() {
+  // 2kb, 4kb and 8kb of battery backed ram
+  for (shift, size) in [(5, 2 * 1024), (6, 4 * 1024), (7, 8 * 1024)] {
+    let mut emu = Nes::boot_from_bytes(&build_rom(shift)).unwrap();
+    assert_eq!(detect_ram_size(&mut emu), size);
+
+    let bus = emu.get_bus();
+    bus.write(0x6000, 0x42);
+    bus.write(0x7FFF, 0x24);
+    for mirror in (0x6000..0x8000).step_by(size) {
+      assert_eq!(bus.read(mirror), 0x42, "${mirror:04X} with {size} bytes of ram");
+      assert_eq!(bus.read(mirror + size as u16 - 1), 0x24, "${:04X} with {size} bytes of ram", mirror + size as u16 - 1);
+    }
+    assert_eq!(emu.save_sram().unwrap().len(), size);
+  }
+}
+
+#[test]
+fn saves_keep_the_ram_size() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(5)).unwrap();
+  let mut save = vec![0; 8 * 1024];
+  save[0] = 0x42;
+  save[2 * 1024] = 0x24;
+  emu.load_sram(save);
+  assert_eq!(emu.save_sram().unwrap().len(), 2 * 1024);
+  assert_eq!(emu.get_bus().read(0x6800), 0x42);
+}
+
+#[test]
+fn ines_prg_ram_size() {
+  // byte 8 counts 8kb units
+  let rom = RomBuilder::new().battery().prg_ram_units(1).build();
+  let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+  assert_eq!(emu.get_cart_header().prg_ram_size, 8 * 1024);
+  assert_eq!(detect_ram_size(&mut emu), 8 * 1024);
+}
+
+// A battery backed board over NES 2.0, with the volatile and battery ram shift counts of byte 10
+fn build_mapper_rom(mapper: u8, ram_shift: u8, nvram_shift: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(mapper)
+    .prg_banks(2, 0)
+    .battery()
+    .prg_ram_shift(ram_shift)
+    .prg_nvram_shift(nvram_shift)
+    .build()
+}
+
+#[test]
+fn fme7_banked_prg_ram() {
+  // 16kb of ram and 16kb of battery ram, banked as one
+  let mut emu = Nes::boot_from_bytes(&build_mapper_rom(69, 8, 8)).unwrap();
+  let bus = emu.get_bus();
+  bus.write(0x8000, 8);
+  for bank in 0..4 {
+    bus.write(0xA000, 0xC0 | bank);
+    bus.write(0x6000, 0x10 + bank);
+  }
+  for bank in 0..4 {
+    bus.write(0xA000, 0xC0 | bank);
+    assert_eq!(bus.read(0x6000), 0x10 + bank);
+  }
+
+  let save = emu.save_sram().unwrap();
+  assert_eq!(save.len(), 32 * 1024);
+  assert_eq!(save[3 * 8 * 1024], 0x13);
+}
+
+// Writes the bank number at the start of every mmc5 ram bank, the value read is where the last write landed
+fn mmc5_banks(emu: &mut Nes) -> Vec<u8> {
+  let bus = emu.get_bus();
+  bus.write(0x5102, 0b10);
+  bus.write(0x5103, 0b01);
+  for bank in 0..8 {
+    bus.write(0x5113, bank);
+    bus.write(0x6000, bank);
+  }
+  (0..8).map(|bank| {
+    bus.write(0x5113, bank);
+    bus.read(0x6000)
+  }).collect()
+}
+
+#[test]
+fn mmc5_prg_ram_chips() {
+  // two chips of 8kb, bit 2 selects one
+  let mut emu = Nes::boot_from_bytes(&build_mapper_rom(5, 7, 7)).unwrap();
+  assert_eq!(mmc5_banks(&mut emu), [3, 3, 3, 3, 7, 7, 7, 7]);
+
+  // one chip of 32kb
+  let mut emu = Nes::boot_from_bytes(&build_mapper_rom(5, 0, 9)).unwrap();
+  assert_eq!(mmc5_banks(&mut emu), [4, 5, 6, 7, 4, 5, 6, 7]);
+
+  // iNes can't tell, the 64kb of the biggest boards are given
+  let rom = RomBuilder::new().mapper(5).prg_banks(2, 0).battery().build();
+  let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+  assert_eq!(mmc5_banks(&mut emu), [0, 1, 2, 3, 4, 5, 6, 7]);
+  let save = emu.save_sram().unwrap();
+  assert_eq!(save.len(), 64 * 1024);
+  assert_eq!(save[7 * 8 * 1024], 7);
+}
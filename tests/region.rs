@@ -0,0 +1,46 @@
+use nen_emulator::{cart::{ConsoleTiming, RegionSource}, config::EmuConfig, frame::fnv_hash, nes::Nes, overrides::GameOverride};
+
+mod common;
+use common::RomBuilder;
+
+fn build_rom(nes2: bool, signature: &[u8]) -> Vec<u8> {
+  let rom = if nes2 { RomBuilder::new().nes2() } else { RomBuilder::new() };
+  rom.code(signature).reset(0xC000).build()
+}
+
+#[test]
+fn region_detection() {
+  let ines = build_rom(false, b"REGN");
+  let emu = Nes::boot_from_bytes(&ines).unwrap();
+  assert_eq!(emu.region(), (ConsoleTiming::Unknown, RegionSource::Default));
+
+  let emu = Nes::boot_from_file(&ines, "Game (Europe).nes").unwrap();
+  assert_eq!(emu.region(), (ConsoleTiming::PAL, RegionSource::FileName));
+  assert_eq!(emu.get_fps(), ConsoleTiming::PAL.fps());
+
+  // the timing of a NES 2.0 header is trusted over the file name
+  let nes2 = build_rom(true, b"REGN");
+  let emu = Nes::boot_from_file(&nes2, "Game (Europe).nes").unwrap();
+  assert_eq!(emu.region(), (ConsoleTiming::NTSC, RegionSource::Header));
+
+  // the config has the last word
+  let config = EmuConfig { region: Some(ConsoleTiming::Dendy), ..Default::default() };
+  let emu = Nes::new_from_file(&ines, "Game (Europe).nes", config).unwrap();
+  assert_eq!(emu.region(), (ConsoleTiming::Dendy, RegionSource::Config));
+
+  // and is kept across savestates
+  let mut emu = Nes::boot_from_file(&ines, "Game (E).nes").unwrap();
+  let state = emu.save_state();
+  emu.load_state(&state).unwrap();
+  assert_eq!(emu.region(), (ConsoleTiming::PAL, RegionSource::FileName));
+}
+
+#[test]
+fn database_region() {
+  let rom = build_rom(false, b"REGN");
+  let mut entry = GameOverride::new(fnv_hash(&rom[16..]), "Pal Game");
+  entry.timing = Some(ConsoleTiming::PAL);
+
+  let emu = Nes::new_with_overrides(&rom, "Pal Game (USA).nes", EmuConfig::default(), vec![entry]).unwrap();
+  assert_eq!(emu.region(), (ConsoleTiming::PAL, RegionSource::Database));
+}
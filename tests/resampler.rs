@@ -0,0 +1,139 @@
+use core::f64::consts::PI;
+
+mod common;
+use common::RomBuilder;
+
+use nen_emulator::{apu::{FilterProfile, ResampleQuality}, config::EmuConfig, nes::Nes};
+
+const QUALITIES: [ResampleQuality; 2] = [ResampleQuality::Linear, ResampleQuality::Sinc];
+
+// Plays a square wave on pulse 1, with the period patched in by build_rom()
+const CODE: &[u8] = &[
+  0xA9, 0xBF, 0x8D, 0x00, 0x40,       // C000: 50% duty, halted length, constant volume 15
+  0xA9, 0x01, 0x8D, 0x15, 0x40,       // C005: enable pulse 1
+  0xA9, 0x00, 0x8D, 0x02, 0x40,       // C00A: period low
+  0xA9, 0x00, 0x8D, 0x03, 0x40,       // C00F: period high
+  0x4C, 0x14, 0xC0,                   // C014: jmp $c014
+];
+
+fn build_rom(period: u16) -> Vec<u8> {
+  let mut code = CODE.to_vec();
+  code[0x0B] = period as u8;
+  code[0x10] = (period >> 8) as u8;
+  RomBuilder::new().code(&code).reset(0xC000).build()
+}
+
+fn boot(period: u16, config: EmuConfig) -> Nes {
+  let mut emu = Nes::new_with_config(&build_rom(period), config).unwrap();
+  // let the filters settle
+  for _ in 0..10 {
+    emu.step_until_vblank();
+    emu.consume_samples();
+  }
+  emu
+}
+
+fn record(emu: &mut Nes, frames: usize) -> Vec<f32> {
+  (0..frames).flat_map(|_| {
+    emu.step_until_vblank();
+    emu.get_samples()
+  }).collect()
+}
+
+// From the rising zero crossings, placed between samples by interpolation
+fn measure_hz(samples: &[f32], sample_rate: u32) -> f64 {
+  let crossings: Vec<f64> = samples.windows(2).enumerate()
+    .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+    .map(|(i, pair)| i as f64 + (-pair[0] / (pair[1] - pair[0])) as f64)
+    .collect();
+  let periods = (crossings.len() - 1) as f64;
+  sample_rate as f64 * periods / (crossings[crossings.len() - 1] - crossings[0])
+}
+
+// Power of a frequency in the samples, with a hann window, by the goertzel algorithm
+fn power(samples: &[f32], sample_rate: u32, freq: f64) -> f64 {
+  let coeff = 2.0 * (2.0 * PI * freq / sample_rate as f64).cos();
+  let (mut s1, mut s2) = (0.0, 0.0);
+  let len = samples.len() as f64;
+  for (i, &sample) in samples.iter().enumerate() {
+    let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / len).cos();
+    let s0 = sample as f64 * window + coeff * s1 - s2;
+    s2 = s1;
+    s1 = s0;
+  }
+  s1 * s1 + s2 * s2 - coeff * s1 * s2
+}
+
+fn set_sample_rate(emu: &mut Nes, sample_rate: u32) {
+  emu.set_config(EmuConfig { sample_rate, ..*emu.get_config() }).unwrap();
+  // the sinc history starts over from silence
+  record(emu, 2);
+}
+
+#[test]
+fn sample_rates() {
+  for quality in QUALITIES {
+    let mut emu = boot(0xFD, EmuConfig { resample_quality: quality, ..Default::default() });
+    for sample_rate in [22_050, 48_000, 96_000] {
+      set_sample_rate(&mut emu, sample_rate);
+      assert_eq!(emu.get_sample_rate(), sample_rate);
+
+      let start = emu.get_cpu().cycles;
+      let count = record(&mut emu, 30).len() as f64;
+      let expected = (emu.get_cpu().cycles - start) as f64 * sample_rate as f64 / 1_789_773.0;
+      assert!((count - expected).abs() <= 2.0, "{quality:?} at {sample_rate}hz: {count} samples, expected {expected}");
+    }
+  }
+}
+
+#[test]
+fn same_pitch_at_every_rate() {
+  let mut emu = boot(0xFD, EmuConfig { sample_rate: 192_000, resample_quality: ResampleQuality::Sinc, ..Default::default() });
+  let expected = measure_hz(&record(&mut emu, 30), 192_000);
+
+  for quality in QUALITIES {
+    let mut emu = boot(0xFD, EmuConfig { resample_quality: quality, ..Default::default() });
+    for sample_rate in [11_025, 44_100, 96_000] {
+      set_sample_rate(&mut emu, sample_rate);
+      let measured = measure_hz(&record(&mut emu, 30), sample_rate);
+      assert!((measured - expected).abs() < 0.01, "{quality:?} at {sample_rate}hz: {measured}hz, expected {expected}hz");
+    }
+  }
+}
+
+#[test]
+fn sinc_filters_aliasing() {
+  // the third harmonic of a ~11khz square is past the nyquist frequency, and folds back at ~10.5khz
+  let config = EmuConfig { sample_rate: 192_000, resample_quality: ResampleQuality::Sinc, ..Default::default() };
+  let fundamental = measure_hz(&record(&mut boot(8, config), 30), 192_000);
+  let alias = 44_100.0 - 3.0 * fundamental;
+
+  let alias_ratio = |quality| {
+    let config = EmuConfig { resample_quality: quality, filter_profile: FilterProfile::Flat, ..Default::default() };
+    let samples = record(&mut boot(8, config), 60);
+    power(&samples, 44_100, alias) / power(&samples, 44_100, fundamental)
+  };
+
+  let linear = alias_ratio(ResampleQuality::Linear);
+  let sinc = alias_ratio(ResampleQuality::Sinc);
+  // at least 30db less
+  assert!(sinc * 1000.0 < linear, "alias power: linear {linear}, sinc {sinc}");
+}
+
+#[test]
+fn savestates_keep_the_resampler() {
+  let config = EmuConfig { sample_rate: 48_000, resample_quality: ResampleQuality::Sinc, ..Default::default() };
+  let mut expected = boot(0xFD, config);
+  let state = expected.save_state();
+  let fast_state = expected.save_state_fast();
+
+  let mut emu = Nes::new_with_config(&build_rom(0xFD), config).unwrap();
+  emu.load_state(&state).unwrap();
+  let mut fast_emu = Nes::new_with_config(&build_rom(0xFD), config).unwrap();
+  fast_emu.load_state_fast(&fast_state).unwrap();
+
+  let samples = record(&mut expected, 10);
+  assert_eq!(record(&mut emu, 10), samples);
+  assert_eq!(record(&mut fast_emu, 10), samples);
+}
+
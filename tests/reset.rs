@@ -0,0 +1,39 @@
+use nen_emulator::nes::Nes;
+
+mod common;
+use common::RomBuilder;
+
+// Enables the nmi right away, then counts the nmis in $00
+const CODE: &[u8] = &[
+  0xA9, 0x80,                         // C000: lda #$80
+  0x8D, 0x00, 0x20,                   // C002: sta $2000
+  0x4C, 0x05, 0xC0,                   // C005: jmp $c005
+  0xE6, 0x00,                         // C008: nmi: inc $00
+  0x40,                               // C00A: rti
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).nmi(0xC008).reset(0xC000).build()
+}
+
+#[test]
+fn soft_reset() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  for _ in 0..3 { emu.step_until_vblank(); }
+  let nmis = emu.peek(0x00);
+  assert!(nmis > 0);
+
+  let (sp, cycles) = (emu.get_cpu().sp, emu.get_cpu().cycles);
+  emu.reset();
+
+  // the reset sequence takes 7 cycles, with the ppu restarted from the top of the picture
+  let cpu = emu.get_cpu();
+  assert_eq!(cpu.pc, 0xC000);
+  assert_eq!(cpu.sp, sp.wrapping_sub(3));
+  assert_eq!(cpu.cycles, cycles + 7);
+  assert_eq!((emu.get_ppu().scanline, emu.get_ppu().cycle), (0, 21));
+
+  // the game enables the nmi too early after the reset, and the ppu ignores it
+  for _ in 0..3 { emu.step_until_vblank(); }
+  assert_eq!(emu.peek(0x00), nmis);
+}
@@ -0,0 +1,296 @@
+use std::time::Instant;
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+mod common;
+use common::RomBuilder;
+
+use nen_emulator::{mem::Memory, nes::Nes, save_bundle::SaveBundle};
+
+// Saves and loads the state every frame, and checks it stays in sync with an uninterrupted run.
+// Catches mappers (or devices) whose serialization misses some field.
+
+const FRAMES: usize = 200;
+
+// The program keeps writing random values to random mapper registers, and to the ppu.
+// Every 4kb of prg holds the same code, so whatever bank gets switched in, execution goes on.
+const CODE: &[u8] = &[
+  0x78, 0xD8, 0xA2, 0xFF, 0x9A,       // F000: sei, cld, ldx #$ff, txs
+  0xA0, 0x00,                         // F005: ldy #0
+  0xA9, 0x01, 0x85, 0x00,             // F007: lda #1, sta $00 (rng seed)
+  0xA9, 0x1E, 0x8D, 0x01, 0x20,       // F00B: enable rendering
+  0x20, 0x2B, 0xF0,                   // F010: jsr rng
+  0x30, 0x04,                         // F013: bmi +4
+  0x29, 0x0F, 0x09, 0x50,             // F015: and #$0f, ora #$50 (registers at $5000..$5FFF)
+  0x85, 0x02,                         // F019: sta $02
+  0x20, 0x2B, 0xF0, 0x85, 0x01,       // F01B: jsr rng, sta $01
+  0x20, 0x2B, 0xF0, 0x91, 0x01,       // F020: jsr rng, sta ($01),y
+  0x8D, 0x07, 0x20,                   // F025: sta $2007
+  0x4C, 0x10, 0xF0,                   // F028: jmp $f010
+  // F02B: rng, 8 bit galois lfsr
+  0xA5, 0x00, 0x0A, 0x90, 0x02, 0x49, 0x1D, 0x85, 0x00, 0x60,
+];
+
+fn build_rom(mapper: u8) -> Vec<u8> {
+  // 128kb prg, 64kb chr
+  let mut rom = RomBuilder::new().mapper(mapper).battery()
+    .prg_banks(8, 0)
+    .chr((0..64 * 1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect());
+  for chunk in rom.prg.chunks_mut(4 * 1024) {
+    chunk[..CODE.len()].copy_from_slice(CODE);
+    for vector in [0xFFA, 0xFFC, 0xFFE] {
+      chunk[vector..vector+2].copy_from_slice(&0xF000u16.to_le_bytes());
+    }
+  }
+  rom.build()
+}
+
+fn save_and_load(emu: &mut Nes) {
+  let state = emu.save_state();
+  emu.load_state(&state).unwrap();
+}
+
+fn save_and_load_fast(emu: &mut Nes) {
+  let state = emu.save_state_fast();
+  emu.load_state_fast(&state).unwrap();
+}
+
+// Mappers whose emulator went out of sync, and at which frame
+fn desynced_mappers(reload: fn(&mut Nes)) -> Vec<(u8, usize)> {
+  let mut desynced = Vec::new();
+
+  for mapper in 0..=255 {
+    let rom = build_rom(mapper);
+    let Ok(mut expected) = Nes::boot_from_bytes(&rom) else { continue; };
+    let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+
+    for frame in 0..FRAMES {
+      expected.step_until_vblank();
+      emu.step_until_vblank();
+      // as a frontend would, so the samples buffer doesn't grow forever
+      expected.consume_samples();
+      emu.consume_samples();
+      // the screen isn't part of the state, it is checked before loading
+      let same_screen = expected.get_screen().hash() == emu.get_screen().hash();
+      reload(&mut emu);
+
+      if !same_screen || expected.state_hash() != emu.state_hash() {
+        desynced.push((mapper, frame));
+        break;
+      }
+    }
+  }
+  desynced
+}
+
+#[test]
+#[ignore = "slow, run with `cargo test --release --test savestate_stress -- --ignored`"]
+fn savestate_every_frame() {
+  let desynced = desynced_mappers(save_and_load);
+  assert!(desynced.is_empty(), "mappers desynced (mapper, frame): {desynced:?}");
+}
+
+#[test]
+#[ignore = "slow, run with `cargo test --release --test savestate_stress -- --ignored`"]
+fn fast_savestate_every_frame() {
+  let desynced = desynced_mappers(save_and_load_fast);
+  assert!(desynced.is_empty(), "mappers desynced (mapper, frame): {desynced:?}");
+}
+
+// A fresh emulator loading the fast state of a running one must end up in the very same state.
+// state_hash() goes through serde, so a field the codec misses shows up here.
+#[test]
+fn fast_savestate_every_mapper() {
+  let mut mismatched = Vec::new();
+
+  for mapper in 0..=255 {
+    let rom = build_rom(mapper);
+    let Ok(mut expected) = Nes::boot_from_bytes(&rom) else { continue; };
+    for _ in 0..10 {
+      expected.step_until_vblank();
+      expected.consume_samples();
+    }
+
+    let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+    emu.load_state_fast(&expected.save_state_fast()).unwrap();
+    if expected.state_hash() != emu.state_hash() {
+      mismatched.push(mapper);
+    }
+  }
+
+  assert!(mismatched.is_empty(), "mappers not restored by fast savestates: {mismatched:?}");
+}
+
+#[test]
+fn fast_savestate_rejects_other_states() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(1)).unwrap();
+  emu.step_until_vblank();
+  let state = emu.save_state_fast();
+  let hash = emu.state_hash();
+
+  let mut other_game = Nes::boot_from_bytes(&build_rom(0)).unwrap();
+  assert!(other_game.load_state_fast(&state).is_err());
+
+  emu.step_until_vblank();
+  let running_hash = emu.state_hash();
+  assert!(emu.load_state_fast(&state[..state.len() - 1]).is_err());
+  assert!(emu.load_state_fast(&emu.save_state()).is_err());
+  // nothing of a rejected state is loaded
+  assert_eq!(emu.state_hash(), running_hash);
+
+  emu.load_state_fast(&state).unwrap();
+  assert_eq!(emu.state_hash(), hash);
+
+  emu.set_hardcore(true);
+  assert!(emu.load_state_fast(&state).is_err());
+}
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release --test savestate_stress -- --ignored --nocapture savestate_speed`"]
+fn savestate_speed() {
+  const RUNS: u32 = 1000;
+  // mmc5, one of the biggest states
+  let mut emu = Nes::boot_from_bytes(&build_rom(5)).unwrap();
+  for _ in 0..60 {
+    emu.step_until_vblank();
+    emu.consume_samples();
+  }
+
+  let start = Instant::now();
+  for _ in 0..RUNS { save_and_load(&mut emu); }
+  let json = start.elapsed() / RUNS;
+
+  let start = Instant::now();
+  for _ in 0..RUNS { save_and_load_fast(&mut emu); }
+  let fast = start.elapsed() / RUNS;
+
+  println!("json: {} bytes, {json:?} per save and load", emu.save_state().len());
+  println!("fast: {} bytes, {fast:?} per save and load", emu.save_state_fast().len());
+}
+
+// Plays a pulse, the triangle and the noise forever
+const AUDIO_CODE: &[u8] = &[
+  0xA9, 0x0F, 0x8D, 0x15, 0x40,       // enable the channels
+  0xA9, 0xBF, 0x8D, 0x00, 0x40,       // pulse 1: 50% duty, constant volume 15
+  0xA9, 0xFD, 0x8D, 0x02, 0x40,
+  0xA9, 0x00, 0x8D, 0x03, 0x40,
+  0xA9, 0xFF, 0x8D, 0x08, 0x40,       // triangle
+  0xA9, 0x80, 0x8D, 0x0A, 0x40,
+  0xA9, 0x00, 0x8D, 0x0B, 0x40,
+  0xA9, 0x3F, 0x8D, 0x0C, 0x40,       // noise: constant volume 15
+  0xA9, 0x05, 0x8D, 0x0E, 0x40,
+  0xA9, 0x00, 0x8D, 0x0F, 0x40,
+  0x4C, 0x32, 0xC0,                   // jmp self
+];
+
+#[test]
+fn savestate_keeps_audio_in_sync() {
+  let rom = RomBuilder::new().code(AUDIO_CODE).reset(0xC000).build();
+
+  let mut expected = Nes::boot_from_bytes(&rom).unwrap();
+  for _ in 0..30 {
+    expected.step_until_vblank();
+    expected.consume_samples();
+  }
+
+  let state = expected.save_state();
+  let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+  emu.load_state(&state).unwrap();
+
+  for _ in 0..30 {
+    expected.step_until_vblank();
+    emu.step_until_vblank();
+    assert_eq!(expected.get_samples(), emu.get_samples());
+  }
+}
+
+// Made by the emulator when mappers were stored as json fields, after 10 frames of build_rom()
+#[test]
+fn migrates_version_1_states() {
+  let states: [(u8, &[u8]); 2] = [
+    (0, include_bytes!("savestates/mapper000_v1.json")),
+    (4, include_bytes!("savestates/mapper004_v1.json")),
+  ];
+
+  for (mapper, state) in states {
+    let rom = build_rom(mapper);
+    let mut expected = Nes::boot_from_bytes(&rom).unwrap();
+    for _ in 0..10 {
+      expected.step_until_vblank();
+      expected.consume_samples();
+    }
+
+    let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+    emu.load_state(state).unwrap();
+
+    // version 1 didn't have the resampler, which starts over after loading: start both over
+    for emu in [&mut emu, &mut expected] {
+      let config = *emu.get_config();
+      emu.set_config(nen_emulator::config::EmuConfig { sample_rate: 48_000, ..config }).unwrap();
+      emu.set_config(config).unwrap();
+    }
+    assert_eq!(emu.state_hash(), expected.state_hash(), "mapper {mapper}");
+  }
+}
+
+#[test]
+fn hardcore_refuses_savestates() {
+  let rom = build_rom(0);
+  let mut emu = Nes::boot_from_bytes(&rom).unwrap();
+  emu.step_until_vblank();
+  let state = emu.save_state();
+
+  emu.set_hardcore(true);
+  assert!(emu.load_state(&state).is_err());
+  assert!(emu.replace_chr(&[0; 64 * 1024]).is_err());
+
+  emu.set_hardcore(false);
+  assert!(emu.load_state(&state).is_ok());
+}
+
+#[test]
+fn hardcore_survives_rom_swaps() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(0)).unwrap();
+  emu.step_until_vblank();
+  let state = emu.save_state();
+
+  emu.set_hardcore(true);
+  emu.swap_rom(&build_rom(0)).unwrap();
+  assert!(emu.is_hardcore());
+  assert!(emu.load_state(&state).is_err());
+}
+
+#[test]
+fn boot_with_config() {
+  let config = nen_emulator::config::EmuConfig {
+    ram_init: nen_emulator::config::RamInit::Ones,
+    region: Some(nen_emulator::cart::ConsoleTiming::PAL),
+    ..Default::default()
+  };
+  let mut emu = Nes::new_with_config(&build_rom(0), config).unwrap();
+  assert_eq!(emu.peek(0x0700), 0xFF);
+  assert_eq!(emu.get_cart_header().timing, nen_emulator::cart::ConsoleTiming::PAL);
+}
+
+#[test]
+fn frontend_state_survives_rom_swaps() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(0)).unwrap();
+  let polls = Arc::new(AtomicUsize::new(0));
+  let counter = polls.clone();
+  emu.set_input_poller(Some(Box::new(move || {
+    counter.fetch_add(1, Ordering::Relaxed);
+    Default::default()
+  })));
+  emu.set_speed(0.5).unwrap();
+  emu.save_bundle(100).unwrap();
+
+  emu.swap_rom(&build_rom(0)).unwrap();
+  assert_eq!(emu.get_speed(), 0.5);
+  // the game strobes the joypads
+  emu.get_bus().write(0x4016, 1);
+  emu.get_bus().write(0x4016, 0);
+  assert_eq!(polls.load(Ordering::Relaxed), 1, "the input poller is still in place");
+
+  // the bundle of a new game starts anew
+  let bundle = SaveBundle::from_bytes(&emu.save_bundle(200).unwrap()).unwrap();
+  assert_eq!(bundle.created_at, 200);
+}
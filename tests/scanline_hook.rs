@@ -0,0 +1,73 @@
+use std::sync::{Arc, Mutex};
+use nen_emulator::nes::Nes;
+
+mod common;
+use common::RomBuilder;
+
+// Rendering off, so the screen is all backdrop
+const CODE: &[u8] = &[
+  0x4C, 0x00, 0xC0, // C000: jmp $c000
+];
+
+fn build_rom() -> Vec<u8> {
+  RomBuilder::new().code(CODE).reset(0xC000).build()
+}
+
+fn set_backdrop(emu: &mut Nes, color: u8) {
+  let ppu = emu.get_ppu();
+  for (addr, val) in [(0x2006, 0x3F), (0x2006, 0x00), (0x2007, color), (0x2006, 0x00), (0x2006, 0x00)] {
+    ppu.write_reg(addr, val);
+  }
+}
+
+#[test]
+fn called_once_per_visible_scanline() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.step_until_vblank();
+
+  let calls = Arc::new(Mutex::new(Vec::new()));
+  let hook_calls = calls.clone();
+  emu.set_scanline_hook(Some(Box::new(move |scanline, emu: &mut Nes| {
+    // called right when the scanline starts
+    let (_, ppu_scanline, dot) = emu.ppu_position();
+    assert_eq!(ppu_scanline, scanline);
+    assert!(dot < 30, "called at dot {dot} of scanline {scanline}");
+    hook_calls.lock().unwrap().push(scanline);
+  })));
+
+  emu.step_until_vblank();
+  assert_eq!(*calls.lock().unwrap(), (0..240).collect::<Vec<_>>());
+}
+
+#[test]
+fn changes_the_picture_mid_frame() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  emu.set_scanline_hook(Some(Box::new(|scanline, emu: &mut Nes| match scanline {
+    0 => set_backdrop(emu, 0x0F),
+    120 => set_backdrop(emu, 0x30),
+    _ => {}
+  })));
+
+  emu.step_until_vblank();
+  emu.step_until_vblank();
+  let screen = emu.get_screen();
+  let red_at = |y: usize| screen.buffer[y * screen.pitch()];
+  assert!(red_at(10) < red_at(200), "black on top and white on the bottom: {} and {}", red_at(10), red_at(200));
+  assert_eq!(red_at(10), red_at(110));
+  assert_eq!(red_at(130), red_at(200));
+}
+
+#[test]
+fn removes_itself() {
+  let mut emu = Nes::boot_from_bytes(&build_rom()).unwrap();
+  let calls = Arc::new(Mutex::new(0));
+  let hook_calls = calls.clone();
+  emu.set_scanline_hook(Some(Box::new(move |_, emu: &mut Nes| {
+    *hook_calls.lock().unwrap() += 1;
+    emu.set_scanline_hook(None);
+  })));
+
+  emu.step_until_vblank();
+  emu.step_until_vblank();
+  assert_eq!(*calls.lock().unwrap(), 1);
+}
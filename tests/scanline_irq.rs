@@ -0,0 +1,163 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+// The prg is all NOPs, which the cpu runs with irqs masked, and jumps back to the reset vector at the end
+fn build_rom(mapper: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(mapper)
+    .prg_banks(2, 0xEA)
+    .put(0xFFF0, &[0x4C, 0xEA, 0xEA])
+    .build()
+}
+
+// Mmc3 with an irq at every counter clock, and the ppu set up with the given ctrl
+fn boot(ctrl: u8, mask: u8) -> Nes {
+  let mut emu = Nes::boot_from_bytes(&build_rom(4)).unwrap();
+  let bus = emu.get_bus();
+  bus.write(0x2000, ctrl);
+  bus.write(0x2001, mask);
+  // a latch of 0 reloads to 0, which raises the irq every time
+  bus.write(0xC000, 0);
+  bus.write(0xC001, 0);
+  bus.write(0xE001, 0);
+  emu
+}
+
+// Steps the ppu alone, and acknowledges every irq
+fn count_clocks(emu: &mut Nes, dots: usize) -> usize {
+  let mut clocks = 0;
+  for _ in 0..dots {
+    emu.get_ppu().step();
+    let bus = emu.get_bus();
+    if bus.irq_poll() {
+      clocks += 1;
+      bus.write(0xE000, 0);
+      bus.write(0xE001, 0);
+    }
+  }
+  clocks
+}
+
+// Counter clocks in a whole frame, from the first visible scanline to the end of the pre-render one
+fn clocks_per_frame(ctrl: u8) -> usize {
+  let mut emu = boot(ctrl, 0b0001_1000);
+  let frame = emu.get_ppu().frame;
+  while emu.get_ppu().frame == frame { count_clocks(&mut emu, 1); }
+  count_clocks(&mut emu, 262 * 341 - 1)
+}
+
+#[test]
+fn a12_rises_once_per_scanline() {
+  // bg at $0000 and sprites at $1000, the usual setup
+  assert_eq!(clocks_per_frame(0b0000_1000), 241);
+  // bg at $1000 and sprites at $0000, the rise comes with the bg fetches of the next scanline.
+  // A12 stays low through vblank, so the first bg fetches of the pre-render scanline clock it once more.
+  assert_eq!(clocks_per_frame(0b0001_0000), 242);
+  // A12 never rises
+  assert_eq!(clocks_per_frame(0), 0);
+}
+
+#[test]
+fn a12_with_8x16_sprites() {
+  // all sprites are at the top with tile 0, filling the 8 slots of the first 16 scanlines from the first table.
+  // The empty slots of the other scanlines fetch tile $FF, from the second table.
+  assert_eq!(clocks_per_frame(0b0010_0000), 241 - 16);
+}
+
+#[test]
+fn a12_from_ppuaddr() {
+  let mut emu = boot(0, 0);
+  let bus = emu.get_bus();
+  bus.write(0x2006, 0x10);
+  bus.write(0x2006, 0x00);
+  assert_eq!(count_clocks(&mut emu, 1), 1);
+
+  // too short a low pulse is filtered out
+  let bus = emu.get_bus();
+  bus.write(0x2006, 0x00);
+  bus.write(0x2006, 0x00);
+  bus.write(0x2006, 0x10);
+  bus.write(0x2006, 0x00);
+  assert_eq!(count_clocks(&mut emu, 1), 0);
+
+  let bus = emu.get_bus();
+  bus.write(0x2006, 0x00);
+  bus.write(0x2006, 0x00);
+  count_clocks(&mut emu, 20);
+  let bus = emu.get_bus();
+  bus.write(0x2006, 0x10);
+  bus.write(0x2006, 0x00);
+  assert_eq!(count_clocks(&mut emu, 1), 1);
+}
+
+// Scanlines of the first frame where the mmc5 raised its irq, with the cpu running
+fn mmc5_irq_scanlines(compare: u8) -> Vec<usize> {
+  let mut emu = Nes::boot_from_bytes(&build_rom(5)).unwrap();
+  let bus = emu.get_bus();
+  // no apu frame irqs
+  bus.write(0x4017, 0x40);
+  bus.write(0x2001, 0b0000_1000);
+  bus.write(0x5203, compare);
+  bus.write(0x5204, 0x80);
+
+  let frame = emu.get_ppu().frame + 1;
+  while emu.get_ppu().frame <= frame { emu.step(); }
+
+  let mut scanlines = Vec::new();
+  while emu.get_ppu().frame == frame + 1 {
+    emu.step();
+    let scanline = emu.get_ppu().scanline;
+    let bus = emu.get_bus();
+    if bus.irq_poll() {
+      scanlines.push(scanline);
+      // reading the status acknowledges it
+      bus.read(0x5204);
+    }
+  }
+  scanlines
+}
+
+#[test]
+fn mmc5_scanline_from_nametable_fetches() {
+  // the first scanline is detected at the start of scanline 0, so the counter matches the scanline number
+  assert_eq!(mmc5_irq_scanlines(10), vec![10]);
+  assert_eq!(mmc5_irq_scanlines(200), vec![200]);
+  // the counter never reaches 0
+  assert!(mmc5_irq_scanlines(0).is_empty());
+}
+
+#[test]
+fn a12_from_ppudata_increments() {
+  let mut emu = boot(0, 0);
+  let bus = emu.get_bus();
+  bus.write(0x2006, 0x0F);
+  bus.write(0x2006, 0xFF);
+  count_clocks(&mut emu, 20);
+  // the address left on the bus after the write is $1000
+  emu.get_bus().write(0x2007, 0);
+  assert_eq!(count_clocks(&mut emu, 1), 1);
+
+  // reads too, with the increment of 32
+  let mut emu = boot(0b0000_0100, 0);
+  let bus = emu.get_bus();
+  bus.write(0x2006, 0x0F);
+  bus.write(0x2006, 0xE0);
+  count_clocks(&mut emu, 20);
+  emu.get_bus().read(0x2007);
+  assert_eq!(count_clocks(&mut emu, 1), 1);
+}
+
+#[test]
+fn a12_when_rendering_stops() {
+  // all fetches from the first pattern table, so A12 only rises from v.
+  // Mid frame, bit 12 of v is bit 0 of the fine y scroll, which goes up every scanline.
+  let clocks_after_disabling = |scanline: usize| {
+    let mut emu = boot(0, 0b0000_1000);
+    while emu.get_ppu().scanline != scanline || emu.get_ppu().cycle != 100 { count_clocks(&mut emu, 1); }
+    emu.get_bus().write(0x2001, 0);
+    count_clocks(&mut emu, 10)
+  };
+  let clocks: Vec<_> = (10..14).map(clocks_after_disabling).collect();
+  assert_eq!(clocks, [0, 1, 0, 1]);
+}
@@ -41,10 +41,21 @@ struct Test {
   cycles: Vec<(u16, u8, String)>
 }
 
+const TESTS_DIR: &str = "./tests/single_step_tests/tests/";
+
+// The tests aren't distributed with the repository, as they are quite big.
+// Get them from https://github.com/SingleStepTests/ProcessorTests/tree/main/nes6502 into TESTS_DIR.
+fn read_tests(file: &str) -> Option<Vec<Test>> {
+  let Ok(json) = fs::read(format!("{TESTS_DIR}{file}")) else {
+    println!("{TESTS_DIR}{file} not found, skipping");
+    return None;
+  };
+  Some(serde_json::from_slice(&json).expect("couldn't parse json"))
+}
+
 #[test]
 fn cpu_test_one() {
-  let json = include_str!("./single_step_tests/tests/00.json");
-  let test: Vec<Test> = serde_json::from_str(json).unwrap();
+  let Some(test) = read_tests("00.json") else { return };
 
   let mut cpu = cpu_from_mock(&test[0].start);
   while cpu.cycles < test[0].cycles.len() {
@@ -62,6 +73,8 @@ fn cpu_test_one() {
 
 fn cpu_from_mock(mock: &CpuMock) -> Cpu<Ram64Kb> {
   let mut cpu = Cpu::with_ram64kb();
+  // SingleStepTests were recorded on a cpu with magic constant 0xEE, which ANE (8B) and LXA (AB) depend on
+  cpu.unstable_magic = 0xEE;
   cpu.a = mock.a;
   cpu.x = mock.x;
   cpu.y = mock.y;
@@ -78,9 +91,11 @@ fn cpu_from_mock(mock: &CpuMock) -> Cpu<Ram64Kb> {
 
 #[test]
 fn cpu_test() {
-  let mut dir = fs::read_dir("./tests/single_step_tests/tests/")
-    .expect("directory not found")
-    .enumerate();
+  let Ok(dir) = fs::read_dir(TESTS_DIR) else {
+    println!("{TESTS_DIR} not found, skipping");
+    return;
+  };
+  let mut dir = dir.enumerate();
 
   while let Some((i, Ok(f))) = dir.next() {
     let json_test = fs::read(f.path()).expect("couldnt't read file");
@@ -116,3 +131,55 @@ fn cpu_test() {
     }
   }
 }
+
+#[test]
+fn cpu_test_unstable_magic() {
+  for opcode in ["8b", "ab"] {
+    let Some(tests) = read_tests(&format!("{opcode}.json")) else { return };
+
+    for test in tests.iter() {
+      let mut cpu = cpu_from_mock(&test.start);
+      while cpu.cycles < test.cycles.len() {
+        cpu.step();
+      }
+
+      let mut my_end = CpuMock::from_cpu(&cpu);
+      for (addr, _) in &test.end.ram {
+        my_end.ram.push((*addr, cpu.read(*addr)))
+      }
+
+      assert_eq!(test.end, my_end, 
+        "Found error in opcode {opcode}, test {:?}\n{}",
+        test.name, diff_words(&my_end.to_string(), &test.end.to_string()));
+    }
+  }
+}
+
+// Runs an immediate ANE or LXA, which don't need the test files
+fn run_unstable(opcode: u8, magic: u8, a: u8, x: u8, imm: u8) -> (u8, u8, bool, bool) {
+  let mut cpu = Cpu::with_ram64kb();
+  cpu.unstable_magic = magic;
+  cpu.pc = 0x0200;
+  cpu.a = a;
+  cpu.x = x;
+  cpu.write(0x0200, opcode);
+  cpu.write(0x0201, imm);
+  cpu.step();
+  assert_eq!(cpu.pc, 0x0202);
+  (cpu.a, cpu.x, cpu.p.contains(CpuFlags::zero), cpu.p.contains(CpuFlags::negative))
+}
+
+#[test]
+fn ane_lxa_magic() {
+  // ANE: A = (A | magic) & X & imm
+  assert_eq!(run_unstable(0x8B, 0xFF, 0x00, 0xF0, 0xCC), (0xC0, 0xF0, false, true));
+  assert_eq!(run_unstable(0x8B, 0xEE, 0x00, 0xFF, 0xFF), (0xEE, 0xFF, false, true));
+  assert_eq!(run_unstable(0x8B, 0xEE, 0x11, 0x0F, 0x0F), (0x0F, 0x0F, false, false));
+  assert_eq!(run_unstable(0x8B, 0xEE, 0x00, 0x11, 0xFF), (0x00, 0x11, true, false));
+
+  // LXA: A = X = (A | magic) & imm
+  assert_eq!(run_unstable(0xAB, 0xFF, 0x00, 0x00, 0x5A), (0x5A, 0x5A, false, false));
+  assert_eq!(run_unstable(0xAB, 0xEE, 0x00, 0x33, 0xFF), (0xEE, 0xEE, false, true));
+  assert_eq!(run_unstable(0xAB, 0xEE, 0x01, 0x33, 0x11), (0x01, 0x01, false, false));
+  assert_eq!(run_unstable(0xAB, 0xEE, 0x00, 0x33, 0x11), (0x00, 0x00, true, false));
+}
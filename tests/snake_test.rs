@@ -25,110 +25,61 @@ static GAME_CODE: [u8; 309] = [
 
 #[cfg(test)]
 mod snake_test {
-    use std::time::Duration;
-    
-    use nen_emulator::{cpu::Cpu, mem::Memory};
-    use rand::Rng;
-    use sdl2::{event::Event, keyboard::Keycode, pixels::{Color, PixelFormatEnum}};
+  use nen_emulator::{cpu::Cpu, mem::{Memory, Ram64Kb}, rng::Rng};
 
-    use super::GAME_CODE;
+  use super::GAME_CODE;
 
-    const PIXEL_SCALE: f32 = 25.0;
-
-  #[test]
-  fn run_snake() {
-    colog::init();
-
-    let ctx = sdl2::init().expect("Couldn't initialize SDL2");
-    let video= ctx.video().expect("Couldn't initialize video subsystem");
-    let mut canvas = video.window("Nen-Emulator", (32.0 * PIXEL_SCALE) as u32, (32.0 * PIXEL_SCALE) as u32)
-        .position_centered()
-        .build().expect("Couldn't initialize window")
-        .into_canvas()
-        .accelerated().present_vsync()
-        .build().expect("Couldn't initialize drawing canvas");
-
-    let mut events = ctx.event_pump().expect("Couldn't get the event pump");
-    canvas.set_scale(PIXEL_SCALE, PIXEL_SCALE).unwrap();
-
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32)
-        .unwrap();
+  // The game reads the last pressed key from $FF, and a random byte from $FE
+  const KEYS: [u8; 4] = [0x77, 0x64, 0x73, 0x61];
+  // The 32x32 screen, one byte per pixel
+  const SCREEN: std::ops::Range<u16> = 0x200..0x600;
 
+  fn boot() -> Cpu<Ram64Kb> {
     let mut emu = Cpu::with_ram64kb();
-  
     for (i, byte) in GAME_CODE.iter().enumerate() {
       emu.write(0x600 + i as u16, *byte);
     }
     emu.pc = 0x600;
+    emu
+  }
 
-    let mut framebuffer = [0u8; 32*32*3];
-    let mut rng = rand::thread_rng();
-
-    'running: loop {
-        // info!("{:?}", &INSTRUCTIONS[emu.bus.read(emu.cpu.pc) as usize]);
-        emu.step();
-
-        for event in events.poll_iter() {
-          match event {
-            Event::Quit { .. } => break 'running,
-            Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-              emu.write(0xFF, 0x77);
-            }
-            Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-              emu.write(0xFF, 0x73);
-            }
-            Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-              emu.write(0xFF, 0x61);
-            }
-            Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-              emu.write(0xFF, 0x64);
-            }
-            _ => {}
-        }
-      }
+  fn screen(emu: &mut Cpu<Ram64Kb>) -> Vec<u8> {
+    SCREEN.map(|addr| emu.read(addr)).collect()
+  }
 
-      emu.write(0xfe, rng.gen_range(2..16));
+  #[test]
+  fn run_snake() {
+    let mut emu = boot();
+    let mut rng = Rng::new(0);
 
-      let mut to_update = false;
-      for i in 0..(0x600-0x200) {
-        let color_idx = emu.read(0x200 + i);
-        let color = match color_idx {
-          0 => Color::BLACK,
-          1 => Color::WHITE,
-          2 | 9 => Color::GREY,
-          3 | 10 => Color::RED,
-          4 | 11 => Color::GREEN,
-          5 | 12 => Color::BLUE,
-          6 | 13 => Color::MAGENTA,
-          7 | 14 => Color::YELLOW,
-          8 | 15 => Color::CYAN,
-          _ => Color::BLACK
-        };
+    for _ in 0..2000 {
+      emu.write(0xFE, 2 + rng.next_u8() % 14);
+      emu.step();
+    }
 
-        let (r, g, b) = color.rgb();
+    // the snake starts 2 pixels long at $0411, plus the apple
+    let drawn = screen(&mut emu).iter().filter(|&&pixel| pixel != 0).count();
+    assert_eq!(drawn, 3);
+    let head = u16::from_le_bytes([emu.read(0x10), emu.read(0x11)]);
+    assert!(SCREEN.contains(&head) && head != 0x411, "the snake should have moved, head at ${head:04X}");
+  }
 
-        if framebuffer[i as usize * 3 + 0] != r 
-        || framebuffer[i as usize * 3 + 1] != g 
-        || framebuffer[i as usize * 3 + 2] != b {
-          framebuffer[i as usize * 3 + 0] = r;
-          framebuffer[i as usize * 3 + 1] = g;
-          framebuffer[i as usize * 3 + 2] = b;
-          to_update = true;
+  #[test]
+  fn snake_is_deterministic() {
+    let play = || {
+      let mut emu = boot();
+      let mut rng = Rng::new(42);
+
+      for step in 0..20_000 {
+        if step % 500 == 0 {
+          emu.write(0xFF, KEYS[rng.next_u8() as usize % KEYS.len()]);
         }
+        emu.write(0xFE, 2 + rng.next_u8() % 14);
+        emu.step();
       }
+      (emu.pc, emu.cycles, screen(&mut emu))
+    };
 
-      if to_update {
-        texture.update(None, &framebuffer, 32*3).unwrap();
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
-      }
-
-      std::thread::sleep(Duration::new(0, 10_000));
-    }
-
-    println!("{:?}", emu);
+    assert_eq!(play(), play());
   }
-  
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+use nen_emulator::{config::EmuConfig, frame::SYS_COLORS, mem::Memory, nes::Nes, ppu::RenderAccuracy};
+
+mod common;
+use common::RomBuilder;
+
+const RED: u8 = 0x16;
+const GREEN: u8 = 0x2A;
+
+// CNROM with 2 chr banks: tile 1 is solid color 1 in the first one, and solid color 2 in the second.
+// The program just loops, the tests set the ppu up through the bus.
+fn build_rom() -> Vec<u8> {
+  let mut chr = vec![0; 16 * 1024];
+  chr[0x10..0x18].fill(0xFF);
+  chr[0x2018..0x2020].fill(0xFF);
+
+  RomBuilder::new().mapper(3)
+    .code(&[0x4C, 0x00, 0xC0])
+    // the bank number, where it is written, so that bus conflicts don't get in the way
+    .put(0xF000, &[1])
+    .reset(0xC000)
+    .chr(chr)
+    .build()
+}
+
+fn boot(accuracy: RenderAccuracy, sprite_limit: bool, oam: &[u8]) -> Nes {
+  let config = EmuConfig { render_accuracy: accuracy, sprite_limit, ..Default::default() };
+  let mut emu = Nes::new_with_config(&build_rom(), config).unwrap();
+  emu.step_until_vblank();
+
+  let bus = emu.get_bus();
+  bus.write(0x2003, 0);
+  for &val in oam { bus.write(0x2004, val); }
+  // sprite palette 0
+  bus.write(0x2006, 0x3F);
+  bus.write(0x2006, 0x11);
+  bus.write(0x2007, RED);
+  bus.write(0x2007, GREEN);
+  bus.write(0x2001, 0x1E);
+  emu
+}
+
+// Every sprite hidden, but the given ones
+fn oam_with(sprites: &[[u8; 4]]) -> Vec<u8> {
+  let mut oam = vec![0xFF; 256];
+  for (i, sprite) in sprites.iter().enumerate() {
+    oam[i * 4..i * 4 + 4].copy_from_slice(sprite);
+  }
+  oam
+}
+
+fn overflow_flag(emu: &mut Nes) -> bool {
+  emu.step_until_vblank();
+  emu.get_bus().read(0x2002) & 0x20 != 0
+}
+
+fn pixel(emu: &Nes, x: usize, y: usize) -> [u8; 3] {
+  let idx = (y * emu.get_screen().width + x) * 4;
+  emu.get_screen().buffer[idx..idx + 3].try_into().unwrap()
+}
+
+fn color(id: u8) -> [u8; 3] {
+  let color = &SYS_COLORS[id as usize];
+  [color.0, color.1, color.2]
+}
+
+#[test]
+fn same_picture_in_both_accuracies() {
+  // 10 sprites on the same lines, and some on their own
+  let mut sprites: Vec<_> = (0..10).map(|i| [50, 1, i & 3, i * 12]).collect();
+  sprites.extend((0..6).map(|i| [100 + i * 20, 1, 0x40 | i, 200 - i * 10]));
+  let oam = oam_with(&sprites);
+
+  for sprite_limit in [false, true] {
+    let mut scanline = boot(RenderAccuracy::Scanline, sprite_limit, &oam);
+    let mut dot = boot(RenderAccuracy::Dot, sprite_limit, &oam);
+    for _ in 0..2 {
+      scanline.step_until_vblank();
+      dot.step_until_vblank();
+    }
+
+    assert_eq!(scanline.get_screen().hash(), dot.get_screen().hash(), "sprite limit {sprite_limit}");
+    assert_eq!(scanline.debug_scanline_sprites(51), dot.debug_scanline_sprites(51));
+  }
+}
+
+#[test]
+fn overflow_bug() {
+  let mut sprites = vec![[50, 1, 0, 0]; 8];
+  // once secondary oam is full, the tile of sprite 9 is taken as its y
+  sprites.push([200, 0, 0, 0]);
+  sprites.push([200, 50, 0, 0]);
+  let oam = oam_with(&sprites);
+
+  assert!(!overflow_flag(&mut boot(RenderAccuracy::Scanline, true, &oam)));
+  assert!(overflow_flag(&mut boot(RenderAccuracy::Dot, true, &oam)));
+
+  // and 9 sprites in range can go unnoticed
+  sprites[9] = [50, 0, 0, 0];
+  let oam = oam_with(&sprites);
+  assert!(overflow_flag(&mut boot(RenderAccuracy::Scanline, true, &oam)));
+  assert!(!overflow_flag(&mut boot(RenderAccuracy::Dot, true, &oam)));
+}
+
+#[test]
+fn oam_reads_while_clearing_secondary_oam() {
+  let oam = oam_with(&[[0x12, 0x34, 0x56, 0x78]]);
+
+  for (accuracy, expected) in [(RenderAccuracy::Scanline, 0x12), (RenderAccuracy::Dot, 0xFF)] {
+    let mut emu = boot(accuracy, true, &oam);
+    emu.get_bus().write(0x2003, 0);
+    while emu.get_ppu().scanline != 100 || emu.get_ppu().cycle < 10 {
+      emu.get_ppu().step();
+    }
+    assert_eq!(emu.get_bus().read(0x2004), expected, "{accuracy:?}");
+  }
+}
+
+#[test]
+fn chr_switch_during_hblank() {
+  // drawn from line 100
+  let oam = oam_with(&[[99, 1, 0, 16]]);
+
+  for (accuracy, expected) in [(RenderAccuracy::Scanline, RED), (RenderAccuracy::Dot, GREEN)] {
+    let mut emu = boot(accuracy, true, &oam);
+    emu.step_until_vblank();
+
+    // after the sprites of line 100 are evaluated, before their patterns are fetched
+    while emu.get_ppu().scanline != 99 || emu.get_ppu().cycle != 258 {
+      emu.get_ppu().step();
+    }
+    emu.get_bus().write(0xF000, 1);
+    emu.step_until_vblank();
+
+    assert_eq!(pixel(&emu, 16, 100), color(expected), "{accuracy:?}");
+    assert_eq!(pixel(&emu, 16, 101), color(GREEN), "{accuracy:?}");
+  }
+}
@@ -0,0 +1,67 @@
+use nen_emulator::{mem::Memory, nes::Nes};
+
+mod common;
+use common::RomBuilder;
+
+fn build_rom(mapper: u8) -> Vec<u8> {
+  RomBuilder::new().mapper(mapper).battery().prg_banks(8, 0).build()
+}
+
+// Writes a value to the sram, and reads it back
+fn sram_roundtrip(emu: &mut Nes, val: u8) -> u8 {
+  let bus = emu.get_bus();
+  bus.write(0x6000, val);
+  bus.read(0x6000)
+}
+
+// The register is written serially, one bit per write. Consecutive writes are ignored, so the bus is ticked between them.
+fn mmc1_write_prg_select(emu: &mut Nes, val: u8) {
+  for bit in 0..5 {
+    let bus = emu.get_bus();
+    bus.write(0xE000, (val >> bit) & 1);
+    bus.tick();
+    bus.tick();
+  }
+}
+
+#[test]
+fn mmc1_ram_disable() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(1)).unwrap();
+  assert_eq!(sram_roundtrip(&mut emu, 0x11), 0x11);
+
+  mmc1_write_prg_select(&mut emu, 0b1_0000);
+  assert_ne!(sram_roundtrip(&mut emu, 0x22), 0x22);
+  mmc1_write_prg_select(&mut emu, 0);
+  assert_eq!(emu.get_bus().read(0x6000), 0x11);
+}
+
+#[test]
+fn mmc3_ram_protect() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(4)).unwrap();
+  assert_eq!(sram_roundtrip(&mut emu, 0x11), 0x11);
+
+  // enabled, but write protected
+  emu.get_bus().write(0xA001, 0b1100_0000);
+  assert_eq!(sram_roundtrip(&mut emu, 0x22), 0x11);
+
+  // disabled
+  emu.get_bus().write(0xA001, 0);
+  assert_ne!(sram_roundtrip(&mut emu, 0x11), 0x11);
+
+  emu.get_bus().write(0xA001, 0b1000_0000);
+  assert_eq!(sram_roundtrip(&mut emu, 0x33), 0x33);
+}
+
+#[test]
+fn mmc5_ram_locks() {
+  let mut emu = Nes::boot_from_bytes(&build_rom(5)).unwrap();
+  assert_eq!(sram_roundtrip(&mut emu, 0x11), 0);
+
+  let bus = emu.get_bus();
+  bus.write(0x5102, 0b10);
+  bus.write(0x5103, 0b01);
+  assert_eq!(sram_roundtrip(&mut emu, 0x11), 0x11);
+
+  emu.get_bus().write(0x5103, 0);
+  assert_eq!(sram_roundtrip(&mut emu, 0x22), 0x11);
+}